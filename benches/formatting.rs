@@ -0,0 +1,116 @@
+//! Benchmarks of the code every checkin goes through on its way to a status,
+//! without the network. `swarmdon simulate` measures the same stages under
+//! load, on a whole server.
+
+use std::collections::HashMap;
+
+use criterion::black_box;
+use criterion::criterion_group;
+use criterion::criterion_main;
+use criterion::Criterion;
+use serde_json::json;
+use serde_json::Value;
+use swarmdon::model::Database;
+use swarmdon::model::Friends;
+use swarmdon::model::Layout;
+use swarmdon::model::UserSettings;
+use swarmdon::status;
+use swarmdon::status::Lookups;
+use swarmdon::swarm::SwarmCheckin;
+use swarmdon::swarm::SwarmCheckinDetail;
+use swarmdon::template::Template;
+
+const LONG_SHOUT: &str = "Long shout to exercise splitting into replies. The quick brown fox jumps over the lazy dog, again and again, until the status runs out of characters and the rest of the text has to be posted in a thread below it. Then a bit more, just to be sure it really doesn't fit into a single status anymore.";
+
+fn checkin(shout: &str) -> Value {
+    json!({
+        "id": "5f0c2d1e9a7b3c0012345678",
+        "type": "checkin",
+        "createdAt": 1714816800,
+        "shout": shout,
+        "user": { "id": "1", "firstName": "Bench", "lastName": "Mark" },
+        "with": [{ "id": "2", "firstName": "Friend", "lastName": "", "handle": "friend" }],
+        "venue": {
+            "id": "4b0588f0f964a520c0d422e3",
+            "name": "Ramen Shop",
+            "location": {
+                "city": "Tokyo",
+                "state": "Tokyo",
+                "cc": "JP",
+                "lat": 35.6595,
+                "lng": 139.7005,
+            },
+            "categories": [{ "id": "4bf58dd8d48988d1d1941735", "name": "Ramen Restaurant", "primary": true }],
+        },
+    })
+}
+
+fn details(checkin: &Value) -> SwarmCheckinDetail {
+    let mut details = checkin.clone();
+    details["checkinShortUrl"] = json!("https://swarmapp.com/c/bench");
+    details["venue"]["beenHere"] = json!({ "count": 3 });
+    serde_json::from_value(details).unwrap()
+}
+
+fn bench_template(c: &mut Criterion) {
+    let source = status::layout_template(Layout::default());
+    c.bench_function("template/parse", |b| {
+        b.iter(|| Template::parse(black_box(source)).unwrap())
+    });
+
+    let template = Template::parse(source).unwrap();
+    let values: HashMap<&str, String> = [
+        ("shout", "Finally trying the ramen everyone talks about 🍜"),
+        ("venue", "Ramen Shop"),
+        ("location", "Tokyo, Japan"),
+        ("url", "https://swarmapp.com/c/bench"),
+    ]
+    .into_iter()
+    .map(|(name, value)| (name, value.to_string()))
+    .collect();
+    c.bench_function("template/render", |b| {
+        b.iter(|| template.render(black_box(&values)))
+    });
+}
+
+fn bench_compose(c: &mut Criterion) {
+    let settings = UserSettings::default();
+    let friends = Friends::default();
+    let lookups = Lookups::default();
+    for (name, shout) in [("short", "Lunch"), ("thread", LONG_SHOUT)] {
+        let value = checkin(shout);
+        let checkin = SwarmCheckin::from_value(value.clone()).unwrap();
+        let details = details(&value);
+        c.bench_function(&format!("compose/{}", name), |b| {
+            b.iter(|| status::compose(&settings, &friends, &checkin, &details, &lookups))
+        });
+    }
+}
+
+fn bench_decode(c: &mut Criterion) {
+    let value = checkin("Lunch");
+    c.bench_function("decode", |b| {
+        b.iter(|| SwarmCheckin::from_value(black_box(value.clone())).unwrap())
+    });
+}
+
+fn bench_dedupe(c: &mut Criterion) {
+    let db = Database::temporary().unwrap();
+    let mut id = 0u64;
+    c.bench_function("dedupe", |b| {
+        b.iter(|| {
+            id += 1;
+            db.mark_processed("bench", &format!("{:024x}", id), 1714816800)
+                .unwrap()
+        })
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_template,
+    bench_compose,
+    bench_decode,
+    bench_dedupe
+);
+criterion_main!(benches);
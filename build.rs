@@ -0,0 +1,29 @@
+use std::process::Command;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+fn main() {
+    // Builds from a tarball have no .git, the commit can be passed in instead.
+    let commit = std::env::var("SWARMDON_GIT_COMMIT")
+        .ok()
+        .filter(|commit| !commit.is_empty())
+        .or_else(|| {
+            Command::new("git")
+                .args(["rev-parse", "--short=12", "HEAD"])
+                .output()
+                .ok()
+                .filter(|output| output.status.success())
+                .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        })
+        .unwrap_or_else(|| "unknown".to_string());
+    let built_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+
+    println!("cargo:rustc-env=SWARMDON_GIT_COMMIT={}", commit);
+    println!("cargo:rustc-env=SWARMDON_BUILD_TIME={}", built_at);
+    println!("cargo:rerun-if-env-changed=SWARMDON_GIT_COMMIT");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+}
@@ -0,0 +1,1140 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::State;
+use axum::headers::Cookie;
+use axum::headers::SetCookie;
+use axum::response::Html;
+use axum::response::Redirect;
+use axum::Form;
+use axum::TypedHeader;
+use serde::Deserialize;
+use url::Host;
+use url::Url;
+
+use crate::categories;
+use crate::clients::http_client;
+use crate::feeds;
+use crate::html::ago;
+use crate::html::escape;
+use crate::html::page;
+use crate::last_seen;
+use crate::model::Coordinates;
+use crate::model::HouseholdMode;
+use crate::model::LastSeen;
+use crate::model::Layout;
+use crate::model::LinkMode;
+use crate::model::OverCap;
+use crate::model::QuietHours;
+use crate::model::Retention;
+use crate::onboarding;
+use crate::recap;
+use crate::recap::Period;
+use crate::retention;
+use crate::rules;
+use crate::schedule;
+use crate::status;
+use crate::venues;
+use crate::AppState;
+use crate::ResultExt;
+
+/// Describes when and from where an account was last linked, so users can
+/// spot links they didn't make.
+fn last_link(state: &AppState, service: &str, user_key: &str) -> anyhow::Result<String> {
+    let linked = state
+        .db
+        .get_last_audit(&format!("link {}", service), user_key)?;
+    let refreshed = state
+        .db
+        .get_last_audit(&format!("refresh {}", service), user_key)?;
+    let (verb, entry) = match (linked, refreshed) {
+        (Some(linked), Some(refreshed)) if refreshed.at > linked.at => ("refreshed", refreshed),
+        (Some(linked), _) => ("connected", linked),
+        (None, Some(refreshed)) => ("refreshed", refreshed),
+        (None, None) => return Ok(String::new()),
+    };
+    Ok(format!(
+        "<br /><small>{} {} from {}</small>",
+        verb,
+        ago(entry.at),
+        escape(&entry.origin.describe())
+    ))
+}
+
+pub async fn get_account(
+    State(state): State<Arc<AppState>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+) -> Result<Html<String>, String> {
+    let user_key = crate::cookie_user_key(&state, &cookie)?;
+    let Some(user) = state.db.get_user(&user_key).from_err()? else {
+        return Err("invalid user".into());
+    };
+    let profile = state
+        .db
+        .get_profile(&user_key)
+        .from_err()?
+        .unwrap_or_default();
+    let status = state.db.get_user_status(&user_key).from_err()?;
+    let settings = state.db.get_settings(&user_key).from_err()?;
+
+    // Opening the account page usually means the user is about to check in,
+    // so watch their checkins closely for a while.
+    if !user.swarm_access_token.is_empty() {
+        state.fast_poll.boost(
+            &user_key,
+            Duration::from_secs(state.flags.fast_poll_duration),
+        );
+    }
+
+    let swarm = if user.swarm_access_token.is_empty() {
+        r#"not connected, <a href="/swarm/connect">connect Swarm</a>"#.to_string()
+    } else {
+        format!(
+            r#"{} <form action="/swarm/disconnect" method="POST"><button type="submit">Disconnect</button></form>"#,
+            escape(profile.swarm_name.as_deref().unwrap_or(&user.swarm_id))
+        )
+    };
+    let mastodon_link = last_link(&state, "mastodon", &user_key).from_err()?;
+    let swarm_link = if user.swarm_access_token.is_empty() {
+        String::new()
+    } else {
+        last_link(&state, "swarm", &user_key).from_err()?
+    };
+    let setup = onboarding::render(&onboarding::missing_steps(
+        &user, &profile, &status, &settings,
+    ));
+
+    let (feed, feed_action) = match &profile.feed_token {
+        Some(token) => (
+            format!(
+                "<code>{}/feeds/{}/checkins.ics</code>",
+                escape(&state.flags.base_url),
+                token
+            ),
+            "Replace the link",
+        ),
+        None => ("not enabled".to_string(), "Create a link"),
+    };
+    let (atom_url, rss_url) = feeds::news_feed_urls(&state, &user_key).from_err()?;
+
+    let layouts = Layout::ALL
+        .iter()
+        .map(|layout| {
+            format!(
+                r#"<label><input type="radio" name="layout" value="{id}" {checked} /> {id}: <code>{example}</code></label>"#,
+                id = layout.id(),
+                checked = if *layout == settings.layout { "checked" } else { "" },
+                example = escape(status::layout_template(*layout)),
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    let coordinates = Coordinates::ALL
+        .iter()
+        .map(|option| {
+            format!(
+                r#"<label><input type="radio" name="coordinates" value="{id}" {checked} /> {description}</label>"#,
+                id = option.id(),
+                checked = if *option == settings.coordinates { "checked" } else { "" },
+                description = option.description(),
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    let links = LinkMode::ALL
+        .iter()
+        .map(|mode| {
+            format!(
+                r#"<label><input type="radio" name="link" value="{id}" {checked} /> {description}</label>"#,
+                id = mode.id(),
+                checked = if *mode == settings.link { "checked" } else { "" },
+                description = mode.description(),
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    let attach_map = if state.flags.static_map_url.is_some() {
+        format!(
+            r#"<label><input type="checkbox" name="attach_map" value="yes" {} /> Attach a map of the venue</label>"#,
+            if settings.attach_map { "checked" } else { "" }
+        )
+    } else {
+        String::new()
+    };
+    let venue_photo = format!(
+        r#"<label><input type="checkbox" name="venue_photo" value="yes" {} /> Attach the venue's best photo from Foursquare when the checkin has no photos, crediting who took it in the alt text</label>"#,
+        if settings.venue_photo { "checked" } else { "" }
+    );
+    let household_modes = HouseholdMode::ALL
+        .iter()
+        .map(|mode| {
+            format!(
+                r#"<label><input type="radio" name="household" value="{id}" {checked} /> {description}</label>"#,
+                id = mode.id(),
+                checked = if *mode == settings.household { "checked" } else { "" },
+                description = mode.description(),
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    let retention_choices = |name: &str, selected: Retention| {
+        Retention::ALL
+            .iter()
+            .map(|retention| {
+                format!(
+                    r#"<label><input type="radio" name="{name}" value="{id}" {checked} /> {description}</label>"#,
+                    id = retention.id(),
+                    checked = if *retention == selected { "checked" } else { "" },
+                    description = retention.description(),
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    // Recaps can't be turned on without a history to build them from.
+    let recap_checkbox = |enabled: bool| match (settings.history_disabled, enabled) {
+        (true, _) => "disabled",
+        (false, true) => "checked",
+        (false, false) => "",
+    };
+    let history_retention = retention_choices("history_retention", settings.history_retention);
+    let audit_retention = retention_choices("audit_retention", settings.audit_retention);
+    let last_seen_choices = LastSeen::ALL
+        .iter()
+        .map(|last_seen| {
+            format!(
+                r#"<label><input type="radio" name="last_seen" value="{id}" {checked} /> {description}</label>"#,
+                id = last_seen.id(),
+                checked = if *last_seen == settings.last_seen { "checked" } else { "" },
+                description = last_seen.description(),
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    Ok(page(
+        "Account",
+        &format!(
+            r##"<h1>Account</h1>
+{setup}
+<dl>
+    <dt>Mastodon</dt>
+    <dd>{mastodon}{mastodon_link}</dd>
+    <dt>Swarm</dt>
+    <dd>{swarm}{swarm_link}</dd>
+</dl>
+<p><a href="/history">History</a> · <a href="/account/friends">Friends to mention</a> · <a href="/account/stats">Stats</a> · <a href="/account/tokens">API tokens</a></p>
+<form action="/account/history" method="POST">
+    <label><input type="checkbox" name="keep_history" value="yes" {keep_history} /> Keep a history of my checkins, used for the calendar feed, recaps and the history page. Turning this off deletes the history; only counts per venue, category and month are kept.</label>
+    <p>Keep my checkins in the history for</p>
+    {history_retention}
+    <p>Keep records of when and from where I linked my accounts for</p>
+    {audit_retention}
+    <button type="submit">Save</button>
+</form>
+<form action="/account/recap" method="POST">
+    <p>{recap_intro}</p>
+    <label><input type="checkbox" name="weekly_recap" value="yes" {weekly_recap} /> Every week</label>
+    <label><input type="checkbox" name="monthly_recap" value="yes" {monthly_recap} /> Every month</label>
+    <label><input type="checkbox" name="yearly_recap" value="yes" {yearly_recap} /> Every year</label>
+    <label for="recap_time">Post at this time on the first day after the period, midnight when empty</label>
+    <input type="time" id="recap_time" name="recap_time" value="{recap_time}" />
+    <label><input type="checkbox" name="recap_thread" value="yes" {recap_thread} /> Post the top venues, categories and new cities in replies</label>
+    <label for="recap_template">Recap template</label>
+    <input type="text" id="recap_template" name="recap_template" value="{recap_template}" placeholder="{recap_placeholder}" />
+    <p>Available fields: {recap_fields}.</p>
+    <button type="submit">Save</button>
+</form>
+<form action="/account/feed" method="POST">
+    <p>Calendar feed of your checkins: {feed}</p>
+    <button type="submit">{feed_action}</button>
+</form>
+<form action="/account/news-feed" method="POST">
+    <p>Feed of your posted checkins: <a href="{atom_url}">Atom</a> · <a href="{rss_url}">RSS</a></p>
+    <button type="submit">Replace the links</button>
+</form>
+<form action="/account/last-seen" method="POST">
+    <p>"{last_seen_field}" field on your Mastodon profile, updated after posting at most every 15 minutes and removed while cross-posting is paused</p>
+    {last_seen_choices}
+    <button type="submit">Save</button>
+</form>
+<form action="/account/template" method="POST">
+    <p>Layout</p>
+    {layouts}
+    <label for="template">Custom template, overrides the layout</label>
+    <input type="text" id="template" name="template" value="{template}" placeholder="{layout_template}" />
+    <p>Available fields: {fields}. Wrap text in <code>{{?field}}...{{/field}}</code> to only include it when the field is set.</p>
+    <label><input type="checkbox" name="country_flag" value="yes" {country_flag} /> Show the country's flag after the location</label>
+    <label><input type="checkbox" name="visit_count" value="yes" {visit_count} /> Mention my first visit to a venue, or how many visits this is</label>
+    <label><input type="checkbox" name="mention_overlaps" value="yes" {mention_overlaps} /> Mention friends from your friends list who were there at the same time, even if they weren't tagged</label>
+    <label><input type="checkbox" name="thread_long_shouts" value="yes" {thread_long_shouts} /> Continue shouts too long for one post in replies, instead of cutting them short</label>
+    <label for="language">Language of your posts, as a two letter code. Detected from each shout when empty. Also used for dates in recaps and roundups, English when empty.</label>
+    <input type="text" id="language" name="language" value="{language}" placeholder="en" />
+    <button type="submit">Save</button>
+</form>
+<form action="/account/location" method="POST">
+    <p>Venue coordinates, for clients that show them on a map. Added at the end unless the template has a place for <code>{{coordinates}}</code>.</p>
+    {coordinates}
+    <p>Link in the status, the <code>{{url}}</code> field. <code>{{osm_url}}</code> is always the OpenStreetMap link.</p>
+    {links}
+    {attach_map}
+    {venue_photo}
+    <button type="submit">Save</button>
+</form>
+<form action="/account/hashtags" method="POST">
+    <label for="hashtags">Hashtags added to every post</label>
+    <input type="text" id="hashtags" name="hashtags" value="{hashtags}" placeholder="#swarm #checkin" />
+    <label><input type="checkbox" name="category_hashtags" value="yes" {category_hashtags} /> Add hashtags for the venue's categories</label>
+    <label for="category_hashtag_map">Custom hashtags, one <code>Category = hashtag</code> per line. Leave the hashtag empty to turn off a built-in one.</label>
+    <textarea id="category_hashtag_map" name="category_hashtag_map" placeholder="Coffee Shop = coffee">{category_hashtag_map}</textarea>
+    <button type="submit">Save</button>
+</form>
+<form action="/account/emoji" method="POST">
+    <label><input type="checkbox" name="category_emoji" value="yes" {category_emoji} /> Start posts with an emoji for the venue's category</label>
+    <label for="category_emoji_map">Custom emoji, one <code>Category = emoji</code> per line. Leave the emoji empty to turn off a built-in one.</label>
+    <textarea id="category_emoji_map" name="category_emoji_map" placeholder="Coffee Shop = ☕">{category_emoji_map}</textarea>
+    <button type="submit">Save</button>
+</form>
+<form action="/account/sensitive" method="POST">
+    <label for="sensitive_categories">Post checkins at these kinds of venues behind a content warning, one category per line</label>
+    <textarea id="sensitive_categories" name="sensitive_categories" placeholder="Hospital">{sensitive_categories}</textarea>
+    <button type="submit">Save</button>
+</form>
+<form action="/account/markers" method="POST">
+    <label for="opt_out_markers">Don't post checkins whose shout contains one of these words</label>
+    <input type="text" id="opt_out_markers" name="opt_out_markers" value="{opt_out_markers}" placeholder="#noshare !private" />
+    <label><input type="checkbox" name="post_marked" value="yes" {post_marked} /> Post them anyway, just without the word</label>
+    <label><input type="checkbox" name="opt_in" value="yes" {opt_in} /> Only post checkins whose shout contains one of these words instead</label>
+    <input type="text" id="share_markers" name="share_markers" value="{share_markers}" placeholder="{default_share_marker}" />
+    <button type="submit">Save</button>
+</form>
+<form action="/account/noise" method="POST">
+    <label for="noise_shouts">Treat these shouts as if there was none, one per line. Wrap a line in <code>/</code> to use a regular expression.</label>
+    <textarea id="noise_shouts" name="noise_shouts" placeholder="/^Checked in with .* sticker$/">{noise_shouts}</textarea>
+    <button type="submit">Save</button>
+</form>
+<form action="/account/rules" method="POST">
+    <label for="rules">Rules deciding which checkins are posted, one per line like <code>category ~ "Bar" AND hour &gt;= 22 -&gt; skip</code>. The first matching rule wins; <code>post</code> overrides the settings below. Fields: {rule_fields}</label>
+    <textarea id="rules" name="rules" rows="6">{rules}</textarea>
+    <button type="submit">Save</button>
+</form>
+<form action="/account/venues" method="POST">
+    <label for="venue_rules">Never post checkins at these venues, one per line: a venue ID, a name, a name pattern wrapped in <code>/</code>, or <code>category:</code> followed by a category</label>
+    <textarea id="venue_rules" name="venue_rules" placeholder="category:Doctor's Office">{venue_rules}</textarea>
+    <label><input type="checkbox" name="venue_allowlist" value="yes" {venue_allowlist} /> Only post checkins at these venues instead</label>
+    <button type="submit">Save</button>
+</form>
+<form action="/account/household" method="POST">
+    <p>When a friend who also uses this service checks me in</p>
+    {household_modes}
+    <button type="submit">Save</button>
+</form>
+<form action="/account/cap" method="POST">
+    <label for="daily_cap">Post at most this many checkins a day, leave empty for no limit</label>
+    <input type="number" id="daily_cap" name="daily_cap" min="1" value="{daily_cap}" />
+    <label><input type="radio" name="over_cap" value="drop" {over_cap_drop} /> Don't post the rest</label>
+    <label><input type="radio" name="over_cap" value="roundup" {over_cap_roundup} /> List the rest in one status at the end of the day</label>
+    <button type="submit">Save</button>
+</form>
+<form action="/account/delay" method="POST">
+    <label for="post_delay">Wait this many minutes after checking in before posting, so the status doesn't tell where I am right now</label>
+    <input type="number" id="post_delay" name="post_delay" min="0" value="{post_delay}" />
+    <label><input type="checkbox" name="mastodon_scheduling" value="yes" {mastodon_scheduling} /> Schedule delayed posts on Mastodon, where they show up under scheduled posts</label>
+    <label><input type="checkbox" name="post_after_leaving" value="yes" {post_after_leaving} /> Wait until I check in somewhere else, or at most this many hours</label>
+    <input type="number" id="leave_timeout" name="leave_timeout" min="1" value="{leave_timeout}" placeholder="{default_leave_timeout}" />
+    <button type="submit">Save</button>
+</form>
+<form action="/account/quiet" method="POST">
+    <label>Hold checkins made between <input type="time" name="quiet_start" value="{quiet_start}" /> and <input type="time" name="quiet_end" value="{quiet_end}" /> and post them once the quiet hours are over. Leave empty to post right away.</label>
+    <label for="timezone">Time zone</label>
+    <input type="text" id="timezone" name="timezone" value="{timezone}" placeholder="Europe/Berlin" />
+    <button type="submit">Save</button>
+</form>
+<form action="/account/proxy" method="POST">
+    <label for="mastodon_proxy">Post through a SOCKS5 proxy on a public address, or one this service allows</label>
+    <input type="text" id="mastodon_proxy" name="mastodon_proxy" value="{mastodon_proxy}" placeholder="socks5h://proxy.example.com:1080" />
+    <button type="submit">Save</button>
+</form>
+<form action="/swarm/checking-in" method="POST">
+    <button type="submit">I'm checking in now</button>
+</form>
+<form action="/logout" method="POST">
+    <button type="submit">Log out</button>
+</form>
+<form action="/account/delete" method="POST">
+    <label><input type="checkbox" name="confirm" value="yes" required /> Remove everything stored about me and revoke access to my Mastodon account</label>
+    <button type="submit">Delete my data</button>
+</form>"##,
+            mastodon = escape(&profile.mastodon_handle),
+            atom_url = escape(&atom_url),
+            last_seen_field = last_seen::FIELD_NAME,
+            rss_url = escape(&rss_url),
+            template = escape(settings.template.as_deref().unwrap_or_default()),
+            language = escape(settings.language.as_deref().unwrap_or_default()),
+            country_flag = if settings.country_flag { "checked" } else { "" },
+            visit_count = if settings.visit_count { "checked" } else { "" },
+            mention_overlaps = if settings.mention_overlaps {
+                "checked"
+            } else {
+                ""
+            },
+            thread_long_shouts = if settings.thread_long_shouts {
+                "checked"
+            } else {
+                ""
+            },
+            hashtags = escape(&settings.hashtags.join(" ")),
+            category_hashtags = if settings.category_hashtags {
+                "checked"
+            } else {
+                ""
+            },
+            category_hashtag_map =
+                escape(&categories::format_table(&settings.category_hashtag_map)),
+            category_emoji = if settings.category_emoji {
+                "checked"
+            } else {
+                ""
+            },
+            category_emoji_map = escape(&categories::format_table(&settings.category_emoji_map)),
+            sensitive_categories = escape(
+                &settings
+                    .sensitive_categories
+                    .iter()
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            ),
+            opt_out_markers = escape(&settings.opt_out_markers.join(" ")),
+            post_marked = if settings.post_marked { "checked" } else { "" },
+            opt_in = if settings.opt_in { "checked" } else { "" },
+            share_markers = escape(&settings.share_markers.join(" ")),
+            default_share_marker = status::DEFAULT_SHARE_MARKER,
+            noise_shouts = escape(&settings.noise_shouts.join("\n")),
+            venue_rules = escape(&settings.venue_rules.join("\n")),
+            keep_history = if settings.history_disabled {
+                ""
+            } else {
+                "checked"
+            },
+            recap_intro = if settings.history_disabled {
+                "Recaps are built from the history of your checkins, which you turned off above. Keep a history to have recaps posted."
+            } else {
+                "Post a recap of my checkins that were posted, once a period is over. Needs the history to be kept for at least the period."
+            },
+            weekly_recap = recap_checkbox(settings.weekly_recap),
+            monthly_recap = recap_checkbox(settings.monthly_recap),
+            yearly_recap = recap_checkbox(settings.yearly_recap),
+            recap_time = settings
+                .recap_time
+                .map(schedule::format_time)
+                .unwrap_or_default(),
+            recap_thread = if settings.recap_thread { "checked" } else { "" },
+            recap_template = escape(settings.recap_template.as_deref().unwrap_or_default()),
+            recap_placeholder = escape(recap::DEFAULT_TEMPLATE),
+            recap_fields = recap::FIELDS
+                .iter()
+                .map(|field| format!("<code>{{{}}}</code>", field))
+                .collect::<Vec<_>>()
+                .join(", "),
+            rules = escape(&settings.rules),
+            rule_fields = rules::FIELDS
+                .iter()
+                .map(|field| format!("<code>{}</code>", field))
+                .collect::<Vec<_>>()
+                .join(", "),
+            daily_cap = settings
+                .daily_cap
+                .map(|cap| cap.to_string())
+                .unwrap_or_default(),
+            over_cap_drop = if settings.over_cap == OverCap::Drop {
+                "checked"
+            } else {
+                ""
+            },
+            over_cap_roundup = if settings.over_cap == OverCap::Roundup {
+                "checked"
+            } else {
+                ""
+            },
+            post_delay = settings.post_delay,
+            mastodon_scheduling = if settings.mastodon_scheduling {
+                "checked"
+            } else {
+                ""
+            },
+            post_after_leaving = if settings.post_after_leaving {
+                "checked"
+            } else {
+                ""
+            },
+            leave_timeout = match settings.leave_timeout {
+                0 => String::new(),
+                hours => hours.to_string(),
+            },
+            default_leave_timeout = schedule::DEFAULT_LEAVE_TIMEOUT,
+            quiet_start = settings
+                .quiet_hours
+                .map(|quiet| schedule::format_time(quiet.start))
+                .unwrap_or_default(),
+            quiet_end = settings
+                .quiet_hours
+                .map(|quiet| schedule::format_time(quiet.end))
+                .unwrap_or_default(),
+            timezone = escape(settings.timezone.as_deref().unwrap_or_default()),
+            venue_allowlist = if settings.venue_allowlist {
+                "checked"
+            } else {
+                ""
+            },
+            mastodon_proxy = escape(settings.mastodon_proxy.as_deref().unwrap_or_default()),
+            layout_template = escape(status::layout_template(settings.layout)),
+            fields = status::FIELDS
+                .iter()
+                .map(|field| format!("<code>{{{}}}</code>", field))
+                .collect::<Vec<_>>()
+                .join(", "),
+        ),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct TemplateForm {
+    layout: Layout,
+    template: String,
+    country_flag: Option<String>,
+    thread_long_shouts: Option<String>,
+    visit_count: Option<String>,
+    mention_overlaps: Option<String>,
+    language: String,
+}
+
+pub async fn post_template(
+    State(state): State<Arc<AppState>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Form(form): Form<TemplateForm>,
+) -> Result<Redirect, String> {
+    let user_key = crate::cookie_user_key(&state, &cookie)?;
+    let template = form.template.trim();
+    let template = if template.is_empty() {
+        None
+    } else {
+        status::parse_template(template).map_err(|e| format!("invalid template: {}", e))?;
+        Some(template.to_string())
+    };
+
+    let mut settings = state.db.get_settings(&user_key).from_err()?;
+    settings.layout = form.layout;
+    settings.country_flag = form.country_flag.is_some();
+    settings.thread_long_shouts = form.thread_long_shouts.is_some();
+    settings.visit_count = form.visit_count.is_some();
+    settings.mention_overlaps = form.mention_overlaps.is_some();
+    settings.language = match form.language.trim().to_lowercase() {
+        language if language.is_empty() => None,
+        language if isolang::Language::from_639_1(&language).is_some() => Some(language),
+        _ => return Err("unknown language code".into()),
+    };
+    settings.template = template;
+    state.db.save_settings(&user_key, &settings).from_err()?;
+    Ok(Redirect::to("/account"))
+}
+
+#[derive(Deserialize)]
+pub struct HashtagsForm {
+    hashtags: String,
+    category_hashtags: Option<String>,
+    category_hashtag_map: String,
+}
+
+pub async fn post_hashtags(
+    State(state): State<Arc<AppState>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Form(form): Form<HashtagsForm>,
+) -> Result<Redirect, String> {
+    let user_key = crate::cookie_user_key(&state, &cookie)?;
+    let table = categories::parse_table(&form.category_hashtag_map)?;
+
+    let mut settings = state.db.get_settings(&user_key).from_err()?;
+    settings.hashtags = status::parse_hashtags(&form.hashtags);
+    settings.category_hashtags = form.category_hashtags.is_some();
+    settings.category_hashtag_map = table;
+    state.db.save_settings(&user_key, &settings).from_err()?;
+    Ok(Redirect::to("/account"))
+}
+
+#[derive(Deserialize)]
+pub struct EmojiForm {
+    category_emoji: Option<String>,
+    category_emoji_map: String,
+}
+
+pub async fn post_emoji(
+    State(state): State<Arc<AppState>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Form(form): Form<EmojiForm>,
+) -> Result<Redirect, String> {
+    let user_key = crate::cookie_user_key(&state, &cookie)?;
+    let table = categories::parse_table(&form.category_emoji_map)?;
+
+    let mut settings = state.db.get_settings(&user_key).from_err()?;
+    settings.category_emoji = form.category_emoji.is_some();
+    settings.category_emoji_map = table;
+    state.db.save_settings(&user_key, &settings).from_err()?;
+    Ok(Redirect::to("/account"))
+}
+
+#[derive(Deserialize)]
+pub struct SensitiveForm {
+    sensitive_categories: String,
+}
+
+pub async fn post_sensitive(
+    State(state): State<Arc<AppState>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Form(form): Form<SensitiveForm>,
+) -> Result<Redirect, String> {
+    let user_key = crate::cookie_user_key(&state, &cookie)?;
+    let mut settings = state.db.get_settings(&user_key).from_err()?;
+    settings.sensitive_categories = categories::parse_list(&form.sensitive_categories);
+    state.db.save_settings(&user_key, &settings).from_err()?;
+    Ok(Redirect::to("/account"))
+}
+
+#[derive(Deserialize)]
+pub struct MarkersForm {
+    opt_out_markers: String,
+    post_marked: Option<String>,
+    opt_in: Option<String>,
+    share_markers: String,
+}
+
+pub async fn post_markers(
+    State(state): State<Arc<AppState>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Form(form): Form<MarkersForm>,
+) -> Result<Redirect, String> {
+    let user_key = crate::cookie_user_key(&state, &cookie)?;
+    let mut settings = state.db.get_settings(&user_key).from_err()?;
+    settings.opt_out_markers = form
+        .opt_out_markers
+        .split_whitespace()
+        .map(str::to_string)
+        .collect();
+    settings.post_marked = form.post_marked.is_some();
+    settings.opt_in = form.opt_in.is_some();
+    settings.share_markers = form
+        .share_markers
+        .split_whitespace()
+        .map(str::to_string)
+        .collect();
+    state.db.save_settings(&user_key, &settings).from_err()?;
+    Ok(Redirect::to("/account"))
+}
+
+#[derive(Deserialize)]
+pub struct NoiseForm {
+    noise_shouts: String,
+}
+
+pub async fn post_noise(
+    State(state): State<Arc<AppState>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Form(form): Form<NoiseForm>,
+) -> Result<Redirect, String> {
+    let user_key = crate::cookie_user_key(&state, &cookie)?;
+    let patterns = form
+        .noise_shouts
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    status::validate_noise(&patterns)?;
+
+    let mut settings = state.db.get_settings(&user_key).from_err()?;
+    settings.noise_shouts = patterns;
+    state.db.save_settings(&user_key, &settings).from_err()?;
+    Ok(Redirect::to("/account"))
+}
+
+#[derive(Deserialize)]
+pub struct HistoryForm {
+    keep_history: Option<String>,
+    history_retention: Retention,
+    audit_retention: Retention,
+}
+
+pub async fn post_history(
+    State(state): State<Arc<AppState>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Form(form): Form<HistoryForm>,
+) -> Result<Redirect, String> {
+    let user_key = crate::cookie_user_key(&state, &cookie)?;
+    let _guard = state.user_locks.lock(&user_key).await;
+    let mut settings = state.db.get_settings(&user_key).from_err()?;
+    settings.history_disabled = form.keep_history.is_none();
+    if settings.history_disabled {
+        // Recaps are built from the history, there is nothing left to recap.
+        settings.weekly_recap = false;
+        settings.monthly_recap = false;
+        settings.yearly_recap = false;
+    }
+    settings.history_retention = form.history_retention;
+    settings.audit_retention = form.audit_retention;
+    state.db.save_settings(&user_key, &settings).from_err()?;
+    if settings.history_disabled {
+        state.db.clear_history(&user_key).from_err()?;
+    }
+    retention::prune_user(&state, &user_key, &settings).from_err()?;
+    Ok(Redirect::to("/account"))
+}
+
+#[derive(Deserialize)]
+pub struct LastSeenForm {
+    last_seen: LastSeen,
+}
+
+pub async fn post_last_seen(
+    State(state): State<Arc<AppState>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Form(form): Form<LastSeenForm>,
+) -> Result<Redirect, String> {
+    let user_key = crate::cookie_user_key(&state, &cookie)?;
+    let mut settings = state.db.get_settings(&user_key).from_err()?;
+    let opted_out = settings.last_seen != LastSeen::Off && form.last_seen == LastSeen::Off;
+    settings.last_seen = form.last_seen;
+    state.db.save_settings(&user_key, &settings).from_err()?;
+    if opted_out {
+        last_seen::clear(&state, &user_key).await;
+    }
+    Ok(Redirect::to("/account"))
+}
+
+#[derive(Deserialize)]
+pub struct RecapForm {
+    weekly_recap: Option<String>,
+    monthly_recap: Option<String>,
+    yearly_recap: Option<String>,
+    recap_time: String,
+    recap_thread: Option<String>,
+    recap_template: String,
+}
+
+pub async fn post_recap(
+    State(state): State<Arc<AppState>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Form(form): Form<RecapForm>,
+) -> Result<Redirect, String> {
+    let user_key = crate::cookie_user_key(&state, &cookie)?;
+    let template = form.recap_template.trim();
+    let template = if template.is_empty() {
+        None
+    } else {
+        recap::parse_template(template).map_err(|e| format!("invalid template: {}", e))?;
+        Some(template.to_string())
+    };
+
+    let recap_time = match form.recap_time.trim() {
+        "" => None,
+        time => Some(schedule::parse_time(time)?),
+    };
+
+    let mut settings = state.db.get_settings(&user_key).from_err()?;
+    let recaps =
+        form.weekly_recap.is_some() || form.monthly_recap.is_some() || form.yearly_recap.is_some();
+    if recaps && settings.history_disabled {
+        return Err("recaps are built from the history, keep a history to turn them on".into());
+    }
+    let before = settings.clone();
+    settings.weekly_recap = form.weekly_recap.is_some();
+    settings.monthly_recap = form.monthly_recap.is_some();
+    settings.yearly_recap = form.yearly_recap.is_some();
+    settings.recap_time = recap_time;
+    settings.recap_thread = form.recap_thread.is_some();
+    settings.recap_template = template;
+    state.db.save_settings(&user_key, &settings).from_err()?;
+    for period in Period::ALL {
+        if period.enabled(&settings) && !period.enabled(&before) {
+            recap::skip_last(&state, &user_key, &settings, period).from_err()?;
+        }
+    }
+    Ok(Redirect::to("/account"))
+}
+
+#[derive(Deserialize)]
+pub struct RulesForm {
+    rules: String,
+}
+
+pub async fn post_rules(
+    State(state): State<Arc<AppState>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Form(form): Form<RulesForm>,
+) -> Result<Redirect, String> {
+    let user_key = crate::cookie_user_key(&state, &cookie)?;
+    rules::RuleSet::parse(&form.rules).map_err(|e| format!("invalid rule on {}", e))?;
+
+    let mut settings = state.db.get_settings(&user_key).from_err()?;
+    settings.rules = form.rules.replace("\r\n", "\n");
+    state.db.save_settings(&user_key, &settings).from_err()?;
+    Ok(Redirect::to("/account"))
+}
+
+#[derive(Deserialize)]
+pub struct VenuesForm {
+    venue_rules: String,
+    venue_allowlist: Option<String>,
+}
+
+pub async fn post_venues(
+    State(state): State<Arc<AppState>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Form(form): Form<VenuesForm>,
+) -> Result<Redirect, String> {
+    let user_key = crate::cookie_user_key(&state, &cookie)?;
+    let rules = form
+        .venue_rules
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    venues::validate_rules(&rules)?;
+
+    let mut settings = state.db.get_settings(&user_key).from_err()?;
+    settings.venue_rules = rules;
+    settings.venue_allowlist = form.venue_allowlist.is_some();
+    state.db.save_settings(&user_key, &settings).from_err()?;
+    Ok(Redirect::to("/account"))
+}
+
+#[derive(Deserialize)]
+pub struct LocationForm {
+    coordinates: Coordinates,
+    link: LinkMode,
+    attach_map: Option<String>,
+    venue_photo: Option<String>,
+}
+
+pub async fn post_location(
+    State(state): State<Arc<AppState>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Form(form): Form<LocationForm>,
+) -> Result<Redirect, String> {
+    let user_key = crate::cookie_user_key(&state, &cookie)?;
+    let mut settings = state.db.get_settings(&user_key).from_err()?;
+    settings.coordinates = form.coordinates;
+    settings.link = form.link;
+    settings.attach_map = form.attach_map.is_some();
+    settings.venue_photo = form.venue_photo.is_some();
+    state.db.save_settings(&user_key, &settings).from_err()?;
+    Ok(Redirect::to("/account"))
+}
+
+#[derive(Deserialize)]
+pub struct HouseholdForm {
+    household: HouseholdMode,
+}
+
+pub async fn post_household(
+    State(state): State<Arc<AppState>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Form(form): Form<HouseholdForm>,
+) -> Result<Redirect, String> {
+    let user_key = crate::cookie_user_key(&state, &cookie)?;
+    let mut settings = state.db.get_settings(&user_key).from_err()?;
+    settings.household = form.household;
+    state.db.save_settings(&user_key, &settings).from_err()?;
+    Ok(Redirect::to("/account"))
+}
+
+#[derive(Deserialize)]
+pub struct CapForm {
+    daily_cap: String,
+    over_cap: OverCap,
+}
+
+pub async fn post_cap(
+    State(state): State<Arc<AppState>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Form(form): Form<CapForm>,
+) -> Result<Redirect, String> {
+    let user_key = crate::cookie_user_key(&state, &cookie)?;
+    let daily_cap = match form.daily_cap.trim() {
+        "" => None,
+        cap => Some(
+            cap.parse::<u32>()
+                .ok()
+                .filter(|cap| *cap > 0)
+                .ok_or_else(|| format!("invalid daily cap {}", cap))?,
+        ),
+    };
+
+    let mut settings = state.db.get_settings(&user_key).from_err()?;
+    settings.daily_cap = daily_cap;
+    settings.over_cap = form.over_cap;
+    state.db.save_settings(&user_key, &settings).from_err()?;
+    Ok(Redirect::to("/account"))
+}
+
+#[derive(Deserialize)]
+pub struct DelayForm {
+    post_delay: String,
+    mastodon_scheduling: Option<String>,
+    post_after_leaving: Option<String>,
+    leave_timeout: String,
+}
+
+pub async fn post_delay(
+    State(state): State<Arc<AppState>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Form(form): Form<DelayForm>,
+) -> Result<Redirect, String> {
+    let user_key = crate::cookie_user_key(&state, &cookie)?;
+    let post_delay = match form.post_delay.trim() {
+        "" => 0,
+        delay => delay
+            .parse::<u32>()
+            .map_err(|_| format!("invalid delay {}", delay))?,
+    };
+
+    let leave_timeout = match form.leave_timeout.trim() {
+        "" => 0,
+        hours => hours
+            .parse::<u32>()
+            .map_err(|_| format!("invalid number of hours {}", hours))?,
+    };
+
+    let mut settings = state.db.get_settings(&user_key).from_err()?;
+    settings.post_delay = post_delay;
+    settings.mastodon_scheduling = form.mastodon_scheduling.is_some();
+    settings.post_after_leaving = form.post_after_leaving.is_some();
+    settings.leave_timeout = leave_timeout;
+    state.db.save_settings(&user_key, &settings).from_err()?;
+    Ok(Redirect::to("/account"))
+}
+
+#[derive(Deserialize)]
+pub struct QuietForm {
+    quiet_start: String,
+    quiet_end: String,
+    timezone: String,
+}
+
+pub async fn post_quiet(
+    State(state): State<Arc<AppState>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Form(form): Form<QuietForm>,
+) -> Result<Redirect, String> {
+    let user_key = crate::cookie_user_key(&state, &cookie)?;
+    let quiet_hours = match (form.quiet_start.trim(), form.quiet_end.trim()) {
+        ("", "") => None,
+        (start, end) => Some(QuietHours {
+            start: schedule::parse_time(start)?,
+            end: schedule::parse_time(end)?,
+        }),
+    };
+    let timezone = match form.timezone.trim() {
+        "" => None,
+        timezone => {
+            timezone
+                .parse::<chrono_tz::Tz>()
+                .map_err(|_| format!("unknown time zone {}", timezone))?;
+            Some(timezone.to_string())
+        }
+    };
+
+    let mut settings = state.db.get_settings(&user_key).from_err()?;
+    settings.quiet_hours = quiet_hours;
+    settings.timezone = timezone;
+    state.db.save_settings(&user_key, &settings).from_err()?;
+    Ok(Redirect::to("/account"))
+}
+
+#[derive(Deserialize)]
+pub struct ProxyForm {
+    mastodon_proxy: String,
+}
+
+/// Whether `ip` is reachable on the internet at large, rather than on this
+/// host or a network it is part of.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || a == 0
+                // Shared address space of carrier-grade NAT, 100.64.0.0/10
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    // Unique local, fc00::/7
+                    || (first & 0xfe00) == 0xfc00
+                    // Link-local, fe80::/10
+                    || (first & 0xffc0) == 0xfe80)
+            }
+        },
+    }
+}
+
+/// Keeps users from pointing their proxy at this host or its network, which
+/// would let them probe services that aren't meant to be reachable. Proxies
+/// the operator allowed with `--allowed-proxy`, such as a local Tor daemon,
+/// are taken wherever they are.
+async fn check_proxy_address(state: &AppState, url: &Url) -> Result<(), String> {
+    let port = url
+        .port()
+        .ok_or_else(|| "the proxy URL needs a port".to_string())?;
+    let host = url.host_str().unwrap_or_default();
+    let address = format!("{}:{}", host, port);
+    if state
+        .flags
+        .allowed_proxy
+        .iter()
+        .any(|allowed| allowed.eq_ignore_ascii_case(&address))
+    {
+        return Ok(());
+    }
+
+    let ips = match url.host() {
+        Some(Host::Ipv4(ip)) => vec![IpAddr::V4(ip)],
+        Some(Host::Ipv6(ip)) => vec![IpAddr::V6(ip)],
+        Some(Host::Domain(domain)) => tokio::net::lookup_host((domain, port))
+            .await
+            .map_err(|e| format!("unable to resolve the proxy: {}", e))?
+            .map(|address| address.ip())
+            .collect(),
+        None => Vec::new(),
+    };
+    if ips.is_empty() || !ips.into_iter().all(is_public) {
+        return Err("the proxy must be on a public address".into());
+    }
+    Ok(())
+}
+
+pub async fn post_proxy(
+    State(state): State<Arc<AppState>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Form(form): Form<ProxyForm>,
+) -> Result<Redirect, String> {
+    let user_key = crate::cookie_user_key(&state, &cookie)?;
+    let proxy = form.mastodon_proxy.trim();
+    let proxy = if proxy.is_empty() {
+        None
+    } else {
+        let url = Url::parse(proxy).map_err(|e| format!("invalid proxy: {}", e))?;
+        if !matches!(url.scheme(), "socks5" | "socks5h") || url.host_str().is_none() {
+            return Err("the proxy must be a socks5:// or socks5h:// URL".into());
+        }
+        check_proxy_address(&state, &url).await?;
+        Some(proxy.to_string())
+    };
+
+    let mut settings = state.db.get_settings(&user_key).from_err()?;
+    settings.mastodon_proxy = proxy;
+    state.db.save_settings(&user_key, &settings).from_err()?;
+    state.mastodon_clients.invalidate(&user_key);
+    Ok(Redirect::to("/account"))
+}
+
+/// Creates the feed link, or replaces it so the old one stops working.
+pub async fn post_feed(
+    State(state): State<Arc<AppState>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+) -> Result<Redirect, String> {
+    let user_key = crate::cookie_user_key(&state, &cookie)?;
+    state.db.rotate_feed_token(&user_key).from_err()?;
+    Ok(Redirect::to("/account"))
+}
+
+/// Replaces the Atom and RSS feed links so the old ones stop working.
+pub async fn post_news_feed(
+    State(state): State<Arc<AppState>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+) -> Result<Redirect, String> {
+    let user_key = crate::cookie_user_key(&state, &cookie)?;
+    state.db.rotate_news_feed_secret(&user_key).from_err()?;
+    Ok(Redirect::to("/account"))
+}
+
+pub async fn post_swarm_disconnect(
+    State(state): State<Arc<AppState>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+) -> Result<Redirect, String> {
+    let user_key = crate::cookie_user_key(&state, &cookie)?;
+    state.db.unlink_swarm(&user_key).from_err()?;
+    state.fast_poll.cancel(&user_key);
+    tracing::info!(user=%user_key, "disconnected Swarm account");
+    Ok(Redirect::to("/account"))
+}
+
+/// Revokes the token so it stops working even if a copy survives somewhere.
+async fn revoke_token(data: &mastodon_async::Data, proxy: Option<&str>) -> anyhow::Result<()> {
+    let url = format!("{}/oauth/revoke", data.base.trim_end_matches('/'));
+    http_client(proxy)?
+        .post(url)
+        .form(&[
+            ("client_id", &*data.client_id),
+            ("client_secret", &*data.client_secret),
+            ("token", &*data.token),
+        ])
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct DeleteForm {
+    confirm: Option<String>,
+}
+
+pub async fn post_delete(
+    State(state): State<Arc<AppState>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Form(form): Form<DeleteForm>,
+) -> Result<(TypedHeader<SetCookie>, Html<String>), String> {
+    if form.confirm.as_deref() != Some("yes") {
+        return Err("please confirm the deletion".into());
+    }
+    let user_key = crate::cookie_user_key(&state, &cookie)?;
+    let _guard = state.user_locks.lock(&user_key).await;
+    let Some(user) = state.db.get_user(&user_key).from_err()? else {
+        return Err("invalid user".into());
+    };
+    let settings = state.db.get_settings(&user_key).from_err()?;
+
+    state.db.delete_user(&user_key).from_err()?;
+    state.mastodon_clients.invalidate(&user_key);
+    state.fast_poll.cancel(&user_key);
+    tracing::info!(user=%user_key, "deleted user at their request");
+
+    if let Err(e) = revoke_token(&user.mastodon, settings.mastodon_proxy.as_deref()).await {
+        tracing::warn!(?e, user=%user_key, "unable to revoke Mastodon token");
+    }
+
+    let cookies = crate::clear_cookies(&["user", "instance_url", "swarm_state"]).from_err()?;
+    Ok((
+        TypedHeader(cookies),
+        page(
+            "Deleted",
+            "<h1>Your data has been deleted</h1>\n<p>Your checkins will no longer be posted. You may also want to remove the app from your Mastodon account settings.</p>",
+        ),
+    ))
+}
+
+pub async fn post_logout() -> Result<(TypedHeader<SetCookie>, Redirect), String> {
+    let cookies = crate::clear_cookies(&["user", "instance_url", "swarm_state"]).from_err()?;
+    Ok((TypedHeader(cookies), Redirect::to("/")))
+}
@@ -0,0 +1,743 @@
+use std::str::FromStr;
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::headers::authorization::Basic;
+use axum::headers::authorization::Bearer;
+use axum::headers::Authorization;
+use axum::headers::Cookie;
+use axum::http::header::WWW_AUTHENTICATE;
+use axum::http::Request;
+use axum::http::StatusCode;
+use axum::middleware;
+use axum::middleware::Next;
+use axum::response::Html;
+use axum::response::IntoResponse;
+use axum::response::Redirect;
+use axum::response::Response;
+use axum::routing::get;
+use axum::routing::post;
+use axum::Extension;
+use axum::Form;
+use axum::Json;
+use axum::Router;
+use axum::TypedHeader;
+use serde::Deserialize;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::error::ApiError;
+use crate::error::PageError;
+use crate::html::ago;
+use crate::html::escape;
+use crate::html::page;
+use crate::last_seen;
+use crate::model::unix_now;
+use crate::outbox;
+use crate::roundup;
+use crate::AppState;
+use crate::ResultExt;
+
+/// Number of audit entries shown on the admin panel.
+const AUDIT_ENTRIES: usize = 50;
+
+/// Characters of a queued status shown on the admin panel.
+const PREVIEW_CHARACTERS: usize = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    /// May look at the admin panel.
+    Viewer,
+    /// May also change or delete users.
+    Admin,
+}
+
+/// An operator allowed into the admin panel, given as `username@instance=role`.
+#[derive(Debug, Clone)]
+pub struct OperatorGrant {
+    pub handle: String,
+    pub role: Role,
+}
+
+impl FromStr for OperatorGrant {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (handle, role) = value
+            .split_once('=')
+            .ok_or_else(|| "expected username@instance=role".to_string())?;
+        let role = match role {
+            "viewer" => Role::Viewer,
+            "admin" => Role::Admin,
+            role => return Err(format!("unknown role '{}', expected viewer or admin", role)),
+        };
+        Ok(Self {
+            handle: handle.trim_start_matches('@').to_lowercase(),
+            role,
+        })
+    }
+}
+
+/// The operator performing the current admin request.
+#[derive(Debug, Clone)]
+pub struct Operator {
+    pub name: String,
+    pub role: Role,
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Identifies the operator either by the admin token, given as basic auth
+/// password or bearer token, or by the Mastodon account they are logged in
+/// with when it is on the operator allowlist.
+fn authenticate(
+    state: &AppState,
+    cookie: Option<&Cookie>,
+    basic: Option<&Authorization<Basic>>,
+    bearer: Option<&Authorization<Bearer>>,
+) -> Option<Operator> {
+    if let Some(token) = state.flags.admin_token.as_deref() {
+        let provided = basic
+            .map(|basic| basic.password())
+            .or_else(|| bearer.map(|bearer| bearer.token()));
+        if let Some(provided) = provided {
+            if constant_time_eq(provided.as_bytes(), token.as_bytes()) {
+                return Some(Operator {
+                    name: "admin token".to_string(),
+                    role: Role::Admin,
+                });
+            }
+        }
+    }
+
+    let user = crate::get_cookie(cookie?, &state.signing_key, "user")?;
+    let (instance_url, mastodon_id) = user.split_once('|')?;
+    let profile = state
+        .db
+        .get_profile(&format!("{}:{}", instance_url, mastodon_id))
+        .ok()??;
+    let handle = profile.mastodon_handle.to_lowercase();
+    let grant = state
+        .flags
+        .operator
+        .iter()
+        .find(|grant| grant.handle == handle)?;
+    Some(Operator {
+        name: profile.mastodon_handle,
+        role: grant.role,
+    })
+}
+
+async fn authorize<B>(
+    required: Role,
+    state: Arc<AppState>,
+    cookie: Option<TypedHeader<Cookie>>,
+    basic: Option<TypedHeader<Authorization<Basic>>>,
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if state.flags.admin_token.is_none() && state.flags.operator.is_empty() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    let operator = authenticate(
+        &state,
+        cookie.as_ref().map(|TypedHeader(cookie)| cookie),
+        basic.as_ref().map(|TypedHeader(basic)| basic),
+        bearer.as_ref().map(|TypedHeader(bearer)| bearer),
+    );
+
+    let api = request.uri().path().starts_with("/admin/api/");
+    match operator {
+        Some(operator) if operator.role >= required => {
+            request.extensions_mut().insert(operator);
+            next.run(request).await
+        }
+        Some(_) if api => ApiError::forbidden("this needs the admin role").into_response(),
+        Some(_) => StatusCode::FORBIDDEN.into_response(),
+        None if api => (
+            [(WWW_AUTHENTICATE, r#"Basic realm="swarmdon admin""#)],
+            ApiError::unauthorized("missing or invalid admin credentials"),
+        )
+            .into_response(),
+        None => (
+            StatusCode::UNAUTHORIZED,
+            [(WWW_AUTHENTICATE, r#"Basic realm="swarmdon admin""#)],
+            page(
+                "Admin",
+                r#"<p>Operators need to <a href="/">log in with their Mastodon account</a> first.</p>"#,
+            ),
+        )
+            .into_response(),
+    }
+}
+
+async fn require_viewer<B>(
+    State(state): State<Arc<AppState>>,
+    cookie: Option<TypedHeader<Cookie>>,
+    basic: Option<TypedHeader<Authorization<Basic>>>,
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    authorize(Role::Viewer, state, cookie, basic, bearer, request, next).await
+}
+
+async fn require_admin<B>(
+    State(state): State<Arc<AppState>>,
+    cookie: Option<TypedHeader<Cookie>>,
+    basic: Option<TypedHeader<Authorization<Basic>>>,
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    authorize(Role::Admin, state, cookie, basic, bearer, request, next).await
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct UserSummary {
+    /// Opaque ID, accepted wherever a user is expected
+    id: String,
+    user: String,
+    mastodon_handle: Option<String>,
+    swarm_id: String,
+    disabled: bool,
+    last_post_at: Option<u64>,
+    suspended: Option<String>,
+    errors: Vec<crate::model::UserError>,
+}
+
+fn list_users(state: &AppState) -> anyhow::Result<Vec<UserSummary>> {
+    let mut users = Vec::new();
+    for (user_key, user) in state.db.get_users()? {
+        let settings = state.db.get_settings(&user_key)?;
+        let status = state.db.get_user_status(&user_key)?;
+        let profile = state.db.get_profile(&user_key)?;
+        users.push(UserSummary {
+            id: state.db.public_id(&user_key)?,
+            user: user_key,
+            mastodon_handle: profile.map(|profile| profile.mastodon_handle),
+            swarm_id: user.swarm_id,
+            disabled: settings.disabled,
+            last_post_at: status.last_post_at,
+            suspended: status.suspended,
+            errors: status.errors,
+        });
+    }
+    Ok(users)
+}
+
+/// Queues operators can look into and act on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum Queue {
+    /// Statuses waiting to be retried, for a posting delay or quiet hours, or
+    /// for the user to leave the venue
+    Outbox,
+    /// Checkins over the daily cap waiting for the end of day roundup
+    Roundup,
+}
+
+impl Queue {
+    fn id(self) -> &'static str {
+        match self {
+            Queue::Outbox => "outbox",
+            Queue::Roundup => "roundup",
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct QueueItem {
+    queue: Queue,
+    /// Opaque ID of the item within its queue
+    id: String,
+    /// Opaque ID of the user
+    user: String,
+    checkin_id: String,
+    /// `retry`, `delayed` or `held` in the outbox, `roundup` in the roundup
+    state: &'static str,
+    attempts: u32,
+    /// When the item is acted on next, unset for roundups which go out once
+    /// the user's day is over
+    due_at: Option<u64>,
+    /// The queued status, or the venue for roundups
+    text: String,
+}
+
+fn list_queues(state: &AppState) -> anyhow::Result<Vec<QueueItem>> {
+    let mut items = Vec::new();
+    for (key, entry) in state.db.get_outbox()? {
+        items.push(QueueItem {
+            queue: Queue::Outbox,
+            id: hex::encode(&key),
+            user: state.db.public_id(&entry.user_key)?,
+            state: outbox::entry_state(&entry),
+            checkin_id: entry.checkin_id,
+            attempts: entry.attempts,
+            due_at: Some(entry.next_attempt_at),
+            text: entry.post.status,
+        });
+    }
+    for (key, item) in state.db.get_roundup()? {
+        items.push(QueueItem {
+            queue: Queue::Roundup,
+            id: hex::encode(&key),
+            user: state.db.public_id(&item.user_key)?,
+            state: "roundup",
+            checkin_id: item.checkin_id,
+            attempts: 0,
+            due_at: None,
+            text: item.venue_name,
+        });
+    }
+    Ok(items)
+}
+
+fn queue_rows(items: &[QueueItem], can_edit: bool) -> String {
+    items
+        .iter()
+        .map(|item| {
+            let actions = if can_edit {
+                format!(
+                    r#"<form action="/admin/queues/retry" method="POST">
+            <input type="hidden" name="queue" value="{queue}" />
+            <input type="hidden" name="id" value="{id}" />
+            <button type="submit">Retry now</button>
+        </form>
+        <form action="/admin/queues/drop" method="POST" onsubmit="return confirm('Drop this item?')">
+            <input type="hidden" name="queue" value="{queue}" />
+            <input type="hidden" name="id" value="{id}" />
+            <button type="submit">Drop</button>
+        </form>"#,
+                    queue = item.queue.id(),
+                    id = escape(&item.id),
+                )
+            } else {
+                String::new()
+            };
+            let mut text = item.text.chars().take(PREVIEW_CHARACTERS).collect::<String>();
+            if text.len() < item.text.len() {
+                text.push('…');
+            }
+            format!(
+                r#"<tr>
+    <td>{queue}</td>
+    <td>{user}</td>
+    <td>{checkin_id}</td>
+    <td>{state}</td>
+    <td>{attempts}</td>
+    <td>{due}</td>
+    <td>{text}</td>
+    <td>
+        {actions}
+    </td>
+</tr>
+"#,
+                queue = item.queue.id(),
+                user = escape(&item.user),
+                checkin_id = escape(&item.checkin_id),
+                state = item.state,
+                attempts = item.attempts,
+                due = item.due_at.map(ago).unwrap_or_else(|| "end of day".into()),
+                text = escape(&text),
+            )
+        })
+        .collect()
+}
+
+async fn get_admin(
+    State(state): State<Arc<AppState>>,
+    Extension(operator): Extension<Operator>,
+) -> Result<Html<String>, String> {
+    let can_edit = operator.role >= Role::Admin;
+    let mut rows = String::new();
+    for user in list_users(&state).from_err()? {
+        let errors = user
+            .errors
+            .iter()
+            .rev()
+            .map(|error| format!("<li>{}: {}</li>", ago(error.at), escape(&error.message)))
+            .collect::<String>();
+        let (toggle_label, toggle_value) = if user.disabled {
+            ("Enable", "false")
+        } else {
+            ("Disable", "true")
+        };
+        let user_key = escape(&user.user);
+        let user_id = escape(&user.id);
+        let actions = if can_edit {
+            format!(
+                r#"<form action="/admin/users/disable" method="POST">
+            <input type="hidden" name="user" value="{user_id}" />
+            <input type="hidden" name="disabled" value="{toggle_value}" />
+            <button type="submit">{toggle_label}</button>
+        </form>
+        <form action="/admin/users/delete" method="POST" onsubmit="return confirm('Delete this user?')">
+            <input type="hidden" name="user" value="{user_id}" />
+            <button type="submit">Delete</button>
+        </form>"#
+            )
+        } else {
+            String::new()
+        };
+
+        rows.push_str(&format!(
+            r#"<tr>
+    <td>{user_key}<br />{handle}</td>
+    <td>{swarm_id}</td>
+    <td>{user_state}</td>
+    <td>{last_post}</td>
+    <td><ul>{errors}</ul></td>
+    <td>
+        {actions}
+    </td>
+</tr>
+"#,
+            handle = escape(user.mastodon_handle.as_deref().unwrap_or_default()),
+            swarm_id = escape(&user.swarm_id),
+            user_state = match (&user.suspended, user.disabled) {
+                (_, true) => "disabled".to_string(),
+                (Some(reason), false) => format!("suspended ({})", escape(reason)),
+                (None, false) => "active".to_string(),
+            },
+            last_post = user.last_post_at.map(ago).unwrap_or_else(|| "never".into()),
+        ));
+    }
+
+    let audit = state
+        .db
+        .get_audit(AUDIT_ENTRIES)
+        .from_err()?
+        .iter()
+        .map(|entry| {
+            let origin = if entry.origin.ip.is_some() || entry.origin.user_agent.is_some() {
+                format!(" from {}", escape(&entry.origin.describe()))
+            } else {
+                String::new()
+            };
+            format!(
+                "<li>{}: {} {} {}{}</li>",
+                ago(entry.at),
+                escape(&entry.operator),
+                escape(&entry.action),
+                escape(&entry.target),
+                origin
+            )
+        })
+        .collect::<String>();
+
+    let queues = queue_rows(&list_queues(&state).from_err()?, can_edit);
+
+    let test_push = match state.db.get_last_test_push().from_err()? {
+        Some(at) => ago(at),
+        None => "never".to_string(),
+    };
+
+    Ok(page(
+        "Admin",
+        &format!(
+            r#"<p>Logged in as {} ({:?})</p>
+<p>Last test push from the Foursquare push console: {}</p>
+<h1>Users</h1>
+<table>
+<tr><th>User</th><th>Swarm ID</th><th>State</th><th>Last post</th><th>Recent errors</th><th>Actions</th></tr>
+{}
+</table>
+<h1>Queues</h1>
+<table>
+<tr><th>Queue</th><th>User</th><th>Checkin</th><th>State</th><th>Attempts</th><th>Due</th><th>Status</th><th>Actions</th></tr>
+{}
+</table>
+<h1>Audit log</h1>
+<ul>{}</ul>"#,
+            escape(&operator.name),
+            operator.role,
+            test_push,
+            rows,
+            queues,
+            audit
+        ),
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/api/users",
+    responses(
+        (status = 200, description = "Every user", body = [UserSummary]),
+        (status = 401, description = "Missing or invalid admin credentials", body = crate::error::ErrorResponse),
+        (status = 403, description = "The operator lacks the viewer role", body = crate::error::ErrorResponse),
+    ),
+    security(("admin" = [])),
+)]
+pub async fn get_api_users(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<UserSummary>>, ApiError> {
+    Ok(Json(list_users(&state)?))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct DisableForm {
+    /// Opaque ID of the user
+    user: String,
+    disabled: bool,
+}
+
+/// Returns the key of the user given by an opaque ID or, for existing API
+/// clients, by their key.
+fn resolve_user(state: &AppState, user: &str) -> anyhow::Result<String> {
+    Ok(state
+        .db
+        .resolve_public_id(user)?
+        .unwrap_or_else(|| user.to_string()))
+}
+
+async fn disable(state: &AppState, operator: &Operator, form: &DisableForm) -> anyhow::Result<()> {
+    let user_key = resolve_user(state, &form.user)?;
+    let mut settings = state.db.get_settings(&user_key)?;
+    settings.disabled = form.disabled;
+    state.db.save_settings(&user_key, &settings)?;
+    let action = if form.disabled { "disable" } else { "enable" };
+    state.db.audit(&operator.name, action, &user_key)?;
+    tracing::info!(operator=%operator.name, user=%user_key, action, "admin changed user state");
+    if form.disabled {
+        last_seen::clear(state, &user_key).await;
+    }
+    Ok(())
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct UserForm {
+    /// Opaque ID of the user
+    user: String,
+}
+
+fn delete(state: &AppState, operator: &Operator, form: &UserForm) -> anyhow::Result<()> {
+    let user_key = resolve_user(state, &form.user)?;
+    state.db.delete_user(&user_key)?;
+    state.mastodon_clients.invalidate(&user_key);
+    state.db.audit(&operator.name, "delete", &user_key)?;
+    tracing::info!(operator=%operator.name, user=%user_key, "admin deleted user");
+    Ok(())
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct QueueForm {
+    queue: Queue,
+    /// ID of the item as listed in its queue
+    id: String,
+}
+
+/// Attempts a queued status right away, or posts a roundup without waiting
+/// for the end of the day. A status held at a venue is let go.
+fn retry(state: &AppState, operator: &Operator, form: &QueueForm) -> anyhow::Result<()> {
+    let key = hex::decode(&form.id).map_err(|_| ApiError::bad_request("invalid ID"))?;
+    let user_key = match form.queue {
+        Queue::Outbox => {
+            let Some(mut entry) = state.db.get_outbox_entry(&key)? else {
+                return Err(ApiError::not_found("no such queued status").into());
+            };
+            entry.hold = None;
+            entry.next_attempt_at = unix_now();
+            state.db.update_outbox(&key, &entry)?;
+            entry.user_key
+        }
+        Queue::Roundup => {
+            let Some(item) = state.db.get_roundup_item(&key)? else {
+                return Err(ApiError::not_found("no such roundup item").into());
+            };
+            roundup::post_now(state, &item.user_key, &item.day)?;
+            item.user_key
+        }
+    };
+    let action = format!("retry {} item {}", form.queue.id(), form.id);
+    state.db.audit(&operator.name, &action, &user_key)?;
+    tracing::info!(operator=%operator.name, user=%user_key, queue=form.queue.id(), id=%form.id, "admin retried queued item");
+    Ok(())
+}
+
+fn drop_item(state: &AppState, operator: &Operator, form: &QueueForm) -> anyhow::Result<()> {
+    let key = hex::decode(&form.id).map_err(|_| ApiError::bad_request("invalid ID"))?;
+    let user_key = match form.queue {
+        Queue::Outbox => {
+            let Some(entry) = state.db.get_outbox_entry(&key)? else {
+                return Err(ApiError::not_found("no such queued status").into());
+            };
+            state.db.remove_outbox(&key)?;
+            entry.user_key
+        }
+        Queue::Roundup => {
+            let Some(item) = state.db.get_roundup_item(&key)? else {
+                return Err(ApiError::not_found("no such roundup item").into());
+            };
+            state.db.remove_roundup(&key)?;
+            item.user_key
+        }
+    };
+    let action = format!("drop {} item {}", form.queue.id(), form.id);
+    state.db.audit(&operator.name, &action, &user_key)?;
+    tracing::info!(operator=%operator.name, user=%user_key, queue=form.queue.id(), id=%form.id, "admin dropped queued item");
+    Ok(())
+}
+
+async fn post_disable(
+    State(state): State<Arc<AppState>>,
+    Extension(operator): Extension<Operator>,
+    Form(form): Form<DisableForm>,
+) -> Result<Redirect, PageError> {
+    disable(&state, &operator, &form).await?;
+    Ok(Redirect::to("/admin"))
+}
+
+async fn post_delete(
+    State(state): State<Arc<AppState>>,
+    Extension(operator): Extension<Operator>,
+    Form(form): Form<UserForm>,
+) -> Result<Redirect, PageError> {
+    delete(&state, &operator, &form)?;
+    Ok(Redirect::to("/admin"))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/api/users/disable",
+    request_body = DisableForm,
+    responses(
+        (status = 204, description = "The user was disabled or enabled"),
+        (status = 401, description = "Missing or invalid admin credentials", body = crate::error::ErrorResponse),
+        (status = 403, description = "The operator lacks the admin role", body = crate::error::ErrorResponse),
+    ),
+    security(("admin" = [])),
+)]
+pub async fn post_api_disable(
+    State(state): State<Arc<AppState>>,
+    Extension(operator): Extension<Operator>,
+    Json(form): Json<DisableForm>,
+) -> Result<StatusCode, ApiError> {
+    disable(&state, &operator, &form).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/api/users/delete",
+    request_body = UserForm,
+    responses(
+        (status = 204, description = "The user was deleted"),
+        (status = 401, description = "Missing or invalid admin credentials", body = crate::error::ErrorResponse),
+        (status = 403, description = "The operator lacks the admin role", body = crate::error::ErrorResponse),
+    ),
+    security(("admin" = [])),
+)]
+pub async fn post_api_delete(
+    State(state): State<Arc<AppState>>,
+    Extension(operator): Extension<Operator>,
+    Json(form): Json<UserForm>,
+) -> Result<StatusCode, ApiError> {
+    delete(&state, &operator, &form)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/api/queues",
+    responses(
+        (status = 200, description = "Queued statuses and roundup items", body = [QueueItem]),
+        (status = 401, description = "Missing or invalid admin credentials", body = crate::error::ErrorResponse),
+        (status = 403, description = "The operator lacks the viewer role", body = crate::error::ErrorResponse),
+    ),
+    security(("admin" = [])),
+)]
+pub async fn get_api_queues(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<QueueItem>>, ApiError> {
+    Ok(Json(list_queues(&state)?))
+}
+
+async fn post_retry(
+    State(state): State<Arc<AppState>>,
+    Extension(operator): Extension<Operator>,
+    Form(form): Form<QueueForm>,
+) -> Result<Redirect, PageError> {
+    retry(&state, &operator, &form)?;
+    Ok(Redirect::to("/admin"))
+}
+
+async fn post_drop(
+    State(state): State<Arc<AppState>>,
+    Extension(operator): Extension<Operator>,
+    Form(form): Form<QueueForm>,
+) -> Result<Redirect, PageError> {
+    drop_item(&state, &operator, &form)?;
+    Ok(Redirect::to("/admin"))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/api/queues/retry",
+    request_body = QueueForm,
+    responses(
+        (status = 204, description = "The item is acted on right away"),
+        (status = 401, description = "Missing or invalid admin credentials", body = crate::error::ErrorResponse),
+        (status = 403, description = "The operator lacks the admin role", body = crate::error::ErrorResponse),
+        (status = 404, description = "No such queued item", body = crate::error::ErrorResponse),
+    ),
+    security(("admin" = [])),
+)]
+pub async fn post_api_retry(
+    State(state): State<Arc<AppState>>,
+    Extension(operator): Extension<Operator>,
+    Json(form): Json<QueueForm>,
+) -> Result<StatusCode, ApiError> {
+    retry(&state, &operator, &form)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/api/queues/drop",
+    request_body = QueueForm,
+    responses(
+        (status = 204, description = "The item was dropped"),
+        (status = 401, description = "Missing or invalid admin credentials", body = crate::error::ErrorResponse),
+        (status = 403, description = "The operator lacks the admin role", body = crate::error::ErrorResponse),
+        (status = 404, description = "No such queued item", body = crate::error::ErrorResponse),
+    ),
+    security(("admin" = [])),
+)]
+pub async fn post_api_drop(
+    State(state): State<Arc<AppState>>,
+    Extension(operator): Extension<Operator>,
+    Json(form): Json<QueueForm>,
+) -> Result<StatusCode, ApiError> {
+    drop_item(&state, &operator, &form)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub fn routes(state: Arc<AppState>) -> Router<Arc<AppState>> {
+    let viewer = Router::new()
+        .route("/admin", get(get_admin))
+        .route("/admin/api/users", get(get_api_users))
+        .route("/admin/api/queues", get(get_api_queues))
+        .route("/admin/metrics", get(crate::metrics::get_metrics))
+        .route("/admin/api/config", get(crate::version::get_config))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            require_viewer,
+        ));
+    let admin = Router::new()
+        .route("/admin/users/disable", post(post_disable))
+        .route("/admin/users/delete", post(post_delete))
+        .route("/admin/api/users/disable", post(post_api_disable))
+        .route("/admin/api/users/delete", post(post_api_delete))
+        .route("/admin/queues/retry", post(post_retry))
+        .route("/admin/queues/drop", post(post_drop))
+        .route("/admin/api/queues/retry", post(post_api_retry))
+        .route("/admin/api/queues/drop", post(post_api_drop))
+        .route_layer(middleware::from_fn_with_state(state, require_admin));
+    viewer.merge(admin)
+}
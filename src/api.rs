@@ -0,0 +1,133 @@
+use std::sync::Arc;
+
+use axum::Router;
+use utoipa::openapi::security::ApiKey;
+use utoipa::openapi::security::ApiKeyValue;
+use utoipa::openapi::security::HttpAuthScheme;
+use utoipa::openapi::security::HttpBuilder;
+use utoipa::openapi::security::SecurityScheme;
+use utoipa::Modify;
+use utoipa::OpenApi;
+use utoipa_swagger_ui::SwaggerUi;
+
+use crate::AppState;
+
+/// Version of the public API, bumped on incompatible changes.
+pub const API_VERSION: &str = "v1";
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Swarmdon", description = "Sync Swarm checkins to Mastodon"),
+    paths(
+        crate::post_swarm_push,
+        crate::nodeinfo::get_well_known_nodeinfo,
+        crate::nodeinfo::get_nodeinfo,
+        crate::version::get_version,
+        crate::usage::get_stats,
+        crate::export::get_geojson,
+        crate::rest::get_history,
+        crate::rest::get_history_search,
+        crate::rest::get_posts,
+        crate::rest::get_queue,
+        crate::rest::post_queue_send,
+        crate::rest::get_settings,
+        crate::rest::get_accounts,
+        crate::ingest::post_owntracks,
+        crate::ingest::post_overland,
+        crate::admin::get_api_users,
+        crate::admin::post_api_disable,
+        crate::admin::post_api_delete,
+        crate::admin::get_api_queues,
+        crate::admin::post_api_retry,
+        crate::admin::post_api_drop,
+        crate::version::get_config,
+    ),
+    components(schemas(
+        crate::error::ErrorResponse,
+        crate::SwarmPush,
+        crate::nodeinfo::WellKnown,
+        crate::nodeinfo::WellKnownLink,
+        crate::nodeinfo::NodeInfo,
+        crate::nodeinfo::Software,
+        crate::nodeinfo::Services,
+        crate::nodeinfo::Usage,
+        crate::nodeinfo::UsageUsers,
+        crate::version::Version,
+        crate::version::Config,
+        crate::usage::UsageStats,
+        crate::model::HistoryEntry,
+        crate::model::ArchivedCheckin,
+        crate::model::UserSettings,
+        crate::model::Layout,
+        crate::model::Coordinates,
+        crate::model::LinkMode,
+        crate::model::QuietHours,
+        crate::model::HouseholdMode,
+        crate::model::OverCap,
+        crate::model::Retention,
+        crate::model::LastSeen,
+        crate::model::UserError,
+        crate::rest::LinkedAccounts,
+        crate::rest::MastodonAccount,
+        crate::rest::SwarmAccount,
+        crate::rest::PostedStatus,
+        crate::rest::QueuedStatus,
+        crate::ingest::OwnTracksMessage,
+        crate::ingest::OverlandBatch,
+        crate::ingest::OverlandFeature,
+        crate::ingest::OverlandPoint,
+        crate::ingest::OverlandProperties,
+        crate::admin::UserSummary,
+        crate::admin::Queue,
+        crate::admin::QueueItem,
+        crate::admin::DisableForm,
+        crate::admin::UserForm,
+        crate::admin::QueueForm,
+    )),
+    modifiers(&SecuritySchemes)
+)]
+pub struct ApiDoc;
+
+/// How the documented routes authenticate.
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let Some(components) = openapi.components.as_mut() else {
+            return;
+        };
+        components.add_security_scheme(
+            "api_token",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .description(Some("API token created on the account page"))
+                    .build(),
+            ),
+        );
+        components.add_security_scheme(
+            "admin",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Basic)
+                    .description(Some(
+                        "The admin token as password, or as bearer token, unless logged in as an operator",
+                    ))
+                    .build(),
+            ),
+        );
+        components.add_security_scheme(
+            "session",
+            SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::new("user"))),
+        );
+    }
+}
+
+/// Serves the OpenAPI document at `/api/openapi.json` along with a Swagger UI.
+pub fn docs() -> Router<Arc<AppState>> {
+    let mut openapi = ApiDoc::openapi();
+    openapi.info.version = format!("{} ({})", API_VERSION, env!("CARGO_PKG_VERSION"));
+    SwaggerUi::new("/api/docs")
+        .url("/api/openapi.json", openapi)
+        .into()
+}
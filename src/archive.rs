@@ -0,0 +1,195 @@
+//! Archive of every processed checkin as received from Swarm, and the page
+//! where users browse the ones that were cross-posted or search them all.
+//!
+//! Like the history, nothing is archived while the user keeps the history
+//! turned off, and archived checkins follow the history's retention.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::extract::Query;
+use axum::extract::State;
+use axum::headers::Cookie;
+use axum::response::Html;
+use axum::TypedHeader;
+
+use crate::html::escape;
+use crate::html::page;
+use crate::model::ArchivedCheckin;
+use crate::model::User;
+use crate::schedule;
+use crate::swarm::SwarmCheckin;
+use crate::AppState;
+use crate::ResultExt;
+
+/// Checkins listed per page, and most search results listed.
+pub const PAGE_SIZE: usize = 50;
+
+/// Archives a processed checkin. `skipped` is why the user's settings kept
+/// it from being posted, if they did.
+pub fn record(state: &AppState, user_key: &str, checkin: &SwarmCheckin, skipped: Option<&str>) {
+    let entry = ArchivedCheckin::new(checkin, skipped);
+    if let Err(e) = state.db.archive_checkin(user_key, &entry) {
+        tracing::warn!(?e, "unable to archive checkin");
+    }
+}
+
+/// Link to one of the user's checkins on Swarm.
+pub fn swarm_url(user: &User, checkin_id: &str) -> String {
+    format!(
+        "https://www.swarmapp.com/user/{}/checkin/{}",
+        user.swarm_id, checkin_id
+    )
+}
+
+/// Link to a status the user posted, given their Mastodon handle.
+pub fn status_url(user: &User, handle: &str, status_id: &str) -> String {
+    let username = handle.split('@').next().unwrap_or_default();
+    format!(
+        "{}/@{}/{}",
+        user.mastodon.base.trim_end_matches('/'),
+        username,
+        status_id
+    )
+}
+
+/// Whether every word of the query shows up in the checkin's venue name,
+/// shout or location, ignoring case.
+fn matches(entry: &ArchivedCheckin, terms: &[String]) -> bool {
+    let text = [
+        Some(entry.venue_name.as_str()),
+        entry.shout.as_deref(),
+        entry.location.as_deref(),
+    ]
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>()
+    .join("\n")
+    .to_lowercase();
+    terms.iter().all(|term| text.contains(term.as_str()))
+}
+
+/// The user's archived checkins matching `query`, newest first, whether
+/// they were posted or not.
+pub fn search(
+    state: &AppState,
+    user_key: &str,
+    query: &str,
+) -> anyhow::Result<Vec<ArchivedCheckin>> {
+    let terms = query
+        .split_whitespace()
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>();
+    if terms.is_empty() {
+        return Ok(Vec::new());
+    }
+    state
+        .db
+        .search_archive(user_key, PAGE_SIZE, |entry| matches(entry, &terms))
+}
+
+/// Lists the user's cross-posted checkins, newest first, linking to both the
+/// checkin on Swarm and the status on Mastodon. With `q` set it lists the
+/// archived checkins matching it instead, including those never posted.
+pub async fn get_history(
+    State(state): State<Arc<AppState>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Html<String>, String> {
+    let user_key = crate::cookie_user_key(&state, &cookie)?;
+    let Some(user) = state.db.get_user(&user_key).from_err()? else {
+        return Err("invalid user".into());
+    };
+    let settings = state.db.get_settings(&user_key).from_err()?;
+    let profile = state
+        .db
+        .get_profile(&user_key)
+        .from_err()?
+        .unwrap_or_default();
+    let before = params.get("before").and_then(|before| before.parse().ok());
+    let query = params
+        .get("q")
+        .map(|query| query.trim())
+        .filter(|query| !query.is_empty());
+
+    let entries = match query {
+        Some(query) => search(&state, &user_key, query).from_err()?,
+        None => state
+            .db
+            .get_archive(&user_key, before, PAGE_SIZE)
+            .from_err()?,
+    };
+    let rows = entries
+        .iter()
+        .filter_map(|entry| {
+            let status = match (&entry.status_id, &entry.skipped) {
+                (Some(status_id), _) => format!(
+                    r#"<a href="{}">Mastodon</a>"#,
+                    escape(&status_url(&user, &profile.mastodon_handle, status_id))
+                ),
+                _ if query.is_none() => return None,
+                (None, Some(reason)) => format!("not posted: {}", escape(reason)),
+                (None, None) => "not posted".to_string(),
+            };
+            Some(format!(
+                r#"<tr>
+    <td>{day}</td>
+    <td>{venue}<br />{location}</td>
+    <td>{shout}</td>
+    <td><a href="{swarm}">Swarm</a> · {status}</td>
+</tr>
+"#,
+                day = schedule::local_day(&settings, entry.created_at),
+                venue = escape(&entry.venue_name),
+                location = escape(entry.location.as_deref().unwrap_or_default()),
+                shout = escape(entry.shout.as_deref().unwrap_or_default()),
+                swarm = escape(&swarm_url(&user, &entry.checkin_id)),
+            ))
+        })
+        .collect::<String>();
+    // Pages are cut by what was processed, so one may list fewer checkins
+    // than it holds.
+    let more = match entries.last() {
+        Some(last) if query.is_none() && entries.len() == PAGE_SIZE => format!(
+            r#"<p><a href="/history?before={}">Older checkins</a></p>"#,
+            last.created_at
+        ),
+        _ => String::new(),
+    };
+    let empty = if settings.history_disabled {
+        "<p>The history is turned off, so checkins aren't archived. It can be turned on from your account page.</p>"
+    } else if rows.is_empty() && query.is_some() {
+        "<p>No archived checkins match.</p>"
+    } else if rows.is_empty() && before.is_none() {
+        "<p>No checkins have been posted yet.</p>"
+    } else {
+        ""
+    };
+    let intro = match query {
+        Some(_) => format!(
+            r#"The {} most recent checkins matching your search, posted or not. <a href="/history">Back to your history</a>"#,
+            PAGE_SIZE
+        ),
+        None => r#"Your checkins posted to Mastodon. Download all as <a href="/export/geojson">GeoJSON</a>, <a href="/export/gpx">GPX</a> or <a href="/export/kml">KML</a> · <a href="/account">Back to your account</a>"#
+            .to_string(),
+    };
+
+    Ok(page(
+        "History",
+        &format!(
+            r#"<h1>History</h1>
+<p>{intro}</p>
+<form method="get" action="/history">
+<input type="search" name="q" value="{query}" placeholder="Venue, shout or city" />
+<input type="submit" value="Search" />
+</form>
+{empty}
+<table>
+<tr><th>Date</th><th>Venue</th><th>Shout</th><th>Links</th></tr>
+{rows}
+</table>
+{more}"#,
+            query = escape(query.unwrap_or_default()),
+        ),
+    ))
+}
@@ -0,0 +1,59 @@
+//! Bounds the push work in flight so a Foursquare retry storm queues up
+//! briefly and is then turned away, instead of piling up in memory.
+
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::OwnedSemaphorePermit;
+use tokio::sync::Semaphore;
+
+pub struct PushLimit {
+    capacity: usize,
+    semaphore: Arc<Semaphore>,
+    waiting: AtomicUsize,
+    rejected: AtomicU64,
+}
+
+impl PushLimit {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            semaphore: Arc::new(Semaphore::new(capacity)),
+            waiting: Default::default(),
+            rejected: Default::default(),
+        }
+    }
+
+    /// Waits up to `wait` for room for another pushed checkin. The permit is
+    /// meant to be held until the checkin is posted.
+    pub async fn acquire(&self, wait: Duration) -> Option<OwnedSemaphorePermit> {
+        self.waiting.fetch_add(1, Ordering::Relaxed);
+        let permit = tokio::time::timeout(wait, self.semaphore.clone().acquire_owned()).await;
+        self.waiting.fetch_sub(1, Ordering::Relaxed);
+        match permit {
+            Ok(Ok(permit)) => Some(permit),
+            _ => {
+                self.rejected.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// Pushed checkins accepted but not posted yet.
+    pub fn in_flight(&self) -> usize {
+        self.capacity - self.semaphore.available_permits()
+    }
+
+    /// Push requests waiting for room.
+    pub fn waiting(&self) -> usize {
+        self.waiting.load(Ordering::Relaxed)
+    }
+
+    /// Push requests turned away since startup.
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+}
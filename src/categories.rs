@@ -0,0 +1,159 @@
+//! Derives extras for a status from the venue's Foursquare categories.
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+
+use crate::model::UserSettings;
+use crate::swarm::SwarmVenue;
+
+/// Built-in hashtags for common categories, matched on the lowercased
+/// category name. Users can override or extend these in their settings.
+const DEFAULT_HASHTAGS: &[(&str, &str)] = &[
+    ("airport", "airport"),
+    ("art gallery", "art"),
+    ("bakery", "bakery"),
+    ("bar", "bar"),
+    ("beach", "beach"),
+    ("bookstore", "books"),
+    ("brewery", "beer"),
+    ("café", "cafe"),
+    ("coffee shop", "coffee"),
+    ("concert hall", "music"),
+    ("gym", "gym"),
+    ("hotel", "travel"),
+    ("movie theater", "movies"),
+    ("museum", "museum"),
+    ("park", "park"),
+    ("pizza place", "pizza"),
+    ("pub", "pub"),
+    ("ramen restaurant", "ramen"),
+    ("stadium", "sports"),
+    ("sushi restaurant", "sushi"),
+    ("train station", "train"),
+];
+
+/// Built-in emoji for common categories, matched on the lowercased category
+/// name.
+const DEFAULT_EMOJI: &[(&str, &str)] = &[
+    ("airport", "✈️"),
+    ("art gallery", "🖼️"),
+    ("bakery", "🥐"),
+    ("bar", "🍺"),
+    ("beach", "🏖️"),
+    ("bookstore", "📚"),
+    ("brewery", "🍺"),
+    ("café", "☕"),
+    ("coffee shop", "☕"),
+    ("concert hall", "🎵"),
+    ("gym", "🏋️"),
+    ("hospital", "🏥"),
+    ("hotel", "🏨"),
+    ("movie theater", "🎬"),
+    ("museum", "🏛️"),
+    ("park", "🌳"),
+    ("pizza place", "🍕"),
+    ("pub", "🍺"),
+    ("ramen restaurant", "🍜"),
+    ("restaurant", "🍽️"),
+    ("stadium", "🏟️"),
+    ("sushi restaurant", "🍣"),
+    ("train station", "🚆"),
+    ("wine bar", "🍷"),
+];
+
+/// Looks a category up in the user's table first, then in `defaults`. An
+/// empty entry in the user's table suppresses the default.
+fn lookup<'a>(
+    overrides: &'a BTreeMap<String, String>,
+    defaults: &'a [(&'a str, &'a str)],
+    category: &str,
+) -> Option<&'a str> {
+    let category = category.to_lowercase();
+    match overrides.get(&category) {
+        Some(value) if value.is_empty() => None,
+        Some(value) => Some(value),
+        None => defaults
+            .iter()
+            .find(|(name, _)| *name == category)
+            .map(|(_, value)| *value),
+    }
+}
+
+/// Hashtags for the venue's categories, primary category first.
+pub fn hashtags(settings: &UserSettings, venue: &SwarmVenue) -> Vec<String> {
+    if !settings.category_hashtags {
+        return Vec::new();
+    }
+    let mut hashtags = Vec::new();
+    for category in venue.categories_by_priority() {
+        if let Some(tag) = lookup(
+            &settings.category_hashtag_map,
+            DEFAULT_HASHTAGS,
+            &category.name,
+        ) {
+            let tag = format!("#{}", tag.trim_start_matches('#'));
+            if !hashtags.contains(&tag) {
+                hashtags.push(tag);
+            }
+        }
+    }
+    hashtags
+}
+
+/// Emoji for the venue, from the first category that has one, primary
+/// category first.
+pub fn emoji(settings: &UserSettings, venue: &SwarmVenue) -> Option<String> {
+    if !settings.category_emoji {
+        return None;
+    }
+    venue
+        .categories_by_priority()
+        .find_map(|category| lookup(&settings.category_emoji_map, DEFAULT_EMOJI, &category.name))
+        .map(str::to_string)
+}
+
+/// Content warning for the checkin if the venue has one of the user's
+/// sensitive categories, e.g. "Checked in at a hospital".
+pub fn content_warning(settings: &UserSettings, venue: &SwarmVenue) -> Option<String> {
+    venue
+        .categories_by_priority()
+        .find(|category| {
+            settings
+                .sensitive_categories
+                .contains(&category.name.to_lowercase())
+        })
+        .map(|category| format!("Checked in at a {}", category.name.to_lowercase()))
+}
+
+/// Parses a comma or newline separated list of category names.
+pub fn parse_list(input: &str) -> BTreeSet<String> {
+    input
+        .split(|c| c == ',' || c == '\n')
+        .map(|name| name.trim().to_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+/// Parses `Category = value` lines as entered on the account page. Keys are
+/// lowercased so they match case-insensitively.
+pub fn parse_table(input: &str) -> Result<BTreeMap<String, String>, String> {
+    input
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| match line.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => {
+                Ok((key.trim().to_lowercase(), value.trim().to_string()))
+            }
+            _ => Err(format!("expected `Category = value`, got `{}`", line)),
+        })
+        .collect()
+}
+
+pub fn format_table(table: &BTreeMap<String, String>) -> String {
+    table
+        .iter()
+        .map(|(key, value)| format!("{} = {}", key, value))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use anyhow::Result;
+use chrono::TimeZone;
+use chrono::Utc;
+use mastodon_async::Data;
+use mastodon_async::Mastodon;
+
+use crate::model::Post;
+use crate::model::User;
+
+/// Builds an HTTP client sending its requests through `proxy`, if any.
+pub fn http_client(proxy: Option<&str>) -> Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder();
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+    }
+    Ok(builder.build()?)
+}
+
+/// Mastodon rejects scheduled statuses due sooner than this many seconds.
+pub const MIN_SCHEDULE_AHEAD: u64 = 5 * 60;
+
+/// Submits a status for Mastodon to publish at `at`. The client library only
+/// knows about immediate posts, whose response differs.
+pub async fn schedule_status(
+    data: &Data,
+    proxy: Option<&str>,
+    checkin_id: &str,
+    post: &Post,
+    at: u64,
+) -> Result<()> {
+    let url = format!("{}/api/v1/statuses", data.base.trim_end_matches('/'));
+    let scheduled_at = Utc
+        .timestamp_opt(at as i64, 0)
+        .single()
+        .ok_or_else(|| anyhow::anyhow!("invalid time {}", at))?
+        .to_rfc3339();
+    let mut form = vec![
+        ("status", post.status.as_str()),
+        ("scheduled_at", scheduled_at.as_str()),
+    ];
+    if let Some(spoiler_text) = &post.spoiler_text {
+        form.push(("spoiler_text", spoiler_text));
+    }
+    if let Some(language) = &post.language {
+        form.push(("language", language));
+    }
+    for media_id in &post.media_ids {
+        form.push(("media_ids[]", media_id));
+    }
+    http_client(proxy)?
+        .post(url)
+        .bearer_auth(&*data.token)
+        // Keeps a retried request from scheduling the status twice.
+        .header("Idempotency-Key", checkin_id)
+        .form(&form)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+/// Caches constructed Mastodon clients so consecutive posts for the same user
+/// reuse the underlying HTTP connection pool.
+#[derive(Default)]
+pub struct MastodonClients {
+    clients: Mutex<HashMap<String, (Option<String>, Mastodon)>>,
+}
+
+impl MastodonClients {
+    /// Returns the client for the user, connecting through `proxy` when set.
+    /// Fails rather than falling back to a direct connection if the proxy
+    /// can't be used.
+    pub fn get(&self, user_key: &str, user: &User, proxy: Option<&str>) -> Result<Mastodon> {
+        let mut clients = self.clients.lock().unwrap();
+        match clients.get(user_key) {
+            // A changed token means the user re-authorized, so the cached
+            // client is stale. So is one built for a different proxy.
+            Some((cached_proxy, client))
+                if client.data.token == user.mastodon.token && cached_proxy.as_deref() == proxy =>
+            {
+                Ok(client.clone())
+            }
+            _ => {
+                let client = match proxy {
+                    Some(_) => Mastodon::new(http_client(proxy)?, user.mastodon.clone()),
+                    None => user.get_mastodon(),
+                };
+                clients.insert(
+                    user_key.to_string(),
+                    (proxy.map(str::to_string), client.clone()),
+                );
+                Ok(client)
+            }
+        }
+    }
+
+    pub fn invalidate(&self, user_key: &str) {
+        self.clients.lock().unwrap().remove(user_key);
+    }
+}
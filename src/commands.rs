@@ -0,0 +1,505 @@
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use axum::http::StatusCode;
+use chrono::NaiveDate;
+use chrono::TimeZone;
+use chrono::Utc;
+use clap::Subcommand;
+
+use crate::categories;
+use crate::clients::http_client;
+use crate::crypto;
+use crate::crypto::TokenCipher;
+use crate::crypto::TokenKey;
+use crate::export;
+use crate::instances;
+use crate::model::Database;
+use crate::model::Post;
+use crate::simulate;
+use crate::status;
+use crate::swarm::SwarmUserApi;
+use crate::venues;
+use crate::AppState;
+use crate::Flags;
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the web server
+    Serve(Flags),
+
+    /// Manage registered users
+    Users {
+        #[clap(subcommand)]
+        command: UsersCommand,
+    },
+
+    /// Cross-post a user's past checkins
+    Backfill {
+        /// User key, as printed by `users list`
+        #[clap(long)]
+        user: String,
+
+        /// Only go back to checkins made on or after this date (YYYY-MM-DD)
+        #[clap(long, value_parser = parse_date)]
+        since: Option<u64>,
+
+        /// Maximum number of checkins to go through
+        #[clap(long, default_value = "250")]
+        limit: u32,
+
+        /// Only list the checkins that would be posted
+        #[clap(long)]
+        dry_run: bool,
+
+        /// Post without asking for confirmation
+        #[clap(long)]
+        yes: bool,
+
+        #[clap(flatten)]
+        flags: Flags,
+    },
+
+    /// Run posted checkins through the current formatter and show what
+    /// changed, optionally editing the posted statuses to match
+    Rerender {
+        /// User key, as printed by `users list`
+        #[clap(long)]
+        user: String,
+
+        /// Only go back to checkins made on or after this date (YYYY-MM-DD)
+        #[clap(long, value_parser = parse_date)]
+        since: Option<u64>,
+
+        /// Edit the statuses that changed, on instances supporting it
+        #[clap(long)]
+        edit: bool,
+
+        /// Edit without asking for confirmation
+        #[clap(long)]
+        yes: bool,
+
+        #[clap(flatten)]
+        flags: Flags,
+    },
+
+    /// Inspect and purge records that failed to decode
+    Quarantine {
+        #[clap(subcommand)]
+        command: QuarantineCommand,
+    },
+
+    /// Dump the whole database to a file
+    Export { path: PathBuf },
+
+    /// Write a user's archived checkins to a GeoJSON file
+    Geojson {
+        /// User key, as printed by `users list`
+        #[clap(long)]
+        user: String,
+
+        path: PathBuf,
+    },
+
+    /// Load a dump created by `export` into an empty database
+    Import { path: PathBuf },
+
+    /// Push synthetic checkins through the pipeline and report how long each
+    /// stage takes
+    Simulate(simulate::Options),
+
+    /// Re-encrypt stored tokens under a new key
+    RotateKey {
+        /// Current key, omit when tokens are not encrypted yet
+        #[clap(long, value_parser = crypto::parse_key)]
+        old: Option<TokenKey>,
+
+        /// Key to encrypt tokens with from now on
+        #[clap(long, value_parser = crypto::parse_key)]
+        new: TokenKey,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum UsersCommand {
+    /// List registered users
+    List,
+
+    /// Remove a user and everything stored for them
+    Remove { user: String },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum QuarantineCommand {
+    /// List quarantined records
+    List,
+
+    /// Delete quarantined records, along with the originals
+    Purge {
+        /// Record IDs, as printed by `quarantine list`
+        ids: Vec<String>,
+
+        /// Purge every quarantined record
+        #[clap(long, conflicts_with = "ids")]
+        all: bool,
+    },
+}
+
+fn quarantine(db: &Database, command: QuarantineCommand) -> Result<()> {
+    match command {
+        QuarantineCommand::List => {
+            for (id, record) in db.get_quarantine()? {
+                println!("{}\t{}\t{}", id, record.at, record.error);
+            }
+        }
+        QuarantineCommand::Purge { ids, all } => {
+            let ids = if all {
+                db.get_quarantine()?.into_iter().map(|(id, _)| id).collect()
+            } else {
+                ids
+            };
+            for id in ids {
+                if db.purge_quarantined(&id)? {
+                    println!("purged {}", id);
+                } else {
+                    println!("no quarantined record {}", id);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn users(db: &Database, command: UsersCommand) -> Result<()> {
+    match command {
+        UsersCommand::List => {
+            for (user_key, user) in db.get_users()? {
+                let settings = db.get_settings(&user_key)?;
+                println!(
+                    "{}\tswarm={}\t{}",
+                    user_key,
+                    if user.swarm_id.is_empty() {
+                        "-"
+                    } else {
+                        &user.swarm_id
+                    },
+                    if settings.disabled {
+                        "disabled"
+                    } else {
+                        "active"
+                    },
+                );
+            }
+        }
+        UsersCommand::Remove { user } => {
+            if db.get_user(&user)?.is_none() {
+                anyhow::bail!("no such user: {}", user);
+            }
+            db.delete_user(&user)?;
+            println!("removed {}", user);
+        }
+    }
+    Ok(())
+}
+
+/// Number of checkins requested per page while backfilling.
+const BACKFILL_PAGE_SIZE: u32 = 100;
+
+fn parse_date(value: &str) -> Result<u64> {
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d")?;
+    let midnight = date
+        .and_hms_opt(0, 0, 0)
+        .ok_or_else(|| anyhow::anyhow!("invalid date: {}", value))?;
+    Ok(Utc.from_utc_datetime(&midnight).timestamp().max(0) as u64)
+}
+
+fn confirm(prompt: &str) -> Result<bool> {
+    print!("{} [y/N] ", prompt);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+async fn backfill(
+    state: Arc<AppState>,
+    user_key: &str,
+    since: Option<u64>,
+    limit: u32,
+    dry_run: bool,
+    yes: bool,
+) -> Result<()> {
+    let user = state
+        .db
+        .get_user(user_key)?
+        .ok_or_else(|| anyhow::anyhow!("no such user: {}", user_key))?;
+    if user.swarm_access_token.is_empty() {
+        anyhow::bail!("user has not connected Swarm");
+    }
+    let settings = state.db.get_settings(user_key)?;
+    let since = since.unwrap_or_default();
+
+    let swarm = SwarmUserApi::new(&user.swarm_access_token);
+    let mut checkins = Vec::new();
+    let mut offset = 0;
+    'pages: while offset < limit {
+        let page = swarm
+            .get_checkins(BACKFILL_PAGE_SIZE.min(limit - offset), offset)
+            .await?;
+        if page.is_empty() {
+            break;
+        }
+        offset += page.len() as u32;
+
+        for checkin in page {
+            if checkin.created_at < since {
+                break 'pages;
+            }
+            checkins.push(checkin);
+        }
+    }
+    checkins.reverse();
+
+    let mut pending = Vec::new();
+    for checkin in checkins {
+        let date = Utc
+            .timestamp_opt(checkin.created_at as i64, 0)
+            .single()
+            .map(|date| date.format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_default();
+        let skip = crate::skip_reason(&settings, &checkin);
+        println!(
+            "{}  {}  {} @ {}{}",
+            checkin.id,
+            date,
+            checkin.shout.as_deref().unwrap_or("-"),
+            checkin.venue.name,
+            skip.map(|reason| format!("  (skipped: {})", reason))
+                .unwrap_or_default(),
+        );
+        if skip.is_none() {
+            pending.push(checkin);
+        }
+    }
+
+    if dry_run || pending.is_empty() {
+        println!("{} checkins would be posted", pending.len());
+        return Ok(());
+    }
+    if !yes && !confirm(&format!("Post {} checkins?", pending.len()))? {
+        return Ok(());
+    }
+
+    let pacing = Duration::from_secs(state.flags.poll_pacing);
+    for (index, checkin) in pending.into_iter().enumerate() {
+        if index > 0 {
+            tokio::time::sleep(pacing).await;
+        }
+        println!("posting {} @ {}", checkin.id, checkin.venue.name);
+        crate::post_checkin(&state, user_key, &user, checkin).await;
+    }
+    Ok(())
+}
+
+/// Edits a posted status, which Mastodon supports since 3.5. Returns
+/// `Ok(false)` if the instance doesn't know the endpoint.
+async fn edit_status(
+    data: &mastodon_async::Data,
+    proxy: Option<&str>,
+    status_id: &str,
+    post: &Post,
+) -> Result<bool> {
+    let url = format!(
+        "{}/api/v1/statuses/{}",
+        data.base.trim_end_matches('/'),
+        status_id
+    );
+    let mut form = vec![("status", post.status.as_str())];
+    if let Some(spoiler_text) = &post.spoiler_text {
+        form.push(("spoiler_text", spoiler_text));
+    }
+    if let Some(language) = &post.language {
+        form.push(("language", language));
+    }
+    let response = http_client(proxy)?
+        .put(url)
+        .bearer_auth(&*data.token)
+        .form(&form)
+        .send()
+        .await?;
+    if matches!(
+        response.status(),
+        StatusCode::NOT_FOUND | StatusCode::METHOD_NOT_ALLOWED
+    ) {
+        return Ok(false);
+    }
+    response.error_for_status()?;
+    Ok(true)
+}
+
+async fn rerender(
+    state: Arc<AppState>,
+    user_key: &str,
+    since: Option<u64>,
+    edit: bool,
+    yes: bool,
+) -> Result<()> {
+    let user = state
+        .db
+        .get_user(user_key)?
+        .ok_or_else(|| anyhow::anyhow!("no such user: {}", user_key))?;
+    if user.swarm_access_token.is_empty() {
+        anyhow::bail!("user has not connected Swarm");
+    }
+    let settings = state.db.get_settings(user_key)?;
+    // Mentions were verified when the statuses were first posted, so the
+    // stored friends are used as they are.
+    let friends = state.db.get_friends(user_key)?;
+    let since = since.unwrap_or_default();
+
+    let history = state
+        .db
+        .get_history(user_key)?
+        .into_iter()
+        .filter(|entry| entry.created_at >= since && entry.status.is_some())
+        .collect::<Vec<_>>();
+
+    let swarm = SwarmUserApi::new(&user.swarm_access_token);
+    let pacing = Duration::from_secs(state.flags.poll_pacing);
+    let mut changed = Vec::new();
+    for (index, entry) in history.into_iter().rev().enumerate() {
+        if index > 0 {
+            tokio::time::sleep(pacing).await;
+        }
+        let details = match swarm.get_checkin_details(&entry.checkin_id).await {
+            Ok(details) => details,
+            Err(e) => {
+                println!("{}  unable to retrieve checkin: {}", entry.checkin_id, e);
+                continue;
+            }
+        };
+        let checkin = &details.basic;
+        let lookups = status::Lookups {
+            parent_venue: venues::parent_venue(&state, &swarm, &checkin.venue.id).await,
+            max_characters: instances::info(
+                &state,
+                &user.mastodon.base,
+                settings.mastodon_proxy.as_deref(),
+            )
+            .await
+            .max_characters,
+        };
+        // Replies can't be added to a posted status after the fact, only the
+        // status itself is compared and edited.
+        let (text, _) = status::compose(&settings, &friends, checkin, &details, &lookups);
+        let post = Post {
+            status: text,
+            language: status::language(&settings, checkin),
+            spoiler_text: categories::content_warning(&settings, &checkin.venue),
+            ..Default::default()
+        };
+        let recorded = entry.status.as_deref().unwrap_or_default();
+        if post.status == recorded {
+            continue;
+        }
+
+        println!("{} @ {}", entry.checkin_id, entry.venue_name);
+        for line in recorded.lines() {
+            println!("- {}", line);
+        }
+        for line in post.status.lines() {
+            println!("+ {}", line);
+        }
+        changed.push((entry, post));
+    }
+
+    println!("{} statuses would change", changed.len());
+    if !edit || changed.is_empty() {
+        return Ok(());
+    }
+    if !yes && !confirm(&format!("Edit {} statuses?", changed.len()))? {
+        return Ok(());
+    }
+
+    let proxy = settings.mastodon_proxy.as_deref();
+    for (entry, post) in changed {
+        let Some(status_id) = &entry.status_id else {
+            println!(
+                "{}  posted before status IDs were recorded, not editing",
+                entry.checkin_id
+            );
+            continue;
+        };
+        if !edit_status(&user.mastodon, proxy, status_id, &post).await? {
+            println!("instance does not support editing statuses, stopping");
+            break;
+        }
+        state
+            .db
+            .set_history_status(user_key, &entry.checkin_id, &post.status, status_id)?;
+        println!("edited {}", entry.checkin_id);
+    }
+    Ok(())
+}
+
+pub async fn run(db: Database, command: Command) -> Result<()> {
+    match command {
+        Command::Serve(flags) => crate::serve(flags, db).await,
+        Command::Users { command } => users(&db, command),
+        Command::Backfill {
+            user,
+            since,
+            limit,
+            dry_run,
+            yes,
+            flags,
+        } => {
+            let (state, _) = AppState::from_flags(flags, db)?;
+            backfill(Arc::new(state), &user, since, limit, dry_run, yes).await
+        }
+        Command::Rerender {
+            user,
+            since,
+            edit,
+            yes,
+            flags,
+        } => {
+            let (state, _) = AppState::from_flags(flags, db)?;
+            rerender(Arc::new(state), &user, since, edit, yes).await
+        }
+        Command::Quarantine { command } => quarantine(&db, command),
+        Command::Export { path } => {
+            db.export(BufWriter::new(File::create(&path)?))?;
+            println!("exported to {}", path.display());
+            Ok(())
+        }
+        Command::Geojson { user, path } => {
+            let collection = export::geojson(&db, &user)?;
+            serde_json::to_writer(BufWriter::new(File::create(&path)?), &collection)?;
+            println!("exported to {}", path.display());
+            Ok(())
+        }
+        Command::Import { path } => {
+            db.import(BufReader::new(File::open(&path)?))?;
+            println!("imported from {}", path.display());
+            Ok(())
+        }
+        Command::Simulate(options) => simulate::run(options).await,
+        Command::RotateKey { old, new } => {
+            let old = old.map(|old| TokenCipher::new(&old, &[]));
+            let count = db.reencrypt_users(old.as_ref(), &TokenCipher::new(&new, &[]))?;
+            println!(
+                "re-encrypted {} users, restart with --token-key set to the new key",
+                count
+            );
+            Ok(())
+        }
+    }
+}
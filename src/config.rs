@@ -0,0 +1,101 @@
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use anyhow::Result;
+use clap::Command;
+
+/// Finds the value of `--config` without running the full parser, since the
+/// file has to be read before the rest of the arguments can be parsed.
+pub fn find_config_path<I: IntoIterator<Item = OsString>>(args: I) -> Option<PathBuf> {
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.to_str().and_then(|arg| arg.strip_prefix("--config=")) {
+            return Some(PathBuf::from(path));
+        }
+    }
+    None
+}
+
+pub fn load(path: &PathBuf) -> Result<toml::Table> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("unable to read config file {}", path.display()))?;
+    toml::from_str(&content)
+        .with_context(|| format!("unable to parse config file {}", path.display()))
+}
+
+fn has_arg(command: &Command, id: &str) -> bool {
+    command.get_arguments().any(|arg| arg.get_id() == id)
+}
+
+fn apply_value(command: Command, id: &str, value: &toml::Value) -> Command {
+    if !has_arg(&command, id) {
+        return command;
+    }
+    let values: Vec<String> = match value {
+        toml::Value::String(value) => vec![value.clone()],
+        toml::Value::Array(values) => values
+            .iter()
+            .map(|value| match value {
+                toml::Value::String(value) => value.clone(),
+                value => value.to_string(),
+            })
+            .collect(),
+        value => vec![value.to_string()],
+    };
+    // clap only counts explicit values towards required arguments, so those
+    // given in the file are no longer required on the command line.
+    command.mut_arg(id, |arg| arg.default_values(values).required(false))
+}
+
+/// Uses values from the config file as defaults of the matching arguments, so
+/// anything given on the command line still takes precedence. Keys are the
+/// long flag names, e.g. `swarm_client_id` or `swarm-client-id`. Required
+/// arguments can be set in the file too.
+pub fn apply(mut command: Command, config: &toml::Table) -> Command {
+    for (key, value) in config {
+        let id = key.replace('-', "_");
+        command = apply_value(command, &id, value);
+        for subcommand in command.get_subcommands_mut() {
+            *subcommand = apply_value(subcommand.clone(), &id, value);
+        }
+    }
+    command
+}
+
+fn apply_env_file(command: Command) -> Result<Command> {
+    let mut defaults = Vec::new();
+    for arg in command.get_arguments() {
+        let Some(env) = arg.get_env().and_then(|env| env.to_str()) else {
+            continue;
+        };
+        if std::env::var_os(env).is_some() {
+            continue;
+        }
+        if let Some(path) = std::env::var_os(format!("{}_FILE", env)) {
+            let value = std::fs::read_to_string(&path)
+                .with_context(|| format!("unable to read {}_FILE", env))?;
+            defaults.push((arg.get_id().to_string(), value.trim_end().to_string()));
+        }
+    }
+
+    Ok(defaults.into_iter().fold(command, |command, (id, value)| {
+        command.mut_arg(id, |arg| arg.default_value(value).required(false))
+    }))
+}
+
+/// Supports the `<ENV>_FILE` convention for arguments that can be read from
+/// the environment: when only the `_FILE` variant is set, the argument
+/// defaults to the content of that file, which also satisfies required
+/// arguments. This keeps secrets out of process listings and shell history,
+/// e.g. with Docker or systemd credentials.
+pub fn apply_env_files(command: Command) -> Result<Command> {
+    let mut command = apply_env_file(command)?;
+    for subcommand in command.get_subcommands_mut() {
+        *subcommand = apply_env_file(subcommand.clone())?;
+    }
+    Ok(command)
+}
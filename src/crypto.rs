@@ -0,0 +1,93 @@
+use std::borrow::Cow;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use chacha20poly1305::aead::Aead;
+use chacha20poly1305::aead::AeadCore;
+use chacha20poly1305::aead::KeyInit;
+use chacha20poly1305::aead::OsRng;
+use chacha20poly1305::Key;
+use chacha20poly1305::XChaCha20Poly1305;
+use chacha20poly1305::XNonce;
+
+/// Prefix of encrypted records, anything else is read as plaintext so
+/// databases created before encryption keep working.
+const MAGIC: &[u8] = b"\0swarmdon-enc1";
+const NONCE_LEN: usize = 24;
+
+pub type TokenKey = [u8; 32];
+
+/// Parses a hex encoded 32 byte key, e.g. from `openssl rand -hex 32`.
+pub fn parse_key(value: &str) -> Result<TokenKey> {
+    hex::decode(value.trim())?
+        .try_into()
+        .map_err(|_| anyhow!("token key must be 32 bytes"))
+}
+
+/// Encrypts stored credentials. New records are always encrypted with the
+/// first key, while any of the keys may decrypt, which allows a transition
+/// period after a rotation.
+pub struct TokenCipher {
+    keys: Vec<XChaCha20Poly1305>,
+}
+
+impl TokenCipher {
+    pub fn new(key: &TokenKey, old_keys: &[TokenKey]) -> Self {
+        let keys = std::iter::once(key)
+            .chain(old_keys)
+            .map(|key| XChaCha20Poly1305::new(Key::from_slice(key)))
+            .collect();
+        Self { keys }
+    }
+
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self.keys[0]
+            .encrypt(&nonce, plaintext)
+            .map_err(|_| anyhow!("unable to encrypt record"))?;
+
+        let mut record = Vec::with_capacity(MAGIC.len() + NONCE_LEN + ciphertext.len());
+        record.extend_from_slice(MAGIC);
+        record.extend_from_slice(&nonce);
+        record.extend_from_slice(&ciphertext);
+        Ok(record)
+    }
+
+    fn decrypt(&self, record: &[u8]) -> Result<Vec<u8>> {
+        let record = &record[MAGIC.len()..];
+        if record.len() < NONCE_LEN {
+            return Err(anyhow!("encrypted record is truncated"));
+        }
+        let (nonce, ciphertext) = record.split_at(NONCE_LEN);
+        let nonce = XNonce::from_slice(nonce);
+        self.keys
+            .iter()
+            .find_map(|key| key.decrypt(nonce, ciphertext).ok())
+            .ok_or_else(|| anyhow!("unable to decrypt record with any configured key"))
+    }
+}
+
+pub fn is_encrypted(record: &[u8]) -> bool {
+    record.starts_with(MAGIC)
+}
+
+/// Returns the plaintext of a stored record, decrypting it if needed.
+pub fn open<'a>(cipher: Option<&TokenCipher>, record: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+    if !is_encrypted(record) {
+        return Ok(Cow::Borrowed(record));
+    }
+    match cipher {
+        Some(cipher) => Ok(Cow::Owned(cipher.decrypt(record)?)),
+        None => Err(anyhow!(
+            "record is encrypted but no token key is configured"
+        )),
+    }
+}
+
+/// Encrypts a record if a cipher is configured.
+pub fn seal(cipher: Option<&TokenCipher>, plaintext: Vec<u8>) -> Result<Vec<u8>> {
+    match cipher {
+        Some(cipher) => cipher.encrypt(&plaintext),
+        None => Ok(plaintext),
+    }
+}
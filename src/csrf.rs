@@ -0,0 +1,70 @@
+//! Keeps other sites from submitting forms on behalf of logged in users.
+//!
+//! Browsers attach the session cookie, and remembered basic auth
+//! credentials, to forms posted from anywhere. Requests that may change
+//! something are only taken when the browser says they come from a page of
+//! this service, through `Origin` or else `Referer`. Clients that send
+//! neither and no cookie, such as scripts using the JSON API or Foursquare
+//! pushing checkins, are left alone.
+
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::http::header::COOKIE;
+use axum::http::header::ORIGIN;
+use axum::http::header::REFERER;
+use axum::http::Method;
+use axum::http::Request;
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use url::Url;
+
+use crate::html::page;
+use crate::AppState;
+
+/// Whether `source`, an `Origin` or `Referer` header, is on the same origin
+/// as `base_url`. `null` origins, sent for sandboxed or privacy sensitive
+/// contexts, never are.
+fn same_origin(base_url: &str, source: &str) -> bool {
+    match (Url::parse(base_url), Url::parse(source)) {
+        (Ok(base), Ok(source)) => base.origin() == source.origin(),
+        _ => false,
+    }
+}
+
+pub async fn check_origin<B>(
+    State(state): State<Arc<AppState>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    ) {
+        return next.run(request).await;
+    }
+    let headers = request.headers();
+    let source = headers
+        .get(ORIGIN)
+        .or_else(|| headers.get(REFERER))
+        .map(|value| value.to_str().unwrap_or_default());
+    let allowed = match source {
+        Some(source) => same_origin(&state.flags.base_url, source),
+        None => !headers.contains_key(COOKIE),
+    };
+    if allowed {
+        return next.run(request).await;
+    }
+
+    tracing::warn!(?source, path=%request.uri().path(), "rejected cross-site request");
+    (
+        StatusCode::FORBIDDEN,
+        page(
+            "Forbidden",
+            "<h1>Forbidden</h1>\n<p>This form was sent from another site. Go back to the page and submit it again from there.</p>",
+        ),
+    )
+        .into_response()
+}
@@ -0,0 +1,138 @@
+use mastodon_async::entities::status::Status;
+use mastodon_async::Error;
+use mastodon_async::Mastodon;
+
+use crate::model::Post;
+use crate::outbox;
+use crate::AppState;
+
+/// Category of a failed Mastodon request, deciding how delivery proceeds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    RateLimited,
+    StatusTooLong,
+    AccountSuspended,
+    TokenRevoked,
+    /// Never returned by `classify`, raised when a stored token lapses.
+    TokenExpired,
+    InstanceUnreachable,
+    Other,
+}
+
+impl ErrorKind {
+    /// Whether trying again later may succeed.
+    pub fn is_retryable(self) -> bool {
+        matches!(
+            self,
+            ErrorKind::RateLimited | ErrorKind::InstanceUnreachable | ErrorKind::Other
+        )
+    }
+
+    /// Whether every further post for the user will fail until they log in
+    /// again.
+    pub fn suspends_delivery(self) -> bool {
+        matches!(
+            self,
+            ErrorKind::AccountSuspended | ErrorKind::TokenRevoked | ErrorKind::TokenExpired
+        )
+    }
+}
+
+pub fn classify(error: &Error) -> ErrorKind {
+    match error {
+        Error::Api { status, response } => {
+            let message = format!("{:?}", response).to_lowercase();
+            match status.as_u16() {
+                429 => ErrorKind::RateLimited,
+                401 => ErrorKind::TokenRevoked,
+                403 if message.contains("suspended") || message.contains("disabled") => {
+                    ErrorKind::AccountSuspended
+                }
+                422 if message.contains("too long") || message.contains("character limit") => {
+                    ErrorKind::StatusTooLong
+                }
+                500..=599 => ErrorKind::InstanceUnreachable,
+                _ => ErrorKind::Other,
+            }
+        }
+        Error::Http(e) if e.is_connect() || e.is_timeout() => ErrorKind::InstanceUnreachable,
+        Error::Http(e) if e.status().map_or(false, |status| status.is_server_error()) => {
+            ErrorKind::InstanceUnreachable
+        }
+        _ => ErrorKind::Other,
+    }
+}
+
+/// Stops delivery for a user after an error that requires them to act.
+pub fn suspend(state: &AppState, user_key: &str, kind: ErrorKind) {
+    let reason = format!("{:?}", kind);
+    tracing::warn!(user=%user_key, %reason, "suspending delivery");
+    if let Err(e) = state
+        .db
+        .update_user_status(user_key, |status| status.suspended = Some(reason))
+    {
+        tracing::warn!(?e, "unable to suspend delivery");
+    }
+}
+
+/// Handles a status that failed to post for the first time: transient errors
+/// are queued for retry, errors that need the user to act suspend delivery,
+/// and anything else is dropped. All of them are recorded for the admin panel.
+pub fn handle_failure(
+    state: &AppState,
+    user_key: &str,
+    checkin_id: &str,
+    post: Post,
+    error: &Error,
+) {
+    let kind = classify(error);
+    crate::record_error(
+        state,
+        user_key,
+        format!("unable to post status ({:?}): {}", kind, error),
+    );
+
+    if kind.suspends_delivery() {
+        suspend(state, user_key, kind);
+    } else if kind.is_retryable() {
+        tracing::warn!(
+            ?kind,
+            "unable to post status, queueing for retry: {}",
+            error
+        );
+        if let Err(e) = outbox::enqueue(state, user_key, checkin_id, post) {
+            tracing::warn!(?e, "unable to queue status for retry");
+        }
+    } else {
+        tracing::warn!(?kind, "unable to post status, dropping: {}", error);
+    }
+}
+
+/// Posts the replies continuing a long shout, each under the previous one.
+/// They aren't retried: the status they hang off is out already, so a retry
+/// couldn't keep the thread in order. The user is told instead.
+pub async fn post_replies(
+    state: &AppState,
+    user_key: &str,
+    client: &Mastodon,
+    post: &Post,
+    posted: &Status,
+) {
+    let mut parent = posted.clone();
+    for reply in &post.replies {
+        match client
+            .new_status(post.reply_new_status(reply, &parent))
+            .await
+        {
+            Ok(posted) => parent = posted,
+            Err(e) => {
+                crate::record_error(
+                    state,
+                    user_key,
+                    format!("unable to post the rest of a long shout: {}", e),
+                );
+                return;
+            }
+        }
+    }
+}
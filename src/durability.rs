@@ -0,0 +1,80 @@
+//! Makes sure what users are told was saved survives a crash.
+//!
+//! Requests that may change something wait for the database to reach the
+//! disk before they are answered, see `Database::barrier`. On shutdown the
+//! server stops taking requests, lets those in flight finish and flushes once
+//! more.
+
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::http::Method;
+use axum::http::Request;
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use axum::response::Response;
+
+use crate::AppState;
+
+/// Requests answered without waiting for the disk. Swarm pushes are only
+/// queued for the push worker and Foursquare wants a quick answer; posting
+/// one makes the processed mark durable on its own.
+const UNFLUSHED: &[&str] = &["/swarm/push"];
+
+/// Flushes the database after requests other than `GET` and `HEAD`, except
+/// for `UNFLUSHED` ones. The OAuth callbacks are `GET` requests and flush on
+/// their own.
+pub async fn flush_writes<B>(
+    State(state): State<Arc<AppState>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let writes = !matches!(*request.method(), Method::GET | Method::HEAD)
+        && !UNFLUSHED.contains(&request.uri().path());
+    let response = next.run(request).await;
+    if writes {
+        if let Err(e) = state.db.barrier().await {
+            tracing::error!(?e, "unable to flush the database");
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to save changes, please try again",
+            )
+                .into_response();
+        }
+    }
+    response
+}
+
+/// Resolves on Ctrl-C, or on SIGTERM as sent by service managers.
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::warn!(?e, "unable to listen for Ctrl-C");
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::signal;
+        use tokio::signal::unix::SignalKind;
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                tracing::warn!(?e, "unable to listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    tracing::info!("received shutdown signal");
+}
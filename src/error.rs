@@ -0,0 +1,127 @@
+//! Errors of the JSON routes, answered with a status code and a JSON body,
+//! and of the HTML pages, answered with the same status code and a page
+//! explaining it.
+
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use axum::Json;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::html::escape;
+use crate::html::page;
+
+/// A failed JSON request. Functions shared with the HTML pages return
+/// `anyhow::Error`, which keeps the status code when it wraps an `ApiError`
+/// and is an internal error otherwise.
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+/// Body of every error response of the JSON routes.
+#[derive(Serialize, ToSchema)]
+pub struct ErrorResponse {
+    /// Kind of error, such as `not_found`, stable for clients to match on
+    #[schema(example = "not_found")]
+    error: String,
+    /// Explanation for people
+    #[schema(example = "no such queued status")]
+    message: String,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, message)
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::UNAUTHORIZED, message)
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::FORBIDDEN, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, message)
+    }
+
+    /// `error` of the response, derived from the status code.
+    fn kind(&self) -> String {
+        self.status
+            .canonical_reason()
+            .unwrap_or("error")
+            .to_lowercase()
+            .replace(' ', "_")
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        match e.downcast::<ApiError>() {
+            Ok(e) => e,
+            Err(e) => {
+                tracing::warn!(?e, "request failed");
+                Self::new(StatusCode::INTERNAL_SERVER_ERROR, "internal error")
+            }
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ErrorResponse {
+            error: self.kind(),
+            message: self.message,
+        };
+        (self.status, Json(body)).into_response()
+    }
+}
+
+/// A failed request of an HTML page. Converts from `ApiError` and anything
+/// `anyhow::Error` does, keeping the status code of an `ApiError` the same
+/// way.
+#[derive(Debug)]
+pub struct PageError(ApiError);
+
+impl<E> From<E> for PageError
+where
+    E: Into<anyhow::Error>,
+{
+    fn from(e: E) -> Self {
+        Self(ApiError::from(e.into()))
+    }
+}
+
+impl IntoResponse for PageError {
+    fn into_response(self) -> Response {
+        let ApiError { status, message } = self.0;
+        let title = status.canonical_reason().unwrap_or("Error");
+        (
+            status,
+            page(
+                title,
+                &format!("<h1>{}</h1>\n<p>{}</p>", title, escape(&message)),
+            ),
+        )
+            .into_response()
+    }
+}
@@ -0,0 +1,257 @@
+//! Exports of a user's archived checkins for mapping tools, as GeoJSON, GPX
+//! and KML.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use axum::extract::State;
+use axum::headers::Cookie;
+use axum::http::header::CONTENT_DISPOSITION;
+use axum::http::header::CONTENT_TYPE;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use axum::TypedHeader;
+use chrono::TimeZone;
+use chrono::Utc;
+use serde_json::json;
+use serde_json::Value;
+
+use crate::archive;
+use crate::error::ApiError;
+use crate::html::escape;
+use crate::model::ArchivedCheckin;
+use crate::model::Database;
+use crate::AppState;
+use crate::ResultExt;
+
+/// An archived checkin at a venue with known coordinates.
+struct Place {
+    entry: ArchivedCheckin,
+    lat: f64,
+    lng: f64,
+    swarm_url: String,
+    status_url: Option<String>,
+}
+
+impl Place {
+    /// The shout and location, for formats with a single description.
+    fn description(&self) -> String {
+        [self.entry.shout.as_deref(), self.entry.location.as_deref()]
+            .into_iter()
+            .flatten()
+            .filter(|text| !text.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// The user's archived checkins, oldest first. Checkins whose venue has no
+/// coordinates are left out.
+fn places(db: &Database, user_key: &str) -> Result<Vec<Place>> {
+    let Some(user) = db.get_user(user_key)? else {
+        anyhow::bail!("no such user");
+    };
+    let profile = db.get_profile(user_key)?.unwrap_or_default();
+    let mut entries = db.get_archive(user_key, None, usize::MAX)?;
+    entries.reverse();
+
+    Ok(entries
+        .into_iter()
+        .filter_map(|entry| {
+            let (lat, lng) = entry.coordinates()?;
+            Some(Place {
+                lat,
+                lng,
+                swarm_url: archive::swarm_url(&user, &entry.checkin_id),
+                status_url: entry
+                    .status_id
+                    .as_deref()
+                    .map(|id| archive::status_url(&user, &profile.mastodon_handle, id)),
+                entry,
+            })
+        })
+        .collect())
+}
+
+fn rfc3339(unix: u64) -> String {
+    Utc.timestamp_opt(unix as i64, 0)
+        .single()
+        .unwrap_or_default()
+        .to_rfc3339()
+}
+
+/// The user's archived checkins as a GeoJSON FeatureCollection.
+pub fn geojson(db: &Database, user_key: &str) -> Result<Value> {
+    let features = places(db, user_key)?
+        .iter()
+        .map(|place| {
+            json!({
+                "type": "Feature",
+                // GeoJSON puts longitude first.
+                "geometry": { "type": "Point", "coordinates": [place.lng, place.lat] },
+                "properties": {
+                    "checkin_id": place.entry.checkin_id,
+                    "venue": place.entry.venue_name,
+                    "location": place.entry.location,
+                    "shout": place.entry.shout,
+                    "time": rfc3339(place.entry.created_at),
+                    "timestamp": place.entry.created_at,
+                    "swarm_url": place.swarm_url,
+                    "status_url": place.status_url,
+                    "skipped": place.entry.skipped,
+                },
+            })
+        })
+        .collect::<Vec<_>>();
+    Ok(json!({
+        "type": "FeatureCollection",
+        "features": features,
+    }))
+}
+
+/// The user's archived checkins as GPX 1.1: a waypoint per checkin, and a
+/// track joining them in order for tools that replay location history.
+pub fn gpx(db: &Database, user_key: &str) -> Result<String> {
+    let places = places(db, user_key)?;
+    let mut gpx = String::from(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<gpx version="1.1" creator="swarmdon" xmlns="http://www.topografix.com/GPX/1/1">
+"#,
+    );
+    for place in &places {
+        gpx.push_str(&format!(
+            r#"<wpt lat="{lat}" lon="{lng}">
+  <time>{time}</time>
+  <name>{name}</name>
+  <desc>{desc}</desc>
+  <link href="{link}"><text>{site}</text></link>
+</wpt>
+"#,
+            lat = place.lat,
+            lng = place.lng,
+            time = rfc3339(place.entry.created_at),
+            name = escape(&place.entry.venue_name),
+            desc = escape(&place.description()),
+            link = escape(place.status_url.as_deref().unwrap_or(&place.swarm_url)),
+            site = if place.status_url.is_some() {
+                "Mastodon"
+            } else {
+                "Swarm"
+            },
+        ));
+    }
+    gpx.push_str("<trk>\n  <name>Checkins</name>\n  <trkseg>\n");
+    for place in &places {
+        gpx.push_str(&format!(
+            "    <trkpt lat=\"{}\" lon=\"{}\"><time>{}</time></trkpt>\n",
+            place.lat,
+            place.lng,
+            rfc3339(place.entry.created_at)
+        ));
+    }
+    gpx.push_str("  </trkseg>\n</trk>\n</gpx>\n");
+    Ok(gpx)
+}
+
+/// The user's archived checkins as KML, a timestamped placemark per checkin.
+pub fn kml(db: &Database, user_key: &str) -> Result<String> {
+    let mut kml = String::from(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<kml xmlns="http://www.opengis.net/kml/2.2">
+<Document>
+<name>Checkins</name>
+"#,
+    );
+    for place in places(db, user_key)? {
+        let links = match &place.status_url {
+            Some(status_url) => format!(
+                r#"<a href="{}">Swarm</a> · <a href="{}">Mastodon</a>"#,
+                escape(&place.swarm_url),
+                escape(status_url)
+            ),
+            None => format!(r#"<a href="{}">Swarm</a>"#, escape(&place.swarm_url)),
+        };
+        // The description is HTML, and escaped once more to fit in the XML.
+        let description = format!(
+            "{}<br />{}",
+            escape(&place.description()).replace('\n', "<br />"),
+            links
+        );
+        kml.push_str(&format!(
+            r#"<Placemark>
+  <name>{name}</name>
+  <description>{description}</description>
+  <TimeStamp><when>{time}</when></TimeStamp>
+  <Point><coordinates>{lng},{lat}</coordinates></Point>
+</Placemark>
+"#,
+            name = escape(&place.entry.venue_name),
+            description = escape(&description),
+            time = rfc3339(place.entry.created_at),
+            lng = place.lng,
+            lat = place.lat,
+        ));
+    }
+    kml.push_str("</Document>\n</kml>\n");
+    Ok(kml)
+}
+
+fn download(content_type: &'static str, disposition: &'static str, body: String) -> Response {
+    (
+        [
+            (CONTENT_TYPE, content_type),
+            (CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+/// Downloads the user's archived checkins as GeoJSON.
+#[utoipa::path(
+    get,
+    path = "/export/geojson",
+    responses(
+        (status = 200, description = "A FeatureCollection with a Point per archived checkin", content_type = "application/geo+json"),
+        (status = 401, description = "Not logged in", body = crate::error::ErrorResponse),
+    ),
+    security(("session" = [])),
+)]
+pub async fn get_geojson(
+    State(state): State<Arc<AppState>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+) -> Result<Response, ApiError> {
+    let user_key = crate::cookie_user_key(&state, &cookie).map_err(ApiError::unauthorized)?;
+    let collection = geojson(&state.db, &user_key)?;
+    Ok(download(
+        "application/geo+json",
+        r#"attachment; filename="checkins.geojson""#,
+        collection.to_string(),
+    ))
+}
+
+/// Downloads the user's archived checkins as GPX.
+pub async fn get_gpx(
+    State(state): State<Arc<AppState>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+) -> Result<Response, String> {
+    let user_key = crate::cookie_user_key(&state, &cookie)?;
+    Ok(download(
+        "application/gpx+xml",
+        r#"attachment; filename="checkins.gpx""#,
+        gpx(&state.db, &user_key).from_err()?,
+    ))
+}
+
+/// Downloads the user's archived checkins as KML.
+pub async fn get_kml(
+    State(state): State<Arc<AppState>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+) -> Result<Response, String> {
+    let user_key = crate::cookie_user_key(&state, &cookie)?;
+    Ok(download(
+        "application/vnd.google-earth.kml+xml",
+        r#"attachment; filename="checkins.kml""#,
+        kml(&state.db, &user_key).from_err()?,
+    ))
+}
@@ -0,0 +1,399 @@
+//! Feeds of a user's checkin history, reachable without a session so
+//! calendar apps and feed readers can subscribe to them.
+//!
+//! The calendar is found through a secret token the user can replace. The
+//! Atom and RSS feeds only carry what was posted to Mastodon anyway, so they
+//! live under the user's public ID, signed so users can't be enumerated. The
+//! signature covers a secret of the user's too, which they can replace to
+//! revoke links they shared.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::extract::Path;
+use axum::extract::Query;
+use axum::extract::State;
+use axum::http::header::CACHE_CONTROL;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::header::ETAG;
+use axum::http::header::IF_NONE_MATCH;
+use axum::http::header::LAST_MODIFIED;
+use axum::http::HeaderMap;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use chrono::DateTime;
+use chrono::TimeZone;
+use chrono::Utc;
+use hmac::Hmac;
+use hmac::Mac;
+use sha2::Sha256;
+
+use crate::archive;
+use crate::html::escape;
+use crate::model::HistoryEntry;
+use crate::model::Profile;
+use crate::model::User;
+use crate::AppState;
+use crate::ResultExt;
+
+/// Escapes a value for an iCalendar TEXT property.
+fn ics_escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+        .replace('\r', "")
+}
+
+/// Folds a content line to at most 75 octets as required by RFC 5545.
+fn ics_fold(line: &str) -> String {
+    let mut folded = String::new();
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            width = 1;
+        }
+        folded.push(c);
+        width += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+fn ics_time(unix: u64) -> String {
+    Utc.timestamp_opt(unix as i64, 0)
+        .single()
+        .unwrap_or_default()
+        .format("%Y%m%dT%H%M%SZ")
+        .to_string()
+}
+
+/// An event at the venue for a checkin, linking to the status when
+/// `status_url` is given.
+fn ics_event(entry: &HistoryEntry, status_url: Option<String>) -> String {
+    let mut lines = vec![
+        "BEGIN:VEVENT".to_string(),
+        format!("UID:{}@swarmdon", entry.checkin_id),
+        format!("DTSTAMP:{}", ics_time(entry.created_at)),
+        format!("DTSTART:{}", ics_time(entry.created_at)),
+        "DURATION:PT30M".to_string(),
+        format!("SUMMARY:{}", ics_escape(&entry.venue_name)),
+    ];
+    let location = match &entry.location {
+        Some(location) => format!("{}, {}", entry.venue_name, location),
+        None => entry.venue_name.clone(),
+    };
+    lines.push(format!("LOCATION:{}", ics_escape(&location)));
+    if let (Some(lat), Some(lng)) = (entry.lat, entry.lng) {
+        lines.push(format!("GEO:{};{}", lat, lng));
+    }
+    if let Some(shout) = &entry.shout {
+        lines.push(format!("DESCRIPTION:{}", ics_escape(shout)));
+    }
+    if let Some(url) = status_url {
+        lines.push(format!("URL:{}", url));
+    }
+    lines.push("END:VEVENT".to_string());
+    lines.iter().map(|line| ics_fold(line)).collect()
+}
+
+/// The calendar of the given checkins. `status_url` links a posted status.
+pub fn ics(history: &[HistoryEntry], status_url: impl Fn(&str) -> String) -> String {
+    let mut calendar = String::new();
+    calendar.push_str(&ics_fold("BEGIN:VCALENDAR"));
+    calendar.push_str(&ics_fold("VERSION:2.0"));
+    calendar.push_str(&ics_fold("PRODID:-//swarmdon//checkins//EN"));
+    calendar.push_str(&ics_fold("X-WR-CALNAME:Checkins"));
+    for entry in history {
+        calendar.push_str(&ics_event(
+            entry,
+            entry.status_id.as_deref().map(&status_url),
+        ));
+    }
+    calendar.push_str(&ics_fold("END:VCALENDAR"));
+    calendar
+}
+
+/// The calendar feed of the user the token was issued to.
+async fn calendar(state: &AppState, token: &str) -> Result<Response, String> {
+    let Some(user_key) = state.db.get_feed_user(token).from_err()? else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    let Some(user) = state.db.get_user(&user_key).from_err()? else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    let profile = state
+        .db
+        .get_profile(&user_key)
+        .from_err()?
+        .unwrap_or_default();
+    // Feed URLs end up in calendar apps and may be shared by accident, so
+    // checkins the user kept private stay out.
+    let history = state
+        .db
+        .get_history(&user_key)
+        .from_err()?
+        .into_iter()
+        .filter(|entry| !entry.private)
+        .collect::<Vec<_>>();
+
+    Ok((
+        [(CONTENT_TYPE, "text/calendar; charset=utf-8")],
+        ics(&history, |status_id| {
+            archive::status_url(&user, &profile.mastodon_handle, status_id)
+        }),
+    )
+        .into_response())
+}
+
+/// Most entries in the Atom and RSS feeds.
+const FEED_LENGTH: usize = 50;
+
+/// Seconds feed readers may cache the Atom and RSS feeds.
+const FEED_MAX_AGE: u32 = 15 * 60;
+
+/// Signs the feed URLs of the user with the given public ID. Users who never
+/// replaced their feed links have no secret, which keeps links handed out
+/// before there was one working.
+fn feed_mac(signing_key: &[u8], id: &str, profile: &Profile) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(signing_key).expect("HMAC takes keys of any length");
+    mac.update(format!("feed:{}", id).as_bytes());
+    if let Some(secret) = &profile.news_feed_secret {
+        mac.update(format!(":{}", secret).as_bytes());
+    }
+    mac
+}
+
+/// Returns the Atom and RSS feed URLs of the user.
+pub fn news_feed_urls(state: &AppState, user_key: &str) -> anyhow::Result<(String, String)> {
+    let id = state.db.public_id(user_key)?;
+    let profile = state.db.get_profile(user_key)?.unwrap_or_default();
+    let signature = hex::encode(
+        feed_mac(&state.signing_key, &id, &profile)
+            .finalize()
+            .into_bytes(),
+    );
+    let url = |file| {
+        format!(
+            "{}/feeds/{}/{}?sig={}",
+            state.flags.base_url, id, file, signature
+        )
+    };
+    Ok((url("checkins.atom"), url("checkins.rss")))
+}
+
+fn datetime(unix: u64) -> DateTime<Utc> {
+    Utc.timestamp_opt(unix as i64, 0)
+        .single()
+        .unwrap_or_default()
+}
+
+/// A posted checkin, as listed in the Atom and RSS feeds.
+struct Item {
+    entry: HistoryEntry,
+    link: String,
+}
+
+fn atom(self_url: &str, title: &str, items: &[Item]) -> String {
+    let updated = items.first().map_or(0, |item| item.entry.created_at);
+    let mut feed = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+<id>{url}</id>
+<title>{title}</title>
+<link rel="self" href="{url}" />
+<updated>{updated}</updated>
+"#,
+        url = escape(self_url),
+        title = escape(title),
+        updated = datetime(updated).to_rfc3339(),
+    );
+    for item in items {
+        feed.push_str(&format!(
+            r#"<entry>
+  <id>{link}</id>
+  <title>{title}</title>
+  <link href="{link}" />
+  <published>{at}</published>
+  <updated>{at}</updated>
+  <content type="text">{content}</content>
+</entry>
+"#,
+            link = escape(&item.link),
+            title = escape(&item.entry.venue_name),
+            at = datetime(item.entry.created_at).to_rfc3339(),
+            content = escape(item.entry.shout.as_deref().unwrap_or_default()),
+        ));
+    }
+    feed.push_str("</feed>\n");
+    feed
+}
+
+fn rss(link: &str, title: &str, items: &[Item]) -> String {
+    let mut feed = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0">
+<channel>
+<title>{title}</title>
+<link>{link}</link>
+<description>{title}</description>
+<ttl>{ttl}</ttl>
+"#,
+        title = escape(title),
+        link = escape(link),
+        ttl = FEED_MAX_AGE / 60,
+    );
+    for item in items {
+        feed.push_str(&format!(
+            r#"<item>
+  <title>{title}</title>
+  <link>{link}</link>
+  <guid isPermaLink="true">{link}</guid>
+  <pubDate>{at}</pubDate>
+  <description>{description}</description>
+</item>
+"#,
+            title = escape(&item.entry.venue_name),
+            link = escape(&item.link),
+            at = datetime(item.entry.created_at).to_rfc2822(),
+            description = escape(item.entry.shout.as_deref().unwrap_or_default()),
+        ));
+    }
+    feed.push_str("</channel>\n</rss>\n");
+    feed
+}
+
+/// The user's most recent posted checkins, linking to their statuses.
+fn items(
+    state: &AppState,
+    user_key: &str,
+    user: &User,
+    profile: &Profile,
+) -> anyhow::Result<Vec<Item>> {
+    Ok(state
+        .db
+        .get_history(user_key)?
+        .into_iter()
+        .filter(|entry| !entry.private)
+        .filter_map(|entry| {
+            let link =
+                archive::status_url(user, &profile.mastodon_handle, entry.status_id.as_deref()?);
+            Some(Item { entry, link })
+        })
+        .take(FEED_LENGTH)
+        .collect())
+}
+
+/// The Atom or RSS feed of the user with the given public ID, answering
+/// conditional requests with 304 while nothing new was posted.
+async fn news_feed(
+    state: &AppState,
+    id: &str,
+    file: &str,
+    params: &HashMap<String, String>,
+    headers: &HeaderMap,
+) -> Result<Response, String> {
+    let Some(user_key) = state.db.resolve_public_id(id).from_err()? else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    let profile = state
+        .db
+        .get_profile(&user_key)
+        .from_err()?
+        .unwrap_or_default();
+    let valid = params
+        .get("sig")
+        .and_then(|signature| hex::decode(signature).ok())
+        .map_or(false, |signature| {
+            feed_mac(&state.signing_key, id, &profile)
+                .verify_slice(&signature)
+                .is_ok()
+        });
+    if !valid {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+    let Some(user) = state.db.get_user(&user_key).from_err()? else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    let items = items(state, &user_key, &user, &profile).from_err()?;
+
+    let latest = items.first().map_or(0, |item| item.entry.created_at);
+    let etag = format!(
+        "\"{}-{}\"",
+        latest,
+        items
+            .first()
+            .and_then(|item| item.entry.status_id.as_deref())
+            .unwrap_or_default()
+    );
+    let cache_headers = [
+        (CACHE_CONTROL, format!("public, max-age={}", FEED_MAX_AGE)),
+        (ETAG, etag.clone()),
+        (
+            LAST_MODIFIED,
+            datetime(latest)
+                .format("%a, %d %b %Y %H:%M:%S GMT")
+                .to_string(),
+        ),
+    ];
+    let unchanged = headers
+        .get(IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .map_or(false, |value| {
+            value.split(',').any(|tag| tag.trim() == etag)
+        });
+    if unchanged {
+        return Ok((StatusCode::NOT_MODIFIED, cache_headers).into_response());
+    }
+
+    let title = format!("Checkins of {}", profile.mastodon_handle);
+    let (atom_url, _) = news_feed_urls(state, &user_key).from_err()?;
+    let (content_type, body) = match file {
+        "checkins.atom" => (
+            "application/atom+xml; charset=utf-8",
+            atom(&atom_url, &title, &items),
+        ),
+        _ => (
+            "application/rss+xml; charset=utf-8",
+            rss(&state.flags.base_url, &title, &items),
+        ),
+    };
+    Ok((
+        cache_headers,
+        [(CONTENT_TYPE, content_type.to_string())],
+        body,
+    )
+        .into_response())
+}
+
+/// Serves `/feeds/<token>/<file>`, where the token is the calendar token or,
+/// for Atom and RSS, the user's public ID.
+pub async fn get_feed(
+    State(state): State<Arc<AppState>>,
+    Path((token, file)): Path<(String, String)>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Result<Response, String> {
+    match file.as_str() {
+        "checkins.ics" => calendar(&state, &token).await,
+        "checkins.atom" | "checkins.rss" => {
+            news_feed(&state, &token, &file, &params, &headers).await
+        }
+        _ => Ok(StatusCode::NOT_FOUND.into_response()),
+    }
+}
+
+/// Serves calendar links handed out as `/feeds/<token>.ics` before feeds
+/// got a directory per token.
+pub async fn get_legacy_feed(
+    State(state): State<Arc<AppState>>,
+    Path(file): Path<String>,
+) -> Result<Response, String> {
+    match file.strip_suffix(".ics") {
+        Some(token) => calendar(&state, token).await,
+        None => Ok(StatusCode::NOT_FOUND.into_response()),
+    }
+}
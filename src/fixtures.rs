@@ -0,0 +1,109 @@
+//! Development helper that records Swarm API responses to disk.
+//!
+//! Only one copy of each distinct response shape is written, with values
+//! scrubbed, so the directory grows into a small corpus of real-world
+//! payloads for exercising the decoders.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::path::PathBuf;
+
+use once_cell::sync::OnceCell;
+use serde_json::Map;
+use serde_json::Value;
+
+static DIRECTORY: OnceCell<PathBuf> = OnceCell::new();
+
+/// Enables recording into `directory`.
+pub fn init(directory: PathBuf) -> std::io::Result<()> {
+    std::fs::create_dir_all(&directory)?;
+    let _ = DIRECTORY.set(directory);
+    Ok(())
+}
+
+pub fn enabled() -> bool {
+    DIRECTORY.get().is_some()
+}
+
+/// Describes the structure of a value: object keys, element shapes and leaf
+/// types, without the values themselves.
+fn shape(value: &Value) -> Value {
+    match value {
+        Value::Null => Value::from("null"),
+        Value::Bool(_) => Value::from("bool"),
+        Value::Number(n) if n.is_f64() => Value::from("float"),
+        Value::Number(_) => Value::from("integer"),
+        Value::String(_) => Value::from("string"),
+        Value::Array(items) => {
+            let mut shapes: Vec<Value> = Vec::new();
+            for item in items.iter().map(shape) {
+                if !shapes.contains(&item) {
+                    shapes.push(item);
+                }
+            }
+            Value::Array(shapes)
+        }
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(key, value)| (key.clone(), shape(value)))
+                .collect(),
+        ),
+    }
+}
+
+/// Replaces anything that could identify a user while keeping types intact.
+fn sanitize(value: &Value) -> Value {
+    match value {
+        Value::String(_) => Value::from("redacted"),
+        Value::Number(n) if n.is_f64() => Value::from(0.0),
+        Value::Array(items) => Value::Array(items.iter().map(sanitize).collect()),
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(key, value)| (key.clone(), sanitize(value)))
+                .collect::<Map<_, _>>(),
+        ),
+        other => other.clone(),
+    }
+}
+
+/// Records `response` from the Swarm API `method` if recording is enabled and
+/// its shape hasn't been seen before.
+pub fn record(method: &str, response: &Value) {
+    let Some(directory) = DIRECTORY.get() else {
+        return;
+    };
+
+    let mut hasher = DefaultHasher::new();
+    shape(response).to_string().hash(&mut hasher);
+    // Object IDs in the path would both leak and defeat deduplication.
+    let name = method
+        .trim_matches('/')
+        .split('/')
+        .map(|segment| {
+            if segment.len() >= 12 && segment.chars().all(|c| c.is_ascii_hexdigit()) {
+                "id".to_string()
+            } else {
+                segment
+                    .chars()
+                    .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                    .collect()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("_");
+    let path = directory.join(format!("{}-{:016x}.json", name, hasher.finish()));
+    if path.exists() {
+        return;
+    }
+
+    let result = serde_json::to_vec_pretty(&sanitize(response))
+        .map_err(std::io::Error::from)
+        .and_then(|contents| std::fs::write(&path, contents));
+    match result {
+        Ok(()) => tracing::info!(path=%path.display(), "recorded new Swarm response shape"),
+        Err(e) => tracing::warn!(?e, path=%path.display(), "unable to record fixture"),
+    }
+}
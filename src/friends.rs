@@ -0,0 +1,328 @@
+use std::collections::BTreeMap;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::headers::Cookie;
+use axum::response::Html;
+use axum::response::Redirect;
+use axum::Form;
+use axum::TypedHeader;
+use mastodon_async::Mastodon;
+use serde::Deserialize;
+use url::Url;
+
+use crate::html::escape;
+use crate::html::page;
+use crate::links;
+use crate::links::Action;
+use crate::model::Friends;
+use crate::model::UserSettings;
+use crate::reconnect;
+use crate::swarm::SwarmCheckin;
+use crate::swarm::SwarmCheckinDetail;
+use crate::AppState;
+use crate::ResultExt;
+
+/// How a friend is identified on Swarm.
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum FriendKind {
+    Handle,
+    Name,
+    Id,
+}
+
+impl FriendKind {
+    fn label(self) -> &'static str {
+        match self {
+            FriendKind::Handle => "handle",
+            FriendKind::Name => "name",
+            FriendKind::Id => "id",
+        }
+    }
+
+    fn map(self, friends: &mut Friends) -> &mut BTreeMap<String, String> {
+        match self {
+            FriendKind::Handle => &mut friends.by_handle,
+            FriendKind::Name => &mut friends.by_name,
+            FriendKind::Id => &mut friends.by_id,
+        }
+    }
+
+    fn normalize(self, friend: &str) -> String {
+        match self {
+            FriendKind::Handle => friend.trim().trim_start_matches('@').to_lowercase(),
+            FriendKind::Name => friend
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ")
+                .to_lowercase(),
+            FriendKind::Id => friend.trim().to_string(),
+        }
+    }
+}
+
+/// Whether `acct` can be found from the poster's instance.
+async fn resolves(mastodon: &Mastodon, acct: &str) -> mastodon_async::Result<bool> {
+    let host = Url::parse(&mastodon.data.base)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+        .unwrap_or_default();
+    let results = mastodon.search_v2(acct, true).await?;
+    // Accounts local to the poster's instance are returned without a domain.
+    Ok(results.accounts.iter().any(|account| {
+        let found = if account.acct.contains('@') {
+            account.acct.clone()
+        } else {
+            format!("{}@{}", account.acct, host)
+        };
+        found.eq_ignore_ascii_case(acct) || account.acct.eq_ignore_ascii_case(acct)
+    }))
+}
+
+/// Looks up the accounts mapped for the checkin's companions on the poster's
+/// instance, and for the friends who were there at the same time if the user
+/// mentions those. Returns the friends whose accounts resolved, along with
+/// the accounts that didn't so they can be reported to the user. With
+/// `mention_consent` set, friends who didn't approve are left out silently.
+pub async fn verify_mentions(
+    mastodon: &Mastodon,
+    settings: &UserSettings,
+    checkin: &SwarmCheckin,
+    details: &SwarmCheckinDetail,
+    friends: &Friends,
+) -> (Friends, Vec<String>) {
+    let overlaps = details
+        .overlapping_users()
+        .filter(|_| settings.mention_overlaps);
+    let mut verified = HashSet::new();
+    let mut unresolved = Vec::new();
+    for acct in checkin
+        .with
+        .iter()
+        .chain(overlaps)
+        .filter_map(|friend| friends.lookup(friend))
+        .filter(|acct| !settings.mention_consent || friends.approved.contains(&acct.to_lowercase()))
+    {
+        if verified.contains(acct) || unresolved.contains(acct) {
+            continue;
+        }
+        match resolves(mastodon, acct).await {
+            Ok(true) => {
+                verified.insert(acct.clone());
+            }
+            Ok(false) => unresolved.push(acct.clone()),
+            Err(e) => {
+                tracing::warn!(?e, %acct, "unable to look up mention");
+                unresolved.push(acct.clone());
+            }
+        }
+    }
+
+    let mut friends = friends.clone();
+    for map in [
+        &mut friends.by_handle,
+        &mut friends.by_name,
+        &mut friends.by_id,
+    ] {
+        map.retain(|_, acct| verified.contains(acct));
+    }
+    (friends, unresolved)
+}
+
+pub async fn get_friends(
+    State(state): State<Arc<AppState>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+) -> Result<Html<String>, String> {
+    let user_key = crate::cookie_user_key(&state, &cookie)?;
+    let friends = state.db.get_friends(&user_key).from_err()?;
+    let settings = state.db.get_settings(&user_key).from_err()?;
+
+    let rows = [
+        (FriendKind::Handle, &friends.by_handle),
+        (FriendKind::Name, &friends.by_name),
+        (FriendKind::Id, &friends.by_id),
+    ]
+    .into_iter()
+    .flat_map(|(kind, map)| map.iter().map(move |entry| (kind, entry)))
+    .map(|(kind, (friend, acct))| {
+        let approval = if friends.approved.contains(&acct.to_lowercase()) {
+            "Approved".to_string()
+        } else {
+            format!(
+                r#"<form action="/account/friends/ask" method="POST"><input type="hidden" name="acct" value="{}" /><button type="submit">Ask to approve</button></form>"#,
+                escape(acct)
+            )
+        };
+        format!(
+            r#"<tr><td>{friend}</td><td>{label}</td><td>@{acct}</td><td>{approval}</td><td><form action="/account/friends/remove" method="POST"><input type="hidden" name="kind" value="{label}" /><input type="hidden" name="friend" value="{friend}" /><button type="submit">Remove</button></form></td></tr>"#,
+            friend = escape(friend),
+            label = kind.label(),
+            acct = escape(acct),
+        )
+    })
+    .collect::<Vec<_>>()
+    .join("\n");
+
+    Ok(page(
+        "Friends",
+        &format!(
+            r#"<h1>Friends</h1>
+<p>When you check in with one of these Swarm friends, they are mentioned on Mastodon. Friends not listed here, or whose account cannot be found from your instance, are left out of the post. Friends without a Swarm handle can be matched by their full name as shown on Swarm.</p>
+<form action="/account/friends/consent" method="POST">
+    <label><input type="checkbox" name="mention_consent" value="yes" {mention_consent} /> Only mention friends who approved it. Asking sends them a direct message from your account with a link to approve, which has them log in to Mastodon to confirm it's them.</label>
+    <button type="submit">Save</button>
+</form>
+<table>
+<tr><th>Swarm friend</th><th>Matched by</th><th>Mastodon account</th><th>Mentions</th><th></th></tr>
+{rows}
+</table>
+<form action="/account/friends" method="POST">
+    <select name="kind">
+        <option value="handle">Swarm handle</option>
+        <option value="name">Full name</option>
+        <option value="id">Swarm user ID</option>
+    </select>
+    <input type="text" name="friend" placeholder="Swarm friend" required />
+    <input type="text" name="acct" placeholder="user@instance" required />
+    <button type="submit">Save</button>
+</form>
+<p><a href="/account">Back to your account</a></p>"#,
+            mention_consent = if settings.mention_consent {
+                "checked"
+            } else {
+                ""
+            },
+        ),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct FriendForm {
+    kind: FriendKind,
+    friend: String,
+    acct: String,
+}
+
+pub async fn post_friend(
+    State(state): State<Arc<AppState>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Form(form): Form<FriendForm>,
+) -> Result<Redirect, String> {
+    let user_key = crate::cookie_user_key(&state, &cookie)?;
+    let friend = form.kind.normalize(&form.friend);
+    let acct = form.acct.trim().trim_start_matches('@');
+    if friend.is_empty() || acct.is_empty() || acct.contains(char::is_whitespace) {
+        return Err("invalid friend".into());
+    }
+
+    let mut friends = state.db.get_friends(&user_key).from_err()?;
+    form.kind.map(&mut friends).insert(friend, acct.to_string());
+    state.db.save_friends(&user_key, &friends).from_err()?;
+    Ok(Redirect::to("/account/friends"))
+}
+
+#[derive(Deserialize)]
+pub struct RemoveFriendForm {
+    kind: FriendKind,
+    friend: String,
+}
+
+pub async fn post_remove_friend(
+    State(state): State<Arc<AppState>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Form(form): Form<RemoveFriendForm>,
+) -> Result<Redirect, String> {
+    let user_key = crate::cookie_user_key(&state, &cookie)?;
+    let mut friends = state.db.get_friends(&user_key).from_err()?;
+    form.kind
+        .map(&mut friends)
+        .remove(&form.kind.normalize(&form.friend));
+    // An approval goes with the last friend mapped to the account.
+    let mapped = friends
+        .by_handle
+        .values()
+        .chain(friends.by_name.values())
+        .chain(friends.by_id.values())
+        .map(|acct| acct.to_lowercase())
+        .collect::<HashSet<_>>();
+    friends.approved.retain(|acct| mapped.contains(acct));
+    state.db.save_friends(&user_key, &friends).from_err()?;
+    Ok(Redirect::to("/account/friends"))
+}
+
+#[derive(Deserialize)]
+pub struct ConsentForm {
+    mention_consent: Option<String>,
+}
+
+pub async fn post_consent(
+    State(state): State<Arc<AppState>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Form(form): Form<ConsentForm>,
+) -> Result<Redirect, String> {
+    let user_key = crate::cookie_user_key(&state, &cookie)?;
+    let mut settings = state.db.get_settings(&user_key).from_err()?;
+    settings.mention_consent = form.mention_consent.is_some();
+    state.db.save_settings(&user_key, &settings).from_err()?;
+    Ok(Redirect::to("/account/friends"))
+}
+
+#[derive(Deserialize)]
+pub struct AskForm {
+    acct: String,
+}
+
+/// Sends a friend a direct message from the user's account with a link to
+/// approve being mentioned.
+pub async fn post_ask(
+    State(state): State<Arc<AppState>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Form(form): Form<AskForm>,
+) -> Result<Html<String>, String> {
+    let user_key = crate::cookie_user_key(&state, &cookie)?;
+    let Some(user) = state.db.get_user(&user_key).from_err()? else {
+        return Err("invalid user".into());
+    };
+    let friends = state.db.get_friends(&user_key).from_err()?;
+    let settings = state.db.get_settings(&user_key).from_err()?;
+    let profile = state
+        .db
+        .get_profile(&user_key)
+        .from_err()?
+        .unwrap_or_default();
+    let Some(acct) = friends
+        .by_handle
+        .values()
+        .chain(friends.by_name.values())
+        .chain(friends.by_id.values())
+        .find(|acct| acct.eq_ignore_ascii_case(&form.acct))
+    else {
+        return Err("not in your friends list".into());
+    };
+
+    let text = format!(
+        "@{} @{} would like to mention you when you check in together on Swarm. Approve it here: {}",
+        acct,
+        profile.mastodon_handle,
+        links::url(&state, Action::ApproveMention, &user_key, acct).from_err()?
+    );
+    reconnect::send(
+        &user.mastodon.base,
+        &user.mastodon.token,
+        settings.mastodon_proxy.as_deref(),
+        &text,
+    )
+    .await
+    .from_err()?;
+    Ok(page(
+        "Friends",
+        &format!(
+            r#"<h1>Approval requested</h1>
+<p>@{} was sent a direct message with a link to approve being mentioned.</p>
+<p><a href="/account/friends">Back to your friends</a></p>"#,
+            escape(acct)
+        ),
+    ))
+}
@@ -0,0 +1,159 @@
+//! Local hooks telling automations on the operator's own machine about
+//! posted statuses, without running a webhook receiver.
+//!
+//! `--post-hook-command` is run through `sh -c` with the event as JSON on its
+//! standard input, and `--post-hook-file` gets the event appended as a line,
+//! which also works for a named pipe. Hooks run one at a time on a thread of
+//! their own, in the order statuses were posted.
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::process::Command;
+use std::process::Stdio;
+use std::sync::mpsc;
+use std::sync::Mutex;
+
+use mastodon_async::entities::status::Status;
+use serde::Serialize;
+use serde_json::json;
+use serde_json::Value;
+
+use crate::model::unix_now;
+use crate::swarm::SwarmCheckin;
+use crate::AppState;
+
+#[derive(Serialize)]
+struct Event<'a> {
+    event: &'static str,
+    /// Opaque ID of the user, see `Database::public_id`
+    user: String,
+    checkin_id: &'a str,
+    /// The checkin as received from Swarm, when it's known
+    checkin: Option<Value>,
+    status: Value,
+    at: u64,
+}
+
+/// Where events go, as configured by the operator.
+struct Targets {
+    command: Option<String>,
+    file: Option<String>,
+}
+
+/// Sends events to the hook thread, started with the first event.
+#[derive(Default)]
+pub struct PostHooks {
+    sender: Mutex<Option<mpsc::Sender<Vec<u8>>>>,
+}
+
+fn run_command(command: &str, line: &[u8]) -> anyhow::Result<()> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(line)?;
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        anyhow::bail!("hook command exited with {}", status);
+    }
+    Ok(())
+}
+
+fn append(path: &str, line: &[u8]) -> anyhow::Result<()> {
+    // Opening a named pipe waits for a reader, so this is done per event
+    // rather than keeping a reader from ever seeing the end of the stream.
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(line)?;
+    Ok(())
+}
+
+fn worker(targets: Targets, receiver: mpsc::Receiver<Vec<u8>>) {
+    for line in receiver {
+        if let Some(command) = &targets.command {
+            if let Err(e) = run_command(command, &line) {
+                tracing::warn!(?e, "post hook command failed");
+            }
+        }
+        if let Some(file) = &targets.file {
+            if let Err(e) = append(file, &line) {
+                tracing::warn!(?e, "unable to write post hook file");
+            }
+        }
+    }
+}
+
+impl PostHooks {
+    fn send(&self, targets: Targets, line: Vec<u8>) {
+        let mut sender = self.sender.lock().expect("post hooks lock is poisoned");
+        let sender = sender.get_or_insert_with(|| {
+            let (sender, receiver) = mpsc::channel();
+            std::thread::spawn(move || worker(targets, receiver));
+            sender
+        });
+        if sender.send(line).is_err() {
+            tracing::warn!("post hook thread is gone, dropping event");
+        }
+    }
+}
+
+/// Hands a `status.posted` event to the hooks, if any are configured.
+/// `checkin` is looked up in the archive when not given.
+pub fn status_posted(
+    state: &AppState,
+    user_key: &str,
+    checkin_id: &str,
+    checkin: Option<&SwarmCheckin>,
+    text: &str,
+    posted: &Status,
+) {
+    let targets = Targets {
+        command: state.flags.post_hook_command.clone(),
+        file: state.flags.post_hook_file.clone(),
+    };
+    if targets.command.is_none() && targets.file.is_none() {
+        return;
+    }
+    let user = match state.db.public_id(user_key) {
+        Ok(user) => user,
+        Err(e) => {
+            tracing::warn!(?e, "unable to look up public user ID for post hook");
+            return;
+        }
+    };
+    let checkin = match checkin.and_then(|checkin| checkin.raw.as_ref()) {
+        Some(raw) => Some((*raw.0).clone()),
+        None => state
+            .db
+            .get_archived_checkin(user_key, checkin_id)
+            .unwrap_or_else(|e| {
+                tracing::warn!(?e, "unable to look up archived checkin for post hook");
+                None
+            })
+            .and_then(|entry| entry.raw),
+    };
+    let event = Event {
+        event: "status.posted",
+        user,
+        checkin_id,
+        checkin,
+        status: json!({
+            "id": posted.id.to_string(),
+            "url": posted.url,
+            "text": text,
+        }),
+        at: unix_now(),
+    };
+    match serde_json::to_vec(&event) {
+        Ok(mut line) => {
+            line.push(b'\n');
+            state.post_hooks.send(targets, line);
+        }
+        Err(e) => tracing::warn!(?e, "unable to encode post hook event"),
+    }
+}
@@ -0,0 +1,108 @@
+//! Detects requests reaching the service under a different host than
+//! `--base-url`.
+//!
+//! OAuth callbacks always go to `base_url`, so when users arrive through
+//! another address the cookies set on the first visit are missing on the
+//! callback and logging in fails with unhelpful errors.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use axum::extract::State;
+use axum::http::header::HOST;
+use axum::http::Method;
+use axum::http::Request;
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use url::Url;
+
+use crate::html::escape;
+use crate::html::page;
+use crate::AppState;
+
+/// Remembers which mismatching hosts were already reported so the log isn't
+/// flooded.
+#[derive(Default)]
+pub struct HostCheck {
+    warned: Mutex<HashSet<String>>,
+}
+
+impl HostCheck {
+    fn first_seen(&self, host: &str) -> bool {
+        self.warned.lock().unwrap().insert(host.to_string())
+    }
+}
+
+/// Host and port from `base_url`, in the form used by the `Host` header.
+fn expected_host(base_url: &str) -> Option<String> {
+    let url = Url::parse(base_url).ok()?;
+    let host = url.host_str()?.to_ascii_lowercase();
+    Some(match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host,
+    })
+}
+
+fn request_host<B>(request: &Request<B>) -> Option<String> {
+    // Behind a reverse proxy the original host is forwarded separately.
+    let host = request
+        .headers()
+        .get("x-forwarded-host")
+        .or_else(|| request.headers().get(HOST))?
+        .to_str()
+        .ok()?
+        .split(',')
+        .next()?
+        .trim()
+        .to_ascii_lowercase();
+    let host = host
+        .strip_suffix(":443")
+        .or_else(|| host.strip_suffix(":80"))
+        .unwrap_or(&host);
+    Some(host.to_string())
+}
+
+pub async fn check_host<B>(
+    State(state): State<Arc<AppState>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let (Some(expected), Some(actual)) =
+        (expected_host(&state.flags.base_url), request_host(&request))
+    else {
+        return next.run(request).await;
+    };
+    if expected == actual {
+        return next.run(request).await;
+    }
+
+    if state.host_check.first_seen(&actual) {
+        tracing::warn!(
+            host = %actual,
+            base_url = %state.flags.base_url,
+            "request reached the service under a host that doesn't match --base-url, OAuth callbacks will go to base_url instead"
+        );
+    }
+
+    // Logging in can't work from here, so explain why instead of letting the
+    // user run into a failing callback. Everything else keeps working.
+    if request.method() == Method::GET && request.uri().path() == "/" {
+        let body = format!(
+            r#"<h1>Setup problem</h1>
+<p>This page was opened as <code>{actual}</code>, but the service is configured with the base URL <code>{base_url}</code>.</p>
+<p>Logging in only works through the configured address. If you run this service, set <code>--base-url</code> to the public address it is reached at, and make sure your reverse proxy forwards the <code>Host</code> or <code>X-Forwarded-Host</code> header.</p>"#,
+            actual = escape(&actual),
+            base_url = escape(&state.flags.base_url),
+        );
+        return (
+            StatusCode::MISDIRECTED_REQUEST,
+            page("Setup problem", &body),
+        )
+            .into_response();
+    }
+
+    next.run(request).await
+}
@@ -0,0 +1,140 @@
+//! Friends using the same instance who tag each other would otherwise both
+//! post nearly identical statuses. Users can leave posting to the friend who
+//! made the checkin, optionally boosting their status.
+
+use anyhow::Result;
+use mastodon_async::entities::status::Status;
+
+use crate::model::HouseholdMode;
+use crate::model::User;
+use crate::model::UserSettings;
+use crate::schedule;
+use crate::swarm::SwarmCheckin;
+use crate::AppState;
+
+fn user_key_for(state: &AppState, swarm_id: &str) -> Option<String> {
+    match state.db.swarm_mapping.get(swarm_id) {
+        Ok(user_key) => user_key.map(|user_key| String::from_utf8_lossy(&user_key).into_owned()),
+        Err(e) => {
+            tracing::warn!(?e, "unable to look up Swarm user");
+            None
+        }
+    }
+}
+
+/// Whether the creator of the checkin is going to post it, as far as can be
+/// told before they do. Errs on the side of posting twice rather than not
+/// at all, e.g. when a daily cap may or may not have room left.
+fn creator_posts(state: &AppState, creator_key: &str, checkin: &SwarmCheckin) -> Result<bool> {
+    let Some(creator) = state.db.get_user(creator_key)? else {
+        return Ok(false);
+    };
+    let settings = state.db.get_settings(creator_key)?;
+    let status = state.db.get_user_status(creator_key)?;
+    if creator.swarm_access_token.is_empty()
+        || status.suspended.is_some()
+        || status.swarm_token_dead_at.is_some()
+        || crate::skip_reason(&settings, checkin).is_some()
+    {
+        return Ok(false);
+    }
+    Ok(match settings.daily_cap {
+        Some(cap) => {
+            let day = schedule::local_day(&settings, checkin.created_at);
+            state.db.get_daily_posts(creator_key, &day)? < cap
+        }
+        None => true,
+    })
+}
+
+/// Whether the checkin was made by another user of this instance tagging
+/// the user, and the user leaves posting to them. Only when the creator is
+/// going to post it, so the checkin isn't left unposted.
+pub fn is_duplicate(
+    state: &AppState,
+    user: &User,
+    settings: &UserSettings,
+    checkin: &SwarmCheckin,
+) -> bool {
+    if settings.household == HouseholdMode::Post {
+        return false;
+    }
+    match &checkin.created_by {
+        Some(creator) if creator.id != user.swarm_id => {
+            let Some(creator_key) = user_key_for(state, &creator.id) else {
+                return false;
+            };
+            creator_posts(state, &creator_key, checkin).unwrap_or_else(|e| {
+                tracing::warn!(?e, creator=%creator_key, "unable to tell whether the creator posts");
+                false
+            })
+        }
+        _ => false,
+    }
+}
+
+/// Boosts a freshly posted status as each tagged friend who asked for it,
+/// given their Swarm IDs. Status IDs are only meaningful on the instance
+/// that issued them, so friends on another instance are left alone.
+pub async fn boost(
+    state: &AppState,
+    user_key: &str,
+    user: &User,
+    checkin_id: &str,
+    tagged: &[String],
+    posted: &Status,
+) {
+    for friend_id in tagged {
+        let Some(friend_key) = user_key_for(state, friend_id) else {
+            continue;
+        };
+        if friend_key == user_key {
+            continue;
+        }
+        let (friend_user, settings) = match (
+            state.db.get_user(&friend_key),
+            state.db.get_settings(&friend_key),
+        ) {
+            (Ok(Some(friend_user)), Ok(settings)) => (friend_user, settings),
+            (Err(e), _) | (_, Err(e)) => {
+                tracing::warn!(?e, friend=%friend_key, "unable to load tagged friend");
+                continue;
+            }
+            (Ok(None), _) => continue,
+        };
+        if settings.disabled
+            || settings.household != HouseholdMode::Boost
+            || friend_user.mastodon.base != user.mastodon.base
+        {
+            continue;
+        }
+
+        let client = match state.mastodon_clients.get(
+            &friend_key,
+            &friend_user,
+            settings.mastodon_proxy.as_deref(),
+        ) {
+            Ok(client) => client,
+            Err(e) => {
+                tracing::warn!(?e, friend=%friend_key, "unable to build Mastodon client");
+                continue;
+            }
+        };
+        match client.reblog(&posted.id).await {
+            Ok(_) => {
+                tracing::info!(checkin=%checkin_id, friend=%friend_key, "boosted status for tagged friend")
+            }
+            Err(e) => {
+                tracing::warn!(?e, friend=%friend_key, "unable to boost status");
+                crate::record_error(
+                    state,
+                    &friend_key,
+                    format!(
+                        "unable to boost the status of a friend who tagged you: {}",
+                        e
+                    ),
+                );
+            }
+        }
+    }
+}
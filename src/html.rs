@@ -0,0 +1,53 @@
+use axum::response::Html;
+
+use crate::model::unix_now;
+
+pub fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Wraps `body` in the common page skeleton. `body` is inserted verbatim and
+/// must already be escaped.
+pub fn page(title: &str, body: &str) -> Html<String> {
+    Html(format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+    <title>{} - Swarm to Mastodon Sync</title>
+</head>
+<body>
+{}
+</body>
+</html>"#,
+        escape(title),
+        body
+    ))
+}
+
+/// Formats a unix timestamp relative to now, e.g. "5 minutes ago".
+pub fn ago(at: u64) -> String {
+    let elapsed = unix_now().saturating_sub(at);
+    let (value, unit) = match elapsed {
+        0..=59 => return "just now".to_string(),
+        60..=3599 => (elapsed / 60, "minute"),
+        3600..=86399 => (elapsed / 3600, "hour"),
+        _ => (elapsed / 86400, "day"),
+    };
+    format!(
+        "{} {}{} ago",
+        value,
+        unit,
+        if value == 1 { "" } else { "s" }
+    )
+}
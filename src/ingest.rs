@@ -0,0 +1,284 @@
+//! Location updates from OwnTracks and Overland, posted like checkins for
+//! users who no longer check in on Swarm.
+//!
+//! Only arrivals are posted: OwnTracks entering one of the user's regions,
+//! and visits recorded by Overland. An arrival close to a venue the user
+//! checked in at before takes that venue's details from the archive.
+//! OwnTracks names the region, so arrivals elsewhere are posted under that
+//! name, while Overland visits at unknown places are left out. Ingested
+//! checkins skip the Swarm lookups and link to OpenStreetMap.
+
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::Extension;
+use axum::Json;
+use chrono::DateTime;
+use serde::Deserialize;
+use serde_json::json;
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use crate::error::ApiError;
+use crate::model::unix_now;
+use crate::recap;
+use crate::status;
+use crate::swarm::SwarmCheckin;
+use crate::swarm::SwarmCheckinDetail;
+use crate::tokens::ApiUser;
+use crate::AppState;
+
+/// `type` of ingested checkins, telling them apart from Swarm's.
+const INGESTED_TYPE: &str = "ingested";
+
+/// Kilometers within which an arrival is taken to be at a known venue.
+const MATCH_RADIUS: f64 = 0.1;
+
+/// Arrivals older than this are not posted, e.g. from a phone that was
+/// offline for days.
+const MAX_AGE: u64 = 24 * 60 * 60;
+
+/// A message OwnTracks publishes over HTTP. Only the fields needed here.
+#[derive(Deserialize, ToSchema)]
+pub struct OwnTracksMessage {
+    #[serde(rename = "_type")]
+    kind: String,
+    #[serde(default)]
+    event: Option<String>,
+    /// Name of the region
+    #[serde(default)]
+    desc: Option<String>,
+    #[serde(default)]
+    lat: Option<f64>,
+    #[serde(default)]
+    lon: Option<f64>,
+    #[serde(default)]
+    tst: Option<u64>,
+    /// Tracker ID, set by the user in the app
+    #[serde(default)]
+    tid: Option<String>,
+}
+
+/// A batch of locations sent by Overland.
+#[derive(Deserialize, ToSchema)]
+pub struct OverlandBatch {
+    #[serde(default)]
+    locations: Vec<OverlandFeature>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct OverlandFeature {
+    geometry: OverlandPoint,
+    properties: OverlandProperties,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct OverlandPoint {
+    /// Longitude first, as in GeoJSON
+    #[schema(value_type = Vec<f64>)]
+    coordinates: (f64, f64),
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct OverlandProperties {
+    /// `visit` for visits, unset for plain locations
+    #[serde(rename = "type", default)]
+    kind: Option<String>,
+    #[serde(default)]
+    arrival_date: Option<String>,
+    #[serde(default)]
+    device_id: Option<String>,
+}
+
+/// An arrival somewhere, from either app.
+struct Arrival {
+    id: String,
+    at: u64,
+    coordinates: (f64, f64),
+    /// Name given by the app, if any
+    name: Option<String>,
+}
+
+/// Whether a checkin came through here rather than from Swarm.
+pub fn is_ingested(checkin: &SwarmCheckin) -> bool {
+    checkin.r#type == INGESTED_TYPE
+}
+
+/// Stands in for the details Swarm would have for a checkin, linking to the
+/// place on OpenStreetMap.
+pub fn details(checkin: &SwarmCheckin) -> SwarmCheckinDetail {
+    SwarmCheckinDetail {
+        basic: checkin.clone(),
+        checkin_short_url: checkin
+            .venue
+            .location
+            .coordinates()
+            .map(status::osm_url)
+            .unwrap_or_default(),
+        overlaps: None,
+    }
+}
+
+/// The venue of the user's archived checkin closest to `coordinates`, as
+/// received from Swarm, if one is close enough.
+fn known_venue(state: &AppState, user_key: &str, coordinates: (f64, f64)) -> Option<Value> {
+    let entries = match state.db.get_archive(user_key, None, usize::MAX) {
+        Ok(entries) => entries,
+        Err(e) => {
+            tracing::warn!(?e, "unable to read archive for ingested location");
+            return None;
+        }
+    };
+    entries
+        .into_iter()
+        .filter_map(|entry| {
+            let distance = recap::distance(entry.coordinates()?, coordinates);
+            let venue = entry.raw?.get("venue")?.clone();
+            Some((distance, venue))
+        })
+        .filter(|(distance, _)| *distance <= MATCH_RADIUS)
+        .min_by(|(a, _), (b, _)| a.total_cmp(b))
+        .map(|(_, venue)| venue)
+}
+
+/// Turns an arrival into a checkin, or `None` when it isn't at a place that
+/// can be named.
+fn checkin(state: &AppState, user_key: &str, arrival: Arrival) -> Option<SwarmCheckin> {
+    let (lat, lng) = arrival.coordinates;
+    let venue = match (
+        known_venue(state, user_key, arrival.coordinates),
+        arrival.name,
+    ) {
+        (Some(venue), _) => venue,
+        (None, Some(name)) => json!({
+            "id": format!("place:{}", name.to_lowercase()),
+            "name": name,
+            "location": { "lat": lat, "lng": lng },
+        }),
+        (None, None) => return None,
+    };
+    let value = json!({
+        "id": arrival.id,
+        "type": INGESTED_TYPE,
+        "createdAt": arrival.at,
+        "private": false,
+        "venue": venue,
+    });
+    match SwarmCheckin::from_value(value) {
+        Ok(checkin) => Some(checkin),
+        Err(e) => {
+            tracing::warn!(?e, "unable to build checkin from ingested location");
+            None
+        }
+    }
+}
+
+/// Posts the arrivals in the background, so apps get their answer quickly.
+fn post(state: Arc<AppState>, user_key: String, arrivals: Vec<Arrival>) {
+    let now = unix_now();
+    let arrivals = arrivals
+        .into_iter()
+        .filter(|arrival| arrival.at + MAX_AGE > now)
+        .collect::<Vec<_>>();
+    if arrivals.is_empty() {
+        return;
+    }
+    tokio::spawn(async move {
+        let user = match state.db.get_user(&user_key) {
+            Ok(Some(user)) => user,
+            Ok(None) => return,
+            Err(e) => {
+                tracing::warn!(?e, "unable to read user for ingested location");
+                return;
+            }
+        };
+        for arrival in arrivals {
+            let Some(checkin) = checkin(&state, &user_key, arrival) else {
+                tracing::debug!(user=%user_key, "ingested visit is not at a known place, skip posting");
+                continue;
+            };
+            tracing::info!(user=%user_key, checkin=%checkin.id, venue=%checkin.venue.name, "posting ingested arrival");
+            crate::post_checkin(&state, &user_key, &user, checkin).await;
+        }
+    });
+}
+
+/// Receives a message from OwnTracks in HTTP mode, answering with the empty
+/// list of messages it expects back.
+#[utoipa::path(
+    post,
+    path = "/api/v1/ingest/owntracks",
+    request_body = OwnTracksMessage,
+    responses(
+        (status = 200, description = "Received, with no messages for the app"),
+        (status = 400, description = "A transition without location", body = crate::error::ErrorResponse),
+        (status = 401, description = "Missing or invalid API token", body = crate::error::ErrorResponse),
+        (status = 403, description = "The API token lacks the trigger-post scope", body = crate::error::ErrorResponse),
+    ),
+    security(("api_token" = ["trigger-post"])),
+)]
+pub async fn post_owntracks(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<ApiUser>,
+    Json(message): Json<OwnTracksMessage>,
+) -> Result<Json<Value>, ApiError> {
+    if message.kind == "transition" && message.event.as_deref() == Some("enter") {
+        let (Some(lat), Some(lon), Some(tst)) = (message.lat, message.lon, message.tst) else {
+            return Err(ApiError::bad_request("transition without location"));
+        };
+        let arrival = Arrival {
+            id: format!(
+                "owntracks-{}-{}",
+                message.tid.as_deref().unwrap_or_default(),
+                tst
+            ),
+            at: tst,
+            coordinates: (lat, lon),
+            name: message.desc.filter(|desc| !desc.trim().is_empty()),
+        };
+        post(state, user.user_key, vec![arrival]);
+    }
+    Ok(Json(json!([])))
+}
+
+/// Receives a batch of locations from Overland, answering with the result it
+/// expects before dropping the batch from the phone.
+#[utoipa::path(
+    post,
+    path = "/api/v1/ingest/overland",
+    request_body = OverlandBatch,
+    responses(
+        (status = 200, description = "Received, `{\"result\": \"ok\"}`"),
+        (status = 401, description = "Missing or invalid API token", body = crate::error::ErrorResponse),
+        (status = 403, description = "The API token lacks the trigger-post scope", body = crate::error::ErrorResponse),
+    ),
+    security(("api_token" = ["trigger-post"])),
+)]
+pub async fn post_overland(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<ApiUser>,
+    Json(batch): Json<OverlandBatch>,
+) -> Result<Json<Value>, ApiError> {
+    let arrivals = batch
+        .locations
+        .into_iter()
+        .filter(|feature| feature.properties.kind.as_deref() == Some("visit"))
+        .filter_map(|feature| {
+            let arrival = feature.properties.arrival_date?;
+            let at = DateTime::parse_from_rfc3339(&arrival).ok()?.timestamp();
+            let (lng, lat) = feature.geometry.coordinates;
+            Some(Arrival {
+                id: format!(
+                    "overland-{}-{}",
+                    feature.properties.device_id.as_deref().unwrap_or_default(),
+                    at
+                ),
+                at: u64::try_from(at).ok()?,
+                coordinates: (lat, lng),
+                name: None,
+            })
+        })
+        .collect();
+    post(state, user.user_key, arrivals);
+    Ok(Json(json!({ "result": "ok" })))
+}
@@ -0,0 +1,173 @@
+//! Details about users' Mastodon instances that affect posting, looked up
+//! from the instance API and cached for all users of an instance.
+
+use anyhow::anyhow;
+use anyhow::Result;
+use mastodon_async::scopes::Read;
+use mastodon_async::scopes::Scopes;
+use mastodon_async::scopes::Write;
+use serde_json::Value;
+
+use crate::clients::http_client;
+use crate::model::has_scope;
+use crate::model::unix_now;
+use crate::model::InstanceInfo;
+use crate::AppState;
+
+/// How long looked up instance details are trusted.
+const INFO_TTL: u64 = 24 * 60 * 60;
+
+/// Reads the status length limit from an instance document. Mastodon
+/// reports it under `configuration`, Pleroma and some forks as
+/// `max_toot_chars`.
+fn max_characters(instance: &Value) -> Option<usize> {
+    instance
+        .pointer("/configuration/statuses/max_characters")
+        .or_else(|| instance.get("max_toot_chars"))
+        .and_then(Value::as_u64)
+        .map(|limit| limit as usize)
+}
+
+async fn fetch(base: &str, proxy: Option<&str>) -> Result<InstanceInfo> {
+    let client = http_client(proxy)?;
+    let base = base.trim_end_matches('/');
+    let mut instance: Value = match client
+        .get(format!("{}/api/v2/instance", base))
+        .send()
+        .await?
+        .error_for_status()
+    {
+        Ok(response) => response.json().await?,
+        Err(_) => Value::Null,
+    };
+    if max_characters(&instance).is_none() {
+        // Instances from before v2 and other software only have v1.
+        instance = client
+            .get(format!("{}/api/v1/instance", base))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+    }
+    Ok(InstanceInfo {
+        max_characters: max_characters(&instance),
+        fetched_at: unix_now(),
+    })
+}
+
+/// Returns what is known about the instance at `base`, looking it up only
+/// when the cached details are missing or stale.
+pub async fn info(state: &AppState, base: &str, proxy: Option<&str>) -> InstanceInfo {
+    let cached = match state.db.get_instance_info(base) {
+        Ok(cached) => cached,
+        Err(e) => {
+            tracing::warn!(?e, "unable to read cached instance details");
+            None
+        }
+    };
+    if let Some(cached) = &cached {
+        if cached.fetched_at + INFO_TTL > unix_now() {
+            return cached.clone();
+        }
+    }
+
+    match fetch(base, proxy).await {
+        Ok(info) => {
+            if let Err(e) = state.db.save_instance_info(base, &info) {
+                tracing::warn!(?e, "unable to cache instance details");
+            }
+            info
+        }
+        Err(e) => {
+            tracing::warn!(?e, instance=%base, "unable to look up instance details");
+            // Stale details are better than none.
+            cached.unwrap_or_default()
+        }
+    }
+}
+
+/// Looks up the name of the server software at `base` through nodeinfo,
+/// e.g. `mastodon` or `gotosocial`. Returns `None` for servers without
+/// nodeinfo.
+pub async fn software(base: &str) -> Result<Option<String>> {
+    let client = http_client(None)?;
+    let base = base.trim_end_matches('/');
+    let well_known: Value = match client
+        .get(format!("{}/.well-known/nodeinfo", base))
+        .send()
+        .await?
+        .error_for_status()
+    {
+        Ok(response) => response.json().await?,
+        Err(_) => return Ok(None),
+    };
+    // Schemas sort by version, the newest is read most reliably.
+    let Some(href) = well_known
+        .get("links")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter(|link| {
+            link.get("rel")
+                .and_then(Value::as_str)
+                .map_or(false, |rel| {
+                    rel.starts_with("http://nodeinfo.diaspora.software/ns/schema/")
+                })
+        })
+        .filter_map(|link| link.get("href").and_then(Value::as_str))
+        .max()
+    else {
+        return Ok(None);
+    };
+    let nodeinfo: Value = client
+        .get(href)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(nodeinfo
+        .pointer("/software/name")
+        .and_then(Value::as_str)
+        .map(str::to_lowercase))
+}
+
+/// Scopes every feature needs. Tokens granted before one was added lack it
+/// until the user logs in again.
+pub const REQUIRED_SCOPES: &[&str] = &[
+    "write:statuses",
+    "write:media",
+    "write:accounts",
+    "read:accounts",
+];
+
+/// Scope for uploading attachments, such as maps of venues.
+pub const MEDIA_SCOPE: &str = "write:media";
+
+/// Scope for changing the user's profile, such as the "Last seen" field.
+pub const ACCOUNTS_SCOPE: &str = "write:accounts";
+
+/// Whether the space separated `granted` scopes include all of
+/// `REQUIRED_SCOPES`.
+pub fn has_required_scopes(granted: &str) -> bool {
+    REQUIRED_SCOPES
+        .iter()
+        .all(|needed| has_scope(granted, needed))
+}
+
+/// Scopes to register the app with on a server running `software`. Mastodon
+/// and most compatible servers take the narrow scopes needed for posting,
+/// others only know the broad `read` and `write`.
+pub fn scopes(software: Option<&str>) -> Result<Scopes> {
+    match software {
+        Some("misskey") => Err(anyhow!(
+            "Misskey servers don't offer the Mastodon API, which is needed for posting"
+        )),
+        Some("gotosocial" | "friendica") => Ok(Scopes::read_all() | Scopes::write_all()),
+        _ => Ok(Scopes::write(Write::Statuses)
+            | Scopes::write(Write::Media)
+            | Scopes::write(Write::Accounts)
+            | Scopes::read(Read::Accounts)),
+    }
+}
@@ -0,0 +1,181 @@
+//! Keeps a "📍 Last seen" field on the user's Mastodon profile showing where
+//! their latest posted checkin was, for users opting in.
+//!
+//! The field is updated at most every `MIN_INTERVAL` and removed again when
+//! cross-posting is paused or the user opts out. Other profile fields are
+//! left as they are, and nothing is added when all of them are taken.
+//! Changing the profile needs `instances::ACCOUNTS_SCOPE`, which users who
+//! logged in before it was asked for are prompted to grant.
+
+use serde::Deserialize;
+
+use crate::clients::http_client;
+use crate::instances;
+use crate::model::unix_now;
+use crate::model::LastSeen;
+use crate::AppState;
+
+/// Name of the profile field.
+pub const FIELD_NAME: &str = "📍 Last seen";
+
+/// Most fields Mastodon keeps on a profile.
+const MAX_FIELDS: usize = 4;
+
+/// Longest field value Mastodon accepts.
+const MAX_VALUE: usize = 255;
+
+/// Seconds between updates of a user's field.
+const MIN_INTERVAL: u64 = 15 * 60;
+
+#[derive(Deserialize)]
+struct Field {
+    name: String,
+    value: String,
+}
+
+#[derive(Deserialize, Default)]
+struct Source {
+    #[serde(default)]
+    fields: Vec<Field>,
+}
+
+#[derive(Deserialize)]
+struct Account {
+    #[serde(default)]
+    source: Source,
+}
+
+/// The user's profile fields as they typed them.
+async fn fields(base: &str, token: &str, proxy: Option<&str>) -> anyhow::Result<Vec<Field>> {
+    let url = format!(
+        "{}/api/v1/accounts/verify_credentials",
+        base.trim_end_matches('/')
+    );
+    let account: Account = http_client(proxy)?
+        .get(url)
+        .bearer_auth(token)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(account.source.fields)
+}
+
+async fn save_fields(
+    base: &str,
+    token: &str,
+    proxy: Option<&str>,
+    fields: &[Field],
+) -> anyhow::Result<()> {
+    let url = format!(
+        "{}/api/v1/accounts/update_credentials",
+        base.trim_end_matches('/')
+    );
+    let mut form = Vec::new();
+    for (i, field) in fields.iter().enumerate() {
+        form.push((
+            format!("fields_attributes[{}][name]", i),
+            field.name.clone(),
+        ));
+        form.push((
+            format!("fields_attributes[{}][value]", i),
+            field.value.clone(),
+        ));
+    }
+    // Without any attribute Mastodon would leave the fields alone.
+    if fields.is_empty() {
+        form.push(("fields_attributes[0][name]".to_string(), String::new()));
+        form.push(("fields_attributes[0][value]".to_string(), String::new()));
+    }
+    http_client(proxy)?
+        .patch(url)
+        .bearer_auth(token)
+        .form(&form)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+/// Sets the field to `value`, or removes it when `value` is `None`.
+async fn set(state: &AppState, user_key: &str, value: Option<String>) -> anyhow::Result<()> {
+    let Some(user) = state.db.get_user(user_key)? else {
+        return Ok(());
+    };
+    let profile = state.db.get_profile(user_key)?.unwrap_or_default();
+    if !profile.has_mastodon_scope(instances::ACCOUNTS_SCOPE) {
+        anyhow::bail!("your Mastodon login doesn't allow changing your profile, log in again");
+    }
+    let settings = state.db.get_settings(user_key)?;
+    let proxy = settings.mastodon_proxy.as_deref();
+    let mut fields = fields(&user.mastodon.base, &user.mastodon.token, proxy).await?;
+    let existing = fields.iter().position(|field| field.name == FIELD_NAME);
+    match (existing, value) {
+        (Some(i), Some(value)) => fields[i].value = value,
+        (None, Some(value)) if fields.len() < MAX_FIELDS => fields.push(Field {
+            name: FIELD_NAME.to_string(),
+            value,
+        }),
+        (None, Some(_)) => anyhow::bail!("all profile fields are taken"),
+        (Some(i), None) => {
+            fields.remove(i);
+        }
+        (None, None) => return Ok(()),
+    }
+    save_fields(&user.mastodon.base, &user.mastodon.token, proxy, &fields).await
+}
+
+/// Updates the field after a status was posted for a checkin at `venue` in
+/// `location`, unless it was updated moments ago.
+pub async fn status_posted(state: &AppState, user_key: &str, venue: &str, location: Option<&str>) {
+    let settings = match state.db.get_settings(user_key) {
+        Ok(settings) => settings,
+        Err(e) => {
+            tracing::warn!(?e, "unable to read settings for the last seen field");
+            return;
+        }
+    };
+    let value = match (settings.last_seen, location) {
+        (LastSeen::Off, _) => return,
+        (LastSeen::City, Some(location)) => location.to_string(),
+        (LastSeen::City, None) => return,
+        (LastSeen::Venue, Some(location)) => format!("{} ({})", venue, location),
+        (LastSeen::Venue, None) => venue.to_string(),
+    };
+    let value = value.chars().take(MAX_VALUE).collect::<String>();
+
+    let now = unix_now();
+    let mut due = false;
+    let result = state.db.update_user_status(user_key, |status| {
+        due = status
+            .last_seen_updated_at
+            .map_or(true, |at| at + MIN_INTERVAL <= now);
+        if due {
+            status.last_seen_updated_at = Some(now);
+        }
+    });
+    if let Err(e) = result {
+        tracing::warn!(?e, "unable to record last seen field update");
+        return;
+    }
+    if !due {
+        tracing::debug!(user=%user_key, "updated the last seen field moments ago, skip updating");
+        return;
+    }
+    if let Err(e) = set(state, user_key, Some(value)).await {
+        tracing::warn!(user=%user_key, ?e, "unable to update the last seen field");
+        crate::record_error(
+            state,
+            user_key,
+            format!("unable to update the last seen profile field: {}", e),
+        );
+    }
+}
+
+/// Removes the field, e.g. when cross-posting is paused.
+pub async fn clear(state: &AppState, user_key: &str) {
+    if let Err(e) = set(state, user_key, None).await {
+        tracing::warn!(user=%user_key, ?e, "unable to remove the last seen field");
+    }
+}
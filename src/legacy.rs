@@ -0,0 +1,32 @@
+use std::sync::Arc;
+
+use axum::http::Uri;
+use axum::response::Redirect;
+use axum::routing::get;
+use axum::Router;
+
+use crate::AppState;
+
+/// Routes that have moved, mapped to their current location. Old onboarding
+/// links may still be bookmarked or pasted in documentation, so keep entries
+/// here rather than deleting them.
+const LEGACY_ROUTES: &[(&str, &str)] = &[("/swarm", "/swarm/connect")];
+
+fn redirect(from: &'static str, to: &'static str, uri: Uri) -> Redirect {
+    tracing::info!(from, to, "request to deprecated route");
+    match uri.query() {
+        Some(query) => Redirect::permanent(&format!("{}?{}", to, query)),
+        None => Redirect::permanent(to),
+    }
+}
+
+pub fn routes() -> Router<Arc<AppState>> {
+    LEGACY_ROUTES
+        .iter()
+        .fold(Router::new(), |router, &(from, to)| {
+            router.route(
+                from,
+                get(move |uri: Uri| async move { redirect(from, to, uri) }),
+            )
+        })
+}
@@ -0,0 +1,13 @@
+//! Parts of swarmdon useful to other programs, e.g. receivers of its
+//! webhooks verifying deliveries, along with the storage, decoding and
+//! formatting code the server is built on so the benchmarks in `benches`
+//! can exercise it.
+
+pub mod categories;
+pub mod crypto;
+pub mod fixtures;
+pub mod model;
+pub mod signature;
+pub mod status;
+pub mod swarm;
+pub mod template;
@@ -0,0 +1,389 @@
+//! Signed, expiring links that carry out one action for a user without them
+//! logging in, sent in direct messages since there is no email to confirm
+//! things with.
+//!
+//! A link signs the action, the user's public ID, an argument and when it
+//! expires. Following it only shows what it does: the action happens once
+//! its page is submitted, so link previews fetched by instances don't use it
+//! up, and each link can be used once.
+//!
+//! Mention approvals go out from the account of the user asking, who can
+//! open them too. They only take effect once whoever follows one logs in
+//! as the mentioned account, see `start_approval`.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::Query;
+use axum::extract::State;
+use axum::response::Html;
+use axum::response::IntoResponse;
+use axum::response::Redirect;
+use axum::response::Response;
+use axum::Form;
+use axum::TypedHeader;
+use hmac::Hmac;
+use hmac::Mac;
+use mastodon_async::Data;
+use sha2::Sha256;
+use url::Url;
+
+use crate::clients::http_client;
+use crate::html::escape;
+use crate::html::page;
+use crate::last_seen;
+use crate::model::unix_now;
+use crate::AppState;
+use crate::ResultExt;
+
+/// How long a mention approval waits for the login confirming it.
+const APPROVAL_LOGIN: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Log in and link Swarm again
+    Relink,
+    /// Stop cross-posting checkins
+    Pause,
+    /// Let the user mention the account given as argument
+    ApproveMention,
+}
+
+impl Action {
+    pub const ALL: [Action; 3] = [Action::Relink, Action::Pause, Action::ApproveMention];
+
+    pub fn id(self) -> &'static str {
+        match self {
+            Action::Relink => "relink",
+            Action::Pause => "pause",
+            Action::ApproveMention => "approve-mention",
+        }
+    }
+
+    /// Seconds a link stays valid.
+    fn validity(self) -> u64 {
+        match self {
+            Action::Relink | Action::Pause => 7 * 24 * 60 * 60,
+            Action::ApproveMention => 30 * 24 * 60 * 60,
+        }
+    }
+
+    fn from_id(id: &str) -> Option<Action> {
+        Action::ALL.into_iter().find(|action| action.id() == id)
+    }
+
+    /// What following the link does, for its confirmation page.
+    fn describe(self, handle: &str, arg: &str) -> String {
+        match self {
+            Action::Relink => format!(
+                "Log in as {} and link Swarm again.",
+                escape(handle)
+            ),
+            Action::Pause => format!(
+                "Stop posting the Swarm checkins of {} to Mastodon. It can be turned back on from the account page.",
+                escape(handle)
+            ),
+            Action::ApproveMention => format!(
+                "Let {} mention you as @{} when you check in together on Swarm. You'll be asked to log in as @{} on Mastodon to confirm it's you; nothing is posted and the login isn't kept.",
+                escape(handle),
+                escape(arg),
+                escape(arg)
+            ),
+        }
+    }
+}
+
+/// A link as followed.
+struct Link {
+    action: Action,
+    id: String,
+    arg: String,
+    expires: u64,
+    signature: Vec<u8>,
+}
+
+fn mac(signing_key: &[u8], action: Action, id: &str, arg: &str, expires: u64) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(signing_key).expect("HMAC takes keys of any length");
+    // The ID and expiry can't hold a colon, so the argument goes last.
+    mac.update(format!("{}:{}:{}:{}", action.id(), id, expires, arg).as_bytes());
+    mac
+}
+
+/// Returns a link carrying out `action` for the user when followed.
+pub fn url(state: &AppState, action: Action, user_key: &str, arg: &str) -> anyhow::Result<String> {
+    let id = state.db.public_id(user_key)?;
+    let expires = unix_now() + action.validity();
+    let signature = hex::encode(
+        mac(&state.signing_key, action, &id, arg, expires)
+            .finalize()
+            .into_bytes(),
+    );
+    let mut query = url::form_urlencoded::Serializer::new(String::new());
+    query
+        .append_pair("do", action.id())
+        .append_pair("user", &id)
+        .append_pair("expires", &expires.to_string())
+        .append_pair("sig", &signature);
+    if !arg.is_empty() {
+        query.append_pair("arg", arg);
+    }
+    Ok(format!("{}/link?{}", state.flags.base_url, query.finish()))
+}
+
+/// Checks a followed link's signature and expiry.
+fn verify(state: &AppState, params: &HashMap<String, String>) -> Result<Link, String> {
+    let (Some(action), Some(id), Some(expires), Some(signature)) = (
+        params.get("do"),
+        params.get("user"),
+        params.get("expires"),
+        params.get("sig"),
+    ) else {
+        return Err("incomplete link".into());
+    };
+    let action = Action::from_id(action).ok_or_else(|| "invalid link".to_string())?;
+    let arg = params.get("arg").cloned().unwrap_or_default();
+    let expires = expires
+        .parse::<u64>()
+        .map_err(|_| "invalid link".to_string())?;
+    let signature = hex::decode(signature).map_err(|_| "invalid link".to_string())?;
+    if mac(&state.signing_key, action, id, &arg, expires)
+        .verify_slice(&signature)
+        .is_err()
+    {
+        return Err("invalid link".into());
+    }
+    if expires < unix_now() {
+        return Err("this link has expired".into());
+    }
+    Ok(Link {
+        action,
+        id: id.clone(),
+        arg,
+        expires,
+        signature,
+    })
+}
+
+/// Shows what a link does, with a button to go ahead.
+pub async fn get_link(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Html<String>, String> {
+    let link = verify(&state, &params)?;
+    let Some(user_key) = state.db.resolve_public_id(&link.id).from_err()? else {
+        return Err("no such user".into());
+    };
+    let profile = state
+        .db
+        .get_profile(&user_key)
+        .from_err()?
+        .unwrap_or_default();
+    let fields = params
+        .iter()
+        .map(|(name, value)| {
+            format!(
+                r#"<input type="hidden" name="{}" value="{}" />"#,
+                escape(name),
+                escape(value)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n    ");
+
+    Ok(page(
+        "Confirm",
+        &format!(
+            r#"<h1>Confirm</h1>
+<p>{}</p>
+<form action="/link" method="POST">
+    {}
+    <button type="submit">Confirm</button>
+</form>"#,
+            link.action.describe(&profile.mastodon_handle, &link.arg),
+            fields
+        ),
+    ))
+}
+
+/// The instance of the account a mention approval is for.
+fn approval_instance(link: &Link) -> Result<Url, String> {
+    let Some((_, host)) = link.arg.rsplit_once('@') else {
+        return Err("invalid link".into());
+    };
+    Url::parse(&format!("https://{}", host)).from_err()
+}
+
+/// Sends whoever follows a mention approval link to log in on the mentioned
+/// account's instance. The link is kept in a cookie until the login comes
+/// back, see `complete_approval`.
+async fn start_approval(
+    state: &AppState,
+    link: &Link,
+    params: &HashMap<String, String>,
+) -> Result<Response, String> {
+    let instance_url = approval_instance(link)?;
+    let registered = crate::get_or_create_registration(
+        &state.db,
+        &state.registration_locks,
+        &state.flags,
+        instance_url,
+    )
+    .await
+    .from_err()?;
+    let query = url::form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(params)
+        .finish();
+    let cookie =
+        crate::set_cookie_for(&state.signing_key, "approve", query, APPROVAL_LOGIN).from_err()?;
+    Ok((
+        TypedHeader(cookie),
+        Redirect::to(&registered.authorize_url().from_err()?),
+    )
+        .into_response())
+}
+
+/// Gives up a token only needed to tell who logged in. Failures are only
+/// logged, the token is dropped either way.
+async fn revoke(data: &Data) {
+    let url = format!("{}/oauth/revoke", data.base.trim_end_matches('/'));
+    let result = async {
+        http_client(None)?
+            .post(url)
+            .form(&[
+                ("client_id", &*data.client_id),
+                ("client_secret", &*data.client_secret),
+                ("token", &*data.token),
+            ])
+            .send()
+            .await?
+            .error_for_status()?;
+        anyhow::Ok(())
+    }
+    .await;
+    if let Err(e) = result {
+        tracing::warn!(?e, "unable to revoke token of mention approval");
+    }
+}
+
+/// Approves a mention once the login started by `start_approval` shows the
+/// mentioned account itself followed the link. `query` is the link as kept
+/// in the cookie.
+pub async fn complete_approval(
+    state: &AppState,
+    query: &str,
+    code: &str,
+) -> Result<Response, String> {
+    let params = url::form_urlencoded::parse(query.as_bytes())
+        .into_owned()
+        .collect::<HashMap<_, _>>();
+    let link = verify(state, &params)?;
+    if link.action != Action::ApproveMention {
+        return Err("invalid link".into());
+    }
+    let Some(user_key) = state.db.resolve_public_id(&link.id).from_err()? else {
+        return Err("no such user".into());
+    };
+    let instance_url = approval_instance(&link)?;
+    let Some(registration) = state
+        .db
+        .get_registration(instance_url.as_str())
+        .from_err()?
+    else {
+        return Err("missing registration".into());
+    };
+    let mastodon = registration
+        .into_registered()
+        .from_err()?
+        .complete(code)
+        .await
+        .from_err()?;
+    let account = mastodon.verify_credentials().await;
+    revoke(&mastodon.data).await;
+    let account = account.from_err()?;
+    let handle = format!(
+        "{}@{}",
+        account.username,
+        instance_url.host_str().unwrap_or_default()
+    );
+    if !handle.eq_ignore_ascii_case(&link.arg) {
+        return Err(format!(
+            "you logged in as @{}, but the approval is for @{}",
+            handle, link.arg
+        ));
+    }
+
+    if !state
+        .db
+        .use_link(&link.signature, link.expires)
+        .from_err()?
+    {
+        return Err("this link was already used".into());
+    }
+    tracing::info!(user=%user_key, "mention approved by the mentioned account");
+    let mut friends = state.db.get_friends(&user_key).from_err()?;
+    friends.approved.insert(link.arg.to_lowercase());
+    state.db.save_friends(&user_key, &friends).from_err()?;
+    let cookie = crate::clear_cookies(&["approve"]).from_err()?;
+    Ok((
+        TypedHeader(cookie),
+        page(
+            "Approved",
+            &format!(
+                "<h1>Mentions approved</h1>\n<p>@{} can now be mentioned when you check in together.</p>",
+                escape(&link.arg)
+            ),
+        ),
+    )
+        .into_response())
+}
+
+/// Carries out the action of a link, once.
+pub async fn post_link(
+    State(state): State<Arc<AppState>>,
+    Form(params): Form<HashMap<String, String>>,
+) -> Result<Response, String> {
+    let link = verify(&state, &params)?;
+    let Some(user_key) = state.db.resolve_public_id(&link.id).from_err()? else {
+        return Err("no such user".into());
+    };
+    if link.action == Action::ApproveMention {
+        return start_approval(&state, &link, &params).await;
+    }
+    if !state
+        .db
+        .use_link(&link.signature, link.expires)
+        .from_err()?
+    {
+        return Err("this link was already used".into());
+    }
+    tracing::info!(user=%user_key, action=link.action.id(), "following action link");
+
+    match link.action {
+        Action::Relink => {
+            let Some((instance_url, mastodon_id)) = user_key.rsplit_once(':') else {
+                return Err("invalid user".into());
+            };
+            let cookie = crate::set_cookie(
+                &state.signing_key,
+                "user",
+                format!("{}|{}", instance_url, mastodon_id),
+            )
+            .from_err()?;
+            Ok((TypedHeader(cookie), Redirect::to("/swarm/connect")).into_response())
+        }
+        Action::Pause => {
+            let mut settings = state.db.get_settings(&user_key).from_err()?;
+            settings.disabled = true;
+            state.db.save_settings(&user_key, &settings).from_err()?;
+            last_seen::clear(&state, &user_key).await;
+            Ok(page(
+                "Paused",
+                "<h1>Cross-posting is paused</h1>\n<p>Checkins won't be posted to Mastodon until it is turned back on from the account page.</p>",
+            )
+            .into_response())
+        }
+        Action::ApproveMention => unreachable!("approvals go through start_approval"),
+    }
+}
@@ -0,0 +1,156 @@
+use std::fmt::Write as _;
+
+use tracing_subscriber::field::MakeExt;
+use tracing_subscriber::fmt;
+use tracing_subscriber::fmt::format::debug_fn;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+
+const REDACTED: &str = "[redacted]";
+
+/// Fields whose values are never written to the log.
+const REDACTED_FIELDS: &[&str] = &[
+    "access_token",
+    "token",
+    "secret",
+    "client_secret",
+    "payload",
+];
+
+/// Query parameters whose values are scrubbed from anything that looks like a
+/// URL, e.g. request errors that embed the failing URL.
+const REDACTED_PARAMS: &[&str] = &["oauth_token=", "access_token=", "client_secret=", "code="];
+
+fn redact_params(value: &str) -> String {
+    let mut redacted = value.to_string();
+    for param in REDACTED_PARAMS {
+        let mut from = 0;
+        while let Some(found) = redacted[from..].find(param) {
+            let found = from + found;
+            let start = found + param.len();
+            if !redacted[..found].ends_with(|c: char| c == '?' || c == '&') {
+                from = start;
+                continue;
+            }
+            let end = redacted[start..]
+                .find(|c: char| c == '&' || c == '"' || c == ')' || c.is_whitespace())
+                .map(|end| start + end)
+                .unwrap_or(redacted.len());
+            redacted.replace_range(start..end, REDACTED);
+            from = start + REDACTED.len();
+        }
+    }
+    redacted
+}
+
+/// Formats the value of the field `name`, unless `redact` is false
+/// replacing it when it is sensitive and scrubbing secret URL parameters
+/// from it otherwise.
+fn format_field(
+    redact: bool,
+    extra_fields: &[String],
+    name: &str,
+    value: &dyn std::fmt::Debug,
+) -> String {
+    if redact && (REDACTED_FIELDS.contains(&name) || extra_fields.iter().any(|extra| extra == name))
+    {
+        return REDACTED.to_string();
+    }
+    let formatted = format!("{:?}", value);
+    if redact {
+        redact_params(&formatted)
+    } else {
+        formatted
+    }
+}
+
+/// Sets up logging. Unless `redact` is false, values of sensitive fields
+/// (including any in `extra_fields`) and secret URL parameters are replaced
+/// before they reach the output.
+pub fn init(redact: bool, extra_fields: Vec<String>) {
+    let fields = debug_fn(move |writer, field, value| {
+        let name = field.name();
+        let formatted = format_field(redact, &extra_fields, name, value);
+        if name == "message" {
+            write!(writer, "{}", formatted)
+        } else {
+            write!(writer, "{}={}", name, formatted)
+        }
+    })
+    .delimited(" ");
+
+    tracing_subscriber::registry()
+        .with(fmt::layer().fmt_fields(fields))
+        .with(EnvFilter::from_default_env())
+        .init();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sensitive_fields_are_redacted() {
+        for name in [
+            "access_token",
+            "token",
+            "secret",
+            "client_secret",
+            "payload",
+        ] {
+            assert_eq!(
+                format_field(true, &[], name, &"hunter2"),
+                REDACTED,
+                "{}",
+                name
+            );
+        }
+        let extra = vec!["password".to_string()];
+        assert_eq!(format_field(true, &extra, "password", &"hunter2"), REDACTED);
+        assert_eq!(format_field(true, &extra, "user", &"alice"), "\"alice\"");
+    }
+
+    #[test]
+    fn nothing_is_redacted_when_disabled() {
+        let extra = vec!["password".to_string()];
+        assert_eq!(
+            format_field(false, &extra, "token", &"hunter2"),
+            "\"hunter2\""
+        );
+        assert_eq!(
+            format_field(false, &extra, "password", &"hunter2"),
+            "\"hunter2\""
+        );
+        assert_eq!(
+            format_field(false, &[], "error", &"https://example.com/?code=abc"),
+            "\"https://example.com/?code=abc\""
+        );
+    }
+
+    #[test]
+    fn secret_params_are_redacted() {
+        let error = "error sending request for url (https://api.foursquare.com/v2/users/self/checkins?v=20230101&oauth_token=hunter2&limit=5)";
+        assert_eq!(
+            format_field(true, &[], "e", &format_args!("{}", error)),
+            "error sending request for url (https://api.foursquare.com/v2/users/self/checkins?v=20230101&oauth_token=[redacted]&limit=5)"
+        );
+        assert_eq!(
+            redact_params("https://mastodon.example/oauth/token?client_secret=s3cret&code=c0de"),
+            "https://mastodon.example/oauth/token?client_secret=[redacted]&code=[redacted]"
+        );
+        assert_eq!(
+            redact_params("\"https://example.com/cb?access_token=abc\" failed"),
+            "\"https://example.com/cb?access_token=[redacted]\" failed"
+        );
+    }
+
+    #[test]
+    fn similar_params_are_kept() {
+        assert_eq!(
+            redact_params("https://example.com/?zipcode=12345&barcode=9"),
+            "https://example.com/?zipcode=12345&barcode=9"
+        );
+        assert_eq!(redact_params("no code= here"), "no code= here");
+    }
+}
@@ -1,43 +1,148 @@
 use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
 use std::{path::PathBuf, sync::Arc};
 
-use anyhow::{Context, Result};
+use anyhow::Result;
 use axum::extract::Query;
 use axum::headers::Cookie;
 use axum::headers::Header;
 use axum::headers::SetCookie;
+use axum::http::header::RETRY_AFTER;
+use axum::http::StatusCode;
 use axum::response::Html;
+use axum::response::IntoResponse;
+use axum::response::Response;
 use axum::routing::post;
 use axum::TypedHeader;
 use axum::{extract::State, response::Redirect, routing::get, Form, Router};
+use clap::CommandFactory;
+use clap::FromArgMatches;
 use clap::Parser;
+use error::ApiError;
+use error::PageError;
 use http::HeaderValue;
-use mastodon_async::scopes::Read;
-use mastodon_async::NewStatus;
 use mastodon_async::{
     apps::{App, AppBuilder},
     registration::Registered,
-    scopes::{Scopes, Write},
+    scopes::Scopes,
     Registration,
 };
-use once_cell::sync::OnceCell;
+use origin::RequestOrigin;
 use serde::Deserialize;
-use serde::Serialize;
 use simple_cookie::decode_cookie;
 use simple_cookie::encode_cookie;
-use tracing_subscriber::fmt;
-use tracing_subscriber::layer::SubscriberExt;
-use tracing_subscriber::util::SubscriberInitExt;
-use tracing_subscriber::EnvFilter;
+use swarm::SwarmCheckin;
+use swarm::SwarmUserApi;
+use swarmdon::categories;
+use swarmdon::crypto;
+use swarmdon::fixtures;
+use swarmdon::model;
+use swarmdon::status;
+use swarmdon::swarm;
+use swarmdon::template;
+use tokio::sync::mpsc::unbounded_channel;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::OwnedSemaphorePermit;
 use url::Url;
 
-mod model;
+mod account;
+mod admin;
+mod api;
+mod archive;
+mod backpressure;
+mod clients;
+mod commands;
+mod config;
+mod csrf;
+mod delivery;
+mod durability;
+mod error;
+mod export;
+mod feeds;
+mod friends;
+mod hooks;
+mod host;
+mod household;
+mod html;
+mod ingest;
+mod instances;
+mod last_seen;
+mod legacy;
+mod links;
+mod locks;
+mod logging;
+mod maps;
+mod metrics;
+mod nodeinfo;
+mod onboarding;
+mod origin;
+mod outbox;
+mod pages;
+mod photos;
+mod poll;
+mod recap;
+mod reconnect;
+mod redelivery;
+mod refresh;
+mod rest;
+mod retention;
+mod roundup;
+mod rules;
+mod schedule;
+mod sequencer;
+mod simulate;
+mod snapshot;
+mod stats;
+mod tokens;
+mod usage;
+mod venues;
+mod version;
+mod webhooks;
 
 #[derive(Debug, Parser)]
-struct Flags {
-    #[clap(short, long, default_value = "swarmdon.db")]
+struct Cli {
+    /// TOML file providing defaults for any of the flags
+    #[clap(long, global = true)]
+    config: Option<PathBuf>,
+
+    #[clap(short, long, global = true, default_value = "swarmdon.db")]
     database: PathBuf,
 
+    /// Log sensitive fields and URL parameters such as tokens verbatim
+    #[clap(long, global = true)]
+    log_unredacted: bool,
+
+    /// Additional log field to redact, may be repeated
+    #[clap(long, global = true)]
+    log_redact_field: Vec<String>,
+
+    /// Hex encoded 32 byte key used to encrypt stored tokens
+    #[clap(
+        long,
+        global = true,
+        env = "SWARMDON_TOKEN_KEY",
+        hide_env_values = true,
+        value_parser = crypto::parse_key
+    )]
+    token_key: Option<crypto::TokenKey>,
+
+    /// Previous token key still accepted for decryption, may be repeated
+    #[clap(long, global = true, value_parser = crypto::parse_key)]
+    token_key_old: Vec<crypto::TokenKey>,
+
+    /// Development aid: write a sanitized copy of every distinct Swarm API
+    /// response shape into this directory
+    #[clap(long, global = true)]
+    record_fixtures: Option<PathBuf>,
+
+    #[clap(subcommand)]
+    command: commands::Command,
+}
+
+#[derive(Debug, Parser)]
+struct Flags {
     #[clap(short, long, default_value = "127.0.0.1:8000")]
     address: String,
 
@@ -47,27 +152,155 @@ struct Flags {
     #[clap(short, long, default_value = "https://127.0.0.1:8000")]
     base_url: String,
 
-    #[clap(long)]
+    #[clap(long, env = "SWARMDON_SWARM_CLIENT_ID")]
     swarm_client_id: String,
 
-    #[clap(long)]
+    #[clap(long, env = "SWARMDON_SWARM_CLIENT_SECRET", hide_env_values = true)]
     swarm_client_secret: String,
 
-    #[clap(long)]
+    #[clap(long, env = "SWARMDON_SWARM_PUSH_SECRET", hide_env_values = true)]
     swarm_push_secret: String,
+
+    /// Publish the number of registered users in the nodeinfo document
+    #[clap(long)]
+    nodeinfo_usage: bool,
+
+    /// Seconds to keep retrying a status that failed to post
+    #[clap(long, default_value = "86400")]
+    outbox_max_age: u64,
+
+    /// Seconds between polls of Swarm for checkins missed by push, regular
+    /// polling is disabled when unset
+    #[clap(long)]
+    poll_interval: Option<u64>,
+
+    /// Seconds after a checkin during which polling notices it being made
+    /// public after it was skipped as private, 0 disables this
+    #[clap(long, default_value = "86400")]
+    private_rescan_window: u64,
+
+    /// Seconds between polls for users who recently asked for fast polling
+    #[clap(long, default_value = "60")]
+    fast_poll_interval: u64,
+
+    /// Seconds fast polling stays active after a user asks for it
+    #[clap(long, default_value = "900")]
+    fast_poll_duration: u64,
+
+    /// Seconds to wait between posts when a user has several checkins pending,
+    /// e.g. when catching up on missed checkins
+    #[clap(long, default_value = "30")]
+    poll_pacing: u64,
+
+    /// Token granting admin access to the admin panel at /admin, the panel is
+    /// disabled when neither this nor an operator is set
+    #[clap(long, env = "SWARMDON_ADMIN_TOKEN", hide_env_values = true)]
+    admin_token: Option<String>,
+
+    /// URL receiving a JSON event for every posted status
+    #[clap(long)]
+    webhook_url: Option<String>,
+
+    /// Secret signing webhook deliveries, see the `Swarmdon-Signature` header
+    #[clap(long, env = "SWARMDON_WEBHOOK_SECRET", hide_env_values = true)]
+    webhook_secret: Option<String>,
+
+    /// Shell command run after every posted status, with the checkin and
+    /// status as JSON on its standard input
+    #[clap(long)]
+    post_hook_command: Option<String>,
+
+    /// File or named pipe a JSON line is appended to after every posted status
+    #[clap(long)]
+    post_hook_file: Option<String>,
+
+    /// Hex encoded 32 byte key signing cookies, generated and stored in the
+    /// database when unset
+    #[clap(
+        long,
+        env = "SWARMDON_COOKIE_KEY",
+        hide_env_values = true,
+        value_parser = crypto::parse_key
+    )]
+    cookie_key: Option<crypto::TokenKey>,
+
+    /// Mastodon account allowed into the admin panel with a role, given as
+    /// `username@instance=viewer` or `username@instance=admin`, may be repeated
+    #[clap(long)]
+    operator: Vec<admin::OperatorGrant>,
+
+    /// Publish instance-wide usage counts, without any per-user data, at
+    /// /stats.json
+    #[clap(long)]
+    public_stats: bool,
+
+    /// Pushed checkins allowed to wait for posting at once, further pushes
+    /// queue up and are eventually answered with 429
+    #[clap(long, default_value = "256")]
+    push_max_in_flight: usize,
+
+    /// Seconds a push request waits for room before it is answered with 429
+    #[clap(long, default_value = "5")]
+    push_queue_timeout: u64,
+
+    /// Seconds after a checkin was made its push counts as re-delivered
+    #[clap(long, default_value = "3600")]
+    redelivery_age: u64,
+
+    /// Re-delivered pushes within a minute that make a bulk re-delivery, whose
+    /// checkins are paced like a catch-up
+    #[clap(long, default_value = "10")]
+    redelivery_burst: usize,
+
+    /// URL of a static map image to attach to statuses, with `{lat}`, `{lng}`
+    /// and `{zoom}` filled in, e.g.
+    /// https://maps.example.com/staticmap?center={lat},{lng}&zoom={zoom}&size=600x400&markers={lat},{lng}.
+    /// Users can't turn on maps unless this is set
+    #[clap(long)]
+    static_map_url: Option<String>,
+
+    /// Zoom level of static maps
+    #[clap(long, default_value = "16")]
+    static_map_zoom: u8,
+
+    /// Base URL of the Mastodon instance of an account sending users direct
+    /// messages, e.g. to reconnect Swarm. Users message themselves when unset
+    #[clap(long, requires = "notify_token")]
+    notify_instance_url: Option<String>,
+
+    /// Access token of the account sending direct messages, needs the
+    /// write:statuses scope
+    #[clap(long, env = "SWARMDON_NOTIFY_TOKEN", hide_env_values = true)]
+    notify_token: Option<String>,
+
+    /// Markdown file served as the privacy page instead of the built-in one
+    #[clap(long)]
+    privacy_file: Option<PathBuf>,
+
+    /// Markdown file served as the about page instead of the built-in one
+    #[clap(long)]
+    about_file: Option<PathBuf>,
+
+    /// SOCKS5 proxy users may post through although it is on a loopback or
+    /// private address, given as `host:port` like `127.0.0.1:9050` for a
+    /// local Tor daemon, may be repeated
+    #[clap(long)]
+    allowed_proxy: Vec<String>,
+
+    /// Take the client address from the `X-Forwarded-For` header, only set
+    /// this behind a reverse proxy that sets it
+    #[clap(long)]
+    trust_proxy: bool,
 }
 
 impl Flags {
-    fn app_builder(&self) -> &'static AppBuilder<'static> {
-        static APP: OnceCell<AppBuilder> = OnceCell::new();
-        APP.get_or_init(|| {
-            let mut builder = App::builder();
-            builder
-                .client_name(self.client_name.clone())
-                .redirect_uris(format!("{}/mastodon/callback", self.base_url))
-                .scopes(Scopes::write(Write::Statuses) | Scopes::read(Read::Accounts));
-            builder
-        })
+    fn app_builder(&self, scopes: Scopes) -> AppBuilder<'static> {
+        let mut builder = App::builder();
+        builder
+            .client_name(self.client_name.clone())
+            .redirect_uris(format!("{}/mastodon/callback", self.base_url))
+            .scopes(scopes);
+        builder
     }
 }
 
@@ -75,10 +308,111 @@ struct AppState {
     flags: Flags,
     db: model::Database,
     signing_key: [u8; 32],
+    push_queue: UnboundedSender<(SwarmCheckin, OwnedSemaphorePermit)>,
+    push_limit: backpressure::PushLimit,
+    redelivery: redelivery::Redelivery,
+    mastodon_clients: clients::MastodonClients,
+    sequencer: sequencer::Sequencer,
+    user_locks: locks::KeyedLocks,
+    registration_locks: locks::KeyedLocks,
+    fast_poll: poll::FastPoll,
+    last_polled: poll::LastPolled,
+    host_check: host::HostCheck,
+    usage: usage::UsageCache,
+    post_hooks: hooks::PostHooks,
+}
+
+impl AppState {
+    /// Builds the state along with the receiving end of the push queue, which
+    /// is expected to be handed to `run_push_worker`.
+    fn from_flags(
+        flags: Flags,
+        db: model::Database,
+    ) -> Result<(
+        Self,
+        UnboundedReceiver<(SwarmCheckin, OwnedSemaphorePermit)>,
+    )> {
+        let signing_key = match flags.cookie_key {
+            Some(key) => key,
+            None => db.get_or_create_signing_key()?,
+        };
+        let (push_queue, push_receiver) = unbounded_channel();
+        let push_limit = backpressure::PushLimit::new(flags.push_max_in_flight);
+        let state = Self {
+            flags,
+            db,
+            signing_key,
+            push_queue,
+            push_limit,
+            redelivery: Default::default(),
+            mastodon_clients: Default::default(),
+            sequencer: Default::default(),
+            user_locks: Default::default(),
+            registration_locks: Default::default(),
+            fast_poll: Default::default(),
+            last_polled: Default::default(),
+            host_check: Default::default(),
+            usage: Default::default(),
+            post_hooks: Default::default(),
+        };
+        Ok((state, push_receiver))
+    }
+}
+
+async fn get_home(
+    State(state): State<Arc<AppState>>,
+    cookie: Option<TypedHeader<Cookie>>,
+) -> Html<String> {
+    let home = include_str!("../static/home.html");
+    let prompt = cookie
+        .and_then(|TypedHeader(cookie)| cookie_user_key(&state, &cookie).ok())
+        .map(|user_key| onboarding::resume_prompt(&state, &user_key))
+        .transpose()
+        .unwrap_or_else(|e| {
+            tracing::warn!(error=%e, "failed to look up onboarding state");
+            None
+        });
+    match prompt {
+        Some(prompt) if !prompt.is_empty() => {
+            Html(home.replacen("<body>", &format!("<body>\n    {}", prompt), 1))
+        }
+        _ => Html(home.to_string()),
+    }
 }
 
-async fn get_home() -> Html<&'static str> {
-    Html(include_str!("../static/home.html"))
+async fn get_done() -> Html<&'static str> {
+    Html(include_str!("../static/done.html"))
+}
+
+/// Returns the key of the user logged in through the signed `user` cookie.
+fn cookie_user_key(state: &AppState, cookie: &Cookie) -> Result<String, String> {
+    let Some(user_id) = get_cookie(cookie, &state.signing_key, "user") else {
+        return Err("missing user cookie".into());
+    };
+    let Some((instance_url, mastodon_id)) = user_id.split_once('|') else {
+        return Err("invalid user cookie".into());
+    };
+    Ok(format!("{}:{}", instance_url, mastodon_id))
+}
+
+async fn post_checking_in(
+    State(state): State<Arc<AppState>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+) -> Result<Html<String>, PageError> {
+    let user_key = cookie_user_key(&state, &cookie).map_err(ApiError::unauthorized)?;
+    let Ok(Some(_)) = state.db.get_user(&user_key) else {
+        return Err(ApiError::unauthorized("invalid user").into());
+    };
+
+    let duration = Duration::from_secs(state.flags.fast_poll_duration);
+    state.fast_poll.boost(&user_key, duration);
+    Ok(html::page(
+        "Checking in",
+        &format!(
+            "<p>Watching your Swarm checkins closely for the next {} minutes.</p>",
+            duration.as_secs() / 60
+        ),
+    ))
 }
 
 #[derive(Deserialize)]
@@ -88,12 +422,29 @@ struct HomeForm {
 
 pub async fn get_or_create_registration<T: Into<String>>(
     db: &model::Database,
-    app: &AppBuilder<'static>,
+    locks: &locks::KeyedLocks,
+    flags: &Flags,
     instance_url: T,
 ) -> Result<Registered> {
     let instance_url = instance_url.into();
+    // Concurrent first logins from the same instance would otherwise each
+    // register an app and overwrite one another.
+    let _guard = locks.lock(&instance_url).await;
+    let mut outdated = false;
     match db.get_registration(&instance_url) {
-        Ok(Some(registration)) => return registration.into_registered(),
+        Ok(Some(registration))
+            if registration.scopes().map_or(false, |scopes| {
+                instances::has_required_scopes(&scopes.to_string())
+            }) =>
+        {
+            return registration.into_registered()
+        }
+        // Apps registered before a feature needed another scope are replaced,
+        // users get the new scopes when they log in again.
+        Ok(Some(_)) => {
+            tracing::info!(instance_url, "registration lacks scopes, registering again");
+            outdated = true;
+        }
         Ok(None) => {}
         Err(error) => {
             tracing::warn!(
@@ -104,11 +455,29 @@ pub async fn get_or_create_registration<T: Into<String>>(
         }
     }
 
+    // Not every server speaking the Mastodon API accepts the same scopes.
+    let software = instances::software(&instance_url)
+        .await
+        .unwrap_or_else(|e| {
+            tracing::info!(instance_url, ?e, "unable to detect instance software");
+            None
+        });
+    let scopes = instances::scopes(software.as_deref())?;
+    tracing::info!(instance_url, ?software, %scopes, "registering app");
     let registered = Registration::new(instance_url.clone())
-        .register(app.clone())
+        .register(flags.app_builder(scopes))
         .await?;
-    db.save_registration(instance_url, registered.clone())?;
-    Ok(registered)
+    if outdated {
+        db.replace_registration(&instance_url, registered.clone())?;
+        return Ok(registered);
+    }
+    match db.create_registration(&instance_url, registered.clone())? {
+        None => Ok(registered),
+        Some(existing) => {
+            tracing::info!(instance_url, "registration already created, reusing it");
+            existing.into_registered()
+        }
+    }
 }
 
 trait ResultExt<Ok, Err> {
@@ -124,11 +493,37 @@ where
     }
 }
 
+/// Builds a `Set-Cookie` header expiring the given cookies.
+fn clear_cookies(keys: &[&str]) -> Result<SetCookie> {
+    let cookies = keys
+        .iter()
+        .map(|key| {
+            HeaderValue::from_str(&format!(
+                "{}=; Path=/; HttpOnly; Max-Age=0; Secure; SameSite=Lax",
+                key
+            ))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut cookies = cookies.iter();
+    Ok(SetCookie::decode(&mut cookies)?)
+}
+
 fn set_cookie(signing_key: &[u8; 32], key: &'static str, value: String) -> Result<SetCookie> {
+    set_cookie_for(signing_key, key, value, Duration::from_secs(604800))
+}
+
+/// Like `set_cookie` for a cookie expiring after `max_age`.
+fn set_cookie_for(
+    signing_key: &[u8; 32],
+    key: &'static str,
+    value: String,
+    max_age: Duration,
+) -> Result<SetCookie> {
     let encoded = format!(
-        "{}={}; Path=/; HttpOnly; Max-Age=604800; Secure",
+        "{}={}; Path=/; HttpOnly; Max-Age={}; Secure; SameSite=Lax",
         key,
-        encode_cookie(signing_key, key, value)
+        encode_cookie(signing_key, key, value),
+        max_age.as_secs()
     );
     let cookies = vec![HeaderValue::from_str(&encoded)?];
     let mut cookies = cookies.iter();
@@ -138,30 +533,40 @@ fn set_cookie(signing_key: &[u8; 32], key: &'static str, value: String) -> Resul
 async fn post_home(
     State(state): State<Arc<AppState>>,
     Form(form): Form<HomeForm>,
-) -> Result<(TypedHeader<SetCookie>, Redirect), String> {
+) -> Result<(TypedHeader<SetCookie>, Redirect), PageError> {
     let mut instance_url = form.instance_url;
 
     if !instance_url.starts_with("https:") {
         instance_url = format!("https://{}", instance_url);
     }
 
-    let instance_url = Url::parse(&instance_url).from_err()?;
+    let instance_url = Url::parse(&instance_url)
+        .map_err(|e| ApiError::bad_request(format!("invalid instance URL: {}", e)))?;
 
     if instance_url.scheme() != "https" {
-        return Err("instance_url must be https".into());
+        return Err(ApiError::bad_request("instance_url must be https").into());
     }
 
-    let registered =
-        get_or_create_registration(&state.db, state.flags.app_builder(), instance_url.clone())
-            .await
-            .from_err()?;
+    let registered = get_or_create_registration(
+        &state.db,
+        &state.registration_locks,
+        &state.flags,
+        instance_url.clone(),
+    )
+    .await
+    .map_err(|e| {
+        tracing::warn!(%instance_url, ?e, "unable to register with the instance");
+        ApiError::new(
+            StatusCode::BAD_GATEWAY,
+            "unable to register with your instance, check its address",
+        )
+    })?;
 
-    let set_cookie =
-        set_cookie(&state.signing_key, "instance_url", instance_url.to_string()).from_err()?;
+    let set_cookie = set_cookie(&state.signing_key, "instance_url", instance_url.to_string())?;
 
     Ok((
         TypedHeader(set_cookie),
-        Redirect::to(&registered.authorize_url().from_err()?),
+        Redirect::to(&registered.authorize_url()?),
     ))
 }
 
@@ -176,63 +581,113 @@ fn get_cookie(cookie: &Cookie, signing_key: &[u8; 32], key: &'static str) -> Opt
 async fn get_mastodon_callback(
     State(state): State<Arc<AppState>>,
     TypedHeader(cookie): TypedHeader<Cookie>,
+    RequestOrigin(origin): RequestOrigin,
     Query(params): Query<HashMap<String, String>>,
-) -> Result<(TypedHeader<SetCookie>, Redirect), String> {
+) -> Result<Response, PageError> {
     let Some(code) = params.get("code") else {
-        return Err("missing code".into());
+        return Err(ApiError::bad_request("missing code").into());
     };
 
+    // Someone approving a mention logs in only to show who they are.
+    if let Some(approval) = get_cookie(&cookie, &state.signing_key, "approve") {
+        return links::complete_approval(&state, &approval, code)
+            .await
+            .map_err(|e| ApiError::bad_request(e).into());
+    }
+
     let Some(instance_url) = get_cookie(&cookie, &state.signing_key, "instance_url") else {
-        return Err("missing instance_url cookie".into());
+        return Err(ApiError::bad_request("missing instance_url cookie").into());
     };
 
     let Ok(Some(registration)) = state.db.get_registration(&instance_url) else {
-        return Err("missing registration".into());
+        return Err(ApiError::bad_request("missing registration").into());
     };
-    let registered = registration.into_registered().from_err()?;
-    let mastodon = registered.complete(&code).await.from_err()?;
-    let account = mastodon.verify_credentials().await.from_err()?;
+    let scopes = registration.scopes()?.to_string();
+    let registered = registration.into_registered()?;
+    let mastodon = registered
+        .complete(&code)
+        .await
+        .map_err(|e| ApiError::new(StatusCode::BAD_GATEWAY, format!("unable to log in: {}", e)))?;
+    let account = mastodon.verify_credentials().await.map_err(|e| {
+        ApiError::new(
+            StatusCode::BAD_GATEWAY,
+            format!("unable to look up your account: {}", e),
+        )
+    })?;
 
-    let _user = match state
+    let (_user, action) = match state
         .db
-        .get_mastodon_user(&instance_url, &account.id.to_string())
-        .from_err()?
+        .get_mastodon_user(&instance_url, &account.id.to_string())?
     {
-        Some(user) => user,
-        None => state
-            .db
-            .create_user(
+        Some(mut user) => {
+            // Logging in again issues a new token, keep the stored one fresh.
+            if user.mastodon.token != mastodon.data.token {
+                let user_key = format!("{}:{}", instance_url, account.id);
+                user.mastodon = mastodon.data.clone();
+                state.db.save_user(&user_key, &user)?;
+                state.mastodon_clients.invalidate(&user_key);
+                let mut credentials = state.db.get_credentials(&user_key)?;
+                credentials.mastodon = Default::default();
+                state.db.save_credentials(&user_key, &credentials)?;
+                // A fresh token resolves revoked tokens and similar failures.
+                state
+                    .db
+                    .update_user_status(&user_key, |status| status.suspended = None)?;
+            }
+            (user, "refresh mastodon")
+        }
+        None => (
+            state.db.create_user(
                 &instance_url,
                 &account.id.to_string(),
                 mastodon.data.clone(),
-            )
-            .from_err()?,
+            )?,
+            "link mastodon",
+        ),
     };
 
+    let host = Url::parse(&instance_url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+        .unwrap_or_default();
+    let user_key = format!("{}:{}", instance_url, account.id);
+    let mut profile = state.db.get_profile(&user_key)?.unwrap_or_default();
+    profile.mastodon_handle = format!("{}@{}", account.username, host);
+    profile.mastodon_scopes = Some(scopes);
+    state.db.save_profile(&user_key, &profile)?;
+    state
+        .db
+        .audit_from(&profile.mastodon_handle, action, &user_key, origin)?;
+    state.db.barrier().await?;
+
     let cookie = set_cookie(
         &state.signing_key,
         "user",
         format!("{}|{}", instance_url, account.id.to_string()),
-    )
-    .from_err()?;
+    )?;
 
-    Ok((TypedHeader(cookie), Redirect::to("/swarm")))
+    Ok((TypedHeader(cookie), Redirect::to("/swarm/connect")).into_response())
 }
 
 async fn get_swarm(
     State(state): State<Arc<AppState>>,
     TypedHeader(cookie): TypedHeader<Cookie>,
-) -> Result<Redirect, String> {
+) -> Result<(TypedHeader<SetCookie>, Redirect), PageError> {
     let Some(user_id) = get_cookie(&cookie, &state.signing_key, "user") else {
-        return Err("missing user cookie".into());
+        return Err(ApiError::unauthorized("missing user cookie").into());
     };
     let Some((instance_url, mastodon_id)) = user_id.split_once('|') else {
-        return Err("invalid user cookie".into());
+        return Err(ApiError::unauthorized("invalid user cookie").into());
     };
     let Ok(_user) = state.db.get_mastodon_user(instance_url, mastodon_id) else {
-        return Err("invalid user".into());
+        return Err(ApiError::unauthorized("invalid user").into());
     };
 
+    // Binds the callback to this browser so a forged callback cannot link
+    // someone else's Swarm account to the session.
+    let oauth_state = hex::encode(simple_cookie::generate_signing_key());
+    let set_cookie = set_cookie(&state.signing_key, "swarm_state", oauth_state.clone())?;
+
     let mut url =
         Url::parse("https://foursquare.com/oauth2/authenticate").expect("invalid swarm url");
     let mut queries = url.query_pairs_mut();
@@ -243,289 +698,653 @@ async fn get_swarm(
         "redirect_uri",
         &format!("{}/swarm/callback", state.flags.base_url),
     );
+    queries.append_pair("state", &oauth_state);
     drop(queries);
 
-    Ok(Redirect::to(&url.to_string()))
-}
-
-async fn swarm_get_access_token(
-    client_id: &str,
-    client_secret: &str,
-    redirect_url: &str,
-    code: &str,
-) -> Result<String> {
-    let mut url =
-        Url::parse("https://foursquare.com/oauth2/access_token").expect("invalid swarm url");
-
-    {
-        let mut queries = url.query_pairs_mut();
-        queries.append_pair("client_id", client_id);
-        queries.append_pair("client_secret", client_secret);
-        queries.append_pair("grant_type", "authorization_code");
-        queries.append_pair("redirect_uri", redirect_url);
-        queries.append_pair("code", code);
-    }
-
-    let response = reqwest::get(url).await?;
-    let response = response.json::<serde_json::Value>().await?;
-    let access_token = response
-        .get("access_token")
-        .and_then(|v| v.as_str())
-        .ok_or_else(|| anyhow::anyhow!("unable to retrieve access token for swarm"))?;
-
-    Ok(access_token.to_string())
-}
-
-#[derive(Deserialize, Serialize, Debug)]
-#[serde(rename_all = "camelCase")]
-struct SwarmUser {
-    id: String,
-    first_name: String,
-    last_name: String,
-}
-
-async fn swarm_api(method: String, access_token: &str) -> Result<serde_json::Value> {
-    let url = format!(
-        "https://api.foursquare.com/v2{}?v=20220722&oauth_token={}",
-        method, access_token
-    );
-
-    let response = reqwest::get(url).await?;
-    let mut response = response.json::<serde_json::Value>().await?;
-    let Some(response) = response
-        .get_mut("response")
-        .map(|v| v.take()) else {
-            return Err(anyhow::anyhow!("unable to retrieve response for swarm"));
-        };
-    Ok(response)
-}
-
-async fn swarm_get_me(access_token: &str) -> Result<SwarmUser> {
-    let mut response = swarm_api(format!("/users/self"), access_token)
-        .await
-        .with_context(|| format!("unable to retrieve information about the user"))?;
-    let response = response
-        .get_mut("user")
-        .take()
-        .ok_or_else(|| anyhow::anyhow!("unable to retrieve user info for swarm"))?
-        .take();
-    Ok(serde_json::from_value(response)?)
+    Ok((TypedHeader(set_cookie), Redirect::to(&url.to_string())))
 }
 
 async fn get_swarm_callback(
     State(state): State<Arc<AppState>>,
     TypedHeader(cookie): TypedHeader<Cookie>,
+    RequestOrigin(origin): RequestOrigin,
     Query(params): Query<HashMap<String, String>>,
-) -> Result<String, String> {
+) -> Result<Redirect, PageError> {
     let Some(code) = params.get("code") else {
-        return Err("missing code".into());
+        return Err(ApiError::bad_request("missing code").into());
     };
+    let Some(expected_state) = get_cookie(&cookie, &state.signing_key, "swarm_state") else {
+        return Err(ApiError::bad_request("missing swarm_state cookie").into());
+    };
+    if params.get("state") != Some(&expected_state) {
+        tracing::warn!("swarm callback with mismatching state");
+        return Err(ApiError::bad_request("invalid state").into());
+    }
     let Some(user_id) = get_cookie(&cookie, &state.signing_key, "user") else {
-        return Err("missing user cookie".into());
+        return Err(ApiError::unauthorized("missing user cookie").into());
     };
     let Some((instance_url, mastodon_id)) = user_id.split_once('|') else {
-        return Err("invalid user cookie".into());
+        return Err(ApiError::unauthorized("invalid user cookie").into());
     };
     let Ok(Some(mut user)) = state.db.get_mastodon_user(instance_url, mastodon_id) else {
-        return Err("invalid user".into());
+        return Err(ApiError::unauthorized("invalid user").into());
     };
 
-    let access_token = swarm_get_access_token(
+    let access_token = swarm::get_access_token(
         &state.flags.swarm_client_id,
         &state.flags.swarm_client_secret,
         &format!("{}/swarm/callback", state.flags.base_url),
         code,
     )
     .await
-    .from_err()?;
-    tracing::debug!(?access_token, "swarm access token");
+    .map_err(|e| {
+        ApiError::new(
+            StatusCode::BAD_GATEWAY,
+            format!("unable to log in to Swarm: {}", e),
+        )
+    })?;
+    tracing::debug!("retrieved swarm access token");
 
-    let swarm_user = swarm_get_me(&access_token).await.from_err()?;
+    let swarm_user = SwarmUserApi::new(&access_token)
+        .get_me()
+        .await
+        .map_err(|e| {
+            ApiError::new(
+                StatusCode::BAD_GATEWAY,
+                format!("unable to look up your Swarm account: {}", e),
+            )
+        })?;
     tracing::debug!(?swarm_user, "swarm user");
+    let action = if user.swarm_id == swarm_user.id {
+        "refresh swarm"
+    } else {
+        "link swarm"
+    };
     user.swarm_id = swarm_user.id.clone();
     user.swarm_access_token = access_token;
+    let user_key = format!("{}:{}", instance_url, mastodon_id);
+    state.db.save_user(&user_key, &user)?;
+    state.db.update_user_status(&user_key, |status| {
+        status.swarm_token_dead_at = None;
+        status.reconnect_prompted_at = None;
+    })?;
+    let mut credentials = state.db.get_credentials(&user_key)?;
+    credentials.swarm = Default::default();
+    state.db.save_credentials(&user_key, &credentials)?;
+    let mut profile = state.db.get_profile(&user_key)?.unwrap_or_default();
+    profile.swarm_name = Some(swarm_user.display_name());
+    profile
+        .setup_completed_at
+        .get_or_insert_with(model::unix_now);
+    state.db.save_profile(&user_key, &profile)?;
     state
         .db
-        .user
-        .insert(
-            format!("{}:{}", instance_url, mastodon_id),
-            bincode::serialize(&user).from_err()?,
-        )
-        .from_err()?;
-    state
-        .db
-        .swarm_mapping
-        .insert(
-            swarm_user.id,
-            format!("{}:{}", instance_url, mastodon_id).into_bytes(),
-        )
-        .from_err()?;
-
-    Ok("done!".into())
-}
-
-#[derive(Deserialize, Debug)]
-struct SwarmLocation {
-    country: Option<String>,
-    city: Option<String>,
-    state: Option<String>,
-}
-
-impl SwarmLocation {
-    fn to_string(&self) -> Option<String> {
-        match (
-            self.city.as_ref(),
-            self.state.as_ref(),
-            self.country.as_ref(),
-        ) {
-            (Some(city), Some(state), _) => Some(format!("{}, {}", city, state)),
-            (None, Some(state), Some(country)) => Some(format!("{}, {}", state, country)),
-            (None, None, Some(country)) => Some(country.to_string()),
-            (_, _, _) => None,
-        }
-    }
-}
-
-#[derive(Deserialize, Debug)]
-struct SwarmVenue {
-    id: String,
-    name: String,
-    location: SwarmLocation,
+        .audit_from(&profile.mastodon_handle, action, &user_key, origin)?;
+    state.db.swarm_mapping.insert(
+        swarm_user.id,
+        format!("{}:{}", instance_url, mastodon_id).into_bytes(),
+    )?;
+    // The user is told Swarm is linked next, which has to hold after a crash.
+    state.db.barrier().await?;
+
+    Ok(Redirect::to("/done"))
 }
 
-#[derive(Deserialize, Debug)]
-struct SwarmCheckin {
-    id: String,
-    r#type: String,
-    private: Option<bool>,
-    shout: Option<String>,
-    user: SwarmUser,
-    venue: SwarmVenue,
-}
-
-#[derive(Deserialize, Debug)]
-struct SwarmCheckinDetail {
-    #[serde(flatten)]
-    basic: SwarmCheckin,
-
-    #[serde(rename = "checkinShortUrl")]
-    checkin_short_url: String,
-}
-
-#[derive(Deserialize, Debug)]
+/// Push notification delivered by Foursquare for every new checkin.
+#[derive(Deserialize, Debug, utoipa::ToSchema)]
 struct SwarmPush {
+    /// JSON encoded checkin
     checkin: String,
+    /// Push secret of the Foursquare application
     secret: String,
 }
 
-async fn get_checkin_details(access_token: &str, checkin_id: &str) -> Result<SwarmCheckinDetail> {
-    let mut response = swarm_api(format!("/checkins/{}", checkin_id), access_token).await?;
-    let response = response
-        .get_mut("checkin")
-        .take()
-        .ok_or_else(|| anyhow::anyhow!("response from Swarm API does not contain checkin"))?
-        .take();
-
-    Ok(serde_json::from_value(response)?)
+/// Whether a push comes from the "send test push" button of the Foursquare
+/// push console. Those carry a sample checkin by the app's developer, a Swarm
+/// user who never linked an account here, while real pushes are only sent for
+/// users who authorized the app through us. Pushes still arriving for an
+/// account that was since unlinked, or that can't be looked up, are handled
+/// like any other.
+fn is_test_push(state: &AppState, checkin: &SwarmCheckin) -> bool {
+    let Some(user) = &checkin.user else {
+        return false;
+    };
+    match state.db.was_swarm_linked(&user.id) {
+        Ok(linked) => !linked,
+        Err(e) => {
+            tracing::warn!(checkin=%checkin.id, ?e, "unable to tell whether this is a test push");
+            false
+        }
+    }
 }
 
+#[utoipa::path(
+    post,
+    path = "/swarm/push",
+    request_body(content = SwarmPush, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "Push accepted, or recognized as a test push"),
+        (status = 429, description = "Too much push work in flight, retry later", body = crate::error::ErrorResponse),
+    ),
+)]
 async fn post_swarm_push(
     State(state): State<Arc<AppState>>,
     Form(SwarmPush { checkin, secret }): Form<SwarmPush>,
-) -> Result<(), String> {
-    tracing::debug!(%checkin, "received push event");
+) -> Response {
+    tracing::debug!(payload=%checkin, "received push event");
     if secret != state.flags.swarm_push_secret {
-        tracing::warn!(%checkin, "received invalid push event");
-        return Ok(());
+        tracing::warn!(payload=%checkin, "received invalid push event");
+        return ().into_response();
     }
 
-    let checkin: SwarmCheckin = match serde_json::from_str(&checkin) {
+    let parsed = serde_json::from_str(&checkin)
+        .map_err(anyhow::Error::from)
+        .and_then(SwarmCheckin::from_value);
+    let checkin = match parsed {
         Ok(checkin) => checkin,
         Err(e) => {
-            tracing::warn!(%checkin, ?e, "unable to parse the checkin push");
-            return Ok(());
+            tracing::warn!(payload=%checkin, ?e, "unable to parse the checkin push");
+            return ().into_response();
         }
     };
-    if checkin.private.unwrap_or(false) {
-        tracing::info!(checkin=%checkin.id, "checkin is private, skip posting.");
-        return Ok(());
+
+    if is_test_push(&state, &checkin) {
+        tracing::info!(checkin=%checkin.id, "received test push");
+        if let Err(e) = state.db.set_last_test_push(model::unix_now()) {
+            tracing::warn!(?e, "unable to record test push");
+        }
+        return "test push received".into_response();
     }
-    let Ok(Some(user_id)) = state.db.swarm_mapping.get(&checkin.user.id) else {
-        tracing::warn!(user_id=checkin.user.id, "received push event for unknown user");
-        return Ok(());
-    };
-    let Ok(Some(user)) = state.db.get_user(String::from_utf8_lossy(&user_id)) else {
-        tracing::warn!(user_id=checkin.user.id, "received push event for unknown user");
-        return Ok(());
+
+    let wait = Duration::from_secs(state.flags.push_queue_timeout);
+    let Some(permit) = state.push_limit.acquire(wait).await else {
+        tracing::warn!(checkin=%checkin.id, "too much push work in flight, rejecting push");
+        // Polling picks the checkin up later should Foursquare give up
+        // retrying.
+        return (
+            [(RETRY_AFTER, PUSH_RETRY_AFTER)],
+            ApiError::new(
+                StatusCode::TOO_MANY_REQUESTS,
+                "too much push work in flight",
+            ),
+        )
+            .into_response();
     };
-    let mastodon = user.get_mastodon();
 
-    let country = checkin
-        .venue
-        .location
-        .to_string()
-        .map(|c| format!(" in {}", c))
-        .unwrap_or_default();
+    // Foursquare expects a timely response, so the actual posting happens in
+    // the push worker.
+    if let Err(e) = state.push_queue.send((checkin, permit)) {
+        tracing::warn!(checkin=%e.0 .0.id, "push worker is gone, dropping checkin");
+    }
+    ().into_response()
+}
+
+/// Seconds Foursquare is asked to wait before retrying a rejected push.
+const PUSH_RETRY_AFTER: &str = "30";
+
+async fn run_push_worker(
+    state: Arc<AppState>,
+    mut queue: UnboundedReceiver<(SwarmCheckin, OwnedSemaphorePermit)>,
+) {
+    while let Some((checkin, permit)) = queue.recv().await {
+        let Some(swarm_user) = checkin.user.as_ref() else {
+            tracing::warn!(checkin=%checkin.id, "push event does not contain user");
+            continue;
+        };
+        let Ok(Some(user_id)) = state.db.swarm_mapping.get(&swarm_user.id) else {
+            tracing::warn!(
+                user_id = swarm_user.id,
+                "received push event for unknown user"
+            );
+            continue;
+        };
+        let user_key = String::from_utf8_lossy(&user_id).into_owned();
+        // A bulk re-delivery takes the catch-up path: the checkins give up
+        // their push permits and are paced. Those already posted are skipped
+        // as usual.
+        let permit = if redelivery::is_stale(&state.flags, &checkin)
+            && state.redelivery.record(&state.flags)
+        {
+            tracing::debug!(checkin=%checkin.id, user=%user_key, "pacing re-delivered push");
+            None
+        } else {
+            Some(permit)
+        };
+        state.sequencer.submit(&state, &user_key, checkin, permit);
+    }
+}
+
+/// Returns why a checkin should not be cross-posted, if it should not be.
+/// Pushed and polled checkins both pass through here.
+fn skip_reason(settings: &model::UserSettings, checkin: &SwarmCheckin) -> Option<&'static str> {
+    if settings.disabled {
+        Some("user is disabled")
+    } else if checkin.private.unwrap_or(false) {
+        Some("checkin is private")
+    } else if let Some(action) = rules::evaluate(settings, checkin) {
+        match action {
+            rules::Action::Skip => Some("matched a skip rule"),
+            rules::Action::Post => None,
+        }
+    } else if settings.venue_allowlist
+        && !venues::matches_rule(&settings.venue_rules, &checkin.venue)
+    {
+        Some("venue is not on the allowlist")
+    } else if !settings.venue_allowlist
+        && venues::matches_rule(&settings.venue_rules, &checkin.venue)
+    {
+        Some("venue is blocked")
+    } else if status::effective_shout(settings, checkin).is_none() && !ingest::is_ingested(checkin)
+    {
+        Some("no shout")
+    } else if settings.opt_in
+        && !checkin
+            .shout
+            .as_deref()
+            .map_or(false, |shout| status::has_share_marker(settings, shout))
+    {
+        Some("shout has no share marker")
+    } else if !settings.post_marked
+        && checkin
+            .shout
+            .as_deref()
+            .map_or(false, |shout| status::has_marker(settings, shout))
+    {
+        Some("shout has an opt-out marker")
+    } else {
+        None
+    }
+}
 
-    let details = match get_checkin_details(&user.swarm_access_token, &checkin.id).await {
-        Ok(details) => details,
+/// Whether the user's Mastodon token may upload attachments. Users asked
+/// for it are prompted to log in again on their account page.
+fn can_upload(state: &AppState, user_key: &str) -> bool {
+    match state.db.get_profile(user_key) {
+        Ok(profile) => profile
+            .unwrap_or_default()
+            .has_mastodon_scope(instances::MEDIA_SCOPE),
         Err(e) => {
-            tracing::warn!(?checkin, ?e, "unable to retrieve checkin details");
-            return Ok(());
+            tracing::warn!(?e, "unable to read profile");
+            false
+        }
+    }
+}
+
+async fn post_checkin(state: &AppState, user_key: &str, user: &model::User, checkin: SwarmCheckin) {
+    // Held until the checkin is posted so a concurrent push and poll of the
+    // same checkin cannot both get past the dedupe check.
+    let _guard = state.user_locks.lock(user_key).await;
+    match state
+        .db
+        .mark_processed(user_key, &checkin.id, checkin.created_at)
+    {
+        Ok(true) => {
+            // Losing the mark in a crash after posting would post it again.
+            if let Err(e) = state.db.barrier().await {
+                tracing::warn!(?e, "unable to flush processed checkin");
+            }
+        }
+        Ok(false) => {
+            tracing::debug!(checkin=%checkin.id, "checkin already processed, skip posting.");
+            return;
+        }
+        Err(e) => tracing::warn!(?e, "unable to record processed checkin"),
+    }
+    if let Err(e) = state.db.set_last_checkin(user_key, checkin.created_at) {
+        tracing::warn!(?e, "unable to record last checkin");
+    }
+    // Kept for polling to notice the checkin being made public later.
+    if checkin.private.unwrap_or(false) && state.flags.private_rescan_window > 0 {
+        if let Err(e) = state
+            .db
+            .record_private_checkin(user_key, &checkin.id, checkin.created_at)
+        {
+            tracing::warn!(?e, "unable to record private checkin");
+        }
+    }
+
+    let settings = state.db.get_settings(user_key).unwrap_or_else(|e| {
+        tracing::warn!(?e, "unable to read user settings");
+        Default::default()
+    });
+    if settings.history_disabled {
+        tracing::debug!(checkin=%checkin.id, "history is disabled, not recording checkin");
+    } else {
+        if let Err(e) = state
+            .db
+            .record_history(user_key, &model::HistoryEntry::from(&checkin))
+        {
+            tracing::warn!(?e, "unable to record checkin history");
+        }
+        archive::record(state, user_key, &checkin, skip_reason(&settings, &checkin));
+    }
+    stats::record(state, user_key, &settings, &checkin);
+    if let Err(e) = outbox::release_held(state, user_key, &checkin) {
+        tracing::warn!(?e, "unable to release held statuses");
+    }
+    match state.db.get_user_status(user_key) {
+        Ok(status) if status.suspended.is_some() => {
+            tracing::info!(checkin=%checkin.id, user=%user_key, "delivery is suspended, skip posting.");
+            return;
+        }
+        Ok(_) => {}
+        Err(e) => tracing::warn!(?e, "unable to read user status"),
+    }
+    if let Some(reason) = skip_reason(&settings, &checkin) {
+        tracing::info!(checkin=%checkin.id, user=%user_key, reason, "skip posting.");
+        return;
+    }
+    if household::is_duplicate(state, user, &settings, &checkin) {
+        tracing::info!(checkin=%checkin.id, user=%user_key, "checked in by a friend posting it, skip posting.");
+        return;
+    }
+    if let Some(cap) = settings.daily_cap {
+        let day = schedule::local_day(&settings, checkin.created_at);
+        match state.db.take_daily_slot(user_key, &day, cap) {
+            Ok(true) => {}
+            Ok(false) => {
+                tracing::info!(checkin=%checkin.id, user=%user_key, over_cap=?settings.over_cap, "daily cap reached, skip posting.");
+                if settings.over_cap == model::OverCap::Roundup {
+                    let item = model::RoundupItem {
+                        user_key: user_key.to_string(),
+                        day,
+                        checkin_id: checkin.id.clone(),
+                        created_at: checkin.created_at,
+                        venue_name: checkin.venue.name.clone(),
+                    };
+                    if let Err(e) = state.db.add_roundup(&item) {
+                        tracing::warn!(?e, "unable to keep checkin for the roundup");
+                    }
+                }
+                return;
+            }
+            Err(e) => tracing::warn!(?e, "unable to count daily posts"),
+        }
+    }
+    let proxy = settings.mastodon_proxy.as_deref();
+    let mastodon = match state.mastodon_clients.get(user_key, user, proxy) {
+        Ok(mastodon) => mastodon,
+        Err(e) => {
+            tracing::warn!(?e, "unable to build Mastodon client");
+            record_error(
+                state,
+                user_key,
+                format!("unable to connect through the proxy: {}", e),
+            );
+            return;
         }
     };
 
-    let url = details.checkin_short_url;
-    let status = if let Some(shout) = checkin.shout {
-        format!("{} (@ {}{}) {}", shout, checkin.venue.name, country, url)
+    let swarm = SwarmUserApi::new(&user.swarm_access_token);
+    let ingested = ingest::is_ingested(&checkin);
+    let details = if ingested {
+        ingest::details(&checkin)
     } else {
-        tracing::info!("no shout for checkin {}, skip posting.", checkin.id);
-        return Ok(());
+        match swarm.get_checkin_details(&checkin.id).await {
+            Ok(details) => details,
+            Err(e) => {
+                tracing::warn!(?checkin, ?e, "unable to retrieve checkin details");
+                record_error(
+                    state,
+                    user_key,
+                    format!("unable to retrieve checkin details: {}", e),
+                );
+                return;
+            }
+        }
     };
 
-    tracing::debug!(checkin=%checkin.id, %status, "posting status");
+    let friends = state.db.get_friends(user_key).unwrap_or_else(|e| {
+        tracing::warn!(?e, "unable to read friends");
+        Default::default()
+    });
+    let (friends, unresolved) =
+        friends::verify_mentions(&mastodon, &settings, &checkin, &details, &friends).await;
+    for acct in unresolved {
+        record_error(
+            state,
+            user_key,
+            format!("left out mention of @{} as it could not be found", acct),
+        );
+    }
+    let lookups = status::Lookups {
+        parent_venue: if ingested {
+            None
+        } else {
+            venues::parent_venue(state, &swarm, &checkin.venue.id).await
+        },
+        max_characters: instances::info(state, &user.mastodon.base, proxy)
+            .await
+            .max_characters,
+    };
+    let (text, replies) = status::compose(&settings, &friends, &checkin, &details, &lookups);
+    let mut post = model::Post {
+        status: text,
+        language: status::language(&settings, &checkin),
+        spoiler_text: categories::content_warning(&settings, &checkin.venue),
+        replies,
+        media_ids: Vec::new(),
+        tagged: checkin
+            .with
+            .iter()
+            .map(|friend| friend.id.clone())
+            .collect(),
+    };
+    if settings.venue_photo && !ingested && can_upload(state, user_key) {
+        post.media_ids
+            .extend(photos::attach(state, &user.mastodon, proxy, &swarm, &details.basic).await);
+    }
+    if settings.attach_map && can_upload(state, user_key) {
+        post.media_ids
+            .extend(maps::attach(state, &user.mastodon, proxy, &checkin.venue).await);
+    }
 
-    if let Err(e) = mastodon
-        .new_status(NewStatus {
-            status: Some(status),
-            ..Default::default()
+    let now = model::unix_now();
+    if settings.post_after_leaving {
+        let timeout_at = schedule::leave_timeout_at(&settings, checkin.created_at, now);
+        tracing::info!(checkin=%checkin.id, user=%user_key, timeout_at, "holding status until the user leaves");
+        if let Err(e) = outbox::hold(state, user_key, &checkin, post, timeout_at) {
+            tracing::warn!(?e, "unable to queue status until the user leaves");
+        }
+        return;
+    }
+    if let Some(at) = schedule::post_at(&settings, checkin.created_at, now)
+        // Replies need the status to exist, which a scheduled one doesn't yet.
+        .filter(|at| {
+            settings.mastodon_scheduling
+                && post.replies.is_empty()
+                && *at >= now + clients::MIN_SCHEDULE_AHEAD
         })
-        .await
     {
-        tracing::warn!("unable to post status: {}", e);
+        match clients::schedule_status(&user.mastodon, proxy, &checkin.id, &post, at).await {
+            Ok(()) => {
+                tracing::info!(checkin=%checkin.id, user=%user_key, at, "scheduled status on Mastodon");
+                return;
+            }
+            Err(e) => {
+                tracing::warn!(
+                    ?e,
+                    "unable to schedule status on Mastodon, queueing it instead"
+                );
+            }
+        }
+    }
+    if let Some(at) = schedule::post_at(&settings, checkin.created_at, now) {
+        tracing::info!(checkin=%checkin.id, user=%user_key, at, "holding status for later");
+        if let Err(e) = outbox::schedule(state, user_key, &checkin.id, post, at) {
+            tracing::warn!(?e, "unable to queue status for later");
+        }
+        return;
+    }
+
+    tracing::debug!(checkin=%checkin.id, status=%post.status, language=?post.language, "posting status");
+
+    match mastodon.new_status(post.to_new_status()).await {
+        Ok(posted) => {
+            if let Err(e) =
+                state
+                    .db
+                    .record_post(user_key, &checkin.id, &post.status, &posted.id.to_string())
+            {
+                tracing::warn!(?e, "unable to record post");
+            }
+            delivery::post_replies(state, user_key, &mastodon, &post, &posted).await;
+            webhooks::status_posted(state, user_key, &checkin.id, &posted);
+            hooks::status_posted(
+                state,
+                user_key,
+                &checkin.id,
+                Some(&checkin),
+                &post.status,
+                &posted,
+            );
+            last_seen::status_posted(
+                state,
+                user_key,
+                &checkin.venue.name,
+                checkin.venue.location.to_string().as_deref(),
+            )
+            .await;
+            household::boost(state, user_key, user, &checkin.id, &post.tagged, &posted).await;
+        }
+        Err(e) => {
+            delivery::handle_failure(state, user_key, &checkin.id, post, &e);
+        }
     }
-    Ok(())
 }
 
-#[tokio::main]
-async fn main() {
-    tracing_subscriber::registry()
-        .with(fmt::layer())
-        .with(EnvFilter::from_default_env())
-        .init();
+fn record_error(state: &AppState, user_key: &str, message: String) {
+    if let Err(e) = state.db.record_error(user_key, message) {
+        tracing::warn!(?e, "unable to record error");
+    }
+}
 
-    let flags = Flags::parse();
+async fn serve(flags: Flags, db: model::Database) -> Result<()> {
     let address = flags.address.clone();
-    let database = flags.database.clone();
-
-    let state = Arc::new(AppState {
-        flags,
-        db: model::Database::open(&database).unwrap(),
-        signing_key: simple_cookie::generate_signing_key(),
-    });
+    let (state, push_receiver) = AppState::from_flags(flags, db)?;
+    let state = Arc::new(state);
+    version::log_banner(&state);
+    snapshot::restore(&state);
+
+    tokio::spawn(outbox::run(state.clone()));
+    tokio::spawn(run_push_worker(state.clone(), push_receiver));
+    tokio::spawn(poll::run(state.clone()));
+    tokio::spawn(refresh::run(state.clone()));
+    tokio::spawn(roundup::run(state.clone()));
+    tokio::spawn(recap::run(state.clone()));
+    tokio::spawn(retention::run(state.clone()));
+    if state.flags.public_stats {
+        tokio::spawn(usage::run(state.clone()));
+    }
 
     let app = Router::new()
         .route("/", get(get_home).post(post_home))
         .route("/mastodon/callback", get(get_mastodon_callback))
-        .route("/swarm", get(get_swarm))
+        .route("/swarm/connect", get(get_swarm))
         .route("/swarm/callback", get(get_swarm_callback))
+        .route("/link", get(links::get_link).post(links::post_link))
+        .route("/swarm/disconnect", post(account::post_swarm_disconnect))
+        .route("/done", get(get_done))
+        .route("/swarm/checking-in", post(post_checking_in))
+        .route("/account", get(account::get_account))
+        .route("/account/template", post(account::post_template))
+        .route("/account/proxy", post(account::post_proxy))
+        .route("/account/location", post(account::post_location))
+        .route("/account/hashtags", post(account::post_hashtags))
+        .route("/account/emoji", post(account::post_emoji))
+        .route("/account/sensitive", post(account::post_sensitive))
+        .route("/account/markers", post(account::post_markers))
+        .route("/account/noise", post(account::post_noise))
+        .route("/account/venues", post(account::post_venues))
+        .route("/account/quiet", post(account::post_quiet))
+        .route("/account/delay", post(account::post_delay))
+        .route("/account/history", post(account::post_history))
+        .route("/account/last-seen", post(account::post_last_seen))
+        .route("/history", get(archive::get_history))
+        .route("/export/geojson", get(export::get_geojson))
+        .route("/export/gpx", get(export::get_gpx))
+        .route("/export/kml", get(export::get_kml))
+        .route("/account/recap", post(account::post_recap))
+        .route("/account/stats", get(stats::get_stats))
+        .route(
+            "/account/tokens",
+            get(tokens::get_tokens).post(tokens::post_token),
+        )
+        .route("/account/tokens/revoke", post(tokens::post_revoke))
+        .route("/account/household", post(account::post_household))
+        .route("/account/cap", post(account::post_cap))
+        .route("/account/rules", post(account::post_rules))
+        .route("/account/delete", post(account::post_delete))
+        .route(
+            "/account/friends",
+            get(friends::get_friends).post(friends::post_friend),
+        )
+        .route("/account/friends/remove", post(friends::post_remove_friend))
+        .route("/account/friends/ask", post(friends::post_ask))
+        .route("/account/friends/consent", post(friends::post_consent))
+        .route("/logout", post(account::post_logout))
         .route("/swarm/push", post(post_swarm_push))
-        .with_state(state);
+        .route("/feeds/:file", get(feeds::get_legacy_feed))
+        .route("/feeds/:token/:file", get(feeds::get_feed))
+        .route("/account/feed", post(account::post_feed))
+        .route("/account/news-feed", post(account::post_news_feed))
+        .route("/privacy", get(pages::get_privacy))
+        .route("/about", get(pages::get_about))
+        .route("/version", get(version::get_version))
+        .route("/stats.json", get(usage::get_stats))
+        .route(
+            "/.well-known/nodeinfo",
+            get(nodeinfo::get_well_known_nodeinfo),
+        )
+        .route("/nodeinfo/2.1", get(nodeinfo::get_nodeinfo))
+        .merge(api::docs())
+        .merge(legacy::routes())
+        .merge(admin::routes(state.clone()))
+        .merge(rest::routes(state.clone()))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            durability::flush_writes,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            csrf::check_origin,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            host::check_host,
+        ))
+        .with_state(state.clone());
 
     tracing::info!("Going to listen at http://{}", address);
 
-    axum::Server::bind(&address.parse().unwrap())
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
+    axum::Server::bind(&address.parse()?)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(durability::shutdown_signal())
+        .await?;
+    tracing::info!("shutting down, flushing the database");
+    if let Err(e) = snapshot::save(&state) {
+        tracing::warn!(?e, "unable to save in-memory state");
+    }
+    state.db.barrier().await?;
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut command = Cli::command();
+    if let Some(path) = config::find_config_path(std::env::args_os()) {
+        command = config::apply(command, &config::load(&path)?);
+    }
+    let command = config::apply_env_files(command)?;
+    let cli = Cli::from_arg_matches(&command.get_matches()).unwrap_or_else(|e| e.exit());
+    logging::init(!cli.log_unredacted, cli.log_redact_field);
+    if let Some(directory) = cli.record_fixtures {
+        fixtures::init(directory)?;
+    }
+
+    let mut db = model::Database::open(&cli.database)?;
+    if let Some(key) = &cli.token_key {
+        db.set_cipher(crypto::TokenCipher::new(key, &cli.token_key_old));
+    }
+    commands::run(db, cli.command).await
 }
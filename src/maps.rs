@@ -0,0 +1,113 @@
+//! Static map images of the venue, fetched from the provider the operator
+//! configured with `--static-map-url` and attached to statuses.
+
+use anyhow::anyhow;
+use anyhow::Result;
+use mastodon_async::Data;
+use reqwest::multipart;
+use serde::Deserialize;
+
+use crate::clients::http_client;
+use crate::swarm::SwarmVenue;
+use crate::AppState;
+
+/// Largest image accepted from the provider, or for venue photos from
+/// Foursquare.
+const MAX_IMAGE_BYTES: usize = 2 * 1024 * 1024;
+
+#[derive(Deserialize)]
+struct Attachment {
+    id: String,
+}
+
+fn map_url(template: &str, (lat, lng): (f64, f64), zoom: u8) -> String {
+    template
+        .replace("{lat}", &format!("{:.5}", lat))
+        .replace("{lng}", &format!("{:.5}", lng))
+        .replace("{zoom}", &zoom.to_string())
+}
+
+/// Describes the map for people who can't see it.
+fn alt_text(venue: &SwarmVenue) -> String {
+    match venue.location.to_string() {
+        Some(location) => format!("Map of the area around {}, {}", venue.name, location),
+        None => format!("Map of the area around {}", venue.name),
+    }
+}
+
+/// Downloads an image, returning it with its content type.
+pub async fn fetch(url: &str) -> Result<(Vec<u8>, String)> {
+    let response = http_client(None)?
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?;
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("image/png")
+        .to_string();
+    if !content_type.starts_with("image/") {
+        return Err(anyhow!("{} is not an image", content_type));
+    }
+    let image = response.bytes().await?;
+    if image.len() > MAX_IMAGE_BYTES {
+        return Err(anyhow!("image is {} bytes", image.len()));
+    }
+    Ok((image.to_vec(), content_type))
+}
+
+/// Uploads an image as a media attachment, returning its ID. The client
+/// library can only upload files from disk.
+pub async fn upload(
+    data: &Data,
+    proxy: Option<&str>,
+    file_name: &str,
+    image: Vec<u8>,
+    content_type: &str,
+    description: String,
+) -> Result<String> {
+    let url = format!("{}/api/v2/media", data.base.trim_end_matches('/'));
+    let file = multipart::Part::bytes(image)
+        .file_name(file_name.to_string())
+        .mime_str(content_type)?;
+    let form = multipart::Form::new()
+        .part("file", file)
+        .text("description", description);
+    let attachment: Attachment = http_client(proxy)?
+        .post(url)
+        .bearer_auth(&*data.token)
+        .multipart(form)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(attachment.id)
+}
+
+/// Attaches a map of the venue to the user's next status, returning the
+/// media ID. Failures are only logged, the status goes out without a map.
+pub async fn attach(
+    state: &AppState,
+    data: &Data,
+    proxy: Option<&str>,
+    venue: &SwarmVenue,
+) -> Option<String> {
+    let template = state.flags.static_map_url.as_deref()?;
+    let coordinates = venue.location.coordinates()?;
+    let url = map_url(template, coordinates, state.flags.static_map_zoom);
+    let result = async {
+        let (image, content_type) = fetch(&url).await?;
+        upload(data, proxy, "map", image, &content_type, alt_text(venue)).await
+    }
+    .await;
+    match result {
+        Ok(id) => Some(id),
+        Err(e) => {
+            tracing::warn!(venue=%venue.id, ?e, "unable to attach map");
+            None
+        }
+    }
+}
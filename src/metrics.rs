@@ -0,0 +1,64 @@
+//! Operational metrics in the Prometheus text format, served to operators
+//! under `/admin/metrics`.
+
+use std::fmt::Write;
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::http::header::CONTENT_TYPE;
+use axum::response::IntoResponse;
+
+use crate::AppState;
+
+fn metric(out: &mut String, name: &str, kind: &str, help: &str, value: impl std::fmt::Display) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+pub fn render(state: &AppState) -> String {
+    let mut out = String::new();
+    metric(
+        &mut out,
+        "swarmdon_push_in_flight",
+        "gauge",
+        "Pushed checkins accepted but not posted yet.",
+        state.push_limit.in_flight(),
+    );
+    metric(
+        &mut out,
+        "swarmdon_push_waiting",
+        "gauge",
+        "Push requests waiting for room.",
+        state.push_limit.waiting(),
+    );
+    metric(
+        &mut out,
+        "swarmdon_push_rejected_total",
+        "counter",
+        "Push requests turned away with 429 since startup.",
+        state.push_limit.rejected(),
+    );
+    metric(
+        &mut out,
+        "swarmdon_push_stale_total",
+        "counter",
+        "Pushes for checkins older than --redelivery-age since startup.",
+        state.redelivery.stale(),
+    );
+    metric(
+        &mut out,
+        "swarmdon_quarantined_records",
+        "gauge",
+        "Records that failed to decode and are left out.",
+        state.db.quarantine.len(),
+    );
+    out
+}
+
+pub async fn get_metrics(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        render(&state),
+    )
+}
@@ -1,37 +1,273 @@
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::io::Read;
+use std::io::Write;
 use std::path::Path;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
 
 use anyhow::anyhow;
 use anyhow::Context;
 use anyhow::Result;
+use isolang::Language;
 use mastodon_async::entities::instance;
+use mastodon_async::entities::status::Status;
 use mastodon_async::registration::Registered;
+use mastodon_async::scopes::Scopes;
 use mastodon_async::Data;
 use mastodon_async::Mastodon;
+use mastodon_async::NewStatus;
 use serde::Deserialize;
 use serde::Serialize;
 use url::Url;
+use utoipa::ToSchema;
+
+use crate::crypto;
+use crate::crypto::TokenCipher;
+use crate::swarm::SwarmCheckin;
+use crate::swarm::SwarmUser;
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+const SIGNING_KEY: &str = "signing_key";
 
 pub struct Database {
     db: sled::Db,
+    cipher: Option<TokenCipher>,
     pub registration: sled::Tree,
     pub user: sled::Tree,
     pub swarm_mapping: sled::Tree,
+    pub outbox: sled::Tree,
+    pub last_checkin: sled::Tree,
+    pub settings: sled::Tree,
+    pub user_status: sled::Tree,
+    pub processed: sled::Tree,
+    pub profile: sled::Tree,
+    pub audit: sled::Tree,
+    pub friends: sled::Tree,
+    pub credentials: sled::Tree,
+    pub history: sled::Tree,
+    pub feed_token: sled::Tree,
+    pub venue_parent: sled::Tree,
+    pub venue_photo: sled::Tree,
+    pub daily_posts: sled::Tree,
+    pub roundup: sled::Tree,
+    pub stats: sled::Tree,
+    pub instance_info: sled::Tree,
+    pub quarantine: sled::Tree,
+    /// State of the deployment rather than of any user
+    pub deployment: sled::Tree,
+    /// User key to the opaque ID standing in for it in URLs
+    pub public_id: sled::Tree,
+    /// Opaque ID to user key
+    pub public_id_user: sled::Tree,
+    /// Hash of an API token to the token's details
+    pub api_token: sled::Tree,
+    /// `{user_key}/{period}` to the last period recapped, like `2024-W05`
+    pub recap: sled::Tree,
+    /// Every processed checkin as received from Swarm, keyed like `history`
+    pub archive: sled::Tree,
+    /// Signature of an action link that was followed to when it expires
+    pub used_link: sled::Tree,
+    /// Swarm ID that was once linked to an account to when it was unlinked
+    pub former_swarm: sled::Tree,
+    /// `{user_key}/{checkin_id}` of a checkin that was private when
+    /// processed to when it was made, see `poll::published`
+    pub private_checkin: sled::Tree,
 }
 
 impl Database {
     pub fn open<P: AsRef<Path>>(p: P) -> Result<Self> {
-        let db = sled::open(p)?;
+        Self::from_sled(sled::open(p)?)
+    }
+
+    /// Opens an empty database that is deleted once dropped, see `simulate`.
+    pub fn temporary() -> Result<Self> {
+        Self::from_sled(sled::Config::new().temporary(true).open()?)
+    }
+
+    fn from_sled(db: sled::Db) -> Result<Self> {
         let registration = db.open_tree("registration")?;
         let user = db.open_tree("user")?;
         let swarm_mapping = db.open_tree("swarm_mapping")?;
+        let outbox = db.open_tree("outbox")?;
+        let last_checkin = db.open_tree("last_checkin")?;
+        let settings = db.open_tree("settings")?;
+        let user_status = db.open_tree("user_status")?;
+        let processed = db.open_tree("processed")?;
+        let profile = db.open_tree("profile")?;
+        let audit = db.open_tree("audit")?;
+        let friends = db.open_tree("friends")?;
+        let credentials = db.open_tree("credentials")?;
+        let history = db.open_tree("history")?;
+        let feed_token = db.open_tree("feed_token")?;
+        let venue_parent = db.open_tree("venue_parent")?;
+        let venue_photo = db.open_tree("venue_photo")?;
+        let daily_posts = db.open_tree("daily_posts")?;
+        let roundup = db.open_tree("roundup")?;
+        let stats = db.open_tree("stats")?;
+        let instance_info = db.open_tree("instance_info")?;
+        let quarantine = db.open_tree("quarantine")?;
+        let deployment = db.open_tree("deployment")?;
+        let public_id = db.open_tree("public_id")?;
+        let public_id_user = db.open_tree("public_id_user")?;
+        let api_token = db.open_tree("api_token")?;
+        let recap = db.open_tree("recap")?;
+        let archive = db.open_tree("archive")?;
+        let used_link = db.open_tree("used_link")?;
+        let former_swarm = db.open_tree("former_swarm")?;
+        let private_checkin = db.open_tree("private_checkin")?;
         Ok(Self {
             db,
+            cipher: None,
             registration,
             user,
             swarm_mapping,
+            outbox,
+            last_checkin,
+            settings,
+            user_status,
+            processed,
+            profile,
+            audit,
+            friends,
+            credentials,
+            history,
+            feed_token,
+            venue_parent,
+            venue_photo,
+            daily_posts,
+            roundup,
+            stats,
+            instance_info,
+            quarantine,
+            deployment,
+            public_id,
+            public_id_user,
+            api_token,
+            recap,
+            archive,
+            used_link,
+            former_swarm,
+            private_checkin,
         })
     }
 
+    /// Returns the key signing cookies, generating it on first run so
+    /// sessions survive restarts.
+    pub fn get_or_create_signing_key(&self) -> Result<[u8; 32]> {
+        let key = simple_cookie::generate_signing_key();
+        // Only the first writer wins, later runs read the stored key back.
+        let _ = self
+            .db
+            .compare_and_swap(SIGNING_KEY, None as Option<&[u8]>, Some(&key[..]))?;
+        let stored = self
+            .db
+            .get(SIGNING_KEY)?
+            .ok_or_else(|| anyhow!("signing key disappeared"))?;
+        stored
+            .as_ref()
+            .try_into()
+            .map_err(|_| anyhow!("stored signing key is corrupted"))
+    }
+
+    /// Encrypts credentials of users saved from now on.
+    pub fn set_cipher(&mut self, cipher: TokenCipher) {
+        self.cipher = Some(cipher);
+    }
+
+    pub fn encrypts_tokens(&self) -> bool {
+        self.cipher.is_some()
+    }
+
+    fn encode_user(&self, user: &User) -> Result<Vec<u8>> {
+        crypto::seal(self.cipher.as_ref(), bincode::serialize(user)?)
+    }
+
+    fn decode_user(&self, value: &[u8]) -> Result<User> {
+        Ok(bincode::deserialize(&crypto::open(
+            self.cipher.as_ref(),
+            value,
+        )?)?)
+    }
+
+    /// Re-encrypts every user and credentials record under `new`, reading
+    /// them with `old` or as plaintext. All records are verified before any is
+    /// written, and each tree is rewritten as a single atomic batch.
+    pub fn reencrypt_users(&self, old: Option<&TokenCipher>, new: &TokenCipher) -> Result<usize> {
+        let (users, count) = Self::reencrypt_tree(&self.user, old, new)?;
+        let (credentials, _) = Self::reencrypt_tree(&self.credentials, old, new)?;
+        self.user.apply_batch(users)?;
+        self.credentials.apply_batch(credentials)?;
+        self.db.flush()?;
+        Ok(count)
+    }
+
+    fn reencrypt_tree(
+        tree: &sled::Tree,
+        old: Option<&TokenCipher>,
+        new: &TokenCipher,
+    ) -> Result<(sled::Batch, usize)> {
+        let mut batch = sled::Batch::default();
+        let mut count = 0;
+        for item in tree.iter() {
+            let (key, value) = item?;
+            let plaintext = crypto::open(old, &value).with_context(|| {
+                format!("unable to read record {}", String::from_utf8_lossy(&key))
+            })?;
+            let sealed = new.encrypt(&plaintext)?;
+            if crypto::open(Some(new), &sealed)? != plaintext {
+                return Err(anyhow!("verification failed for re-encrypted record"));
+            }
+            batch.insert(key, sealed);
+            count += 1;
+        }
+        Ok((batch, count))
+    }
+
+    /// Waits until every write made so far is on disk. sled only flushes on
+    /// its own every half second, so writes a user is told about, or that
+    /// keep a status from being posted twice, go through here.
+    pub async fn barrier(&self) -> Result<()> {
+        self.db.flush_async().await?;
+        Ok(())
+    }
+
+    /// Writes every tree of the database to `writer`.
+    pub fn export<W: Write>(&self, writer: W) -> Result<()> {
+        let export: Vec<(Vec<u8>, Vec<u8>, Vec<Vec<Vec<u8>>>)> = self
+            .db
+            .export()
+            .into_iter()
+            .map(|(kind, name, items)| (kind.to_vec(), name.to_vec(), items.collect()))
+            .collect();
+        bincode::serialize_into(writer, &export)?;
+        Ok(())
+    }
+
+    /// Loads a dump produced by `export`. Only meant for a fresh database.
+    pub fn import<R: Read>(&self, reader: R) -> Result<()> {
+        if !self.user.is_empty() || !self.registration.is_empty() {
+            return Err(anyhow!("refusing to import into a non-empty database"));
+        }
+        let export: Vec<(Vec<u8>, Vec<u8>, Vec<Vec<Vec<u8>>>)> = bincode::deserialize_from(reader)?;
+        self.db.import(
+            export
+                .into_iter()
+                .map(|(kind, name, items)| (kind.into(), name.into(), items.into_iter()))
+                .collect(),
+        );
+        self.db.flush()?;
+        Ok(())
+    }
+
     pub fn get_registration(&self, instance_url: &str) -> Result<Option<AppRegistration>> {
         if let Some(registration) = self.registration.get(instance_url)? {
             Ok(Some(bincode::deserialize(&registration)?))
@@ -40,20 +276,138 @@ impl Database {
         }
     }
 
-    pub fn save_registration(&self, key: String, registered: Registered) -> Result<()> {
-        self.registration
-            .insert(key, bincode::serialize(&AppRegistration::from(registered))?)?;
+    /// Stores the registration unless one already exists for the instance, in
+    /// which case the existing registration is returned instead.
+    pub fn create_registration(
+        &self,
+        instance_url: &str,
+        registered: Registered,
+    ) -> Result<Option<AppRegistration>> {
+        let value = bincode::serialize(&AppRegistration::from(registered))?;
+        match self.registration.compare_and_swap(
+            instance_url,
+            None as Option<&[u8]>,
+            Some(value),
+        )? {
+            Ok(()) => Ok(None),
+            Err(sled::CompareAndSwapError {
+                current: Some(current),
+                ..
+            }) => Ok(Some(bincode::deserialize(&current)?)),
+            Err(_) => unreachable!("compare_and_swap only fails when a value is present"),
+        }
+    }
+
+    /// Stores the registration in place of any existing one, for apps
+    /// registered again with more scopes.
+    pub fn replace_registration(&self, instance_url: &str, registered: Registered) -> Result<()> {
+        let value = bincode::serialize(&AppRegistration::from(registered))?;
+        self.registration.insert(instance_url, value)?;
         Ok(())
     }
 
     pub fn get_user<T: AsRef<str>>(&self, key: T) -> Result<Option<User>> {
         if let Some(user) = self.user.get(key.as_ref())? {
-            Ok(Some(bincode::deserialize(&user)?))
+            Ok(Some(self.decode_user(&user)?))
         } else {
             Ok(None)
         }
     }
 
+    pub fn save_user<T: AsRef<str>>(&self, key: T, user: &User) -> Result<()> {
+        self.user.insert(key.as_ref(), self.encode_user(user)?)?;
+        Ok(())
+    }
+
+    /// Decodes the records listed by `items`. Records that fail to decode are
+    /// reported to the quarantine and left out, so one corrupt record doesn't
+    /// take the whole listing down. They stay in `tree` until purged, in case
+    /// they can be recovered.
+    fn decode_all<T, I, F>(
+        &self,
+        tree: &sled::Tree,
+        items: I,
+        decode: F,
+    ) -> Result<Vec<(sled::IVec, T)>>
+    where
+        I: Iterator<Item = sled::Result<(sled::IVec, sled::IVec)>>,
+        F: Fn(&[u8]) -> Result<T>,
+    {
+        let mut records = Vec::new();
+        for item in items {
+            let (key, value) = item?;
+            match decode(&value) {
+                Ok(record) => records.push((key, record)),
+                Err(e) => self.quarantine_record(tree, &key, &e)?,
+            }
+        }
+        Ok(records)
+    }
+
+    fn quarantine_record(
+        &self,
+        tree: &sled::Tree,
+        key: &[u8],
+        error: &anyhow::Error,
+    ) -> Result<()> {
+        let record = QuarantinedRecord {
+            tree: String::from_utf8_lossy(&tree.name()).into_owned(),
+            key: hex::encode(key),
+            error: format!("{:#}", error),
+            at: unix_now(),
+        };
+        let id = format!("{}/{}", record.tree, record.key);
+        if !self.quarantine.contains_key(&id)? {
+            tracing::warn!(tree=%record.tree, key=%String::from_utf8_lossy(key), error=%record.error, "quarantined undecodable record");
+        }
+        self.quarantine.insert(id, serde_json::to_vec(&record)?)?;
+        Ok(())
+    }
+
+    pub fn get_quarantine(&self) -> Result<Vec<(String, QuarantinedRecord)>> {
+        self.quarantine
+            .iter()
+            .map(|item| {
+                let (id, value) = item?;
+                Ok((
+                    String::from_utf8_lossy(&id).into_owned(),
+                    serde_json::from_slice(&value)?,
+                ))
+            })
+            .collect()
+    }
+
+    /// Deletes a quarantined record along with the original. Returns false if
+    /// there is no such record.
+    pub fn purge_quarantined(&self, id: &str) -> Result<bool> {
+        let Some(value) = self.quarantine.get(id)? else {
+            return Ok(false);
+        };
+        let record: QuarantinedRecord = serde_json::from_slice(&value)?;
+        self.db
+            .open_tree(&record.tree)?
+            .remove(hex::decode(&record.key)?)?;
+        self.quarantine.remove(id)?;
+        Ok(true)
+    }
+
+    pub fn get_users(&self) -> Result<Vec<(String, User)>> {
+        let users = self.decode_all(&self.user, self.user.iter(), |value| {
+            self.decode_user(value)
+        })?;
+        // When no user decodes at all, the token key is more likely wrong
+        // than every record corrupt.
+        if users.is_empty() && !self.user.is_empty() {
+            return Err(anyhow!(
+                "no user record could be decoded, check --token-key or `quarantine list`"
+            ));
+        }
+        Ok(users
+            .into_iter()
+            .map(|(key, user)| (String::from_utf8_lossy(&key).into_owned(), user))
+            .collect())
+    }
+
     pub fn get_mastodon_user(&self, instance_url: &str, mastodon_id: &str) -> Result<Option<User>> {
         self.get_user(format!("{}:{}", instance_url, mastodon_id))
     }
@@ -66,10 +420,901 @@ impl Database {
         };
         self.user.insert(
             format!("{}:{}", instance_url, mastodon_id),
-            bincode::serialize(&user)?,
+            self.encode_user(&user)?,
         )?;
         Ok(user)
     }
+
+    /// Disconnects the user's Swarm account while keeping their Mastodon
+    /// registration.
+    pub fn unlink_swarm(&self, user_key: &str) -> Result<()> {
+        let Some(mut user) = self.get_user(user_key)? else {
+            return Ok(());
+        };
+        if !user.swarm_id.is_empty() {
+            // Leave the mapping alone if the Swarm account has since been
+            // linked to someone else.
+            let _ = self.swarm_mapping.compare_and_swap(
+                &user.swarm_id,
+                Some(user_key.as_bytes()),
+                None as Option<&[u8]>,
+            )?;
+            self.remember_unlinked_swarm(&user.swarm_id)?;
+        }
+        user.swarm_id.clear();
+        user.swarm_access_token.clear();
+        self.save_user(user_key, &user)?;
+        self.last_checkin.remove(user_key)?;
+        if let Some(mut profile) = self.get_profile(user_key)? {
+            profile.swarm_name = None;
+            self.save_profile(user_key, &profile)?;
+        }
+        Ok(())
+    }
+
+    /// Notes that a Swarm account is no longer linked, so that pushes still
+    /// arriving for it aren't mistaken for tests from the push console.
+    fn remember_unlinked_swarm(&self, swarm_id: &str) -> Result<()> {
+        self.former_swarm
+            .insert(swarm_id, &unix_now().to_be_bytes())?;
+        Ok(())
+    }
+
+    /// Whether the Swarm account was ever linked to an account here.
+    pub fn was_swarm_linked(&self, swarm_id: &str) -> Result<bool> {
+        Ok(self.swarm_mapping.contains_key(swarm_id)?
+            || self.former_swarm.contains_key(swarm_id)?)
+    }
+
+    /// Removes the user along with everything stored for them.
+    pub fn delete_user(&self, user_key: &str) -> Result<()> {
+        if let Some(user) = self.get_user(user_key)? {
+            if !user.swarm_id.is_empty() {
+                self.swarm_mapping.remove(&user.swarm_id)?;
+                self.remember_unlinked_swarm(&user.swarm_id)?;
+            }
+        }
+        self.user.remove(user_key)?;
+        self.last_checkin.remove(user_key)?;
+        self.settings.remove(user_key)?;
+        self.user_status.remove(user_key)?;
+        // The feed token is only known from the profile.
+        if let Some(token) = self.get_profile(user_key)?.and_then(|p| p.feed_token) {
+            self.feed_token.remove(token)?;
+        }
+        self.profile.remove(user_key)?;
+        self.friends.remove(user_key)?;
+        self.credentials.remove(user_key)?;
+        self.stats.remove(user_key)?;
+        self.clear_history(user_key)?;
+        if let Some(id) = self.public_id.remove(user_key)? {
+            self.public_id_user.remove(id)?;
+        }
+        for (hash, _) in self.get_api_tokens(user_key)? {
+            self.api_token.remove(hash)?;
+        }
+        for key in self.processed.scan_prefix(format!("{}/", user_key)).keys() {
+            self.processed.remove(key?)?;
+        }
+        for key in self
+            .private_checkin
+            .scan_prefix(format!("{}/", user_key))
+            .keys()
+        {
+            self.private_checkin.remove(key?)?;
+        }
+        for key in self
+            .daily_posts
+            .scan_prefix(format!("{}/", user_key))
+            .keys()
+        {
+            self.daily_posts.remove(key?)?;
+        }
+        for key in self.roundup.scan_prefix(format!("{}/", user_key)).keys() {
+            self.roundup.remove(key?)?;
+        }
+        for key in self.recap.scan_prefix(format!("{}/", user_key)).keys() {
+            self.recap.remove(key?)?;
+        }
+        for (key, entry) in self.get_outbox()? {
+            if entry.user_key == user_key {
+                self.outbox.remove(key)?;
+            }
+        }
+        // Link records carry the user's IP address, operator actions stay.
+        for item in self.audit.iter() {
+            let (key, value) = item?;
+            let entry: AuditEntry = serde_json::from_slice(&value)?;
+            if entry.target == user_key && entry.is_user_activity() {
+                self.audit.remove(key)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the user's checkins made before `before` from their history
+    /// and archive. Returns how many were removed from the history.
+    pub fn prune_history(&self, user_key: &str, before: u64) -> Result<usize> {
+        let mut pruned = 0;
+        let start = format!("{}/", user_key);
+        let end = format!("{}/{:020}", user_key, before);
+        for key in self.history.range(start.clone()..end.clone()).keys() {
+            self.history.remove(key?)?;
+            pruned += 1;
+        }
+        for key in self.archive.range(start..end).keys() {
+            self.archive.remove(key?)?;
+        }
+        Ok(pruned)
+    }
+
+    /// Removes the records of users' own activity made before the cutoff
+    /// given for each user. Operator actions stay.
+    pub fn prune_audit(&self, cutoffs: &HashMap<String, u64>) -> Result<usize> {
+        let mut pruned = 0;
+        for item in self.audit.iter() {
+            let (key, value) = item?;
+            let Ok(entry) = serde_json::from_slice::<AuditEntry>(&value) else {
+                continue;
+            };
+            if !entry.is_user_activity() {
+                continue;
+            }
+            if matches!(cutoffs.get(&entry.target), Some(cutoff) if entry.at < *cutoff) {
+                self.audit.remove(key)?;
+                pruned += 1;
+            }
+        }
+        Ok(pruned)
+    }
+
+    pub fn get_settings(&self, user_key: &str) -> Result<UserSettings> {
+        match self.settings.get(user_key)? {
+            Some(settings) => Ok(serde_json::from_slice(&settings)?),
+            None => Ok(UserSettings::default()),
+        }
+    }
+
+    pub fn save_settings(&self, user_key: &str, settings: &UserSettings) -> Result<()> {
+        self.settings
+            .insert(user_key, serde_json::to_vec(settings)?)?;
+        Ok(())
+    }
+
+    /// Credentials are kept apart from `User`, which is stored with bincode
+    /// and can't grow new fields without a migration.
+    pub fn get_credentials(&self, user_key: &str) -> Result<Credentials> {
+        match self.credentials.get(user_key)? {
+            Some(value) => Ok(serde_json::from_slice(&crypto::open(
+                self.cipher.as_ref(),
+                &value,
+            )?)?),
+            None => Ok(Credentials::default()),
+        }
+    }
+
+    pub fn save_credentials(&self, user_key: &str, credentials: &Credentials) -> Result<()> {
+        self.credentials.insert(
+            user_key,
+            crypto::seal(self.cipher.as_ref(), serde_json::to_vec(credentials)?)?,
+        )?;
+        Ok(())
+    }
+
+    pub fn get_friends(&self, user_key: &str) -> Result<Friends> {
+        match self.friends.get(user_key)? {
+            Some(friends) => Ok(serde_json::from_slice(&friends)?),
+            None => Ok(Friends::default()),
+        }
+    }
+
+    pub fn save_friends(&self, user_key: &str, friends: &Friends) -> Result<()> {
+        self.friends
+            .insert(user_key, serde_json::to_vec(friends)?)?;
+        Ok(())
+    }
+
+    pub fn get_profile(&self, user_key: &str) -> Result<Option<Profile>> {
+        match self.profile.get(user_key)? {
+            Some(profile) => Ok(Some(serde_json::from_slice(&profile)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn save_profile(&self, user_key: &str, profile: &Profile) -> Result<()> {
+        self.profile
+            .insert(user_key, serde_json::to_vec(profile)?)?;
+        Ok(())
+    }
+
+    /// Records an administrative action for later review.
+    pub fn audit(&self, operator: &str, action: &str, target: &str) -> Result<()> {
+        self.audit_from(operator, action, target, Origin::default())
+    }
+
+    /// Records an audit entry along with where the request came from.
+    pub fn audit_from(
+        &self,
+        operator: &str,
+        action: &str,
+        target: &str,
+        origin: Origin,
+    ) -> Result<()> {
+        let entry = AuditEntry {
+            at: unix_now(),
+            operator: operator.to_string(),
+            action: action.to_string(),
+            target: target.to_string(),
+            origin,
+        };
+        let id = self.db.generate_id()?;
+        self.audit
+            .insert(id.to_be_bytes(), serde_json::to_vec(&entry)?)?;
+        Ok(())
+    }
+
+    /// Returns the most recent audit entries, newest first.
+    pub fn get_audit(&self, limit: usize) -> Result<Vec<AuditEntry>> {
+        Ok(self
+            .decode_all(&self.audit, self.audit.iter().rev().take(limit), |value| {
+                Ok(serde_json::from_slice(value)?)
+            })?
+            .into_iter()
+            .map(|(_, entry)| entry)
+            .collect())
+    }
+
+    /// Returns the most recent audit entry for `action` on `target`.
+    pub fn get_last_audit(&self, action: &str, target: &str) -> Result<Option<AuditEntry>> {
+        for item in self.audit.iter().rev() {
+            let (_, value) = item?;
+            let entry: AuditEntry = serde_json::from_slice(&value)?;
+            if entry.action == action && entry.target == target {
+                return Ok(Some(entry));
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn get_user_status(&self, user_key: &str) -> Result<UserStatus> {
+        match self.user_status.get(user_key)? {
+            Some(status) => Ok(serde_json::from_slice(&status)?),
+            None => Ok(UserStatus::default()),
+        }
+    }
+
+    pub fn update_user_status<F>(&self, user_key: &str, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut UserStatus),
+    {
+        let mut status = self.get_user_status(user_key)?;
+        f(&mut status);
+        self.user_status
+            .insert(user_key, serde_json::to_vec(&status)?)?;
+        Ok(())
+    }
+
+    /// Records a successful post, keeping the posted status in the history.
+    pub fn record_post(
+        &self,
+        user_key: &str,
+        checkin_id: &str,
+        status: &str,
+        status_id: &str,
+    ) -> Result<()> {
+        self.set_history_status(user_key, checkin_id, status, status_id)?;
+        self.set_archive_status(user_key, checkin_id, status_id)?;
+        self.update_user_status(user_key, |status| status.last_post_at = Some(unix_now()))
+    }
+
+    /// Replaces the status remembered for a checkin, e.g. after editing it.
+    pub fn set_history_status(
+        &self,
+        user_key: &str,
+        checkin_id: &str,
+        status: &str,
+        status_id: &str,
+    ) -> Result<()> {
+        for item in self.history.scan_prefix(format!("{}/", user_key)).rev() {
+            let (key, value) = item?;
+            let mut entry: HistoryEntry = serde_json::from_slice(&value)?;
+            if entry.checkin_id == checkin_id {
+                entry.status = Some(status.to_string());
+                entry.status_id = Some(status_id.to_string());
+                self.history.insert(key, serde_json::to_vec(&entry)?)?;
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns up to `limit` of the user's archived checkins `matches` accepts,
+    /// newest first.
+    pub fn search_archive<F>(
+        &self,
+        user_key: &str,
+        limit: usize,
+        matches: F,
+    ) -> Result<Vec<ArchivedCheckin>>
+    where
+        F: Fn(&ArchivedCheckin) -> bool,
+    {
+        let items = self.archive.scan_prefix(format!("{}/", user_key)).rev();
+        Ok(self
+            .decode_all(&self.archive, items, |value| {
+                Ok(serde_json::from_slice(value)?)
+            })?
+            .into_iter()
+            .map(|(_, entry)| entry)
+            .filter(|entry| matches(entry))
+            .take(limit)
+            .collect())
+    }
+
+    /// Looks up an archived checkin, starting from the most recent ones.
+    fn find_archived(
+        &self,
+        user_key: &str,
+        checkin_id: &str,
+    ) -> Result<Option<(sled::IVec, ArchivedCheckin)>> {
+        for item in self.archive.scan_prefix(format!("{}/", user_key)).rev() {
+            let (key, value) = item?;
+            let entry: ArchivedCheckin = serde_json::from_slice(&value)?;
+            if entry.checkin_id == checkin_id {
+                return Ok(Some((key, entry)));
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn get_archived_checkin(
+        &self,
+        user_key: &str,
+        checkin_id: &str,
+    ) -> Result<Option<ArchivedCheckin>> {
+        Ok(self
+            .find_archived(user_key, checkin_id)?
+            .map(|(_, entry)| entry))
+    }
+
+    /// Notes the status posted for an archived checkin.
+    pub fn set_archive_status(
+        &self,
+        user_key: &str,
+        checkin_id: &str,
+        status_id: &str,
+    ) -> Result<()> {
+        if let Some((key, mut entry)) = self.find_archived(user_key, checkin_id)? {
+            entry.status_id = Some(status_id.to_string());
+            self.archive.insert(key, serde_json::to_vec(&entry)?)?;
+        }
+        Ok(())
+    }
+
+    /// Adds a checkin to the user's history.
+    pub fn record_history(&self, user_key: &str, entry: &HistoryEntry) -> Result<()> {
+        self.history.insert(
+            format!("{}/{:020}/{}", user_key, entry.created_at, entry.checkin_id),
+            serde_json::to_vec(entry)?,
+        )?;
+        Ok(())
+    }
+
+    /// Removes the user's history along with their archived checkins.
+    pub fn clear_history(&self, user_key: &str) -> Result<()> {
+        for key in self.history.scan_prefix(format!("{}/", user_key)).keys() {
+            self.history.remove(key?)?;
+        }
+        for key in self.archive.scan_prefix(format!("{}/", user_key)).keys() {
+            self.archive.remove(key?)?;
+        }
+        Ok(())
+    }
+
+    pub fn archive_checkin(&self, user_key: &str, entry: &ArchivedCheckin) -> Result<()> {
+        self.archive.insert(
+            format!("{}/{:020}/{}", user_key, entry.created_at, entry.checkin_id),
+            serde_json::to_vec(entry)?,
+        )?;
+        Ok(())
+    }
+
+    /// Returns up to `limit` of the user's archived checkins made before
+    /// `before`, newest first.
+    pub fn get_archive(
+        &self,
+        user_key: &str,
+        before: Option<u64>,
+        limit: usize,
+    ) -> Result<Vec<ArchivedCheckin>> {
+        let end = match before {
+            Some(before) => format!("{}/{:020}", user_key, before),
+            None => format!("{}0", user_key),
+        };
+        let items = self
+            .archive
+            .range(format!("{}/", user_key)..end)
+            .rev()
+            .take(limit);
+        Ok(self
+            .decode_all(&self.archive, items, |value| {
+                Ok(serde_json::from_slice(value)?)
+            })?
+            .into_iter()
+            .map(|(_, entry)| entry)
+            .collect())
+    }
+
+    pub fn get_stats(&self, user_key: &str) -> Result<CheckinStats> {
+        match self.stats.get(user_key)? {
+            Some(stats) => Ok(serde_json::from_slice(&stats)?),
+            None => Ok(CheckinStats::default()),
+        }
+    }
+
+    pub fn update_stats<F>(&self, user_key: &str, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut CheckinStats),
+    {
+        let mut stats = self.get_stats(user_key)?;
+        f(&mut stats);
+        self.stats.insert(user_key, serde_json::to_vec(&stats)?)?;
+        Ok(())
+    }
+
+    /// Returns the user's checkin history, newest first.
+    pub fn get_history(&self, user_key: &str) -> Result<Vec<HistoryEntry>> {
+        let items = self.history.scan_prefix(format!("{}/", user_key)).rev();
+        Ok(self
+            .decode_all(&self.history, items, |value| {
+                Ok(serde_json::from_slice(value)?)
+            })?
+            .into_iter()
+            .map(|(_, entry)| entry)
+            .collect())
+    }
+
+    /// Returns the user's checkins made from `from` until before `to`, oldest
+    /// first.
+    pub fn get_history_between(
+        &self,
+        user_key: &str,
+        from: u64,
+        to: u64,
+    ) -> Result<Vec<HistoryEntry>> {
+        let items = self
+            .history
+            .range(format!("{}/{:020}", user_key, from)..format!("{}/{:020}", user_key, to));
+        Ok(self
+            .decode_all(&self.history, items, |value| {
+                Ok(serde_json::from_slice(value)?)
+            })?
+            .into_iter()
+            .map(|(_, entry)| entry)
+            .collect())
+    }
+
+    /// Returns the last period of the given kind recapped for the user.
+    pub fn get_last_recap(&self, user_key: &str, period: &str) -> Result<Option<String>> {
+        Ok(self
+            .recap
+            .get(format!("{}/{}", user_key, period))?
+            .map(|value| String::from_utf8_lossy(&value).into_owned()))
+    }
+
+    pub fn set_last_recap(&self, user_key: &str, period: &str, label: &str) -> Result<()> {
+        self.recap
+            .insert(format!("{}/{}", user_key, period), label.as_bytes())?;
+        Ok(())
+    }
+
+    /// Marks an action link as used, returning whether it was unused.
+    pub fn use_link(&self, signature: &[u8], expires: u64) -> Result<bool> {
+        Ok(self
+            .used_link
+            .compare_and_swap(
+                signature,
+                None as Option<&[u8]>,
+                Some(&expires.to_be_bytes()),
+            )?
+            .is_ok())
+    }
+
+    /// Forgets used action links that have expired anyway, returning how
+    /// many were forgotten.
+    pub fn prune_used_links(&self, now: u64) -> Result<usize> {
+        let mut pruned = 0;
+        for item in self.used_link.iter() {
+            let (key, value) = item?;
+            let expires = value
+                .as_ref()
+                .try_into()
+                .map(u64::from_be_bytes)
+                .unwrap_or_default();
+            if expires < now {
+                self.used_link.remove(key)?;
+                pruned += 1;
+            }
+        }
+        Ok(pruned)
+    }
+
+    pub fn get_instance_info(&self, base: &str) -> Result<Option<InstanceInfo>> {
+        match self.instance_info.get(base)? {
+            Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn save_instance_info(&self, base: &str, info: &InstanceInfo) -> Result<()> {
+        self.instance_info.insert(base, serde_json::to_vec(info)?)?;
+        Ok(())
+    }
+
+    pub fn get_venue_parent(&self, venue_id: &str) -> Result<Option<VenueParent>> {
+        match self.venue_parent.get(venue_id)? {
+            Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn save_venue_parent(&self, venue_id: &str, parent: &VenueParent) -> Result<()> {
+        self.venue_parent
+            .insert(venue_id, serde_json::to_vec(parent)?)?;
+        Ok(())
+    }
+
+    pub fn get_venue_photo(&self, venue_id: &str) -> Result<Option<VenuePhoto>> {
+        match self.venue_photo.get(venue_id)? {
+            Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn save_venue_photo(&self, venue_id: &str, photo: &VenuePhoto) -> Result<()> {
+        self.venue_photo
+            .insert(venue_id, serde_json::to_vec(photo)?)?;
+        Ok(())
+    }
+
+    /// Returns the opaque ID identifying the user in URLs, creating it on
+    /// first use. Keeps the format of user keys out of anything public, so
+    /// they can change without breaking links.
+    pub fn public_id(&self, user_key: &str) -> Result<String> {
+        if let Some(id) = self.public_id.get(user_key)? {
+            return Ok(String::from_utf8_lossy(&id).into_owned());
+        }
+        let id = hex::encode(&simple_cookie::generate_signing_key()[..8]);
+        // Lost races leave the other request's ID in place.
+        match self.public_id.compare_and_swap(
+            user_key,
+            None as Option<&[u8]>,
+            Some(id.as_bytes()),
+        )? {
+            Ok(()) => {
+                self.public_id_user.insert(&id, user_key)?;
+                Ok(id)
+            }
+            Err(e) => Ok(String::from_utf8_lossy(&e.current.unwrap_or_default()).into_owned()),
+        }
+    }
+
+    /// Returns the user an opaque ID from `public_id` stands for.
+    pub fn resolve_public_id(&self, id: &str) -> Result<Option<String>> {
+        Ok(self
+            .public_id_user
+            .get(id)?
+            .map(|value| String::from_utf8_lossy(&value).into_owned()))
+    }
+
+    /// Stores a new API token under the hash of its secret, which is all that
+    /// is kept of it.
+    pub fn add_api_token(&self, hash: &str, token: &ApiToken) -> Result<()> {
+        self.api_token.insert(hash, serde_json::to_vec(token)?)?;
+        Ok(())
+    }
+
+    pub fn get_api_token(&self, hash: &str) -> Result<Option<ApiToken>> {
+        match self.api_token.get(hash)? {
+            Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the user's API tokens along with their hashes, oldest first.
+    pub fn get_api_tokens(&self, user_key: &str) -> Result<Vec<(String, ApiToken)>> {
+        let mut tokens = Vec::new();
+        for item in self.api_token.iter() {
+            let (hash, value) = item?;
+            let token: ApiToken = serde_json::from_slice(&value)?;
+            if token.user_key == user_key {
+                tokens.push((String::from_utf8_lossy(&hash).into_owned(), token));
+            }
+        }
+        tokens.sort_by_key(|(_, token)| token.created_at);
+        Ok(tokens)
+    }
+
+    /// Revokes one of the user's API tokens by its ID. Returns false if the
+    /// user has no such token.
+    pub fn revoke_api_token(&self, user_key: &str, id: &str) -> Result<bool> {
+        match self
+            .get_api_tokens(user_key)?
+            .into_iter()
+            .find(|(_, token)| token.id == id)
+        {
+            Some((hash, _)) => {
+                self.api_token.remove(hash)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Records that an API token was just used. Does nothing if the token
+    /// changed meanwhile, so a revoked token stays revoked.
+    pub fn touch_api_token(&self, hash: &str, at: u64) -> Result<()> {
+        let Some(old) = self.api_token.get(hash)? else {
+            return Ok(());
+        };
+        let mut token: ApiToken = serde_json::from_slice(&old)?;
+        token.last_used_at = Some(at);
+        let _ =
+            self.api_token
+                .compare_and_swap(hash, Some(old), Some(serde_json::to_vec(&token)?))?;
+        Ok(())
+    }
+
+    /// Replaces the user's feed token with a new random one.
+    pub fn rotate_feed_token(&self, user_key: &str) -> Result<String> {
+        let mut profile = self.get_profile(user_key)?.unwrap_or_default();
+        if let Some(old) = profile.feed_token.take() {
+            self.feed_token.remove(old)?;
+        }
+        let token = hex::encode(simple_cookie::generate_signing_key());
+        self.feed_token.insert(&token, user_key)?;
+        profile.feed_token = Some(token.clone());
+        self.save_profile(user_key, &profile)?;
+        Ok(token)
+    }
+
+    /// Replaces the secret signing the user's Atom and RSS feed URLs, so the
+    /// old ones stop working.
+    pub fn rotate_news_feed_secret(&self, user_key: &str) -> Result<()> {
+        let mut profile = self.get_profile(user_key)?.unwrap_or_default();
+        profile.news_feed_secret = Some(hex::encode(simple_cookie::generate_signing_key()));
+        self.save_profile(user_key, &profile)
+    }
+
+    /// Returns the user a feed token belongs to.
+    pub fn get_feed_user(&self, token: &str) -> Result<Option<String>> {
+        Ok(self
+            .feed_token
+            .get(token)?
+            .map(|value| String::from_utf8_lossy(&value).into_owned()))
+    }
+
+    pub fn record_error(&self, user_key: &str, message: String) -> Result<()> {
+        self.update_user_status(user_key, |status| status.record_error(unix_now(), message))
+    }
+
+    /// Marks a checkin as processed. Returns false if it already was.
+    pub fn mark_processed(
+        &self,
+        user_key: &str,
+        checkin_id: &str,
+        created_at: u64,
+    ) -> Result<bool> {
+        let swapped = self.processed.compare_and_swap(
+            format!("{}/{}", user_key, checkin_id),
+            None as Option<&[u8]>,
+            Some(&created_at.to_be_bytes()[..]),
+        )?;
+        Ok(swapped.is_ok())
+    }
+
+    /// Forgets that a checkin was processed, so it goes through posting again.
+    pub fn unmark_processed(&self, user_key: &str, checkin_id: &str) -> Result<()> {
+        self.processed
+            .remove(format!("{}/{}", user_key, checkin_id))?;
+        Ok(())
+    }
+
+    /// Remembers a checkin that was private when processed, kept apart from
+    /// the history so it works for users who turned the history off.
+    pub fn record_private_checkin(
+        &self,
+        user_key: &str,
+        checkin_id: &str,
+        created_at: u64,
+    ) -> Result<()> {
+        self.private_checkin.insert(
+            format!("{}/{}", user_key, checkin_id),
+            &created_at.to_be_bytes(),
+        )?;
+        Ok(())
+    }
+
+    /// IDs of the user's checkins that were private when processed and made
+    /// at `since` or later. Older ones are forgotten along the way.
+    pub fn get_private_checkins(&self, user_key: &str, since: u64) -> Result<HashSet<String>> {
+        let prefix = format!("{}/", user_key);
+        let mut checkins = HashSet::new();
+        for item in self.private_checkin.scan_prefix(&prefix) {
+            let (key, value) = item?;
+            let created_at = value
+                .as_ref()
+                .try_into()
+                .map(u64::from_be_bytes)
+                .unwrap_or_default();
+            if created_at < since {
+                self.private_checkin.remove(&key)?;
+            } else {
+                let key = String::from_utf8_lossy(&key);
+                checkins.insert(key[prefix.len()..].to_string());
+            }
+        }
+        Ok(checkins)
+    }
+
+    /// Forgets that a checkin was private, e.g. once it was made public.
+    pub fn remove_private_checkin(&self, user_key: &str, checkin_id: &str) -> Result<()> {
+        self.private_checkin
+            .remove(format!("{}/{}", user_key, checkin_id))?;
+        Ok(())
+    }
+
+    /// Returns when the last test push from the Foursquare push console came
+    /// in.
+    pub fn get_last_test_push(&self) -> Result<Option<u64>> {
+        Ok(self
+            .deployment
+            .get("last_test_push")?
+            .and_then(|value| value.as_ref().try_into().ok())
+            .map(u64::from_be_bytes))
+    }
+
+    pub fn set_last_test_push(&self, at: u64) -> Result<()> {
+        self.deployment
+            .insert("last_test_push", &at.to_be_bytes())?;
+        Ok(())
+    }
+
+    /// Stores what was only held in memory when the server stopped.
+    pub fn save_runtime_state(&self, state: &RuntimeState) -> Result<()> {
+        self.deployment
+            .insert("runtime_state", serde_json::to_vec(state)?)?;
+        Ok(())
+    }
+
+    /// Returns the state stored on the last shutdown, removing it so a later
+    /// crash doesn't bring it back a second time.
+    pub fn take_runtime_state(&self) -> Result<Option<RuntimeState>> {
+        match self.deployment.remove("runtime_state")? {
+            Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the creation time of the newest checkin seen for the user.
+    pub fn get_last_checkin(&self, user_key: &str) -> Result<Option<u64>> {
+        Ok(self
+            .last_checkin
+            .get(user_key)?
+            .and_then(|value| value.as_ref().try_into().ok())
+            .map(u64::from_be_bytes))
+    }
+
+    /// Records a seen checkin, keeping the newest timestamp.
+    pub fn set_last_checkin(&self, user_key: &str, created_at: u64) -> Result<()> {
+        self.last_checkin.fetch_and_update(user_key, |old| {
+            let old = old
+                .and_then(|value| value.try_into().ok())
+                .map(u64::from_be_bytes)
+                .unwrap_or_default();
+            Some(old.max(created_at).to_be_bytes().to_vec())
+        })?;
+        Ok(())
+    }
+
+    /// Counts a post towards the user's cap for `day`. Returns false, without
+    /// counting it, if the cap is already reached.
+    pub fn take_daily_slot(&self, user_key: &str, day: &str, cap: u32) -> Result<bool> {
+        let mut taken = false;
+        self.daily_posts
+            .fetch_and_update(format!("{}/{}", user_key, day), |old| {
+                let count = old
+                    .and_then(|value| value.try_into().ok())
+                    .map(u32::from_be_bytes)
+                    .unwrap_or_default();
+                taken = count < cap;
+                Some(if taken { count + 1 } else { count }.to_be_bytes().to_vec())
+            })?;
+        Ok(taken)
+    }
+
+    /// Returns how many statuses the user posted on `day`, as counted for
+    /// their daily cap.
+    pub fn get_daily_posts(&self, user_key: &str, day: &str) -> Result<u32> {
+        Ok(self
+            .daily_posts
+            .get(format!("{}/{}", user_key, day))?
+            .and_then(|value| value.as_ref().try_into().ok())
+            .map(u32::from_be_bytes)
+            .unwrap_or_default())
+    }
+
+    /// Drops post counts for days before `day`, which can't be reached again.
+    pub fn prune_daily_posts(&self, day: &str) -> Result<()> {
+        for key in self.daily_posts.iter().keys() {
+            let key = key?;
+            let old = String::from_utf8_lossy(&key)
+                .rsplit_once('/')
+                .map_or(false, |(_, counted)| counted < day);
+            if old {
+                self.daily_posts.remove(key)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Keeps a checkin over the daily cap for the user's roundup of `day`.
+    pub fn add_roundup(&self, item: &RoundupItem) -> Result<()> {
+        self.roundup.insert(
+            format!(
+                "{}/{}/{:020}/{}",
+                item.user_key, item.day, item.created_at, item.checkin_id
+            ),
+            serde_json::to_vec(item)?,
+        )?;
+        Ok(())
+    }
+
+    pub fn get_roundup(&self) -> Result<Vec<(sled::IVec, RoundupItem)>> {
+        self.decode_all(&self.roundup, self.roundup.iter(), |value| {
+            Ok(serde_json::from_slice(value)?)
+        })
+    }
+
+    pub fn get_roundup_item(&self, key: &[u8]) -> Result<Option<RoundupItem>> {
+        Ok(match self.roundup.get(key)? {
+            Some(value) => Some(serde_json::from_slice(&value)?),
+            None => None,
+        })
+    }
+
+    pub fn remove_roundup(&self, key: &[u8]) -> Result<()> {
+        self.roundup.remove(key)?;
+        Ok(())
+    }
+
+    pub fn enqueue_outbox(&self, entry: &OutboxEntry) -> Result<()> {
+        let id = self.db.generate_id()?;
+        self.outbox
+            .insert(id.to_be_bytes(), serde_json::to_vec(entry)?)?;
+        Ok(())
+    }
+
+    pub fn get_outbox(&self) -> Result<Vec<(sled::IVec, OutboxEntry)>> {
+        self.decode_all(&self.outbox, self.outbox.iter(), decode_outbox_entry)
+    }
+
+    pub fn get_outbox_entry(&self, key: &[u8]) -> Result<Option<OutboxEntry>> {
+        self.outbox
+            .get(key)?
+            .map(|value| decode_outbox_entry(&value))
+            .transpose()
+    }
+
+    pub fn update_outbox(&self, key: &[u8], entry: &OutboxEntry) -> Result<()> {
+        self.outbox.insert(key, serde_json::to_vec(entry)?)?;
+        Ok(())
+    }
+
+    pub fn remove_outbox(&self, key: &[u8]) -> Result<()> {
+        self.outbox.remove(key)?;
+        Ok(())
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -82,14 +1327,20 @@ pub struct AppRegistration {
 }
 
 impl AppRegistration {
+    /// Scopes the app was registered with, and so granted to tokens issued
+    /// for it.
+    pub fn scopes(&self) -> Result<Scopes> {
+        bincode::deserialize(&self.scopes)
+            .with_context(|| anyhow!("unable to deserialize scope '{:?}'", self.scopes))
+    }
+
     pub fn into_registered(self) -> Result<Registered> {
         Ok(Registered::from_parts(
             &self.base,
             &self.client_id,
             &self.client_secret,
             &self.redirect_uri,
-            bincode::deserialize(&self.scopes)
-                .with_context(|| anyhow!("unable to deserialize scope '{:?}'", self.scopes))?,
+            self.scopes()?,
             false,
         ))
     }
@@ -121,3 +1372,841 @@ impl User {
         self.mastodon.clone().into()
     }
 }
+
+/// A status ready to be posted to Mastodon, along with its options.
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct Post {
+    pub status: String,
+    /// ISO 639-1 code of the status language
+    pub language: Option<String>,
+    /// Content warning the status is hidden behind
+    pub spoiler_text: Option<String>,
+    /// Rest of a long shout, posted as replies to the status
+    pub replies: Vec<String>,
+    /// Attachments uploaded ahead of posting, e.g. a map of the venue
+    pub media_ids: Vec<String>,
+    /// Swarm IDs of friends tagged in the checkin, who may boost the status,
+    /// see `household::boost`
+    pub tagged: Vec<String>,
+}
+
+impl Post {
+    pub fn to_new_status(&self) -> NewStatus {
+        NewStatus {
+            status: Some(self.status.clone()),
+            language: self.language.as_deref().and_then(Language::from_639_1),
+            spoiler_text: self.spoiler_text.clone(),
+            media_ids: Some(self.media_ids.clone()).filter(|ids| !ids.is_empty()),
+            ..Default::default()
+        }
+    }
+
+    /// Builds one of `replies`, visible to the same audience as the status it
+    /// replies to.
+    pub fn reply_new_status(&self, reply: &str, in_reply_to: &Status) -> NewStatus {
+        NewStatus {
+            status: Some(reply.to_string()),
+            in_reply_to_id: Some(in_reply_to.id.to_string()),
+            visibility: Some(in_reply_to.visibility),
+            media_ids: None,
+            ..self.to_new_status()
+        }
+    }
+}
+
+/// A status that failed to post and is waiting to be retried.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct OutboxEntry {
+    pub user_key: String,
+    pub checkin_id: String,
+    pub post: Post,
+    pub created_at: u64,
+    pub attempts: u32,
+    pub next_attempt_at: u64,
+    /// Set while the status waits for the user to leave the venue.
+    #[serde(default)]
+    pub hold: Option<Hold>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Hold {
+    pub venue_id: String,
+    pub checkin_at: u64,
+}
+
+fn decode_outbox_entry(value: &[u8]) -> Result<OutboxEntry> {
+    // Entries queued before the outbox moved to JSON are bincode.
+    Ok(match serde_json::from_slice(value) {
+        Ok(entry) => entry,
+        Err(_) => bincode::deserialize::<LegacyOutboxEntry>(value)?.into(),
+    })
+}
+
+/// Outbox entry as stored with bincode before posts carried options.
+#[derive(Deserialize)]
+struct LegacyOutboxEntry {
+    user_key: String,
+    checkin_id: String,
+    status: String,
+    created_at: u64,
+    attempts: u32,
+    next_attempt_at: u64,
+}
+
+impl From<LegacyOutboxEntry> for OutboxEntry {
+    fn from(entry: LegacyOutboxEntry) -> Self {
+        Self {
+            user_key: entry.user_key,
+            checkin_id: entry.checkin_id,
+            post: Post {
+                status: entry.status,
+                ..Default::default()
+            },
+            created_at: entry.created_at,
+            attempts: entry.attempts,
+            next_attempt_at: entry.next_attempt_at,
+            hold: None,
+        }
+    }
+}
+
+/// Per-user preferences. Stored as JSON so new settings can be added without
+/// migrating existing records.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, ToSchema)]
+#[serde(default)]
+pub struct UserSettings {
+    /// Set by an administrator to stop posting for the user.
+    pub disabled: bool,
+    /// Preset arrangement of the status, used unless `template` is set.
+    pub layout: Layout,
+    /// ISO 639-1 code of the language the user posts in. Detected from the
+    /// shout when unset.
+    pub language: Option<String>,
+    /// Status template chosen by the user, see `status::FIELDS`.
+    pub template: Option<String>,
+    /// SOCKS5 proxy, e.g. Tor, for requests to the user's Mastodon instance.
+    pub mastodon_proxy: Option<String>,
+    /// Show the country's flag after the location.
+    pub country_flag: bool,
+    /// Continue shouts too long for one status in replies rather than
+    /// cutting them short.
+    pub thread_long_shouts: bool,
+    /// How the venue's coordinates are added to the status.
+    pub coordinates: Coordinates,
+    /// Which map the status links to.
+    pub link: LinkMode,
+    /// Attach a map of the venue, when the operator set up a map provider.
+    pub attach_map: bool,
+    /// Attach the venue's best photo from Foursquare to checkins without
+    /// photos of their own.
+    pub venue_photo: bool,
+    /// Note the first visit to a venue, or how many visits this is.
+    pub visit_count: bool,
+    /// Mention mapped friends who were at the venue at the same time, even
+    /// if they weren't tagged.
+    pub mention_overlaps: bool,
+    /// Only mention friends who approved it through a link sent to them.
+    pub mention_consent: bool,
+    /// Hashtags appended to every status, including the `#`.
+    pub hashtags: Vec<String>,
+    /// Append hashtags derived from the venue's categories.
+    pub category_hashtags: bool,
+    /// Lowercased category name to hashtag, overriding the built-in table.
+    pub category_hashtag_map: BTreeMap<String, String>,
+    /// Prefix the status with an emoji for the venue's category.
+    pub category_emoji: bool,
+    /// Lowercased category name to emoji, overriding the built-in table.
+    pub category_emoji_map: BTreeMap<String, String>,
+    /// Lowercased category names whose checkins are posted behind a content
+    /// warning.
+    #[schema(value_type = Vec<String>)]
+    pub sensitive_categories: BTreeSet<String>,
+    /// Words such as `#noshare` that keep a checkin from being posted when
+    /// they appear in its shout.
+    pub opt_out_markers: Vec<String>,
+    /// Post checkins with a marker anyway, with the marker removed.
+    pub post_marked: bool,
+    /// Only post checkins whose shout contains a share marker.
+    pub opt_in: bool,
+    /// Words that mark a checkin for posting in opt-in mode. Empty means
+    /// `status::DEFAULT_SHARE_MARKER`.
+    pub share_markers: Vec<String>,
+    /// Shouts treated as no shout at all, such as ones Swarm fills in from
+    /// stickers. See `status::is_noise`.
+    pub noise_shouts: Vec<String>,
+    /// Venues checkins at which are never posted, or the only ones posted
+    /// at with `venue_allowlist`. See `venues::matches_rule`.
+    pub venue_rules: Vec<String>,
+    pub venue_allowlist: bool,
+    /// IANA time zone name such as `Europe/Berlin`, UTC when unset.
+    pub timezone: Option<String>,
+    /// Window during which statuses are held back. See `schedule::quiet_until`.
+    pub quiet_hours: Option<QuietHours>,
+    /// Minutes to wait after a checkin before posting it, so the status
+    /// doesn't give away where the user is right now.
+    pub post_delay: u32,
+    /// Have Mastodon hold delayed statuses as scheduled posts rather than
+    /// keeping them in the outbox.
+    pub mastodon_scheduling: bool,
+    /// Hold statuses until the user checks in at another venue, or until
+    /// `leave_timeout` hours have passed.
+    pub post_after_leaving: bool,
+    /// Zero means `schedule::DEFAULT_LEAVE_TIMEOUT`.
+    pub leave_timeout: u32,
+    /// What to do with checkins made by another user of this instance
+    /// tagging the user. See `household`.
+    pub household: HouseholdMode,
+    /// Most statuses posted per day, in the user's time zone.
+    pub daily_cap: Option<u32>,
+    pub over_cap: OverCap,
+    /// Filter rules, one per line. See `rules`.
+    pub rules: String,
+    /// Don't keep a history of checkins, only the aggregates in
+    /// `CheckinStats`.
+    pub history_disabled: bool,
+    /// How long checkins are kept in the history.
+    pub history_retention: Retention,
+    /// How long records of linking accounts, with the IP address they were
+    /// linked from, are kept.
+    pub audit_retention: Retention,
+    /// Post a recap of the week's checkins once it's over. See `recap`.
+    pub weekly_recap: bool,
+    pub monthly_recap: bool,
+    pub yearly_recap: bool,
+    /// Template of recaps, the default one when unset.
+    pub recap_template: Option<String>,
+    /// Minutes after midnight recaps are posted at once the period is over.
+    pub recap_time: Option<u32>,
+    /// Post the lists of a recap in replies, keeping the status short.
+    pub recap_thread: bool,
+    /// What the "Last seen" field on the Mastodon profile shows.
+    pub last_seen: LastSeen,
+}
+
+/// Running counts of a user's public checkins, kept even when the history
+/// isn't.
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct CheckinStats {
+    pub total: u32,
+    pub venues: BTreeMap<String, VenueCount>,
+    pub categories: BTreeMap<String, u32>,
+    /// Checkins per `YYYY-MM` in the user's time zone
+    pub months: BTreeMap<String, u32>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+pub struct VenueCount {
+    pub name: String,
+    pub count: u32,
+}
+
+/// What happens to checkins beyond the daily cap.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum OverCap {
+    #[default]
+    Drop,
+    /// Listed in a single status once the day is over
+    Roundup,
+}
+
+/// A checkin left out by the daily cap, waiting for the end of day roundup.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct RoundupItem {
+    pub user_key: String,
+    /// Local date of the checkin, `YYYY-MM-DD`
+    pub day: String,
+    pub checkin_id: String,
+    pub created_at: u64,
+    pub venue_name: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum HouseholdMode {
+    /// Post them like any other checkin
+    #[default]
+    Post,
+    /// Leave posting to the friend who tagged the user
+    Skip,
+    /// Boost the friend's status instead
+    Boost,
+}
+
+impl HouseholdMode {
+    pub const ALL: [HouseholdMode; 3] = [
+        HouseholdMode::Post,
+        HouseholdMode::Skip,
+        HouseholdMode::Boost,
+    ];
+
+    pub fn id(self) -> &'static str {
+        match self {
+            HouseholdMode::Post => "post",
+            HouseholdMode::Skip => "skip",
+            HouseholdMode::Boost => "boost",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            HouseholdMode::Post => "Post them like my own checkins",
+            HouseholdMode::Skip => "Don't post them, their status covers it",
+            HouseholdMode::Boost => "Boost their status instead, if we are on the same instance",
+        }
+    }
+}
+
+/// How long records about the user are kept, see `retention`.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum Retention {
+    SevenDays,
+    NinetyDays,
+    #[default]
+    Forever,
+}
+
+impl Retention {
+    pub const ALL: [Retention; 3] = [
+        Retention::SevenDays,
+        Retention::NinetyDays,
+        Retention::Forever,
+    ];
+
+    pub fn id(self) -> &'static str {
+        match self {
+            Retention::SevenDays => "seven-days",
+            Retention::NinetyDays => "ninety-days",
+            Retention::Forever => "forever",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Retention::SevenDays => "7 days",
+            Retention::NinetyDays => "90 days",
+            Retention::Forever => "Until I delete it",
+        }
+    }
+
+    /// Age in seconds past which records are removed.
+    pub fn seconds(self) -> Option<u64> {
+        match self {
+            Retention::SevenDays => Some(7 * 24 * 60 * 60),
+            Retention::NinetyDays => Some(90 * 24 * 60 * 60),
+            Retention::Forever => None,
+        }
+    }
+}
+
+/// What the "Last seen" field on the user's profile shows, see `last_seen`.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum LastSeen {
+    #[default]
+    Off,
+    City,
+    Venue,
+}
+
+impl LastSeen {
+    pub const ALL: [LastSeen; 3] = [LastSeen::Off, LastSeen::City, LastSeen::Venue];
+
+    pub fn id(self) -> &'static str {
+        match self {
+            LastSeen::Off => "off",
+            LastSeen::City => "city",
+            LastSeen::Venue => "venue",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            LastSeen::Off => "Don't add the field",
+            LastSeen::City => "The city of my latest posted checkin",
+            LastSeen::Venue => "The venue and city of my latest posted checkin",
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum Coordinates {
+    /// Leave them out
+    #[default]
+    None,
+    /// A `geo:` URI, which map-aware clients open in a map
+    GeoUri,
+    /// Latitude and longitude as plain numbers
+    Plain,
+}
+
+impl Coordinates {
+    pub const ALL: [Coordinates; 3] = [Coordinates::None, Coordinates::GeoUri, Coordinates::Plain];
+
+    pub fn id(self) -> &'static str {
+        match self {
+            Coordinates::None => "none",
+            Coordinates::GeoUri => "geo-uri",
+            Coordinates::Plain => "plain",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Coordinates::None => "Don't include the venue's coordinates",
+            Coordinates::GeoUri => "Include them as a <code>geo:</code> link",
+            Coordinates::Plain => "Include them as plain numbers",
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum LinkMode {
+    /// The checkin on Foursquare
+    #[default]
+    Foursquare,
+    /// The venue on OpenStreetMap
+    OpenStreetMap,
+    /// Both links
+    Both,
+}
+
+impl LinkMode {
+    pub const ALL: [LinkMode; 3] = [
+        LinkMode::Foursquare,
+        LinkMode::OpenStreetMap,
+        LinkMode::Both,
+    ];
+
+    pub fn id(self) -> &'static str {
+        match self {
+            LinkMode::Foursquare => "foursquare",
+            LinkMode::OpenStreetMap => "open-street-map",
+            LinkMode::Both => "both",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            LinkMode::Foursquare => "Link to the checkin on Foursquare",
+            LinkMode::OpenStreetMap => "Link to the venue on OpenStreetMap instead",
+            LinkMode::Both => "Link to both",
+        }
+    }
+}
+
+/// Local times of day, in minutes after midnight. `start` may be after `end`
+/// for a window spanning midnight.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, ToSchema)]
+pub struct QuietHours {
+    pub start: u32,
+    pub end: u32,
+}
+
+/// Preset status layouts covering the common cases without writing a
+/// template.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum Layout {
+    /// Shout, venue and location, then the link
+    #[default]
+    Classic,
+    /// The link first, so it gets the preview card
+    UrlFirst,
+    /// Shout and venue only
+    Minimal,
+}
+
+impl Layout {
+    pub const ALL: [Layout; 3] = [Layout::Classic, Layout::UrlFirst, Layout::Minimal];
+
+    pub fn id(self) -> &'static str {
+        match self {
+            Layout::Classic => "classic",
+            Layout::UrlFirst => "url-first",
+            Layout::Minimal => "minimal",
+        }
+    }
+}
+
+/// Number of recent errors kept for each user.
+const MAX_RECENT_ERRORS: usize = 10;
+
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+pub struct UserError {
+    pub at: u64,
+    pub message: String,
+}
+
+/// Delivery bookkeeping shown to administrators.
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct UserStatus {
+    pub last_post_at: Option<u64>,
+    pub errors: Vec<UserError>,
+    /// Set when posting failed in a way only the user can fix, e.g. a revoked
+    /// token. Cleared when they log in again.
+    pub suspended: Option<String>,
+    /// When Swarm was found to reject the user's access token. Cleared when
+    /// they link Swarm again.
+    pub swarm_token_dead_at: Option<u64>,
+    /// When the user was sent a message asking them to link Swarm again, so
+    /// they are only asked once.
+    pub reconnect_prompted_at: Option<u64>,
+    /// When the "Last seen" profile field was last updated
+    pub last_seen_updated_at: Option<u64>,
+}
+
+impl UserStatus {
+    pub fn record_error(&mut self, at: u64, message: String) {
+        self.errors.push(UserError { at, message });
+        if self.errors.len() > MAX_RECENT_ERRORS {
+            self.errors.remove(0);
+        }
+    }
+}
+
+/// Display information about a user's linked accounts.
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct Profile {
+    /// `username@instance` of the Mastodon account
+    pub mastodon_handle: String,
+    /// Name of the linked Swarm account
+    pub swarm_name: Option<String>,
+    /// Secret part of the user's feed URLs
+    pub feed_token: Option<String>,
+    /// Secret mixed into the signature of the user's Atom and RSS feed URLs,
+    /// replaced to revoke them. Unset until first replaced.
+    pub news_feed_secret: Option<String>,
+    /// When both accounts were first linked, unset while setup is unfinished
+    pub setup_completed_at: Option<u64>,
+    /// Scopes granted to the Mastodon token, space separated. Unset for
+    /// tokens from before they were recorded.
+    pub mastodon_scopes: Option<String>,
+}
+
+/// Whether the space separated `granted` scopes include `needed`, directly
+/// or through the broad scope it is part of, e.g. `write` for `write:media`.
+pub fn has_scope(granted: &str, needed: &str) -> bool {
+    let broad = needed.split(':').next().unwrap_or(needed);
+    granted
+        .split_whitespace()
+        .any(|scope| scope == needed || scope == broad)
+}
+
+impl Profile {
+    /// Whether the Mastodon token is known to have been granted `scope`.
+    pub fn has_mastodon_scope(&self, scope: &str) -> bool {
+        self.mastodon_scopes
+            .as_deref()
+            .map_or(false, |granted| has_scope(granted, scope))
+    }
+}
+
+/// Cached lookup of the venue a venue is part of. Venues shared by all
+/// users, so the cache isn't per user.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct VenueParent {
+    pub name: Option<String>,
+    pub fetched_at: u64,
+}
+
+/// Cached lookup of a venue's best photo on Foursquare, shared by all users
+/// like `VenueParent`.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct VenuePhoto {
+    /// Unset when the venue has no photos
+    pub url: Option<String>,
+    /// Name of whoever took the photo
+    pub credit: Option<String>,
+    pub fetched_at: u64,
+}
+
+/// In-memory state carried over a restart, with times as unix timestamps.
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct RuntimeState {
+    pub saved_at: u64,
+    /// Recent pushes of stale checkins, telling whether a re-delivery burst
+    /// is still going on
+    #[serde(default)]
+    pub stale_pushes: Vec<u64>,
+    /// Users on fast polling, with when it ends
+    #[serde(default)]
+    pub fast_poll: HashMap<String, u64>,
+    /// When each user was last polled
+    #[serde(default)]
+    pub last_polled: HashMap<String, u64>,
+    /// Checkins waiting in the sequencer, as received
+    #[serde(default)]
+    pub pending: HashMap<String, Vec<serde_json::Value>>,
+}
+
+/// What an API token may be used for.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Scope {
+    ReadHistory,
+    ManageSettings,
+    TriggerPost,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 3] = [
+        Scope::ReadHistory,
+        Scope::ManageSettings,
+        Scope::TriggerPost,
+    ];
+
+    pub fn id(self) -> &'static str {
+        match self {
+            Scope::ReadHistory => "read-history",
+            Scope::ManageSettings => "manage-settings",
+            Scope::TriggerPost => "trigger-post",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Scope::ReadHistory => "Read my checkin history",
+            Scope::ManageSettings => "View and change my settings",
+            Scope::TriggerPost => "Post checkins",
+        }
+    }
+}
+
+/// A token for the JSON API, stored under the hash of its secret.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ApiToken {
+    /// Identifies the token on the account page, unrelated to the secret
+    pub id: String,
+    pub user_key: String,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub created_at: u64,
+    #[serde(default)]
+    pub last_used_at: Option<u64>,
+}
+
+/// A record that failed to decode, see `Database::decode_all`.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct QuarantinedRecord {
+    pub tree: String,
+    /// Hex encoded key of the record in `tree`
+    pub key: String,
+    pub error: String,
+    pub at: u64,
+}
+
+/// What is known about a Mastodon instance, shared by its users.
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct InstanceInfo {
+    pub max_characters: Option<usize>,
+    pub fetched_at: u64,
+}
+
+/// A checkin as remembered in the user's history.
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+pub struct HistoryEntry {
+    pub checkin_id: String,
+    pub created_at: u64,
+    pub venue_id: String,
+    pub venue_name: String,
+    #[serde(default)]
+    pub location: Option<String>,
+    #[serde(default)]
+    pub city: Option<String>,
+    /// Name of the venue's primary category
+    #[serde(default)]
+    pub category: Option<String>,
+    #[serde(default)]
+    pub lat: Option<f64>,
+    #[serde(default)]
+    pub lng: Option<f64>,
+    #[serde(default)]
+    pub shout: Option<String>,
+    #[serde(default)]
+    pub private: bool,
+    /// The status posted to Mastodon, if it was posted
+    #[serde(default)]
+    pub status: Option<String>,
+    /// Mastodon ID of the posted status
+    #[serde(default)]
+    pub status_id: Option<String>,
+}
+
+impl From<&SwarmCheckin> for HistoryEntry {
+    fn from(checkin: &SwarmCheckin) -> Self {
+        Self {
+            checkin_id: checkin.id.clone(),
+            created_at: checkin.created_at,
+            venue_id: checkin.venue.id.clone(),
+            venue_name: checkin.venue.name.clone(),
+            location: checkin.venue.location.to_string(),
+            city: checkin.venue.location.city.clone(),
+            category: checkin
+                .venue
+                .categories_by_priority()
+                .next()
+                .map(|category| category.name.clone()),
+            lat: checkin.venue.location.lat,
+            lng: checkin.venue.location.lng,
+            shout: checkin.shout.clone(),
+            private: checkin.private.unwrap_or(false),
+            status: None,
+            status_id: None,
+        }
+    }
+}
+
+/// A processed checkin as received from Swarm, along with what became of it.
+#[derive(Deserialize, Serialize, Debug, Clone, ToSchema)]
+pub struct ArchivedCheckin {
+    pub checkin_id: String,
+    pub created_at: u64,
+    pub venue_name: String,
+    #[serde(default)]
+    pub location: Option<String>,
+    #[serde(default)]
+    pub lat: Option<f64>,
+    #[serde(default)]
+    pub lng: Option<f64>,
+    #[serde(default)]
+    pub shout: Option<String>,
+    /// Why the user's settings kept the checkin from being posted, if they did
+    #[serde(default)]
+    pub skipped: Option<String>,
+    /// Mastodon ID of the posted status
+    #[serde(default)]
+    pub status_id: Option<String>,
+    /// The checkin's JSON as received, when it was kept
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub raw: Option<serde_json::Value>,
+}
+
+impl ArchivedCheckin {
+    /// Archives a checkin as received. `skipped` is why the user's settings
+    /// kept it from being posted, if they did.
+    pub fn new(checkin: &SwarmCheckin, skipped: Option<&str>) -> Self {
+        Self {
+            checkin_id: checkin.id.clone(),
+            created_at: checkin.created_at,
+            venue_name: checkin.venue.name.clone(),
+            location: checkin.venue.location.to_string(),
+            lat: checkin.venue.location.lat,
+            lng: checkin.venue.location.lng,
+            shout: checkin.shout.clone(),
+            skipped: skipped.map(str::to_string),
+            status_id: None,
+            raw: checkin.raw.as_ref().map(|raw| (*raw.0).clone()),
+        }
+    }
+
+    /// The venue's latitude and longitude, looked up in the raw checkin for
+    /// entries archived before they were kept.
+    pub fn coordinates(&self) -> Option<(f64, f64)> {
+        if let (Some(lat), Some(lng)) = (self.lat, self.lng) {
+            return Some((lat, lng));
+        }
+        let location = self.raw.as_ref()?.get("venue")?.get("location")?;
+        Some((
+            location.get("lat")?.as_f64()?,
+            location.get("lng")?.as_f64()?,
+        ))
+    }
+}
+
+/// Expiry and refresh information for a stored token. Neither Mastodon nor
+/// Swarm issue expiring tokens today, so this stays empty for them.
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct TokenInfo {
+    pub expires_at: Option<u64>,
+    pub refresh_token: Option<String>,
+}
+
+impl TokenInfo {
+    pub fn expires_within(&self, seconds: u64) -> bool {
+        self.expires_at
+            .map_or(false, |expires_at| expires_at <= unix_now() + seconds)
+    }
+}
+
+/// Token metadata for each service a user has linked.
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct Credentials {
+    pub mastodon: TokenInfo,
+    pub swarm: TokenInfo,
+}
+
+/// A user's Swarm friends and the Mastodon accounts to mention for them.
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct Friends {
+    /// Lowercased Swarm handle to `user@instance`
+    pub by_handle: BTreeMap<String, String>,
+    /// Lowercased `"First Last"` to `user@instance`, for friends without a
+    /// handle
+    pub by_name: BTreeMap<String, String>,
+    /// Swarm user ID to `user@instance`
+    pub by_id: BTreeMap<String, String>,
+    /// Accounts that approved being mentioned, see `mention_consent`
+    pub approved: BTreeSet<String>,
+}
+
+impl Friends {
+    /// Returns the Mastodon account to mention for a Swarm user, trying the
+    /// handle first, then the user ID and finally the full name.
+    pub fn lookup(&self, user: &SwarmUser) -> Option<&String> {
+        user.handle
+            .as_deref()
+            .filter(|handle| !handle.is_empty())
+            .and_then(|handle| self.by_handle.get(&handle.to_lowercase()))
+            .or_else(|| self.by_id.get(&user.id))
+            .or_else(|| self.by_name.get(&user.full_name().to_lowercase()))
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct AuditEntry {
+    pub at: u64,
+    pub operator: String,
+    pub action: String,
+    pub target: String,
+    #[serde(flatten)]
+    pub origin: Origin,
+}
+
+impl AuditEntry {
+    /// Whether the entry records the user's own activity, like linking an
+    /// account, rather than an operator's action.
+    pub fn is_user_activity(&self) -> bool {
+        self.action.starts_with("link ") || self.action.starts_with("refresh ")
+    }
+}
+
+/// Where a request came from.
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+pub struct Origin {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl Origin {
+    pub fn describe(&self) -> String {
+        match (&self.ip, &self.user_agent) {
+            (Some(ip), Some(user_agent)) => format!("{} ({})", ip, user_agent),
+            (Some(ip), None) => ip.clone(),
+            (None, Some(user_agent)) => user_agent.clone(),
+            (None, None) => "an unknown location".to_string(),
+        }
+    }
+}
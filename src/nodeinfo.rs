@@ -0,0 +1,115 @@
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::Json;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::AppState;
+
+const NODEINFO_SCHEMA: &str = "http://nodeinfo.diaspora.software/ns/schema/2.1";
+
+#[derive(Serialize, ToSchema)]
+pub struct WellKnownLink {
+    rel: &'static str,
+    href: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct WellKnown {
+    links: Vec<WellKnownLink>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Software {
+    name: &'static str,
+    version: &'static str,
+    repository: &'static str,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Services {
+    inbound: Vec<&'static str>,
+    outbound: Vec<&'static str>,
+}
+
+#[derive(Serialize, Default, ToSchema)]
+pub struct UsageUsers {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total: Option<usize>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Usage {
+    users: UsageUsers,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeInfo {
+    version: &'static str,
+    software: Software,
+    protocols: Vec<&'static str>,
+    services: Services,
+    open_registrations: bool,
+    usage: Usage,
+    #[schema(value_type = Object)]
+    metadata: serde_json::Value,
+}
+
+#[utoipa::path(
+    get,
+    path = "/.well-known/nodeinfo",
+    responses(
+        (status = 200, description = "Links to the supported nodeinfo documents", body = WellKnown),
+    ),
+)]
+pub async fn get_well_known_nodeinfo(State(state): State<Arc<AppState>>) -> Json<WellKnown> {
+    Json(WellKnown {
+        links: vec![WellKnownLink {
+            rel: NODEINFO_SCHEMA,
+            href: format!("{}/nodeinfo/2.1", state.flags.base_url),
+        }],
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/nodeinfo/2.1",
+    responses(
+        (status = 200, description = "Nodeinfo document describing this deployment", body = NodeInfo),
+    ),
+)]
+pub async fn get_nodeinfo(State(state): State<Arc<AppState>>) -> Json<NodeInfo> {
+    // User counts are only published when the operator explicitly opts in.
+    let users = if state.flags.nodeinfo_usage {
+        UsageUsers {
+            total: Some(state.db.user.len()),
+        }
+    } else {
+        UsageUsers::default()
+    };
+
+    Json(NodeInfo {
+        version: "2.1",
+        software: Software {
+            name: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION"),
+            repository: "https://github.com/fanzeyi/swarmdon",
+        },
+        // The schema wants at least one protocol. Statuses reach the fediverse
+        // through ActivityPub, even if the users' instances speak it for us.
+        protocols: vec!["activitypub"],
+        services: Services {
+            inbound: vec![],
+            outbound: vec![],
+        },
+        open_registrations: true,
+        usage: Usage { users },
+        metadata: serde_json::json!({
+            "nodeName": state.flags.client_name,
+            "privacyPolicy": format!("{}/privacy", state.flags.base_url),
+            "about": format!("{}/about", state.flags.base_url),
+        }),
+    })
+}
@@ -0,0 +1,138 @@
+//! Tracks how far users got through linking their accounts, so someone who
+//! linked Mastodon but never finished the Swarm step is prompted to resume
+//! instead of being left half set up.
+
+use crate::html::escape;
+use crate::instances;
+use crate::model::LastSeen;
+use crate::model::Profile;
+use crate::model::User;
+use crate::model::UserSettings;
+use crate::model::UserStatus;
+use crate::AppState;
+
+/// A setup step the user still has to take.
+pub struct Step {
+    pub description: String,
+    pub link: &'static str,
+    pub action: &'static str,
+}
+
+/// Returns the steps missing before checkins can be posted, in the order
+/// they should be taken.
+pub fn missing_steps(
+    user: &User,
+    profile: &Profile,
+    status: &UserStatus,
+    settings: &UserSettings,
+) -> Vec<Step> {
+    let mut steps = Vec::new();
+    if let Some(reason) = &status.suspended {
+        steps.push(Step {
+            description: format!(
+                "Posting is paused because of a problem with your Mastodon account ({}).",
+                reason
+            ),
+            link: "/",
+            action: "Log in again",
+        });
+    }
+    let missing = missing_permissions(profile, settings);
+    if !missing.is_empty() {
+        steps.push(Step {
+            description: format!(
+                "Your Mastodon login doesn't allow {} yet.",
+                missing.join(" or ")
+            ),
+            link: "/",
+            action: "Log in again",
+        });
+    }
+    if status.swarm_token_dead_at.is_some() && !user.swarm_access_token.is_empty() {
+        steps.push(Step {
+            description: "Swarm stopped accepting access to your checkins, they aren't posted."
+                .to_string(),
+            link: "/swarm/connect",
+            action: "Connect Swarm again",
+        });
+    }
+    if user.swarm_access_token.is_empty() {
+        steps.push(Step {
+            description: if profile.setup_completed_at.is_some() {
+                "Your Swarm account is disconnected, checkins aren't posted.".to_string()
+            } else {
+                "Connect your Swarm account so your checkins can be posted.".to_string()
+            },
+            link: "/swarm/connect",
+            action: "Connect Swarm",
+        });
+    }
+    steps
+}
+
+/// Describes what the user turned on that their Mastodon token wasn't
+/// granted the scope for, as it was issued before the feature asked for it.
+fn missing_permissions(profile: &Profile, settings: &UserSettings) -> Vec<&'static str> {
+    let mut missing = Vec::new();
+    if (settings.attach_map || settings.venue_photo)
+        && !profile.has_mastodon_scope(instances::MEDIA_SCOPE)
+    {
+        missing.push("attaching maps and photos");
+    }
+    if settings.last_seen != LastSeen::Off && !profile.has_mastodon_scope(instances::ACCOUNTS_SCOPE)
+    {
+        missing.push("updating the Last seen field");
+    }
+    missing
+}
+
+/// Renders the missing steps as a list with a link for each, or nothing
+/// when setup is complete.
+pub fn render(steps: &[Step]) -> String {
+    if steps.is_empty() {
+        return String::new();
+    }
+    let items = steps
+        .iter()
+        .map(|step| {
+            format!(
+                r#"<li>{} <a href="{}">{}</a></li>"#,
+                escape(&step.description),
+                step.link,
+                step.action
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!("<p>Setup isn't finished:</p>\n<ol>\n{}\n</ol>", items)
+}
+
+/// Returns the prompt shown on the home page to a browser that is still
+/// logged in, pointing them back to where they left off.
+pub fn resume_prompt(state: &AppState, user_key: &str) -> anyhow::Result<String> {
+    let Some(user) = state.db.get_user(user_key)? else {
+        return Ok(String::new());
+    };
+    let profile = state.db.get_profile(user_key)?.unwrap_or_default();
+    let status = state.db.get_user_status(user_key)?;
+    let settings = state.db.get_settings(user_key)?;
+    let steps = missing_steps(&user, &profile, &status, &settings);
+    let Some(step) = steps.first() else {
+        return Ok(format!(
+            r#"<p>Logged in as {}. <a href="/account">Go to your account</a></p>"#,
+            escape(&profile.mastodon_handle)
+        ));
+    };
+    let title = if profile.setup_completed_at.is_some() {
+        "Your account needs attention"
+    } else {
+        "Resume setup"
+    };
+    Ok(format!(
+        r#"<p><strong>{}</strong>: {} <a href="{}">{}</a> or <a href="/account">go to your account</a>.</p>"#,
+        title,
+        escape(&step.description),
+        step.link,
+        step.action
+    ))
+}
@@ -0,0 +1,55 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::async_trait;
+use axum::extract::ConnectInfo;
+use axum::extract::FromRequestParts;
+use axum::http::header::USER_AGENT;
+use axum::http::request::Parts;
+
+use crate::model::Origin;
+use crate::AppState;
+
+/// Extracts where a request came from, for the audit log.
+pub struct RequestOrigin(pub Origin);
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for RequestOrigin {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let header = |name| {
+            parts
+                .headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+
+        // Behind a reverse proxy the peer is the proxy, prefer the client it
+        // forwarded for. Anyone can send the header otherwise, so it is only
+        // believed when the operator says a proxy sets it, and then only the
+        // address the proxy appended last.
+        let forwarded = state
+            .flags
+            .trust_proxy
+            .then(|| header("x-forwarded-for"))
+            .flatten();
+        let ip = forwarded
+            .and_then(|value| value.rsplit(',').next().map(|ip| ip.trim().to_string()))
+            .or_else(|| {
+                parts
+                    .extensions
+                    .get::<ConnectInfo<SocketAddr>>()
+                    .map(|ConnectInfo(addr)| addr.ip().to_string())
+            });
+
+        Ok(Self(Origin {
+            ip,
+            user_agent: header(USER_AGENT.as_str()),
+        }))
+    }
+}
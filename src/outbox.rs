@@ -0,0 +1,257 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::delivery;
+use crate::hooks;
+use crate::household;
+use crate::last_seen;
+use crate::model::unix_now;
+use crate::model::Hold;
+use crate::model::OutboxEntry;
+use crate::model::Post;
+use crate::schedule;
+use crate::swarm::SwarmCheckin;
+use crate::webhooks;
+use crate::AppState;
+
+const INITIAL_BACKOFF: u64 = 30;
+const MAX_BACKOFF: u64 = 60 * 60;
+const TICK: Duration = Duration::from_secs(15);
+
+fn backoff(attempts: u32) -> u64 {
+    INITIAL_BACKOFF
+        .saturating_mul(1u64 << attempts.min(16))
+        .min(MAX_BACKOFF)
+}
+
+/// `retry`, `delayed` for a posting delay or quiet hours, or `held` until the
+/// user leaves the venue.
+pub fn entry_state(entry: &OutboxEntry) -> &'static str {
+    if entry.hold.is_some() {
+        "held"
+    } else if entry.attempts == 0 && entry.next_attempt_at <= entry.created_at {
+        "delayed"
+    } else {
+        "retry"
+    }
+}
+
+/// Queues a status for another attempt after it failed to post.
+pub fn enqueue(state: &AppState, user_key: &str, checkin_id: &str, post: Post) -> Result<()> {
+    let now = unix_now();
+    state.db.enqueue_outbox(&OutboxEntry {
+        user_key: user_key.to_string(),
+        checkin_id: checkin_id.to_string(),
+        post,
+        created_at: now,
+        attempts: 0,
+        next_attempt_at: now + backoff(0),
+        hold: None,
+    })
+}
+
+/// Queues a status to be posted at `at`, e.g. after the user's posting delay
+/// or once their quiet hours are over. Failures are retried like any other queued status.
+pub fn schedule(
+    state: &AppState,
+    user_key: &str,
+    checkin_id: &str,
+    post: Post,
+    at: u64,
+) -> Result<()> {
+    state.db.enqueue_outbox(&OutboxEntry {
+        user_key: user_key.to_string(),
+        checkin_id: checkin_id.to_string(),
+        post,
+        created_at: at,
+        attempts: 0,
+        next_attempt_at: at,
+        hold: None,
+    })
+}
+
+/// Queues a status until the user checks in at another venue, see
+/// `release_held`, or until `timeout_at`.
+pub fn hold(
+    state: &AppState,
+    user_key: &str,
+    checkin: &SwarmCheckin,
+    post: Post,
+    timeout_at: u64,
+) -> Result<()> {
+    state.db.enqueue_outbox(&OutboxEntry {
+        user_key: user_key.to_string(),
+        checkin_id: checkin.id.clone(),
+        post,
+        created_at: timeout_at,
+        attempts: 0,
+        next_attempt_at: timeout_at,
+        hold: Some(Hold {
+            venue_id: checkin.venue.id.clone(),
+            checkin_at: checkin.created_at,
+        }),
+    })
+}
+
+/// Lets statuses held at other venues go now that the user checked in
+/// somewhere else.
+pub fn release_held(state: &AppState, user_key: &str, checkin: &SwarmCheckin) -> Result<()> {
+    let settings = state.db.get_settings(user_key)?;
+    let now = unix_now();
+    for (key, mut entry) in state.db.get_outbox()? {
+        let Some(hold) = &entry.hold else {
+            continue;
+        };
+        if entry.user_key != user_key
+            || hold.venue_id == checkin.venue.id
+            || hold.checkin_at >= checkin.created_at
+        {
+            continue;
+        }
+        let at = schedule::post_at(&settings, hold.checkin_at, now).unwrap_or(now);
+        tracing::info!(checkin=%entry.checkin_id, at, "user left the venue, releasing status");
+        entry.hold = None;
+        entry.created_at = at;
+        entry.next_attempt_at = at;
+        state.db.update_outbox(&key, &entry)?;
+    }
+    Ok(())
+}
+
+async fn process(state: &AppState) -> Result<()> {
+    let now = unix_now();
+    for (key, mut entry) in state.db.get_outbox()? {
+        if entry.next_attempt_at > now {
+            continue;
+        }
+
+        let Some(user) = state.db.get_user(&entry.user_key)? else {
+            tracing::info!(checkin=%entry.checkin_id, "user no longer exists, dropping queued status");
+            state.db.remove_outbox(&key)?;
+            continue;
+        };
+
+        // Checked again on delivery like for new checkins, the user may have
+        // been disabled or suspended since the status was queued.
+        let settings = state.db.get_settings(&entry.user_key)?;
+        if settings.disabled {
+            tracing::info!(checkin=%entry.checkin_id, user=%entry.user_key, "user is disabled, dropping queued status");
+            state.db.remove_outbox(&key)?;
+            continue;
+        }
+        if state
+            .db
+            .get_user_status(&entry.user_key)?
+            .suspended
+            .is_some()
+        {
+            tracing::info!(checkin=%entry.checkin_id, user=%entry.user_key, "delivery is suspended, dropping queued status");
+            state.db.remove_outbox(&key)?;
+            continue;
+        }
+        let client = match state.mastodon_clients.get(
+            &entry.user_key,
+            &user,
+            settings.mastodon_proxy.as_deref(),
+        ) {
+            Ok(client) => client,
+            Err(e) => {
+                entry.attempts += 1;
+                entry.next_attempt_at = now + backoff(entry.attempts);
+                tracing::warn!(checkin=%entry.checkin_id, ?e, "unable to build Mastodon client");
+                state.db.update_outbox(&key, &entry)?;
+                continue;
+            }
+        };
+        let result = client.new_status(entry.post.to_new_status()).await;
+
+        let e = match result {
+            Ok(posted) => {
+                tracing::info!(checkin=%entry.checkin_id, attempts=entry.attempts, "delivered queued status");
+                state.db.remove_outbox(&key)?;
+                state.db.barrier().await?;
+                state.db.record_post(
+                    &entry.user_key,
+                    &entry.checkin_id,
+                    &entry.post.status,
+                    &posted.id.to_string(),
+                )?;
+                delivery::post_replies(state, &entry.user_key, &client, &entry.post, &posted).await;
+                webhooks::status_posted(state, &entry.user_key, &entry.checkin_id, &posted);
+                hooks::status_posted(
+                    state,
+                    &entry.user_key,
+                    &entry.checkin_id,
+                    None,
+                    &entry.post.status,
+                    &posted,
+                );
+                match state
+                    .db
+                    .get_archived_checkin(&entry.user_key, &entry.checkin_id)
+                {
+                    Ok(Some(checkin)) => {
+                        last_seen::status_posted(
+                            state,
+                            &entry.user_key,
+                            &checkin.venue_name,
+                            checkin.location.as_deref(),
+                        )
+                        .await
+                    }
+                    Ok(None) => {}
+                    Err(e) => tracing::warn!(?e, "unable to look up archived checkin"),
+                }
+                household::boost(
+                    state,
+                    &entry.user_key,
+                    &user,
+                    &entry.checkin_id,
+                    &entry.post.tagged,
+                    &posted,
+                )
+                .await;
+                continue;
+            }
+            Err(e) => e,
+        };
+
+        let kind = delivery::classify(&e);
+        if !kind.is_retryable() {
+            tracing::warn!(checkin=%entry.checkin_id, ?kind, "queued status failed permanently");
+            state.db.remove_outbox(&key)?;
+            state.db.record_error(
+                &entry.user_key,
+                format!("unable to post status ({:?}): {}", kind, e),
+            )?;
+            if kind.suspends_delivery() {
+                delivery::suspend(state, &entry.user_key, kind);
+            }
+        } else if now.saturating_sub(entry.created_at) > state.flags.outbox_max_age {
+            tracing::warn!(checkin=%entry.checkin_id, ?e, "giving up on queued status");
+            state.db.remove_outbox(&key)?;
+            state
+                .db
+                .record_error(&entry.user_key, format!("gave up posting: {}", e))?;
+        } else {
+            entry.attempts += 1;
+            entry.next_attempt_at = now + backoff(entry.attempts);
+            tracing::debug!(checkin=%entry.checkin_id, ?e, ?kind, attempts=entry.attempts, "retry failed");
+            state.db.update_outbox(&key, &entry)?;
+        }
+    }
+    Ok(())
+}
+
+/// Background worker that retries queued statuses with exponential backoff.
+pub async fn run(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(TICK);
+    loop {
+        interval.tick().await;
+        if let Err(e) = process(&state).await {
+            tracing::warn!(?e, "unable to process outbox");
+        }
+    }
+}
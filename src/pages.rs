@@ -0,0 +1,82 @@
+//! Informational pages whose content operators can replace with their own
+//! markdown files.
+//!
+//! Files are read on every request so they can be edited without a restart.
+//! `{name}` and `{base_url}` are substituted using the status template syntax.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::response::Html;
+
+use crate::html::page;
+use crate::template::Template;
+use crate::AppState;
+use crate::ResultExt;
+
+const FIELDS: &[&str] = &["name", "base_url"];
+
+const DEFAULT_PRIVACY: &str = "# Privacy
+
+{name} cross-posts your Swarm checkins to your Mastodon account. To do so it stores:
+
+- your Mastodon account handle and an access token allowing it to post for you
+- your Swarm user ID, name and an access token allowing it to read your checkins
+- the IDs of checkins it has already handled, so they are not posted twice
+- a history of your checkins: venue, time, shout and the status posted for it, along with each checkin as received from Swarm, unless you turn it off
+- counts of your public checkins per venue, category and month
+- recent delivery errors, shown on your account page
+- when and from which IP address you linked your accounts
+
+The history and the records of linking your accounts are kept until you delete them, or for 7 or 90 days if you choose so on your [account page]({base_url}/account). You can delete all of the above at any time from there.
+";
+
+const DEFAULT_ABOUT: &str = "# About
+
+{name} posts your [Swarm](https://www.swarmapp.com/) checkins to Mastodon.
+
+It runs [swarmdon](https://github.com/fanzeyi/swarmdon). See the [privacy page]({base_url}/privacy) for what is stored about you.
+";
+
+fn render(
+    state: &AppState,
+    title: &str,
+    file: Option<&Path>,
+    default: &str,
+) -> anyhow::Result<Html<String>> {
+    let source = match file {
+        Some(path) => std::fs::read_to_string(path)?,
+        None => default.to_string(),
+    };
+
+    let mut values = HashMap::new();
+    values.insert("name", state.flags.client_name.clone());
+    values.insert("base_url", state.flags.base_url.clone());
+    let markdown = Template::parse_with_fields(&source, FIELDS)?.render(&values);
+
+    let mut body = String::new();
+    pulldown_cmark::html::push_html(&mut body, pulldown_cmark::Parser::new(&markdown));
+    Ok(page(title, &body))
+}
+
+pub async fn get_privacy(State(state): State<Arc<AppState>>) -> Result<Html<String>, String> {
+    render(
+        &state,
+        "Privacy",
+        state.flags.privacy_file.as_deref(),
+        DEFAULT_PRIVACY,
+    )
+    .from_err()
+}
+
+pub async fn get_about(State(state): State<Arc<AppState>>) -> Result<Html<String>, String> {
+    render(
+        &state,
+        "About",
+        state.flags.about_file.as_deref(),
+        DEFAULT_ABOUT,
+    )
+    .from_err()
+}
@@ -0,0 +1,90 @@
+//! The venue's best photo on Foursquare, attached to statuses of checkins
+//! the user didn't add photos to.
+
+use mastodon_async::Data;
+
+use crate::maps;
+use crate::model::unix_now;
+use crate::model::VenuePhoto;
+use crate::swarm::SwarmCheckin;
+use crate::swarm::SwarmUserApi;
+use crate::AppState;
+
+/// How long a looked up photo is trusted. Venues with plenty of checkins
+/// are looked up often, so this keeps them from using up the API limits.
+const PHOTO_TTL: u64 = 7 * 24 * 60 * 60;
+
+/// Width the photo is fetched at.
+const PHOTO_WIDTH: u32 = 960;
+
+/// Returns the venue's best photo, looking it up on Foursquare only when the
+/// cached answer is missing or stale.
+async fn best_photo(
+    state: &AppState,
+    swarm: &SwarmUserApi<'_>,
+    venue_id: &str,
+) -> Option<VenuePhoto> {
+    match state.db.get_venue_photo(venue_id) {
+        Ok(Some(cached)) if cached.fetched_at + PHOTO_TTL > unix_now() => return Some(cached),
+        Ok(_) => {}
+        Err(e) => tracing::warn!(?e, "unable to read cached venue photo"),
+    }
+
+    let photo = match swarm.get_venue_photo(venue_id).await {
+        Ok(photo) => photo,
+        Err(e) => {
+            tracing::warn!(?e, venue=%venue_id, "unable to look up venue photo");
+            return None;
+        }
+    };
+    let photo = VenuePhoto {
+        url: photo.as_ref().map(|photo| photo.url(PHOTO_WIDTH)),
+        credit: photo
+            .and_then(|photo| photo.user)
+            .map(|user| user.full_name()),
+        fetched_at: unix_now(),
+    };
+    if let Err(e) = state.db.save_venue_photo(venue_id, &photo) {
+        tracing::warn!(?e, "unable to cache venue photo");
+    }
+    Some(photo)
+}
+
+/// Describes the photo, crediting whoever took it.
+fn alt_text(venue: &str, credit: Option<&str>) -> String {
+    match credit {
+        Some(credit) => format!("Photo of {} by {} on Foursquare", venue, credit),
+        None => format!("Photo of {} on Foursquare", venue),
+    }
+}
+
+/// Attaches the venue's best photo to the user's next status when the
+/// checkin has no photos, returning the media ID. Failures are only logged,
+/// the status goes out without a photo. The token has to have been granted
+/// `instances::MEDIA_SCOPE`, or Mastodon rejects the upload.
+pub async fn attach(
+    state: &AppState,
+    data: &Data,
+    proxy: Option<&str>,
+    swarm: &SwarmUserApi<'_>,
+    checkin: &SwarmCheckin,
+) -> Option<String> {
+    if checkin.photos.as_ref().map_or(0, |photos| photos.count) > 0 {
+        return None;
+    }
+    let photo = best_photo(state, swarm, &checkin.venue.id).await?;
+    let url = photo.url?;
+    let result = async {
+        let (image, content_type) = maps::fetch(&url).await?;
+        let description = alt_text(&checkin.venue.name, photo.credit.as_deref());
+        maps::upload(data, proxy, "photo", image, &content_type, description).await
+    }
+    .await;
+    match result {
+        Ok(id) => Some(id),
+        Err(e) => {
+            tracing::warn!(venue=%checkin.venue.id, ?e, "unable to attach venue photo");
+            None
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::Result;
+
+use crate::model::unix_now;
+use crate::model::User;
+use crate::reconnect;
+use crate::swarm::InvalidToken;
+use crate::swarm::SwarmCheckin;
+use crate::swarm::SwarmUserApi;
+use crate::AppState;
+
+/// Number of recent checkins fetched per poll.
+const POLL_LIMIT: u32 = 20;
+
+async fn poll_user(state: Arc<AppState>, user_key: String, user: User) -> Result<()> {
+    let swarm = SwarmUserApi::new(&user.swarm_access_token);
+    let checkins = swarm.get_checkins(POLL_LIMIT, 0).await?;
+
+    let Some(last_checkin) = state.db.get_last_checkin(&user_key)? else {
+        // First time seeing this user, start from their newest checkin instead
+        // of posting their whole history.
+        if let Some(newest) = checkins.first() {
+            state.db.set_last_checkin(&user_key, newest.created_at)?;
+        }
+        return Ok(());
+    };
+
+    let missed: Vec<_> = checkins
+        .iter()
+        .take_while(|checkin| checkin.created_at > last_checkin)
+        .cloned()
+        .collect();
+
+    if !missed.is_empty() {
+        tracing::info!(user=%user_key, count=missed.len(), "found checkins missed by push");
+    }
+
+    for checkin in missed {
+        state.sequencer.submit(&state, &user_key, checkin, None);
+    }
+
+    for checkin in published(&state, &user_key, last_checkin, &checkins)? {
+        tracing::info!(user=%user_key, checkin=%checkin.id, "private checkin was made public");
+        state.db.unmark_processed(&user_key, &checkin.id)?;
+        state.db.remove_private_checkin(&user_key, &checkin.id)?;
+        state
+            .sequencer
+            .submit(&state, &user_key, checkin.clone(), None);
+    }
+
+    Ok(())
+}
+
+/// Finds checkins that were private when first seen but have since been made
+/// public. Push doesn't report the change and the scan for missed checkins
+/// has moved past them, so they'd be lost otherwise. Only checkins within
+/// the rescan window and the latest poll are considered. Private checkins are
+/// tracked on their own rather than through the history, which users may
+/// have turned off.
+fn published<'a>(
+    state: &AppState,
+    user_key: &str,
+    last_checkin: u64,
+    checkins: &'a [SwarmCheckin],
+) -> Result<Vec<&'a SwarmCheckin>> {
+    let window = state.flags.private_rescan_window;
+    if window == 0 {
+        return Ok(Vec::new());
+    }
+    let since = unix_now().saturating_sub(window);
+    let private = state.db.get_private_checkins(user_key, since)?;
+
+    Ok(checkins
+        .iter()
+        .filter(|checkin| checkin.created_at <= last_checkin && checkin.created_at >= since)
+        .filter(|checkin| !checkin.private.unwrap_or(false) && private.contains(&checkin.id))
+        .collect())
+}
+
+/// Tracks users who asked for closer polling, e.g. because they are about to
+/// check in and their push delivery is unreliable.
+#[derive(Default)]
+pub struct FastPoll {
+    until: Mutex<HashMap<String, Instant>>,
+}
+
+impl FastPoll {
+    /// Polls the user on the fast tier for the configured duration.
+    pub fn boost(&self, user_key: &str, duration: Duration) {
+        self.until
+            .lock()
+            .unwrap()
+            .insert(user_key.to_string(), Instant::now() + duration);
+    }
+
+    pub fn cancel(&self, user_key: &str) {
+        self.until.lock().unwrap().remove(user_key);
+    }
+
+    /// Users with fast polling active, with when it ends.
+    pub fn active(&self) -> HashMap<String, Instant> {
+        let now = Instant::now();
+        let mut until = self.until.lock().unwrap();
+        until.retain(|_, deadline| *deadline > now);
+        until.clone()
+    }
+
+    fn is_active(&self, user_key: &str) -> bool {
+        let mut until = self.until.lock().unwrap();
+        match until.get(user_key) {
+            Some(deadline) if *deadline > Instant::now() => true,
+            Some(_) => {
+                until.remove(user_key);
+                false
+            }
+            None => false,
+        }
+    }
+}
+
+/// When each user was last polled, kept across restarts so every user isn't
+/// polled at once after one.
+#[derive(Default)]
+pub struct LastPolled {
+    at: Mutex<HashMap<String, Instant>>,
+}
+
+impl LastPolled {
+    fn get(&self, user_key: &str) -> Option<Instant> {
+        self.at.lock().unwrap().get(user_key).copied()
+    }
+
+    fn set(&self, user_key: &str) {
+        self.at
+            .lock()
+            .unwrap()
+            .insert(user_key.to_string(), Instant::now());
+    }
+
+    pub fn all(&self) -> HashMap<String, Instant> {
+        self.at.lock().unwrap().clone()
+    }
+
+    pub fn restore(&self, times: HashMap<String, Instant>) {
+        self.at.lock().unwrap().extend(times);
+    }
+}
+
+async fn poll(state: &Arc<AppState>) -> Result<()> {
+    let interval = state.flags.poll_interval.map(Duration::from_secs);
+    for (user_key, user) in state.db.get_users()? {
+        if user.swarm_access_token.is_empty() || state.db.get_settings(&user_key)?.disabled {
+            continue;
+        }
+        let status = state.db.get_user_status(&user_key)?;
+        if status.suspended.is_some() || status.swarm_token_dead_at.is_some() {
+            continue;
+        }
+
+        let due = match (interval, state.last_polled.get(&user_key)) {
+            (Some(_), None) => true,
+            (Some(interval), Some(last)) => last.elapsed() >= interval,
+            (None, _) => false,
+        };
+        if !due && !state.fast_poll.is_active(&user_key) {
+            continue;
+        }
+        state.last_polled.set(&user_key);
+
+        let state = state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = poll_user(state.clone(), user_key.clone(), user).await {
+                if e.is::<InvalidToken>() {
+                    reconnect::token_died(&state, &user_key).await;
+                } else {
+                    tracing::warn!(user=%user_key, ?e, "unable to poll checkins");
+                }
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Background task that picks up checkins Foursquare failed to push. Every
+/// user is polled on the regular interval, if set, while users with fast
+/// polling active are polled on every tick.
+pub async fn run(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(state.flags.fast_poll_interval));
+    loop {
+        interval.tick().await;
+        if let Err(e) = poll(&state).await {
+            tracing::warn!(?e, "unable to poll checkins");
+        }
+    }
+}
@@ -0,0 +1,396 @@
+//! Opt-in recaps of a user's checkins over the past week, month or year,
+//! built from their history once the period is over in their time zone. Only
+//! checkins that were posted are counted, so a recap never gives away a venue
+//! the user kept to themselves. Users who turned the history off get no
+//! recaps.
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::Datelike;
+use chrono::NaiveDate;
+use chrono::TimeZone;
+use chrono::Utc;
+
+use crate::model::unix_now;
+use crate::model::HistoryEntry;
+use crate::model::Post;
+use crate::model::UserSettings;
+use crate::outbox;
+use crate::schedule;
+use crate::stats;
+use crate::status;
+use crate::template::Template;
+use crate::template::TemplateError;
+use crate::AppState;
+
+const TICK: Duration = Duration::from_secs(15 * 60);
+
+/// Fields that can be used in a recap template.
+pub const FIELDS: &[&str] = &[
+    "period",
+    "checkins",
+    "venues",
+    "cities",
+    "top_venue",
+    "top_venues",
+    "top_categories",
+    "new_cities",
+    "distance",
+];
+
+pub const DEFAULT_TEMPLATE: &str = "My {period}: {checkins} at {venues}{?cities} across {cities}{/cities}.{?top_venues} Most visited: {top_venues}.{/top_venues}{?top_categories} Mostly at {top_categories}.{/top_categories}{?new_cities} New cities: {new_cities}.{/new_cities}{?distance} {distance} between check-ins.{/distance}";
+
+/// Mean radius of the earth in kilometers.
+const EARTH_RADIUS: f64 = 6371.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Period {
+    Week,
+    Month,
+    Year,
+}
+
+/// A period that is over, as recapped.
+struct Span {
+    /// Identifies the period, like `2024-W05`, `2024-01` or `2024`
+    label: String,
+    /// How the period is called in the status
+    name: String,
+    from: u64,
+    to: u64,
+}
+
+impl Period {
+    pub const ALL: [Period; 3] = [Period::Week, Period::Month, Period::Year];
+
+    /// Key of the last period recapped, see `Database::get_last_recap`.
+    pub fn id(self) -> &'static str {
+        match self {
+            Period::Week => "week",
+            Period::Month => "month",
+            Period::Year => "year",
+        }
+    }
+
+    pub fn enabled(self, settings: &UserSettings) -> bool {
+        match self {
+            Period::Week => settings.weekly_recap,
+            Period::Month => settings.monthly_recap,
+            Period::Year => settings.yearly_recap,
+        }
+    }
+
+    /// Most venues, categories or cities listed.
+    fn list_length(self) -> usize {
+        match self {
+            Period::Week | Period::Month => 3,
+            Period::Year => 5,
+        }
+    }
+
+    /// The first day of the period `day` falls in.
+    fn start(self, day: NaiveDate) -> Option<NaiveDate> {
+        match self {
+            Period::Week => {
+                Some(day - chrono::Duration::days(day.weekday().num_days_from_monday() as i64))
+            }
+            Period::Month => day.with_day(1),
+            Period::Year => NaiveDate::from_ymd_opt(day.year(), 1, 1),
+        }
+    }
+
+    /// The period before the one `now` falls in, in the user's time zone.
+    fn last(self, settings: &UserSettings, now: u64) -> Option<Span> {
+        let timezone = schedule::timezone(settings);
+        let today = Utc
+            .timestamp_opt(now as i64, 0)
+            .single()?
+            .with_timezone(&timezone)
+            .date_naive();
+        let current = self.start(today)?;
+        let last = self.start(current.pred_opt()?)?;
+        let start = |day: NaiveDate| {
+            timezone
+                .from_local_datetime(&day.and_hms_opt(0, 0, 0)?)
+                .earliest()
+                .map(|at| at.timestamp() as u64)
+        };
+        let (label, name) = match self {
+            Period::Week => (
+                last.format("%G-W%V").to_string(),
+                format!("week of {}", status::format_date(settings, last)),
+            ),
+            Period::Month => (
+                last.format("%Y-%m").to_string(),
+                status::format_month(settings, last),
+            ),
+            Period::Year => (last.format("%Y").to_string(), last.format("%Y").to_string()),
+        };
+        Some(Span {
+            label,
+            name,
+            from: start(last)?,
+            to: start(current)?,
+        })
+    }
+}
+
+pub fn parse_template(source: &str) -> Result<Template, TemplateError> {
+    Template::parse_with_fields(source, FIELDS)
+}
+
+fn template(settings: &UserSettings) -> Template {
+    if let Some(source) = &settings.recap_template {
+        match parse_template(source) {
+            Ok(template) => return template,
+            Err(e) => tracing::warn!(%e, "invalid recap template, using the default"),
+        }
+    }
+    parse_template(DEFAULT_TEMPLATE).expect("the default recap template is valid")
+}
+
+fn count(count: usize, one: &str, many: &str) -> String {
+    if count == 1 {
+        format!("1 {}", one)
+    } else {
+        format!("{} {}", count, many)
+    }
+}
+
+/// Great-circle distance between two points in kilometers.
+pub fn distance((lat1, lng1): (f64, f64), (lat2, lng2): (f64, f64)) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let d_lat = lat2 - lat1;
+    let d_lng = (lng2 - lng1).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lng / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS * a.sqrt().asin()
+}
+
+/// Joins as many items as fit in a status after `prefix`.
+fn list(prefix: &str, items: &[String]) -> String {
+    let mut joined = String::new();
+    for item in items {
+        let candidate = if joined.is_empty() {
+            item.clone()
+        } else {
+            format!("{}, {}", joined, item)
+        };
+        if status::length(&format!("{}{}", prefix, candidate)) > status::MAX_CHARACTERS {
+            break;
+        }
+        joined = candidate;
+    }
+    joined
+}
+
+/// Composes the recap of the given checkins, or nothing if none of them
+/// were posted. `earlier` are the user's checkins before the period, telling
+/// which cities are new. With `recap_thread` set the lists go into replies.
+fn compose(
+    settings: &UserSettings,
+    period: Period,
+    name: &str,
+    entries: &[HistoryEntry],
+    earlier: &[HistoryEntry],
+) -> Option<(String, Vec<String>)> {
+    let posted = |entry: &&HistoryEntry| entry.status.is_some() && !entry.private;
+    let entries = entries.iter().filter(posted).collect::<Vec<_>>();
+    if entries.is_empty() {
+        return None;
+    }
+
+    let mut venues: BTreeMap<&str, (&str, u32)> = BTreeMap::new();
+    let mut categories: BTreeMap<&str, u32> = BTreeMap::new();
+    let mut cities = Vec::new();
+    for entry in &entries {
+        venues
+            .entry(entry.venue_id.as_str())
+            .or_insert((entry.venue_name.as_str(), 0))
+            .1 += 1;
+        if let Some(category) = &entry.category {
+            *categories.entry(category.as_str()).or_default() += 1;
+        }
+        if let Some(city) = entry.city.as_deref() {
+            if !cities.contains(&city) {
+                cities.push(city);
+            }
+        }
+    }
+    // A venue only stands out when the user went back to it.
+    let top_venues = stats::top(venues.values().copied())
+        .into_iter()
+        .filter(|(_, visits)| *visits > 1)
+        .take(period.list_length())
+        .map(|(name, visits)| format!("{} ({}×)", name, visits))
+        .collect::<Vec<_>>();
+    let top_categories = stats::top(categories.into_iter())
+        .into_iter()
+        .take(period.list_length())
+        .map(|(name, visits)| format!("{} ({}×)", name, visits))
+        .collect::<Vec<_>>();
+    // Without anything to compare with, every city would be new.
+    let known = earlier
+        .iter()
+        .filter(posted)
+        .filter_map(|entry| entry.city.as_deref())
+        .collect::<BTreeSet<_>>();
+    let new_cities = if known.is_empty() {
+        Vec::new()
+    } else {
+        cities
+            .iter()
+            .filter(|city| !known.contains(*city))
+            .map(|city| city.to_string())
+            .collect::<Vec<_>>()
+    };
+    let coordinates = entries
+        .iter()
+        .filter_map(|entry| Some((entry.lat?, entry.lng?)))
+        .collect::<Vec<_>>();
+    let kilometers: f64 = coordinates
+        .windows(2)
+        .map(|pair| distance(pair[0], pair[1]))
+        .sum();
+    let distance = match kilometers {
+        km if km < 1.0 => String::new(),
+        km if km < 100.0 => format!("{:.1} km", km),
+        km => format!("{:.0} km", km),
+    };
+
+    let sections = [
+        ("top_venues", "Most visited: ", top_venues.clone()),
+        ("top_categories", "Mostly at ", top_categories),
+        ("new_cities", "New cities: ", new_cities),
+    ];
+    let mut values = HashMap::new();
+    values.insert("period", name.to_string());
+    values.insert("checkins", count(entries.len(), "check-in", "check-ins"));
+    values.insert("venues", count(venues.len(), "venue", "venues"));
+    values.insert(
+        "cities",
+        if cities.len() > 1 {
+            count(cities.len(), "city", "cities")
+        } else {
+            String::new()
+        },
+    );
+    let mut replies = Vec::new();
+    if settings.recap_thread {
+        for (field, prefix, items) in sections {
+            if !items.is_empty() {
+                replies.push(format!("{}{}", prefix, list(prefix, &items)));
+            }
+            values.insert(field, String::new());
+        }
+        if !distance.is_empty() {
+            replies.push(format!("{} between check-ins.", distance));
+        }
+        values.insert("top_venue", String::new());
+        values.insert("distance", String::new());
+    } else {
+        for (field, prefix, items) in sections {
+            values.insert(field, list(prefix, &items));
+        }
+        values.insert(
+            "top_venue",
+            top_venues.into_iter().next().unwrap_or_default(),
+        );
+        values.insert("distance", distance);
+    }
+    Some((
+        template(settings).render(&values).trim().to_string(),
+        replies,
+    ))
+}
+
+async fn process(state: &AppState) -> Result<()> {
+    let now = unix_now();
+    for (user_key, _) in state.db.get_users()? {
+        let settings = state.db.get_settings(&user_key)?;
+        if settings.disabled {
+            continue;
+        }
+        for period in Period::ALL {
+            if !period.enabled(&settings) {
+                continue;
+            }
+            let Some(span) = period.last(&settings, now) else {
+                continue;
+            };
+            let post_at = span.to + settings.recap_time.unwrap_or_default() as u64 * 60;
+            if now < post_at
+                || state.db.get_last_recap(&user_key, period.id())? >= Some(span.label.clone())
+            {
+                continue;
+            }
+
+            // Without a history there is nothing to recap. The period is
+            // still marked as done, so keeping a history again doesn't recap
+            // a period it only covers part of.
+            if settings.history_disabled {
+                state
+                    .db
+                    .set_last_recap(&user_key, period.id(), &span.label)?;
+                continue;
+            }
+            let entries = state
+                .db
+                .get_history_between(&user_key, span.from, span.to)?;
+            let earlier = state.db.get_history_between(&user_key, 0, span.from)?;
+            if let Some((text, replies)) =
+                compose(&settings, period, &span.name, &entries, &earlier)
+            {
+                let post = Post {
+                    status: text,
+                    language: settings.language.clone(),
+                    replies,
+                    ..Default::default()
+                };
+                tracing::info!(user=%user_key, period=%span.label, "posting recap");
+                outbox::schedule(
+                    state,
+                    &user_key,
+                    &format!("recap-{}", span.label),
+                    post,
+                    now,
+                )?;
+            }
+            state
+                .db
+                .set_last_recap(&user_key, period.id(), &span.label)?;
+        }
+    }
+    Ok(())
+}
+
+/// Marks the period that just ended as recapped, so a user opting in gets
+/// their first recap once the current period is over rather than right away.
+pub fn skip_last(
+    state: &AppState,
+    user_key: &str,
+    settings: &UserSettings,
+    period: Period,
+) -> Result<()> {
+    if let Some(span) = period.last(settings, unix_now()) {
+        state
+            .db
+            .set_last_recap(user_key, period.id(), &span.label)?;
+    }
+    Ok(())
+}
+
+/// Background task posting recaps of periods that are over.
+pub async fn run(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(TICK);
+    loop {
+        interval.tick().await;
+        if let Err(e) = process(&state).await {
+            tracing::warn!(?e, "unable to post recaps");
+        }
+    }
+}
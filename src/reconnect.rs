@@ -0,0 +1,87 @@
+//! Asks users whose Swarm token stopped working to link Swarm again, with a
+//! direct message carrying action links straight into the Swarm step or to
+//! pause cross-posting instead.
+//!
+//! The message comes from the operator's account given by
+//! `--notify-instance-url` and `--notify-token`, or else from the user's own
+//! account, where it shows up among their direct messages.
+
+use crate::clients::http_client;
+use crate::links;
+use crate::links::Action;
+use crate::model::unix_now;
+use crate::AppState;
+
+/// Posts a direct message through the given account.
+pub async fn send(base: &str, token: &str, proxy: Option<&str>, text: &str) -> anyhow::Result<()> {
+    let url = format!("{}/api/v1/statuses", base.trim_end_matches('/'));
+    http_client(proxy)?
+        .post(url)
+        .bearer_auth(token)
+        .form(&[("status", text), ("visibility", "direct")])
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+async fn prompt(state: &AppState, user_key: &str) -> anyhow::Result<()> {
+    let Some(user) = state.db.get_user(user_key)? else {
+        return Ok(());
+    };
+    let profile = state.db.get_profile(user_key)?.unwrap_or_default();
+    let text = format!(
+        "@{} Swarm stopped accepting {}'s access to your checkins, so they aren't posted anymore. Link Swarm again within a week here: {} or stop cross-posting: {}",
+        profile.mastodon_handle,
+        state.flags.client_name,
+        links::url(state, Action::Relink, user_key, "")?,
+        links::url(state, Action::Pause, user_key, "")?
+    );
+    match (&state.flags.notify_instance_url, &state.flags.notify_token) {
+        (Some(base), Some(token)) => send(base, token, None, &text).await,
+        _ => {
+            let settings = state.db.get_settings(user_key)?;
+            send(
+                &user.mastodon.base,
+                &user.mastodon.token,
+                settings.mastodon_proxy.as_deref(),
+                &text,
+            )
+            .await
+        }
+    }
+}
+
+/// Records that Swarm rejected the user's token and, the first time, asks
+/// them to link Swarm again.
+pub async fn token_died(state: &AppState, user_key: &str) {
+    let now = unix_now();
+    let mut prompted = true;
+    let result = state.db.update_user_status(user_key, |status| {
+        if status.swarm_token_dead_at.is_none() {
+            status.swarm_token_dead_at = Some(now);
+            status.record_error(now, "Swarm rejected the access token".to_string());
+        }
+        prompted = status.reconnect_prompted_at.is_some();
+    });
+    if let Err(e) = result {
+        tracing::warn!(?e, "unable to record dead Swarm token");
+        return;
+    }
+    if prompted {
+        return;
+    }
+
+    tracing::info!(user=%user_key, "Swarm token died, asking user to reconnect");
+    match prompt(state, user_key).await {
+        Ok(()) => {
+            if let Err(e) = state
+                .db
+                .update_user_status(user_key, |status| status.reconnect_prompted_at = Some(now))
+            {
+                tracing::warn!(?e, "unable to record reconnection prompt");
+            }
+        }
+        Err(e) => tracing::warn!(user=%user_key, ?e, "unable to send reconnection prompt"),
+    }
+}
@@ -0,0 +1,85 @@
+//! Spots Foursquare re-delivering old pushes in bulk, as it does after an
+//! outage on its side, so they are paced like a catch-up instead of flooding
+//! timelines with stale statuses.
+
+use std::collections::VecDeque;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::model::unix_now;
+use crate::swarm::SwarmCheckin;
+use crate::Flags;
+
+/// Stale pushes are counted over this window to tell a burst from the odd
+/// late delivery.
+const WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Default)]
+pub struct Redelivery {
+    recent: Mutex<VecDeque<Instant>>,
+    bursting: AtomicBool,
+    stale: AtomicU64,
+}
+
+impl Redelivery {
+    /// Records a stale push. Returns whether it is part of a bulk
+    /// re-delivery, logging when one starts.
+    pub fn record(&self, flags: &Flags) -> bool {
+        self.stale.fetch_add(1, Ordering::Relaxed);
+        let now = Instant::now();
+        let mut recent = self.recent.lock().unwrap();
+        while recent
+            .front()
+            .map_or(false, |at| now.duration_since(*at) > WINDOW)
+        {
+            recent.pop_front();
+        }
+        recent.push_back(now);
+        let bursting = recent.len() >= flags.redelivery_burst;
+        if bursting != self.bursting.swap(bursting, Ordering::Relaxed) {
+            if bursting {
+                tracing::warn!(
+                    count = recent.len(),
+                    "Foursquare is re-delivering old pushes"
+                );
+            } else {
+                tracing::info!("re-delivery of old pushes is over");
+            }
+        }
+        bursting
+    }
+
+    /// Times of the stale pushes still counted towards a burst.
+    pub fn recent(&self) -> Vec<Instant> {
+        self.recent.lock().unwrap().iter().copied().collect()
+    }
+
+    /// Picks up counting from before a restart, so a burst that was being
+    /// paced stays paced.
+    pub fn restore(&self, flags: &Flags, times: Vec<Instant>) {
+        let now = Instant::now();
+        let mut recent = self.recent.lock().unwrap();
+        recent.extend(
+            times
+                .into_iter()
+                .filter(|at| now.duration_since(*at) <= WINDOW),
+        );
+        self.bursting
+            .store(recent.len() >= flags.redelivery_burst, Ordering::Relaxed);
+    }
+
+    /// Stale pushes received since startup.
+    pub fn stale(&self) -> u64 {
+        self.stale.load(Ordering::Relaxed)
+    }
+}
+
+/// Whether the checkin is older than a push normally arrives, meaning it is
+/// re-delivered or was held up somewhere.
+pub fn is_stale(flags: &Flags, checkin: &SwarmCheckin) -> bool {
+    checkin.created_at + flags.redelivery_age < unix_now()
+}
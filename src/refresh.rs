@@ -0,0 +1,133 @@
+//! Keeps expiring tokens fresh.
+//!
+//! Mastodon and Swarm tokens don't expire today, so nothing is refreshed yet.
+//! The scheduler is in place for backends that do: once a token's expiry is
+//! recorded in `Credentials`, it is refreshed ahead of time where supported,
+//! and delivery is suspended once it lapses otherwise.
+//!
+//! Swarm tokens are also checked every `HEALTH_INTERVAL`, as users revoking
+//! access on Foursquare's side is only noticed otherwise once pushes stop.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use anyhow::Result;
+
+use crate::delivery;
+use crate::model::Credentials;
+use crate::model::TokenInfo;
+use crate::reconnect;
+use crate::swarm::InvalidToken;
+use crate::swarm::SwarmUserApi;
+use crate::AppState;
+
+const TICK: Duration = Duration::from_secs(10 * 60);
+/// Tokens expiring within this many seconds are refreshed.
+const MARGIN: u64 = 60 * 60;
+/// How often every Swarm token is checked.
+const HEALTH_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
+#[derive(Debug, Clone, Copy)]
+enum Service {
+    Mastodon,
+    Swarm,
+}
+
+impl Service {
+    fn token(self, credentials: &mut Credentials) -> &mut TokenInfo {
+        match self {
+            Service::Mastodon => &mut credentials.mastodon,
+            Service::Swarm => &mut credentials.swarm,
+        }
+    }
+}
+
+/// Exchanges the refresh token for a new token. Backends supporting refresh
+/// should also update the token stored in `User` here.
+async fn refresh(service: Service, _token: &TokenInfo) -> Result<TokenInfo> {
+    match service {
+        Service::Mastodon | Service::Swarm => {
+            Err(anyhow!("{:?} does not support refreshing tokens", service))
+        }
+    }
+}
+
+async fn process(state: &AppState) -> Result<()> {
+    for (user_key, _) in state.db.get_users()? {
+        let mut credentials = state.db.get_credentials(&user_key)?;
+        let mut changed = false;
+
+        for service in [Service::Mastodon, Service::Swarm] {
+            let token = service.token(&mut credentials);
+            if !token.expires_within(MARGIN) {
+                continue;
+            }
+
+            if token.refresh_token.is_some() {
+                match refresh(service, token).await {
+                    Ok(refreshed) => {
+                        tracing::info!(user=%user_key, ?service, "refreshed token");
+                        *token = refreshed;
+                        changed = true;
+                        continue;
+                    }
+                    Err(e) => {
+                        tracing::warn!(user=%user_key, ?service, ?e, "unable to refresh token")
+                    }
+                }
+            }
+
+            if token.expires_within(0) && state.db.get_user_status(&user_key)?.suspended.is_none() {
+                delivery::suspend(state, &user_key, delivery::ErrorKind::TokenExpired);
+            }
+        }
+
+        if changed {
+            state.db.save_credentials(&user_key, &credentials)?;
+        }
+    }
+    Ok(())
+}
+
+/// Asks Swarm about every user whose token isn't known to be dead yet.
+async fn check_health(state: &AppState) -> Result<()> {
+    for (user_key, user) in state.db.get_users()? {
+        if user.swarm_access_token.is_empty()
+            || state
+                .db
+                .get_user_status(&user_key)?
+                .swarm_token_dead_at
+                .is_some()
+        {
+            continue;
+        }
+        match SwarmUserApi::new(&user.swarm_access_token).get_me().await {
+            Ok(_) => {}
+            Err(e) if e.is::<InvalidToken>() => reconnect::token_died(state, &user_key).await,
+            Err(e) => tracing::debug!(user=%user_key, ?e, "unable to check Swarm token"),
+        }
+    }
+    Ok(())
+}
+
+/// Background worker refreshing tokens before they expire and checking that
+/// Swarm still accepts them.
+pub async fn run(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(TICK);
+    let mut health = tokio::time::interval(HEALTH_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                if let Err(e) = process(&state).await {
+                    tracing::warn!(?e, "unable to refresh tokens");
+                }
+            }
+            _ = health.tick() => {
+                if let Err(e) = check_health(&state).await {
+                    tracing::warn!(?e, "unable to check Swarm tokens");
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,324 @@
+//! JSON API for users' own scripts and apps, authenticated with the API
+//! tokens from `tokens`.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::extract::Path;
+use axum::extract::Query;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::middleware;
+use axum::routing::get;
+use axum::routing::post;
+use axum::Extension;
+use axum::Json;
+use axum::Router;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::archive;
+use crate::error::ApiError;
+use crate::ingest;
+use crate::model::unix_now;
+use crate::model::ArchivedCheckin;
+use crate::model::HistoryEntry;
+use crate::model::UserSettings;
+use crate::outbox;
+use crate::tokens;
+use crate::tokens::ApiUser;
+use crate::AppState;
+
+/// Posts returned when `limit` isn't given, and the most returned at once.
+const DEFAULT_POSTS: usize = 20;
+const MAX_POSTS: usize = 200;
+
+#[derive(Serialize, ToSchema)]
+pub struct LinkedAccounts {
+    mastodon: MastodonAccount,
+    /// Unset until Swarm is connected
+    swarm: Option<SwarmAccount>,
+    /// When both accounts were first linked
+    setup_completed_at: Option<u64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct MastodonAccount {
+    handle: String,
+    instance: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SwarmAccount {
+    id: String,
+    name: Option<String>,
+    /// Swarm stopped accepting the token, so Swarm has to be connected again
+    needs_relink: bool,
+}
+
+/// A status posted for one of the user's checkins.
+#[derive(Serialize, ToSchema)]
+pub struct PostedStatus {
+    checkin_id: String,
+    created_at: u64,
+    venue_name: String,
+    status_id: String,
+    status_url: String,
+    text: Option<String>,
+}
+
+/// A status of the user waiting in the outbox.
+#[derive(Serialize, ToSchema)]
+pub struct QueuedStatus {
+    /// Opaque ID of the queued status
+    id: String,
+    checkin_id: String,
+    /// `retry`, `delayed` or `held`, see `outbox::entry_state`
+    #[schema(example = "delayed")]
+    state: &'static str,
+    attempts: u32,
+    due_at: u64,
+    text: String,
+}
+
+/// The user's settings, as changed on the account page.
+#[utoipa::path(
+    get,
+    path = "/api/v1/settings",
+    responses(
+        (status = 200, description = "The user's settings", body = UserSettings),
+        (status = 401, description = "Missing or invalid API token", body = crate::error::ErrorResponse),
+        (status = 403, description = "The API token lacks the manage-settings scope", body = crate::error::ErrorResponse),
+    ),
+    security(("api_token" = ["manage-settings"])),
+)]
+pub async fn get_settings(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<ApiUser>,
+) -> Result<Json<UserSettings>, ApiError> {
+    Ok(Json(state.db.get_settings(&user.user_key)?))
+}
+
+/// The Mastodon and Swarm accounts linked by the user.
+#[utoipa::path(
+    get,
+    path = "/api/v1/accounts",
+    responses(
+        (status = 200, description = "The linked accounts", body = LinkedAccounts),
+        (status = 401, description = "Missing or invalid API token", body = crate::error::ErrorResponse),
+        (status = 403, description = "The API token lacks the manage-settings scope", body = crate::error::ErrorResponse),
+    ),
+    security(("api_token" = ["manage-settings"])),
+)]
+pub async fn get_accounts(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<ApiUser>,
+) -> Result<Json<LinkedAccounts>, ApiError> {
+    let Some(account) = state.db.get_user(&user.user_key)? else {
+        return Err(ApiError::not_found("no such user"));
+    };
+    let profile = state.db.get_profile(&user.user_key)?.unwrap_or_default();
+    let status = state.db.get_user_status(&user.user_key)?;
+    let swarm = if account.swarm_access_token.is_empty() {
+        None
+    } else {
+        Some(SwarmAccount {
+            id: account.swarm_id.clone(),
+            name: profile.swarm_name,
+            needs_relink: status.swarm_token_dead_at.is_some(),
+        })
+    };
+    Ok(Json(LinkedAccounts {
+        mastodon: MastodonAccount {
+            handle: profile.mastodon_handle,
+            instance: account.mastodon.base.to_string(),
+        },
+        swarm,
+        setup_completed_at: profile.setup_completed_at,
+    }))
+}
+
+/// The statuses recently posted for the user's checkins, newest first, up to
+/// `limit` of them.
+#[utoipa::path(
+    get,
+    path = "/api/v1/posts",
+    params(("limit" = Option<usize>, Query, description = "Most statuses returned, 20 by default and at most 200")),
+    responses(
+        (status = 200, description = "Posted statuses, newest first", body = [PostedStatus]),
+        (status = 401, description = "Missing or invalid API token", body = crate::error::ErrorResponse),
+        (status = 403, description = "The API token lacks the read-history scope", body = crate::error::ErrorResponse),
+        (status = 400, description = "Invalid limit", body = crate::error::ErrorResponse),
+    ),
+    security(("api_token" = ["read-history"])),
+)]
+pub async fn get_posts(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<ApiUser>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Json<Vec<PostedStatus>>, ApiError> {
+    let limit = match params.get("limit") {
+        Some(limit) => limit
+            .parse::<usize>()
+            .map_err(|_| ApiError::bad_request("invalid limit"))?
+            .min(MAX_POSTS),
+        None => DEFAULT_POSTS,
+    };
+    let Some(account) = state.db.get_user(&user.user_key)? else {
+        return Err(ApiError::not_found("no such user"));
+    };
+    let profile = state.db.get_profile(&user.user_key)?.unwrap_or_default();
+    let posts = state
+        .db
+        .get_history(&user.user_key)?
+        .into_iter()
+        .filter_map(|entry| {
+            let status_id = entry.status_id?;
+            Some(PostedStatus {
+                status_url: archive::status_url(&account, &profile.mastodon_handle, &status_id),
+                checkin_id: entry.checkin_id,
+                created_at: entry.created_at,
+                venue_name: entry.venue_name,
+                status_id,
+                text: entry.status,
+            })
+        })
+        .take(limit)
+        .collect();
+    Ok(Json(posts))
+}
+
+/// The user's statuses waiting in the outbox, due first.
+#[utoipa::path(
+    get,
+    path = "/api/v1/queue",
+    responses(
+        (status = 200, description = "Queued statuses, due first", body = [QueuedStatus]),
+        (status = 401, description = "Missing or invalid API token", body = crate::error::ErrorResponse),
+        (status = 403, description = "The API token lacks the read-history scope", body = crate::error::ErrorResponse),
+    ),
+    security(("api_token" = ["read-history"])),
+)]
+pub async fn get_queue(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<ApiUser>,
+) -> Result<Json<Vec<QueuedStatus>>, ApiError> {
+    let mut queue = state
+        .db
+        .get_outbox()?
+        .into_iter()
+        .filter(|(_, entry)| entry.user_key == user.user_key)
+        .map(|(key, entry)| QueuedStatus {
+            id: hex::encode(&key),
+            state: outbox::entry_state(&entry),
+            checkin_id: entry.checkin_id,
+            attempts: entry.attempts,
+            due_at: entry.next_attempt_at,
+            text: entry.post.status,
+        })
+        .collect::<Vec<_>>();
+    queue.sort_by_key(|status| status.due_at);
+    Ok(Json(queue))
+}
+
+/// Posts a queued status right away, skipping any delay or hold.
+#[utoipa::path(
+    post,
+    path = "/api/v1/queue/{id}/send",
+    params(("id" = String, Path, description = "ID of the queued status")),
+    responses(
+        (status = 202, description = "The status is posted with the next outbox run"),
+        (status = 401, description = "Missing or invalid API token", body = crate::error::ErrorResponse),
+        (status = 403, description = "The API token lacks the trigger-post scope", body = crate::error::ErrorResponse),
+        (status = 404, description = "No such queued status", body = crate::error::ErrorResponse),
+    ),
+    security(("api_token" = ["trigger-post"])),
+)]
+pub async fn post_queue_send(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<ApiUser>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    let key = hex::decode(&id).map_err(|_| ApiError::not_found("no such queued status"))?;
+    let entry = state.db.get_outbox_entry(&key)?;
+    let Some(mut entry) = entry.filter(|entry| entry.user_key == user.user_key) else {
+        return Err(ApiError::not_found("no such queued status"));
+    };
+    entry.hold = None;
+    entry.next_attempt_at = unix_now();
+    state.db.update_outbox(&key, &entry)?;
+    tracing::info!(user=%user.user_key, checkin=%entry.checkin_id, "API released queued status");
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// The user's checkin history, newest first.
+#[utoipa::path(
+    get,
+    path = "/api/v1/history",
+    responses(
+        (status = 200, description = "Checkin history, newest first", body = [HistoryEntry]),
+        (status = 401, description = "Missing or invalid API token", body = crate::error::ErrorResponse),
+        (status = 403, description = "The API token lacks the read-history scope", body = crate::error::ErrorResponse),
+    ),
+    security(("api_token" = ["read-history"])),
+)]
+pub async fn get_history(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<ApiUser>,
+) -> Result<Json<Vec<HistoryEntry>>, ApiError> {
+    Ok(Json(state.db.get_history(&user.user_key)?))
+}
+
+/// Up to `archive::PAGE_SIZE` of the user's archived checkins whose venue,
+/// shout or location match `q`, newest first.
+#[utoipa::path(
+    get,
+    path = "/api/v1/history/search",
+    params(("q" = String, Query, description = "Words that all have to appear in the venue, shout or location")),
+    responses(
+        (status = 200, description = "Matching archived checkins, newest first", body = [ArchivedCheckin]),
+        (status = 401, description = "Missing or invalid API token", body = crate::error::ErrorResponse),
+        (status = 403, description = "The API token lacks the read-history scope", body = crate::error::ErrorResponse),
+    ),
+    security(("api_token" = ["read-history"])),
+)]
+pub async fn get_history_search(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<ApiUser>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Json<Vec<ArchivedCheckin>>, ApiError> {
+    let query = params.get("q").map(String::as_str).unwrap_or_default();
+    let mut entries = archive::search(&state, &user.user_key, query)?;
+    for entry in &mut entries {
+        entry.raw = None;
+    }
+    Ok(Json(entries))
+}
+
+pub fn routes(state: Arc<AppState>) -> Router<Arc<AppState>> {
+    let read_history = Router::new()
+        .route("/api/v1/history", get(get_history))
+        .route("/api/v1/history/search", get(get_history_search))
+        .route("/api/v1/posts", get(get_posts))
+        .route("/api/v1/queue", get(get_queue))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            tokens::require_read_history,
+        ));
+    let manage_settings = Router::new()
+        .route("/api/v1/settings", get(get_settings))
+        .route("/api/v1/accounts", get(get_accounts))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            tokens::require_manage_settings,
+        ));
+    let trigger_post = Router::new()
+        .route("/api/v1/queue/:id/send", post(post_queue_send))
+        .route("/api/v1/ingest/owntracks", post(ingest::post_owntracks))
+        .route("/api/v1/ingest/overland", post(ingest::post_overland))
+        .route_layer(middleware::from_fn_with_state(
+            state,
+            tokens::require_trigger_post,
+        ));
+    read_history.merge(manage_settings).merge(trigger_post)
+}
@@ -0,0 +1,65 @@
+//! Removes what is kept about each user once it is older than the retention
+//! they picked on their account page.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::model::unix_now;
+use crate::model::UserSettings;
+use crate::AppState;
+
+const TICK: Duration = Duration::from_secs(60 * 60);
+
+/// Prunes a single user's records, e.g. right after they shortened the
+/// retention.
+pub fn prune_user(state: &AppState, user_key: &str, settings: &UserSettings) -> Result<()> {
+    let now = unix_now();
+    if let Some(age) = settings.history_retention.seconds() {
+        state.db.prune_history(user_key, now.saturating_sub(age))?;
+    }
+    if let Some(age) = settings.audit_retention.seconds() {
+        let cutoffs = HashMap::from([(user_key.to_string(), now.saturating_sub(age))]);
+        state.db.prune_audit(&cutoffs)?;
+    }
+    Ok(())
+}
+
+async fn process(state: &AppState) -> Result<()> {
+    let now = unix_now();
+    let mut history = 0;
+    let mut audit_cutoffs = HashMap::new();
+    for (user_key, _) in state.db.get_users()? {
+        let settings = state.db.get_settings(&user_key)?;
+        if let Some(age) = settings.history_retention.seconds() {
+            history += state.db.prune_history(&user_key, now.saturating_sub(age))?;
+        }
+        if let Some(age) = settings.audit_retention.seconds() {
+            audit_cutoffs.insert(user_key, now.saturating_sub(age));
+        }
+    }
+    // The audit log isn't keyed by user, so it is gone through once for all.
+    let audit = if audit_cutoffs.is_empty() {
+        0
+    } else {
+        state.db.prune_audit(&audit_cutoffs)?
+    };
+    let links = state.db.prune_used_links(now)?;
+    if history > 0 || audit > 0 || links > 0 {
+        tracing::info!(history, audit, links, "pruned records past their retention");
+    }
+    Ok(())
+}
+
+/// Background task enforcing users' retention settings.
+pub async fn run(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(TICK);
+    loop {
+        interval.tick().await;
+        if let Err(e) = process(&state).await {
+            tracing::warn!(?e, "unable to prune records past their retention");
+        }
+    }
+}
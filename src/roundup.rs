@@ -0,0 +1,117 @@
+//! Checkins beyond a user's daily cap can be listed in a single status once
+//! their day is over, instead of being dropped.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::Utc;
+
+use crate::model::unix_now;
+use crate::model::Post;
+use crate::model::RoundupItem;
+use crate::model::UserSettings;
+use crate::outbox;
+use crate::schedule;
+use crate::status;
+use crate::AppState;
+
+const TICK: Duration = Duration::from_secs(15 * 60);
+
+/// Lists the venues, leaving out those that don't fit in a status.
+fn compose(settings: &UserSettings, day: &str, items: &[RoundupItem]) -> String {
+    let mut status = format!(
+        "Also checked in on {}:",
+        status::format_local_day(settings, day)
+    );
+    let mut left = items.len();
+    for item in items {
+        let line = format!("\n- {}", item.venue_name);
+        let more = format!("\n…and {} more", left);
+        if status::length(&format!("{}{}{}", status, line, more)) > status::MAX_CHARACTERS {
+            status.push_str(&more);
+            return status;
+        }
+        status.push_str(&line);
+        left -= 1;
+    }
+    status
+}
+
+/// Queues the roundup of `day` for posting and clears its items.
+fn post(
+    state: &AppState,
+    user_key: &str,
+    day: &str,
+    items: Vec<(sled::IVec, RoundupItem)>,
+) -> Result<()> {
+    let settings = state.db.get_settings(user_key)?;
+    let (keys, items): (Vec<_>, Vec<_>) = items.into_iter().unzip();
+    let post = Post {
+        status: compose(&settings, day, &items),
+        language: settings.language.clone(),
+        ..Default::default()
+    };
+    tracing::info!(user=%user_key, %day, count=items.len(), "posting roundup");
+    outbox::schedule(
+        state,
+        user_key,
+        &format!("roundup-{}", day),
+        post,
+        unix_now(),
+    )?;
+    for key in keys {
+        state.db.remove_roundup(&key)?;
+    }
+    Ok(())
+}
+
+/// Posts the user's roundup of `day` without waiting for the day to be over.
+pub fn post_now(state: &AppState, user_key: &str, day: &str) -> Result<()> {
+    let items = state
+        .db
+        .get_roundup()?
+        .into_iter()
+        .filter(|(_, item)| item.user_key == user_key && item.day == day)
+        .collect::<Vec<_>>();
+    if items.is_empty() {
+        return Ok(());
+    }
+    post(state, user_key, day, items)
+}
+
+async fn process(state: &AppState) -> Result<()> {
+    let now = unix_now();
+    let mut days: BTreeMap<(String, String), Vec<(sled::IVec, RoundupItem)>> = BTreeMap::new();
+    for (key, item) in state.db.get_roundup()? {
+        days.entry((item.user_key.clone(), item.day.clone()))
+            .or_default()
+            .push((key, item));
+    }
+
+    for ((user_key, day), items) in days {
+        let settings = state.db.get_settings(&user_key)?;
+        if day >= schedule::local_day(&settings, now) {
+            continue;
+        }
+        post(state, &user_key, &day, items)?;
+    }
+
+    // Counts are only needed until the day is over in every time zone.
+    let cutoff = (Utc::now() - chrono::Duration::days(2))
+        .format("%Y-%m-%d")
+        .to_string();
+    state.db.prune_daily_posts(&cutoff)
+}
+
+/// Background task posting roundups for days that are over.
+pub async fn run(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(TICK);
+    loop {
+        interval.tick().await;
+        if let Err(e) = process(&state).await {
+            tracing::warn!(?e, "unable to post roundups");
+        }
+    }
+}
@@ -0,0 +1,521 @@
+//! A small rule language deciding which checkins get posted.
+//!
+//! Every line is a rule `<condition> -> <action>`, where the action is
+//! `skip` or `post`. The first rule whose condition matches decides; `post`
+//! also overrides the other filter settings such as venue lists and opt-out
+//! markers. Empty lines and lines starting with `#` are ignored, e.g.
+//!
+//! ```text
+//! # No late night bar hopping
+//! category ~ "Bar" AND hour >= 22 -> skip
+//! venue_id = "4b0588e8f964a520e5d522e3" -> post
+//! ```
+//!
+//! A condition compares a field with a value, and conditions combine with
+//! `AND`, `OR`, `NOT` and parentheses. Text fields support `=` and `!=`,
+//! ignoring case, and `~`, matching a regular expression anywhere in the
+//! text. Number fields support `=`, `!=`, `<`, `<=`, `>` and `>=`.
+//!
+//! The venue lists, the shout requirement and the share and opt-out markers
+//! remain settings of their own rather than being turned into rules. They
+//! are edited through plain form fields that most users never outgrow, and
+//! existing settings keep working without rewriting anyone's preferences.
+//! Rules run before them so they can make exceptions either way.
+
+use std::fmt;
+
+use chrono::Datelike;
+use chrono::TimeZone;
+use chrono::Timelike;
+use chrono::Utc;
+use regex::Regex;
+use regex::RegexBuilder;
+
+use crate::model::UserSettings;
+use crate::schedule;
+use crate::swarm::SwarmCheckin;
+
+/// Longest rule accepted, in characters. Conditions chained with `AND` or
+/// `OR` nest one level per comparison, so this also bounds their depth.
+const MAX_LINE_LENGTH: usize = 1000;
+
+/// Deepest nesting of `NOT` and parentheses accepted.
+const MAX_DEPTH: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    /// Any of the venue's category names
+    Category,
+    Venue,
+    VenueId,
+    /// Name of the event checked in to, empty for plain checkins
+    Event,
+    Shout,
+    City,
+    /// Two letter country code
+    Country,
+    /// Three letter day of the week in the user's time zone, like `sat`
+    Weekday,
+    /// Hour of the checkin in the user's time zone, 0 to 23
+    Hour,
+    /// Number of friends tagged
+    With,
+}
+
+/// Fields usable in rules, for the editor's help text.
+pub const FIELDS: &[&str] = &[
+    "category", "venue", "venue_id", "event", "shout", "city", "country", "weekday", "hour", "with",
+];
+
+impl Field {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "category" => Field::Category,
+            "venue" => Field::Venue,
+            "venue_id" => Field::VenueId,
+            "event" => Field::Event,
+            "shout" => Field::Shout,
+            "city" => Field::City,
+            "country" => Field::Country,
+            "weekday" => Field::Weekday,
+            "hour" => Field::Hour,
+            "with" => Field::With,
+            _ => return None,
+        })
+    }
+
+    fn is_number(self) -> bool {
+        matches!(self, Field::Hour | Field::With)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Matches,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone)]
+enum Operand {
+    Text(String),
+    Pattern(Regex),
+    Number(i64),
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(Field, Op, Operand),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Skip,
+    Post,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    condition: Expr,
+    action: Action,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for RuleError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Text(String),
+    Number(i64),
+    Op(&'static str),
+    Open,
+    Close,
+    Arrow,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "'{}'", word),
+            Token::Text(text) => write!(f, "\"{}\"", text),
+            Token::Number(number) => write!(f, "{}", number),
+            Token::Op(op) => write!(f, "'{}'", op),
+            Token::Open => write!(f, "'('"),
+            Token::Close => write!(f, "')'"),
+            Token::Arrow => write!(f, "'->'"),
+        }
+    }
+}
+
+fn tokenize(line: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            '"' => {
+                chars.next();
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(escaped) => text.push(escaped),
+                            None => return Err("a string is never closed".into()),
+                        },
+                        Some(c) => text.push(c),
+                        None => return Err("a string is never closed".into()),
+                    }
+                }
+                tokens.push(Token::Text(text));
+            }
+            '-' => {
+                chars.next();
+                if chars.next_if_eq(&'>').is_some() {
+                    tokens.push(Token::Arrow);
+                } else {
+                    return Err("expected '->'".into());
+                }
+            }
+            '=' | '~' => {
+                chars.next();
+                tokens.push(Token::Op(if c == '=' { "=" } else { "~" }));
+            }
+            '!' | '<' | '>' => {
+                chars.next();
+                let op = match (c, chars.next_if_eq(&'=').is_some()) {
+                    ('!', true) => "!=",
+                    ('<', true) => "<=",
+                    ('>', true) => ">=",
+                    ('<', false) => "<",
+                    ('>', false) => ">",
+                    _ => return Err("expected '!='".into()),
+                };
+                tokens.push(Token::Op(op));
+            }
+            c if c.is_ascii_digit() => {
+                let mut number = String::new();
+                while let Some(digit) = chars.next_if(|c| c.is_ascii_digit()) {
+                    number.push(digit);
+                }
+                let number = number
+                    .parse()
+                    .map_err(|_| format!("number {} is too large", number))?;
+                tokens.push(Token::Number(number));
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut word = String::new();
+                while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || *c == '_') {
+                    word.push(c);
+                }
+                tokens.push(Token::Word(word));
+            }
+            c => return Err(format!("unexpected character '{}'", c)),
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    /// `NOT`s and parentheses currently open
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword) => {
+                self.position += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Runs `parse` one level deeper, failing past `MAX_DEPTH`.
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, String>,
+    ) -> Result<T, String> {
+        if self.depth == MAX_DEPTH {
+            return Err(format!("conditions nest deeper than {} levels", MAX_DEPTH));
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.keyword("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.not()?;
+        while self.keyword("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr, String> {
+        if self.keyword("not") {
+            Ok(Expr::Not(Box::new(self.nested(Self::not)?)))
+        } else {
+            self.primary()
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Open) => {
+                let expr = self.nested(Self::or)?;
+                match self.next() {
+                    Some(Token::Close) => Ok(expr),
+                    Some(token) => Err(format!("expected ')', found {}", token)),
+                    None => Err("a '(' is never closed".into()),
+                }
+            }
+            Some(Token::Word(name)) => {
+                let field =
+                    Field::parse(&name).ok_or_else(|| format!("unknown field '{}'", name))?;
+                self.comparison(field)
+            }
+            Some(token) => Err(format!("expected a field, found {}", token)),
+            None => Err("expected a field".into()),
+        }
+    }
+
+    fn comparison(&mut self, field: Field) -> Result<Expr, String> {
+        let op = match self.next() {
+            Some(Token::Op("=")) => Op::Eq,
+            Some(Token::Op("!=")) => Op::Ne,
+            Some(Token::Op("~")) => Op::Matches,
+            Some(Token::Op("<")) => Op::Lt,
+            Some(Token::Op("<=")) => Op::Le,
+            Some(Token::Op(">")) => Op::Gt,
+            Some(Token::Op(">=")) => Op::Ge,
+            Some(token) => return Err(format!("expected a comparison, found {}", token)),
+            None => return Err("expected a comparison".into()),
+        };
+        let operand = match (self.next(), field.is_number()) {
+            (Some(Token::Number(number)), true) if op != Op::Matches => Operand::Number(number),
+            (Some(Token::Text(text)), false) if op == Op::Matches => Operand::Pattern(
+                RegexBuilder::new(&text)
+                    .case_insensitive(true)
+                    .build()
+                    .map_err(|e| format!("invalid pattern \"{}\": {}", text, e))?,
+            ),
+            (Some(Token::Text(text)), false) if matches!(op, Op::Eq | Op::Ne) => {
+                Operand::Text(text)
+            }
+            (_, true) => return Err("number fields compare with a number".into()),
+            (_, false) => return Err("text fields compare with =, != or ~ and a string".into()),
+        };
+        Ok(Expr::Compare(field, op, operand))
+    }
+}
+
+fn parse_rule(line: &str) -> Result<Rule, String> {
+    if line.chars().count() > MAX_LINE_LENGTH {
+        return Err(format!(
+            "rule is longer than {} characters",
+            MAX_LINE_LENGTH
+        ));
+    }
+    let mut parser = Parser {
+        tokens: tokenize(line)?,
+        position: 0,
+        depth: 0,
+    };
+    let condition = parser.or()?;
+    match parser.next() {
+        Some(Token::Arrow) => {}
+        Some(token) => return Err(format!("expected '->', found {}", token)),
+        None => return Err("expected '->' and an action".into()),
+    }
+    let action = match parser.next() {
+        Some(Token::Word(word)) if word.eq_ignore_ascii_case("skip") => Action::Skip,
+        Some(Token::Word(word)) if word.eq_ignore_ascii_case("post") => Action::Post,
+        Some(token) => return Err(format!("expected skip or post, found {}", token)),
+        None => return Err("expected skip or post".into()),
+    };
+    if let Some(token) = parser.next() {
+        return Err(format!("unexpected {} after the action", token));
+    }
+    Ok(Rule { condition, action })
+}
+
+/// What rules are evaluated against.
+struct Facts<'a> {
+    checkin: &'a SwarmCheckin,
+    hour: i64,
+    weekday: String,
+}
+
+impl<'a> Facts<'a> {
+    fn new(settings: &UserSettings, checkin: &'a SwarmCheckin) -> Self {
+        let local = Utc
+            .timestamp_opt(checkin.created_at as i64, 0)
+            .single()
+            .unwrap_or_default()
+            .with_timezone(&schedule::timezone(settings));
+        Self {
+            checkin,
+            hour: local.hour() as i64,
+            weekday: local.weekday().to_string().to_lowercase(),
+        }
+    }
+
+    fn texts(&self, field: Field) -> Vec<&str> {
+        let venue = &self.checkin.venue;
+        match field {
+            Field::Category => venue
+                .categories
+                .iter()
+                .map(|category| category.name.as_str())
+                .collect(),
+            Field::Venue => vec![venue.name.as_str()],
+            Field::VenueId => vec![venue.id.as_str()],
+            Field::Event => vec![self
+                .checkin
+                .event
+                .as_ref()
+                .map_or("", |event| event.name.as_str())],
+            Field::Shout => vec![self.checkin.shout.as_deref().unwrap_or_default()],
+            Field::City => vec![venue.location.city.as_deref().unwrap_or_default()],
+            Field::Country => vec![venue.location.cc.as_deref().unwrap_or_default()],
+            Field::Weekday => vec![self.weekday.as_str()],
+            Field::Hour | Field::With => Vec::new(),
+        }
+    }
+
+    fn number(&self, field: Field) -> i64 {
+        match field {
+            Field::Hour => self.hour,
+            Field::With => self.checkin.with.len() as i64,
+            _ => 0,
+        }
+    }
+}
+
+impl Expr {
+    fn matches(&self, facts: &Facts) -> bool {
+        match self {
+            Expr::And(left, right) => left.matches(facts) && right.matches(facts),
+            Expr::Or(left, right) => left.matches(facts) || right.matches(facts),
+            Expr::Not(expr) => !expr.matches(facts),
+            Expr::Compare(field, op, Operand::Number(value)) => {
+                let actual = facts.number(*field);
+                match op {
+                    Op::Eq => actual == *value,
+                    Op::Ne => actual != *value,
+                    Op::Lt => actual < *value,
+                    Op::Le => actual <= *value,
+                    Op::Gt => actual > *value,
+                    Op::Ge => actual >= *value,
+                    Op::Matches => false,
+                }
+            }
+            Expr::Compare(field, Op::Ne, Operand::Text(value)) => !facts
+                .texts(*field)
+                .iter()
+                .any(|text| text.eq_ignore_ascii_case(value)),
+            Expr::Compare(field, _, Operand::Text(value)) => facts
+                .texts(*field)
+                .iter()
+                .any(|text| text.eq_ignore_ascii_case(value)),
+            Expr::Compare(field, _, Operand::Pattern(pattern)) => facts
+                .texts(*field)
+                .iter()
+                .any(|text| pattern.is_match(text)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RuleSet {
+    rules: Vec<Rule>,
+}
+
+impl RuleSet {
+    pub fn parse(source: &str) -> Result<Self, RuleError> {
+        let mut rules = Vec::new();
+        for (index, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            rules.push(parse_rule(line).map_err(|message| RuleError {
+                line: index + 1,
+                message,
+            })?);
+        }
+        Ok(Self { rules })
+    }
+
+    /// The action of the first rule matching the checkin, if any.
+    pub fn evaluate(&self, settings: &UserSettings, checkin: &SwarmCheckin) -> Option<Action> {
+        let facts = Facts::new(settings, checkin);
+        self.rules
+            .iter()
+            .find(|rule| rule.condition.matches(&facts))
+            .map(|rule| rule.action)
+    }
+}
+
+/// Evaluates the user's rules. Rules are validated when saved, so ones that
+/// fail to parse are ignored rather than reported here.
+pub fn evaluate(settings: &UserSettings, checkin: &SwarmCheckin) -> Option<Action> {
+    if settings.rules.trim().is_empty() {
+        return None;
+    }
+    RuleSet::parse(&settings.rules)
+        .ok()?
+        .evaluate(settings, checkin)
+}
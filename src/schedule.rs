@@ -0,0 +1,104 @@
+//! Decides when a composed status gets posted.
+
+use chrono::NaiveTime;
+use chrono::TimeZone;
+use chrono::Timelike;
+use chrono::Utc;
+use chrono_tz::Tz;
+
+use crate::model::QuietHours;
+use crate::model::UserSettings;
+
+/// The user's time zone, UTC when unset or unknown.
+pub fn timezone(settings: &UserSettings) -> Tz {
+    settings
+        .timezone
+        .as_deref()
+        .and_then(|timezone| timezone.parse().ok())
+        .unwrap_or(Tz::UTC)
+}
+
+/// The date of `at` in the user's time zone, as `YYYY-MM-DD`.
+pub fn local_day(settings: &UserSettings, at: u64) -> String {
+    Utc.timestamp_opt(at as i64, 0)
+        .single()
+        .unwrap_or_default()
+        .with_timezone(&timezone(settings))
+        .format("%Y-%m-%d")
+        .to_string()
+}
+
+/// Parses a time of day given as `HH:MM` into minutes after midnight.
+pub fn parse_time(value: &str) -> Result<u32, String> {
+    let time = NaiveTime::parse_from_str(value.trim(), "%H:%M")
+        .map_err(|e| format!("invalid time {}: {}", value, e))?;
+    Ok(time.hour() * 60 + time.minute())
+}
+
+pub fn format_time(minutes: u32) -> String {
+    format!("{:02}:{:02}", minutes / 60, minutes % 60)
+}
+
+fn contains(quiet: &QuietHours, minute: u32) -> bool {
+    if quiet.start <= quiet.end {
+        quiet.start <= minute && minute < quiet.end
+    } else {
+        minute >= quiet.start || minute < quiet.end
+    }
+}
+
+/// Hours a status waits for the user to leave the venue before it's posted
+/// anyway.
+pub const DEFAULT_LEAVE_TIMEOUT: u32 = 12;
+
+/// Moves `at` out of the user's quiet hours.
+fn release_at(settings: &UserSettings, at: u64) -> u64 {
+    quiet_until(settings, at).unwrap_or(at)
+}
+
+/// Returns when a checkin made at `created_at` should be posted if not right
+/// away, honoring the user's posting delay and quiet hours.
+pub fn post_at(settings: &UserSettings, created_at: u64, now: u64) -> Option<u64> {
+    let at = release_at(
+        settings,
+        now.max(created_at + u64::from(settings.post_delay) * 60),
+    );
+    (at > now).then_some(at)
+}
+
+/// When a status held until the user leaves the venue is posted anyway.
+pub fn leave_timeout_at(settings: &UserSettings, created_at: u64, now: u64) -> u64 {
+    let hours = match settings.leave_timeout {
+        0 => DEFAULT_LEAVE_TIMEOUT,
+        hours => hours,
+    };
+    let timeout = created_at + u64::from(hours) * 60 * 60;
+    post_at(settings, created_at, now)
+        .unwrap_or(now)
+        .max(release_at(settings, timeout))
+}
+
+/// If `now` falls in the user's quiet hours, returns when they end.
+pub fn quiet_until(settings: &UserSettings, now: u64) -> Option<u64> {
+    let quiet = settings.quiet_hours?;
+    let timezone = timezone(settings);
+    let local = Utc
+        .timestamp_opt(now as i64, 0)
+        .single()?
+        .with_timezone(&timezone);
+    let minute = local.hour() * 60 + local.minute();
+    if !contains(&quiet, minute) {
+        return None;
+    }
+
+    let mut date = local.date_naive();
+    if minute >= quiet.end {
+        // The window spans midnight and ends tomorrow.
+        date = date.succ_opt()?;
+    }
+    let end = date.and_time(NaiveTime::from_hms_opt(quiet.end / 60, quiet.end % 60, 0)?);
+    // An end falling into a DST gap doesn't exist locally, post right away
+    // rather than guess.
+    let end = timezone.from_local_datetime(&end).earliest()?;
+    Some(end.timestamp().max(0) as u64)
+}
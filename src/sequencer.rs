@@ -0,0 +1,103 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::sync::OwnedSemaphorePermit;
+
+use crate::redelivery;
+use crate::swarm::SwarmCheckin;
+use crate::AppState;
+
+/// How long a newly submitted checkin waits before processing starts, giving
+/// an older checkin arriving through the other path a chance to go first.
+const HOLD: Duration = Duration::from_secs(5);
+
+/// Orders checkins from push and poll per user so they are posted in the order
+/// they were created. Each user with pending checkins gets a single drain task;
+/// the presence of an entry in `queues` means that task is running.
+#[derive(Default)]
+pub struct Sequencer {
+    queues: Mutex<HashMap<String, BTreeMap<(u64, String), Pending>>>,
+}
+
+/// A checkin waiting to be posted, along with the push permit it holds, if
+/// it came in through push.
+type Pending = (SwarmCheckin, Option<OwnedSemaphorePermit>);
+
+impl Sequencer {
+    pub fn submit(
+        &self,
+        state: &Arc<AppState>,
+        user_key: &str,
+        checkin: SwarmCheckin,
+        permit: Option<OwnedSemaphorePermit>,
+    ) {
+        let mut queues = self.queues.lock().unwrap();
+        let running = queues.contains_key(user_key);
+        queues
+            .entry(user_key.to_string())
+            .or_default()
+            .insert((checkin.created_at, checkin.id.clone()), (checkin, permit));
+
+        if !running {
+            tokio::spawn(drain(state.clone(), user_key.to_string()));
+        }
+    }
+
+    /// Checkins still waiting, as received, by user. Checkins without the
+    /// JSON they came from are left out.
+    pub fn pending(&self) -> HashMap<String, Vec<serde_json::Value>> {
+        self.queues
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(user_key, queue)| {
+                let checkins = queue
+                    .values()
+                    .filter_map(|(checkin, _)| Some(checkin.raw.as_ref()?.0.as_ref().clone()))
+                    .collect();
+                (user_key.clone(), checkins)
+            })
+            .collect()
+    }
+
+    /// Takes the oldest pending checkin for the user and whether more are left
+    /// behind it. Retires the user's queue once it is empty.
+    fn pop(&self, user_key: &str) -> Option<(Pending, bool)> {
+        let mut queues = self.queues.lock().unwrap();
+        let queue = queues.get_mut(user_key)?;
+        match queue.pop_first() {
+            Some((_, checkin)) => Some((checkin, !queue.is_empty())),
+            None => {
+                queues.remove(user_key);
+                None
+            }
+        }
+    }
+}
+
+async fn drain(state: Arc<AppState>, user_key: String) {
+    tokio::time::sleep(HOLD).await;
+
+    let pacing = Duration::from_secs(state.flags.poll_pacing);
+    while let Some(((checkin, permit), more)) = state.sequencer.pop(&user_key) {
+        // Stale checkins from catch-ups are spaced out even when they trickle
+        // in one by one, as in a bulk re-delivery. Staying around for the
+        // pacing lets the next one queue up behind.
+        let paced = more || (permit.is_none() && redelivery::is_stale(&state.flags, &checkin));
+        match state.db.get_user(&user_key) {
+            Ok(Some(user)) => crate::post_checkin(&state, &user_key, &user, checkin).await,
+            Ok(None) => tracing::warn!(user=%user_key, "user disappeared, dropping checkin"),
+            Err(e) => tracing::warn!(user=%user_key, ?e, "unable to load user"),
+        }
+
+        // Space out bursts, e.g. catch-ups, to stay under instance rate
+        // limits and avoid flooding followers' timelines.
+        drop(permit);
+        if paced {
+            tokio::time::sleep(pacing).await;
+        }
+    }
+}
@@ -0,0 +1,229 @@
+//! Signatures of outbound webhook deliveries.
+//!
+//! Every delivery carries a `Swarmdon-Signature` header like
+//! `t=1700000000,v1=5257a869...`: the unix time it was signed at and the hex
+//! encoded HMAC-SHA256 of `"{t}.{body}"` under the webhook secret. Signing
+//! the time along with the body lets receivers turn away recorded deliveries
+//! replayed later, by rejecting timestamps outside a short window.
+
+use std::fmt;
+
+use hmac::Hmac;
+use hmac::Mac;
+use sha2::Sha256;
+
+/// Name of the header carrying the signature.
+pub const HEADER: &str = "Swarmdon-Signature";
+
+/// Seconds a delivery is accepted after it was signed, and before to allow
+/// for clock skew.
+pub const DEFAULT_TOLERANCE: u64 = 5 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureError {
+    /// The header isn't in the `t=...,v1=...` format.
+    Malformed,
+    /// The delivery was signed too long ago, or too far in the future.
+    Expired,
+    /// No signature in the header matches the body.
+    Mismatch,
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureError::Malformed => write!(f, "malformed signature header"),
+            SignatureError::Expired => write!(f, "signature timestamp outside the tolerance"),
+            SignatureError::Mismatch => write!(f, "signature does not match"),
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {}
+
+fn mac(secret: &[u8], timestamp: u64, body: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac
+}
+
+/// Returns the signature header value for a delivery of `body` at
+/// `timestamp`.
+pub fn sign(secret: &[u8], timestamp: u64, body: &[u8]) -> String {
+    format!(
+        "t={},v1={}",
+        timestamp,
+        hex::encode(mac(secret, timestamp, body).finalize().into_bytes())
+    )
+}
+
+/// Checks a delivery's signature header against the raw request body, as
+/// received and before any parsing. `now` is the current unix time and
+/// `tolerance` how many seconds old a delivery may be, usually
+/// `DEFAULT_TOLERANCE`. Headers may carry several `v1` signatures while the
+/// secret is rotated, any of them matching is enough.
+///
+/// ```
+/// use swarmdon::signature;
+///
+/// let body = br#"{"event":"status.posted"}"#;
+/// let header = signature::sign(b"secret", 1_700_000_000, body);
+/// assert!(signature::verify(b"secret", &header, body, 1_700_000_060, 300).is_ok());
+/// assert!(signature::verify(b"other", &header, body, 1_700_000_060, 300).is_err());
+/// assert!(signature::verify(b"secret", &header, body, 1_700_001_000, 300).is_err());
+/// ```
+pub fn verify(
+    secret: &[u8],
+    header: &str,
+    body: &[u8],
+    now: u64,
+    tolerance: u64,
+) -> Result<(), SignatureError> {
+    let mut timestamp = None;
+    let mut signatures = Vec::new();
+    for part in header.split(',') {
+        match part.trim().split_once('=') {
+            Some(("t", value)) => {
+                timestamp = Some(
+                    value
+                        .parse::<u64>()
+                        .map_err(|_| SignatureError::Malformed)?,
+                )
+            }
+            Some(("v1", value)) => {
+                signatures.push(hex::decode(value).map_err(|_| SignatureError::Malformed)?)
+            }
+            // Unknown schemes are skipped, so new ones can be added.
+            Some(_) => {}
+            None => return Err(SignatureError::Malformed),
+        }
+    }
+    let timestamp = timestamp.ok_or(SignatureError::Malformed)?;
+    if signatures.is_empty() {
+        return Err(SignatureError::Malformed);
+    }
+    if timestamp.abs_diff(now) > tolerance {
+        return Err(SignatureError::Expired);
+    }
+    let mac = mac(secret, timestamp, body);
+    if signatures
+        .iter()
+        .any(|signature| mac.clone().verify_slice(signature).is_ok())
+    {
+        Ok(())
+    } else {
+        Err(SignatureError::Mismatch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &[u8] = b"secret";
+    const BODY: &[u8] = br#"{"event":"status.posted"}"#;
+    const NOW: u64 = 1_700_000_000;
+
+    /// The hex encoded `v1` signature of `body` at `timestamp`.
+    fn v1(secret: &[u8], timestamp: u64, body: &[u8]) -> String {
+        sign(secret, timestamp, body)
+            .split_once(",v1=")
+            .unwrap()
+            .1
+            .to_string()
+    }
+
+    #[test]
+    fn round_trip() {
+        let header = sign(SECRET, NOW, BODY);
+        assert_eq!(
+            verify(SECRET, &header, BODY, NOW, DEFAULT_TOLERANCE),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn malformed_headers() {
+        let signature = v1(SECRET, NOW, BODY);
+        for header in [
+            String::new(),
+            format!("v1={}", signature),
+            format!("t=soon,v1={}", signature),
+            format!("t=-5,v1={}", signature),
+            format!("t={}", NOW),
+            format!("t={},v1=not-hex", NOW),
+            format!("t={},garbage", NOW),
+        ] {
+            assert_eq!(
+                verify(SECRET, &header, BODY, NOW, DEFAULT_TOLERANCE),
+                Err(SignatureError::Malformed),
+                "{}",
+                header
+            );
+        }
+    }
+
+    #[test]
+    fn any_of_several_signatures() {
+        let header = format!(
+            "t={},v1={},v1={}",
+            NOW,
+            v1(b"old secret", NOW, BODY),
+            v1(SECRET, NOW, BODY)
+        );
+        assert_eq!(
+            verify(SECRET, &header, BODY, NOW, DEFAULT_TOLERANCE),
+            Ok(())
+        );
+        assert_eq!(
+            verify(b"other", &header, BODY, NOW, DEFAULT_TOLERANCE),
+            Err(SignatureError::Mismatch)
+        );
+    }
+
+    #[test]
+    fn timestamps_outside_the_tolerance() {
+        let tolerance = 300;
+        for (signed_at, result) in [
+            (NOW - tolerance, Ok(())),
+            (NOW + tolerance, Ok(())),
+            (NOW - tolerance - 1, Err(SignatureError::Expired)),
+            (NOW + tolerance + 1, Err(SignatureError::Expired)),
+        ] {
+            let header = sign(SECRET, signed_at, BODY);
+            assert_eq!(
+                verify(SECRET, &header, BODY, NOW, tolerance),
+                result,
+                "{}",
+                signed_at
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_schemes_are_ignored() {
+        let header = format!("t={},v0=abc,v1={},v2=def", NOW, v1(SECRET, NOW, BODY));
+        assert_eq!(
+            verify(SECRET, &header, BODY, NOW, DEFAULT_TOLERANCE),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn tampered_bodies() {
+        let header = sign(SECRET, NOW, BODY);
+        let tampered = br#"{"event":"status.deleted"}"#;
+        assert_eq!(
+            verify(SECRET, &header, tampered, NOW, DEFAULT_TOLERANCE),
+            Err(SignatureError::Mismatch)
+        );
+        // Moving the timestamp invalidates the signature too.
+        let replayed = header.replace(&NOW.to_string(), &(NOW + 60).to_string());
+        assert_eq!(
+            verify(SECRET, &replayed, BODY, NOW + 60, DEFAULT_TOLERANCE),
+            Err(SignatureError::Mismatch)
+        );
+    }
+}
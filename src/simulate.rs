@@ -0,0 +1,445 @@
+//! Synthetic load through the checkin pipeline, to catch performance
+//! regressions before a release.
+//!
+//! Checkins for made-up users arrive at a steady rate and go through the
+//! same decoding, dedupe, history, formatting and outbox code as real ones,
+//! on a temporary database. Swarm and Mastodon are mocked: details are built
+//! from the checkin itself and posting only waits `--upstream-latency-ms`.
+//! Each stage's latencies are reported, and with `--baseline` compared to
+//! an earlier report.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::Result;
+use clap::Parser;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use serde_json::Value;
+
+use crate::model::unix_now;
+use crate::model::ArchivedCheckin;
+use crate::model::Database;
+use crate::model::Friends;
+use crate::model::HistoryEntry;
+use crate::model::OutboxEntry;
+use crate::model::Post;
+use crate::model::UserSettings;
+use crate::status;
+use crate::swarm::SwarmCheckin;
+use crate::swarm::SwarmCheckinDetail;
+
+#[derive(Debug, Parser)]
+pub struct Options {
+    /// Number of synthetic users
+    #[clap(long, default_value = "100")]
+    users: u32,
+
+    /// Checkins per minute, across all users
+    #[clap(long, default_value = "600")]
+    rate: u32,
+
+    /// Seconds to generate checkins for
+    #[clap(long, default_value = "60")]
+    duration: u64,
+
+    /// Share of checkins delivered a second time, as when both a push and a
+    /// poll pick one up
+    #[clap(long, default_value = "0.1")]
+    duplicates: f64,
+
+    /// Time the mocked Mastodon takes to accept a status
+    #[clap(long, default_value = "50")]
+    upstream_latency_ms: u64,
+
+    /// Seed for the generated checkins, so runs can be compared
+    #[clap(long, default_value = "1")]
+    seed: u64,
+
+    /// Write the report as JSON to this file
+    #[clap(long)]
+    report: Option<PathBuf>,
+
+    /// Report of an earlier run to compare with, failing if a stage got
+    /// slower than `--tolerance` allows
+    #[clap(long)]
+    baseline: Option<PathBuf>,
+
+    /// How much slower a stage's 95th percentile may get than in the
+    /// baseline, e.g. 0.2 for 20%
+    #[clap(long, default_value = "0.2")]
+    tolerance: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Stage {
+    /// Parsing the pushed checkin and the details from the mocked Swarm
+    Decode,
+    /// Marking the checkin processed, dropping duplicates
+    Dedupe,
+    /// Recording the history and archive
+    History,
+    /// Skip rules and composing the status
+    Format,
+    /// Queueing the status in the outbox
+    Queue,
+    /// Going through the outbox and recording posts
+    Deliver,
+}
+
+impl Stage {
+    const ALL: [Stage; 6] = [
+        Stage::Decode,
+        Stage::Dedupe,
+        Stage::History,
+        Stage::Format,
+        Stage::Queue,
+        Stage::Deliver,
+    ];
+
+    fn id(self) -> &'static str {
+        match self {
+            Stage::Decode => "decode",
+            Stage::Dedupe => "dedupe",
+            Stage::History => "history",
+            Stage::Format => "format",
+            Stage::Queue => "queue",
+            Stage::Deliver => "deliver",
+        }
+    }
+}
+
+#[derive(Default)]
+struct Timings(Mutex<BTreeMap<Stage, Vec<Duration>>>);
+
+impl Timings {
+    fn time<T>(&self, stage: Stage, f: impl FnOnce() -> T) -> T {
+        let started = Instant::now();
+        let result = f();
+        let elapsed = started.elapsed();
+        self.0
+            .lock()
+            .expect("timings lock is poisoned")
+            .entry(stage)
+            .or_default()
+            .push(elapsed);
+        result
+    }
+}
+
+/// Latencies of a stage in microseconds.
+#[derive(Debug, Deserialize, Serialize)]
+struct StageReport {
+    count: usize,
+    p50: u64,
+    p95: u64,
+    p99: u64,
+    max: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Report {
+    checkins: u64,
+    posted: u64,
+    /// Checkins processed per second
+    throughput: f64,
+    stages: BTreeMap<String, StageReport>,
+}
+
+/// xorshift64*, plenty for picking venues and shouts.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn chance(&mut self, p: f64) -> bool {
+        ((self.next() >> 11) as f64 / (1u64 << 53) as f64) < p
+    }
+}
+
+const CITIES: &[(&str, &str, &str, f64, f64)] = &[
+    ("Seattle", "WA", "US", 47.61, -122.33),
+    ("Portland", "OR", "US", 45.52, -122.68),
+    ("Berlin", "Berlin", "DE", 52.52, 13.40),
+    ("Tokyo", "Tokyo", "JP", 35.68, 139.69),
+    ("Taipei", "Taipei", "TW", 25.03, 121.56),
+];
+
+const SHOUTS: &[&str] = &[
+    "",
+    "Lunch",
+    "Finally trying the ramen everyone talks about 🍜",
+    "Meeting the team for the quarterly planning session, bringing snacks for everyone #work",
+    "Long shout to exercise splitting into replies. The quick brown fox jumps over the lazy dog, again and again, until the status runs out of characters and the rest of the text has to be posted in a thread below it. Then a bit more, just to be sure it really doesn't fit into a single status anymore.",
+];
+
+/// A checkin as Swarm would push it.
+fn checkin(rng: &mut Rng, user: u32, id: u64, created_at: u64) -> Value {
+    let venue = rng.below(500);
+    let (city, state, cc, lat, lng) = CITIES[venue as usize % CITIES.len()];
+    let mut checkin = json!({
+        "id": format!("sim{:016x}", id),
+        "type": "checkin",
+        "createdAt": created_at,
+        "shout": SHOUTS[rng.below(SHOUTS.len() as u64) as usize],
+        "user": { "id": format!("swarm{}", user), "firstName": "Sim", "lastName": user.to_string() },
+        "venue": {
+            "id": format!("venue{}", venue),
+            "name": format!("Venue {}", venue),
+            "location": {
+                "city": city,
+                "state": state,
+                "cc": cc,
+                "lat": lat + (venue as f64) / 10_000.0,
+                "lng": lng,
+            },
+            "categories": [{ "id": "cat", "name": "Restaurant", "primary": true }],
+        },
+    });
+    if rng.chance(0.2) {
+        checkin["with"] =
+            json!([{ "id": "friend", "firstName": "Friend", "lastName": "", "handle": "friend" }]);
+    }
+    checkin
+}
+
+/// Details of a checkin as the mocked Swarm returns them.
+fn details(checkin: &Value) -> Value {
+    let mut details = checkin.clone();
+    details["checkinShortUrl"] = json!("https://swarmapp.com/c/simulated");
+    details["venue"]["beenHere"] = json!({ "count": 3 });
+    details
+}
+
+fn process(
+    db: &Database,
+    timings: &Timings,
+    settings: &UserSettings,
+    friends: &Friends,
+    user_key: &str,
+    payload: Value,
+) -> Result<()> {
+    let (checkin, details) = timings.time(Stage::Decode, || -> Result<_> {
+        let details: SwarmCheckinDetail = serde_json::from_value(details(&payload))?;
+        Ok((SwarmCheckin::from_value(payload)?, details))
+    })?;
+    let fresh = timings.time(Stage::Dedupe, || {
+        db.mark_processed(user_key, &checkin.id, checkin.created_at)
+    })?;
+    if !fresh {
+        return Ok(());
+    }
+    let skipped = crate::skip_reason(settings, &checkin);
+    timings.time(Stage::History, || -> Result<()> {
+        db.set_last_checkin(user_key, checkin.created_at)?;
+        db.record_history(user_key, &HistoryEntry::from(&checkin))?;
+        db.archive_checkin(user_key, &ArchivedCheckin::new(&checkin, skipped))
+    })?;
+    if skipped.is_some() {
+        return Ok(());
+    }
+    let lookups = status::Lookups {
+        parent_venue: None,
+        max_characters: Some(status::MAX_CHARACTERS),
+    };
+    let post = timings.time(Stage::Format, || {
+        let (text, replies) = status::compose(settings, friends, &checkin, &details, &lookups);
+        Post {
+            status: text,
+            language: status::language(settings, &checkin),
+            replies,
+            ..Default::default()
+        }
+    });
+    timings.time(Stage::Queue, || {
+        db.enqueue_outbox(&OutboxEntry {
+            user_key: user_key.to_string(),
+            checkin_id: checkin.id.clone(),
+            post,
+            created_at: checkin.created_at,
+            attempts: 0,
+            next_attempt_at: checkin.created_at,
+            hold: None,
+        })
+    })
+}
+
+/// Posts what's due in the outbox to the mocked Mastodon, returning how many
+/// statuses were posted.
+async fn deliver(db: &Database, timings: &Timings, latency: Duration) -> Result<u64> {
+    let now = unix_now();
+    let due = timings.time(Stage::Deliver, || db.get_outbox())?;
+    let mut posted = 0;
+    for (key, entry) in due {
+        if entry.next_attempt_at > now {
+            continue;
+        }
+        tokio::time::sleep(latency).await;
+        timings.time(Stage::Deliver, || -> Result<()> {
+            let status_id = format!("{}", 100_000 + posted);
+            db.record_post(
+                &entry.user_key,
+                &entry.checkin_id,
+                &entry.post.status,
+                &status_id,
+            )?;
+            db.remove_outbox(&key)
+        })?;
+        posted += 1;
+    }
+    Ok(posted)
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> u64 {
+    let index = ((sorted.len() as f64 * p).ceil() as usize).clamp(1, sorted.len()) - 1;
+    sorted[index].as_micros() as u64
+}
+
+fn summarize(timings: &Timings, checkins: u64, posted: u64, elapsed: Duration) -> Report {
+    let mut stages = BTreeMap::new();
+    for (stage, mut samples) in
+        std::mem::take(&mut *timings.0.lock().expect("timings lock is poisoned"))
+    {
+        if samples.is_empty() {
+            continue;
+        }
+        samples.sort();
+        stages.insert(
+            stage.id().to_string(),
+            StageReport {
+                count: samples.len(),
+                p50: percentile(&samples, 0.5),
+                p95: percentile(&samples, 0.95),
+                p99: percentile(&samples, 0.99),
+                max: samples.last().map_or(0, |max| max.as_micros() as u64),
+            },
+        );
+    }
+    Report {
+        checkins,
+        posted,
+        throughput: checkins as f64 / elapsed.as_secs_f64(),
+        stages,
+    }
+}
+
+/// Stages whose 95th percentile got slower than the baseline allows.
+fn regressions(report: &Report, baseline: &Report, tolerance: f64) -> Vec<String> {
+    Stage::ALL
+        .iter()
+        .filter_map(|stage| {
+            let now = report.stages.get(stage.id())?;
+            let before = baseline.stages.get(stage.id())?;
+            // Sub-millisecond jitter isn't worth failing a release over.
+            let allowed = (before.p95 as f64 * (1.0 + tolerance)).max(before.p95 as f64 + 1000.0);
+            (now.p95 as f64 > allowed)
+                .then(|| format!("{}: p95 {}µs, was {}µs", stage.id(), now.p95, before.p95))
+        })
+        .collect()
+}
+
+pub async fn run(options: Options) -> Result<()> {
+    let db = Arc::new(Database::temporary()?);
+    let timings = Arc::new(Timings::default());
+    let settings = Arc::new(UserSettings::default());
+    let mut friends = Friends::default();
+    friends
+        .by_handle
+        .insert("friend".to_string(), "friend@example.com".to_string());
+    let friends = Arc::new(friends);
+    let latency = Duration::from_millis(options.upstream_latency_ms);
+
+    let total = options.rate as u64 * options.duration / 60;
+    let mut ticks = tokio::time::interval(Duration::from_secs(60) / options.rate.max(1));
+    let mut rng = Rng(options.seed.max(1));
+    let mut recent: Vec<(String, Value)> = Vec::new();
+    let mut tasks = Vec::new();
+    let mut posted = 0;
+    let mut delivered_at = Instant::now();
+    let started = Instant::now();
+    println!(
+        "simulating {} checkins from {} users over {}s",
+        total, options.users, options.duration
+    );
+
+    for id in 0..total {
+        ticks.tick().await;
+        let (user_key, payload) = match recent.len() {
+            len if len > 0 && rng.chance(options.duplicates) => {
+                recent[rng.below(len as u64) as usize].clone()
+            }
+            _ => {
+                let user = rng.below(options.users.max(1) as u64) as u32;
+                let user_key = format!("https://sim.example:{}", user);
+                let payload = checkin(&mut rng, user, id, unix_now());
+                (user_key, payload)
+            }
+        };
+        if recent.len() >= 100 {
+            recent.remove(0);
+        }
+        recent.push((user_key.clone(), payload.clone()));
+
+        let (db, timings, settings, friends) = (
+            db.clone(),
+            timings.clone(),
+            settings.clone(),
+            friends.clone(),
+        );
+        tasks.push(tokio::task::spawn_blocking(move || {
+            process(&db, &timings, &settings, &friends, &user_key, payload)
+        }));
+
+        if delivered_at.elapsed() >= Duration::from_secs(1) {
+            posted += deliver(&db, &timings, latency).await?;
+            delivered_at = Instant::now();
+        }
+    }
+    for task in tasks {
+        task.await??;
+    }
+    posted += deliver(&db, &timings, latency).await?;
+
+    let report = summarize(&timings, total, posted, started.elapsed());
+    println!(
+        "{} checkins, {} posted, {:.1} checkins/s",
+        report.checkins, report.posted, report.throughput
+    );
+    println!(
+        "{:<8} {:>8} {:>10} {:>10} {:>10} {:>10}",
+        "stage", "count", "p50 µs", "p95 µs", "p99 µs", "max µs"
+    );
+    for (stage, stats) in &report.stages {
+        println!(
+            "{:<8} {:>8} {:>10} {:>10} {:>10} {:>10}",
+            stage, stats.count, stats.p50, stats.p95, stats.p99, stats.max
+        );
+    }
+    if let Some(path) = &options.report {
+        std::fs::write(path, serde_json::to_vec_pretty(&report)?)?;
+        println!("report written to {}", path.display());
+    }
+    if let Some(path) = &options.baseline {
+        let baseline: Report = serde_json::from_slice(&std::fs::read(path)?)?;
+        let regressions = regressions(&report, &baseline, options.tolerance);
+        if !regressions.is_empty() {
+            anyhow::bail!("slower than {}: {}", path.display(), regressions.join(", "));
+        }
+        println!("no regressions against {}", path.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,126 @@
+//! Carries state only held in memory over a restart, such as a deploy.
+//!
+//! Without it a restarted server polls every user at once, forgets a
+//! re-delivery burst it was pacing, drops fast polling users asked for and
+//! loses checkins waiting in the sequencer. Delivery suspensions and outbox
+//! retries are in the database already and need nothing here.
+
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::Result;
+
+use crate::model::unix_now;
+use crate::model::RuntimeState;
+use crate::swarm::SwarmCheckin;
+use crate::AppState;
+
+/// Converts a point in time to a unix timestamp, relative to `now`, which is
+/// `unix` in unix time.
+fn to_unix(at: Instant, now: Instant, unix: u64) -> u64 {
+    if at > now {
+        unix + (at - now).as_secs()
+    } else {
+        unix.saturating_sub((now - at).as_secs())
+    }
+}
+
+/// Reverses `to_unix`. `None` when the time can't be told apart from a
+/// fresh start, e.g. when it is before the machine booted.
+fn from_unix(at: u64, now: Instant, unix: u64) -> Option<Instant> {
+    if at > unix {
+        now.checked_add(Duration::from_secs(at - unix))
+    } else {
+        now.checked_sub(Duration::from_secs(unix - at))
+    }
+}
+
+/// Stores the state, meant to run once the server stopped taking requests.
+pub fn save(state: &AppState) -> Result<()> {
+    let now = Instant::now();
+    let unix = unix_now();
+    let snapshot = RuntimeState {
+        saved_at: unix,
+        stale_pushes: state
+            .redelivery
+            .recent()
+            .into_iter()
+            .map(|at| to_unix(at, now, unix))
+            .collect(),
+        fast_poll: state
+            .fast_poll
+            .active()
+            .into_iter()
+            .map(|(user_key, until)| (user_key, to_unix(until, now, unix)))
+            .collect(),
+        last_polled: state
+            .last_polled
+            .all()
+            .into_iter()
+            .map(|(user_key, at)| (user_key, to_unix(at, now, unix)))
+            .collect(),
+        pending: state.sequencer.pending(),
+    };
+    let pending = snapshot.pending.values().map(Vec::len).sum::<usize>();
+    state.db.save_runtime_state(&snapshot)?;
+    tracing::info!(pending, "saved in-memory state");
+    Ok(())
+}
+
+/// Restores the state saved on the last shutdown, if any. Pending checkins
+/// go back into the sequencer, where the processed marks keep any that were
+/// posted after all from going out twice.
+pub fn restore(state: &Arc<AppState>) {
+    let snapshot = match state.db.take_runtime_state() {
+        Ok(Some(snapshot)) => snapshot,
+        Ok(None) => return,
+        Err(e) => {
+            tracing::warn!(?e, "unable to read saved in-memory state, starting afresh");
+            return;
+        }
+    };
+    let now = Instant::now();
+    let unix = unix_now();
+
+    state.redelivery.restore(
+        &state.flags,
+        snapshot
+            .stale_pushes
+            .into_iter()
+            .filter_map(|at| from_unix(at, now, unix))
+            .collect(),
+    );
+    for (user_key, until) in snapshot.fast_poll {
+        if until > unix {
+            state
+                .fast_poll
+                .boost(&user_key, Duration::from_secs(until - unix));
+        }
+    }
+    state.last_polled.restore(
+        snapshot
+            .last_polled
+            .into_iter()
+            .filter_map(|(user_key, at)| Some((user_key, from_unix(at, now, unix)?)))
+            .collect(),
+    );
+
+    let mut pending = 0;
+    for (user_key, checkins) in snapshot.pending {
+        for value in checkins {
+            match SwarmCheckin::from_value(value) {
+                Ok(checkin) => {
+                    state.sequencer.submit(state, &user_key, checkin, None);
+                    pending += 1;
+                }
+                Err(e) => tracing::warn!(user=%user_key, ?e, "unable to restore pending checkin"),
+            }
+        }
+    }
+    tracing::info!(
+        pending,
+        age = unix.saturating_sub(snapshot.saved_at),
+        "restored in-memory state"
+    );
+}
@@ -0,0 +1,106 @@
+//! Aggregated checkin counts, updated as checkins come in so they survive
+//! users turning off the history.
+
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::headers::Cookie;
+use axum::response::Html;
+use axum::TypedHeader;
+
+use crate::html::escape;
+use crate::html::page;
+use crate::model::CheckinStats;
+use crate::model::UserSettings;
+use crate::schedule;
+use crate::swarm::SwarmCheckin;
+use crate::AppState;
+use crate::ResultExt;
+
+/// Rows shown per table on the stats page.
+const TOP: usize = 10;
+
+/// Counts a checkin. Private checkins stay out, like everywhere else the
+/// checkins are shown.
+pub fn record(state: &AppState, user_key: &str, settings: &UserSettings, checkin: &SwarmCheckin) {
+    if checkin.private.unwrap_or(false) {
+        return;
+    }
+    let month = schedule::local_day(settings, checkin.created_at)[..7].to_string();
+    let result = state.db.update_stats(user_key, |stats| {
+        stats.total += 1;
+        let venue = stats.venues.entry(checkin.venue.id.clone()).or_default();
+        venue.name = checkin.venue.name.clone();
+        venue.count += 1;
+        if let Some(category) = checkin.venue.categories_by_priority().next() {
+            *stats.categories.entry(category.name.clone()).or_default() += 1;
+        }
+        *stats.months.entry(month).or_default() += 1;
+    });
+    if let Err(e) = result {
+        tracing::warn!(?e, "unable to update checkin stats");
+    }
+}
+
+/// The `TOP` entries with the highest counts.
+pub fn top<'a, I>(counts: I) -> Vec<(&'a str, u32)>
+where
+    I: IntoIterator<Item = (&'a str, u32)>,
+{
+    let mut counts = counts.into_iter().collect::<Vec<_>>();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    counts.truncate(TOP);
+    counts
+}
+
+fn table(heading: &str, rows: &[(&str, u32)]) -> String {
+    let rows = rows
+        .iter()
+        .map(|(name, count)| format!("<tr><td>{}</td><td>{}</td></tr>", escape(name), count))
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!("<h2>{}</h2>\n<table>\n{}\n</table>", escape(heading), rows)
+}
+
+fn render(stats: &CheckinStats) -> String {
+    let venues = top(stats
+        .venues
+        .values()
+        .map(|venue| (venue.name.as_str(), venue.count)));
+    let categories = top(stats
+        .categories
+        .iter()
+        .map(|(name, count)| (name.as_str(), *count)));
+    let months = stats
+        .months
+        .iter()
+        .rev()
+        .take(12)
+        .map(|(month, count)| (month.as_str(), *count))
+        .collect::<Vec<_>>();
+    [
+        table("Top venues", &venues),
+        table("Top categories", &categories),
+        table("Recent months", &months),
+    ]
+    .join("\n")
+}
+
+pub async fn get_stats(
+    State(state): State<Arc<AppState>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+) -> Result<Html<String>, String> {
+    let user_key = crate::cookie_user_key(&state, &cookie)?;
+    let stats = state.db.get_stats(&user_key).from_err()?;
+    Ok(page(
+        "Stats",
+        &format!(
+            r#"<h1>Stats</h1>
+<p>{total} public checkins seen since you connected.</p>
+{tables}
+<p><a href="/account">Back to your account</a></p>"#,
+            total = stats.total,
+            tables = render(&stats),
+        ),
+    ))
+}
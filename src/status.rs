@@ -0,0 +1,565 @@
+use std::collections::HashMap;
+
+use chrono::Locale;
+use chrono::NaiveDate;
+use chrono::TimeZone;
+use chrono::Utc;
+use isolang::Language;
+use regex::Regex;
+
+use crate::categories;
+use crate::model::Coordinates;
+use crate::model::Friends;
+use crate::model::Layout;
+use crate::model::LinkMode;
+use crate::model::UserSettings;
+use crate::swarm::SwarmCheckin;
+use crate::swarm::SwarmCheckinDetail;
+use crate::swarm::SwarmLocation;
+use crate::template::Template;
+use crate::template::TemplateError;
+
+/// Fields that can be used in a status template.
+pub const FIELDS: &[&str] = &[
+    "shout",
+    "venue",
+    "parent_venue",
+    "event",
+    "location",
+    "url",
+    "osm_url",
+    "coordinates",
+    "visits",
+    "also_here",
+];
+
+/// Mastodon's default status length. Instances may allow more.
+pub const MAX_CHARACTERS: usize = 500;
+/// Mastodon counts every link as this many characters, whatever its length.
+const URL_CHARACTERS: usize = 23;
+
+/// Most replies a long shout is continued in.
+const MAX_REPLIES: usize = 3;
+
+/// Template behind each preset layout.
+pub fn layout_template(layout: Layout) -> &'static str {
+    match layout {
+        Layout::Classic => concat!(
+            "{?shout}{shout} {/shout}",
+            "(@ {venue}{?parent_venue} @ {parent_venue}{/parent_venue}{?event} for {event}{/event}",
+            "{?location} in {location}{/location}) {url}",
+        ),
+        Layout::UrlFirst => concat!(
+            "{url} {?shout}{shout} {/shout}",
+            "(@ {venue}{?parent_venue} @ {parent_venue}{/parent_venue}{?event} for {event}{/event}",
+            "{?location} in {location}{/location})",
+        ),
+        Layout::Minimal => concat!(
+            "{?shout}{shout} {/shout}",
+            "@ {venue}{?parent_venue} @ {parent_venue}{/parent_venue}{?event} for {event}{/event}",
+        ),
+    }
+}
+
+pub fn parse_template(source: &str) -> Result<Template, TemplateError> {
+    Template::parse_with_fields(source, FIELDS)
+}
+
+fn template(settings: &UserSettings) -> Template {
+    if let Some(source) = &settings.template {
+        match parse_template(source) {
+            Ok(template) => return template,
+            Err(e) => tracing::warn!(%e, "invalid status template, using the default"),
+        }
+    }
+    parse_template(layout_template(settings.layout)).expect("layout templates are valid")
+}
+
+/// Whether a shout matches one of the user's noise patterns. A pattern
+/// wrapped in slashes, like `/^I'm at .*/`, is a regular expression; any
+/// other pattern must match the whole shout, ignoring case.
+pub fn is_noise(settings: &UserSettings, shout: &str) -> bool {
+    let shout = shout.trim();
+    settings.noise_shouts.iter().any(|pattern| {
+        match pattern
+            .strip_prefix('/')
+            .and_then(|pattern| pattern.strip_suffix('/'))
+        {
+            Some(regex) => Regex::new(regex).map_or(false, |regex| regex.is_match(shout)),
+            None => pattern.trim().eq_ignore_ascii_case(shout),
+        }
+    })
+}
+
+/// The shout, unless it's missing or only noise.
+pub fn effective_shout<'a>(settings: &UserSettings, checkin: &'a SwarmCheckin) -> Option<&'a str> {
+    checkin
+        .shout
+        .as_deref()
+        .filter(|shout| !shout.trim().is_empty() && !is_noise(settings, shout))
+}
+
+/// Checks the patterns accepted by `is_noise`, returning the first invalid
+/// regular expression's error.
+pub fn validate_noise(patterns: &[String]) -> Result<(), String> {
+    for pattern in patterns {
+        if let Some(regex) = pattern
+            .strip_prefix('/')
+            .and_then(|pattern| pattern.strip_suffix('/'))
+        {
+            Regex::new(regex).map_err(|e| format!("invalid pattern {}: {}", pattern, e))?;
+        }
+    }
+    Ok(())
+}
+
+/// Share marker used in opt-in mode when the user hasn't picked their own.
+pub const DEFAULT_SHARE_MARKER: &str = "#share";
+
+fn is_marker(settings: &UserSettings, word: &str) -> bool {
+    settings
+        .opt_out_markers
+        .iter()
+        .any(|marker| marker.eq_ignore_ascii_case(word))
+}
+
+fn is_share_marker(settings: &UserSettings, word: &str) -> bool {
+    if settings.share_markers.is_empty() {
+        DEFAULT_SHARE_MARKER.eq_ignore_ascii_case(word)
+    } else {
+        settings
+            .share_markers
+            .iter()
+            .any(|marker| marker.eq_ignore_ascii_case(word))
+    }
+}
+
+/// Whether the shout contains one of the user's share markers.
+pub fn has_share_marker(settings: &UserSettings, shout: &str) -> bool {
+    shout
+        .split_whitespace()
+        .any(|word| is_share_marker(settings, word))
+}
+
+/// Whether the shout contains one of the user's opt-out markers.
+pub fn has_marker(settings: &UserSettings, shout: &str) -> bool {
+    shout
+        .split_whitespace()
+        .any(|word| is_marker(settings, word))
+}
+
+/// Removes opt-out markers from a shout that is posted anyway, and share
+/// markers in opt-in mode.
+fn strip_markers(settings: &UserSettings, shout: String) -> String {
+    let opt_in = settings.opt_in && has_share_marker(settings, &shout);
+    if !opt_in && !has_marker(settings, &shout) {
+        return shout;
+    }
+    shout
+        .split_whitespace()
+        .filter(|word| !is_marker(settings, word))
+        .filter(|word| !(settings.opt_in && is_share_marker(settings, word)))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Mentions of the companions the user has mapped to a Mastodon account.
+/// Companions without a mapping are left out rather than named.
+fn mentions(checkin: &SwarmCheckin, friends: &Friends) -> Vec<String> {
+    checkin
+        .with
+        .iter()
+        .filter_map(|friend| friends.lookup(friend))
+        .map(|acct| format!("@{}", acct))
+        .collect()
+}
+
+/// Mentions of mapped friends who were at the venue at the same time, if the
+/// user wants them. Friends already mentioned as companions are left out.
+fn also_here(
+    settings: &UserSettings,
+    details: &SwarmCheckinDetail,
+    friends: &Friends,
+    mentions: &[String],
+) -> Option<String> {
+    if !settings.mention_overlaps {
+        return None;
+    }
+    let mut also_here: Vec<String> = Vec::new();
+    for mention in details
+        .overlapping_users()
+        .filter_map(|friend| friends.lookup(friend))
+        .map(|acct| format!("@{}", acct))
+    {
+        if !mentions.contains(&mention) && !also_here.contains(&mention) {
+            also_here.push(mention);
+        }
+    }
+    if also_here.is_empty() {
+        None
+    } else {
+        Some(format!("(also here: {})", also_here.join(" ")))
+    }
+}
+
+fn with_mentions(shout: &str, mentions: &[String]) -> String {
+    if mentions.is_empty() {
+        shout.to_string()
+    } else {
+        format!("{} with {}", shout, mentions.join(" "))
+            .trim()
+            .to_string()
+    }
+}
+
+/// Cuts at least `excess` characters off the end of the shout, at a word
+/// boundary where possible, marking the cut with an ellipsis.
+fn shorten(shout: &str, excess: usize) -> String {
+    let keep = shout.chars().count().saturating_sub(excess + 1);
+    let cut: String = shout.chars().take(keep).collect();
+    let cut = match cut.rfind(char::is_whitespace) {
+        // Don't lose most of the shout to find a word boundary.
+        Some(space) if space * 2 >= cut.len() => &cut[..space],
+        _ => &cut,
+    };
+    let cut = cut.trim_end();
+    if cut.is_empty() {
+        String::new()
+    } else {
+        format!("{}…", cut)
+    }
+}
+
+/// Length of a status as counted by Mastodon.
+pub fn length(status: &str) -> usize {
+    status
+        .split(' ')
+        .map(|word| {
+            if word.starts_with("https://") || word.starts_with("http://") {
+                URL_CHARACTERS
+            } else {
+                word.chars().count()
+            }
+        })
+        .sum::<usize>()
+        + status.matches(' ').count()
+}
+
+/// Normalizes user-entered hashtags, e.g. `swarm #checkin` into
+/// `["#swarm", "#checkin"]`.
+pub fn parse_hashtags(input: &str) -> Vec<String> {
+    input
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .map(|tag| tag.trim_start_matches('#'))
+        .filter(|tag| !tag.is_empty())
+        .map(|tag| format!("#{}", tag))
+        .collect()
+}
+
+/// Language of the status: the user's choice, or else whatever the shout
+/// is reliably detected to be written in. Returns an ISO 639-1 code.
+pub fn language(settings: &UserSettings, checkin: &SwarmCheckin) -> Option<String> {
+    if let Some(language) = &settings.language {
+        return Some(language.clone());
+    }
+    let info = whatlang::detect(effective_shout(settings, checkin)?)?;
+    if !info.is_reliable() {
+        return None;
+    }
+    Language::from_639_3(info.lang().code())?
+        .to_639_1()
+        .map(str::to_string)
+}
+
+/// Locale dates are spelled out in for the user's language, with the
+/// patterns of a day and of a month in it. English when the language is
+/// unset or not listed.
+fn date_locale(settings: &UserSettings) -> (Locale, &'static str, &'static str) {
+    let language = settings.language.as_deref().unwrap_or_default();
+    match language.split(['-', '_']).next().unwrap_or_default() {
+        "de" => (Locale::de_DE, "%A, %-d. %B", "%B %Y"),
+        "es" => (Locale::es_ES, "%A, %-d de %B", "%B de %Y"),
+        "fr" => (Locale::fr_FR, "%A %-d %B", "%B %Y"),
+        "it" => (Locale::it_IT, "%A %-d %B", "%B %Y"),
+        "ja" => (Locale::ja_JP, "%-m月%-d日(%a)", "%Y年%-m月"),
+        "nl" => (Locale::nl_NL, "%A %-d %B", "%B %Y"),
+        "pt" => (Locale::pt_PT, "%A, %-d de %B", "%B de %Y"),
+        "zh" => (Locale::zh_CN, "%-m月%-d日%A", "%Y年%-m月"),
+        _ => (Locale::en_US, "%A, %B %-d", "%B %Y"),
+    }
+}
+
+fn format_localized(day: NaiveDate, pattern: &str, locale: Locale) -> String {
+    match day.and_hms_opt(0, 0, 0) {
+        Some(midnight) => Utc
+            .from_utc_datetime(&midnight)
+            .format_localized(pattern, locale)
+            .to_string(),
+        None => day.to_string(),
+    }
+}
+
+/// The day spelled out in the user's language, like `Saturday, May 4`.
+pub fn format_date(settings: &UserSettings, day: NaiveDate) -> String {
+    let (locale, pattern, _) = date_locale(settings);
+    format_localized(day, pattern, locale)
+}
+
+/// The month `day` falls in, like `May 2024`.
+pub fn format_month(settings: &UserSettings, day: NaiveDate) -> String {
+    let (locale, _, pattern) = date_locale(settings);
+    format_localized(day, pattern, locale)
+}
+
+/// Like `format_date` for a day from `schedule::local_day`, kept as is when
+/// it can't be read.
+pub fn format_local_day(settings: &UserSettings, day: &str) -> String {
+    match NaiveDate::parse_from_str(day, "%Y-%m-%d") {
+        Ok(day) => format_date(settings, day),
+        Err(_) => day.to_string(),
+    }
+}
+
+/// Information about a checkin that has to be looked up before composing
+/// the status.
+#[derive(Default)]
+pub struct Lookups {
+    pub parent_venue: Option<String>,
+    /// Status length limit of the user's instance, if it's known
+    pub max_characters: Option<usize>,
+}
+
+/// Notes how often the user has been to the venue, if they want that and
+/// Swarm says.
+fn visits(settings: &UserSettings, details: &SwarmCheckinDetail) -> Option<String> {
+    if !settings.visit_count {
+        return None;
+    }
+    match details.basic.venue.been_here.as_ref()?.count {
+        0 => None,
+        1 => Some("First visit!".to_string()),
+        count => Some(format!("Visit #{}", count)),
+    }
+}
+
+/// Link to a map of the coordinates on OpenStreetMap, with a marker.
+pub fn osm_url((lat, lng): (f64, f64)) -> String {
+    format!(
+        "https://www.openstreetmap.org/?mlat={lat:.5}&mlon={lng:.5}#map=18/{lat:.5}/{lng:.5}",
+        lat = lat,
+        lng = lng
+    )
+}
+
+/// The link the user chose for the `url` field. Venues without coordinates
+/// keep the Foursquare link.
+fn url(settings: &UserSettings, checkin_url: &str, osm_url: Option<&str>) -> String {
+    match (settings.link, osm_url) {
+        (LinkMode::OpenStreetMap, Some(osm_url)) => osm_url.to_string(),
+        (LinkMode::Both, Some(osm_url)) if osm_url != checkin_url => {
+            format!("{} {}", checkin_url, osm_url)
+        }
+        _ => checkin_url.to_string(),
+    }
+}
+
+/// Formats the venue's coordinates as the user chose, if they are known.
+fn coordinates(settings: &UserSettings, location: &SwarmLocation) -> Option<String> {
+    let (lat, lng) = location.coordinates()?;
+    match settings.coordinates {
+        Coordinates::None => None,
+        Coordinates::GeoUri => Some(format!("geo:{:.5},{:.5}", lat, lng)),
+        Coordinates::Plain => Some(format!("{:.5}, {:.5}", lat, lng)),
+    }
+}
+
+/// Splits the part of a shout cut off the status into replies, each marked as
+/// continuing with an ellipsis. Past `MAX_REPLIES` the shout is cut short
+/// after all.
+fn thread_replies(rest: &str, max_characters: usize) -> Vec<String> {
+    let mut replies = Vec::new();
+    let mut reply = String::new();
+    for word in rest.split_whitespace() {
+        let candidate = if reply.is_empty() {
+            format!("…{}", word)
+        } else {
+            format!("{} {}", reply, word)
+        };
+        // Leave room for the ellipsis leading into the next reply.
+        if length(&candidate) + 1 > max_characters && !reply.is_empty() {
+            replies.push(format!("{}…", reply));
+            if replies.len() == MAX_REPLIES {
+                return replies;
+            }
+            reply = format!("…{}", word);
+        } else {
+            reply = candidate;
+        }
+    }
+    if !reply.is_empty() {
+        replies.push(reply);
+    }
+    replies
+}
+
+/// Builds the status posted to Mastodon for a checkin, along with replies
+/// continuing the shout when it doesn't fit and the user prefers a thread.
+pub fn compose(
+    settings: &UserSettings,
+    friends: &Friends,
+    checkin: &SwarmCheckin,
+    details: &SwarmCheckinDetail,
+    lookups: &Lookups,
+) -> (String, Vec<String>) {
+    let max_characters = lookups.max_characters.unwrap_or(MAX_CHARACTERS);
+    let mentions = mentions(checkin, friends);
+    let mut shout = strip_markers(
+        settings,
+        effective_shout(settings, checkin)
+            .unwrap_or_default()
+            .to_string(),
+    );
+
+    let mut values = HashMap::new();
+    values.insert("shout", with_mentions(&shout, &mentions));
+    values.insert("venue", checkin.venue.name.clone());
+    values.insert(
+        "event",
+        checkin
+            .event
+            .as_ref()
+            .map(|event| event.name.clone())
+            .unwrap_or_default(),
+    );
+    values.insert(
+        "parent_venue",
+        lookups.parent_venue.clone().unwrap_or_default(),
+    );
+    let location = &checkin.venue.location;
+    let flag = location.flag().filter(|_| settings.country_flag);
+    let location = match (location.to_string(), flag) {
+        (Some(location), Some(flag)) => format!("{} {}", location, flag),
+        (location, _) => location.unwrap_or_default(),
+    };
+    values.insert("location", location);
+    let osm_url = checkin.venue.location.coordinates().map(osm_url);
+    values.insert(
+        "url",
+        url(settings, &details.checkin_short_url, osm_url.as_deref()),
+    );
+    values.insert("osm_url", osm_url.unwrap_or_default());
+    let coordinates = coordinates(settings, &checkin.venue.location).unwrap_or_default();
+    values.insert("coordinates", coordinates.clone());
+    let visits = visits(settings, details).unwrap_or_default();
+    values.insert("visits", visits.clone());
+    let also_here = also_here(settings, details, friends, &mentions).unwrap_or_default();
+    values.insert("also_here", also_here.clone());
+    let template = template(settings);
+    let emoji = categories::emoji(settings, &checkin.venue);
+    // Templates without a place for these get them at the end.
+    let append = [
+        ("also_here", also_here),
+        ("visits", visits),
+        ("coordinates", coordinates),
+    ]
+    .into_iter()
+    .filter(|(field, value)| !value.is_empty() && !template.uses(field))
+    .map(|(_, value)| value)
+    .collect::<Vec<_>>()
+    .join(" ");
+    let render = |values: &HashMap<&str, String>| {
+        let status = format!("{} {}", template.render(values).trim(), append)
+            .trim()
+            .to_string();
+        match &emoji {
+            Some(emoji) => format!("{} {}", emoji, status),
+            None => status,
+        }
+    };
+    let mut status = render(&values);
+
+    // Rather than failing to post, give up on the end of the shout. The venue,
+    // link and mentions are what the status is about.
+    let full_shout = shout.clone();
+    while length(&status) > max_characters && !shout.is_empty() {
+        shout = shorten(&shout, length(&status) - max_characters);
+        values.insert("shout", with_mentions(&shout, &mentions));
+        status = render(&values);
+    }
+    let replies = if settings.thread_long_shouts && shout != full_shout {
+        // The shortened shout is a prefix of the full one plus an ellipsis.
+        let kept = shout.trim_end_matches('…');
+        thread_replies(&full_shout[kept.len()..], max_characters)
+    } else {
+        Vec::new()
+    };
+
+    // Hashtags are optional, add as many as fit rather than making the
+    // status too long to post.
+    let mut added: Vec<String> = Vec::new();
+    let hashtags = settings
+        .hashtags
+        .iter()
+        .cloned()
+        .chain(categories::hashtags(settings, &checkin.venue));
+    for hashtag in hashtags {
+        if added.iter().any(|tag| tag.eq_ignore_ascii_case(&hashtag)) {
+            continue;
+        }
+        let candidate = format!("{} {}", status, hashtag);
+        if length(&candidate) > max_characters {
+            break;
+        }
+        status = candidate;
+        added.push(hashtag);
+    }
+    (status, replies)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(language: Option<&str>) -> UserSettings {
+        UserSettings {
+            language: language.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn dates_in_the_users_language() {
+        let day = NaiveDate::from_ymd_opt(2024, 5, 4).unwrap();
+        let cases = [
+            (Some("en"), "Saturday, May 4", "May 2024"),
+            (Some("de"), "Samstag, 4. Mai", "Mai 2024"),
+            (Some("de-AT"), "Samstag, 4. Mai", "Mai 2024"),
+            (Some("es"), "sábado, 4 de mayo", "mayo de 2024"),
+            (Some("fr"), "samedi 4 mai", "mai 2024"),
+            (Some("it"), "sabato 4 maggio", "maggio 2024"),
+            (Some("ja"), "5月4日(土)", "2024年5月"),
+            (Some("nl"), "zaterdag 4 mei", "mei 2024"),
+            (Some("pt"), "sábado, 4 de maio", "maio de 2024"),
+            (Some("pt-BR"), "sábado, 4 de maio", "maio de 2024"),
+            (Some("zh"), "5月4日星期六", "2024年5月"),
+            (Some("zh_TW"), "5月4日星期六", "2024年5月"),
+            (Some("xx"), "Saturday, May 4", "May 2024"),
+            (Some(""), "Saturday, May 4", "May 2024"),
+            (None, "Saturday, May 4", "May 2024"),
+        ];
+        for (language, date, month) in cases {
+            let settings = settings(language);
+            assert_eq!(format_date(&settings, day), date, "{:?}", language);
+            assert_eq!(format_month(&settings, day), month, "{:?}", language);
+        }
+    }
+
+    #[test]
+    fn unreadable_local_day_is_kept() {
+        assert_eq!(
+            format_local_day(&settings(Some("de")), "2024-05-04"),
+            "Samstag, 4. Mai"
+        );
+        assert_eq!(format_local_day(&settings(None), "someday"), "someday");
+    }
+}
@@ -0,0 +1,389 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use anyhow::Result;
+use serde::Deserialize;
+use serde::Serialize;
+use url::Url;
+
+/// Swarm no longer accepts the user's access token, e.g. because they revoked
+/// access to the app. Only linking Swarm again helps.
+#[derive(Debug)]
+pub struct InvalidToken;
+
+impl std::fmt::Display for InvalidToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Swarm rejected the access token")
+    }
+}
+
+impl std::error::Error for InvalidToken {}
+
+pub async fn get_access_token(
+    client_id: &str,
+    client_secret: &str,
+    redirect_url: &str,
+    code: &str,
+) -> Result<String> {
+    let mut url =
+        Url::parse("https://foursquare.com/oauth2/access_token").expect("invalid swarm url");
+
+    {
+        let mut queries = url.query_pairs_mut();
+        queries.append_pair("client_id", client_id);
+        queries.append_pair("client_secret", client_secret);
+        queries.append_pair("grant_type", "authorization_code");
+        queries.append_pair("redirect_uri", redirect_url);
+        queries.append_pair("code", code);
+    }
+
+    let response = reqwest::get(url).await.map_err(|e| e.without_url())?;
+    let response = response
+        .json::<serde_json::Value>()
+        .await
+        .map_err(|e| e.without_url())?;
+    let access_token = response
+        .get("access_token")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| anyhow::anyhow!("unable to retrieve access token for swarm"))?;
+
+    Ok(access_token.to_string())
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SwarmUser {
+    pub id: String,
+    pub first_name: String,
+    #[serde(default)]
+    pub last_name: String,
+    #[serde(default)]
+    pub handle: Option<String>,
+}
+
+impl SwarmUser {
+    pub fn full_name(&self) -> String {
+        format!("{} {}", self.first_name, self.last_name)
+            .trim()
+            .to_string()
+    }
+
+    /// Name to show for the user, preferring their handle.
+    pub fn display_name(&self) -> String {
+        match &self.handle {
+            Some(handle) if !handle.is_empty() => format!("@{}", handle),
+            _ => self.full_name(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct SwarmLocation {
+    pub country: Option<String>,
+    pub city: Option<String>,
+    pub state: Option<String>,
+    /// ISO 3166-1 alpha-2 country code
+    pub cc: Option<String>,
+    pub lat: Option<f64>,
+    pub lng: Option<f64>,
+}
+
+impl SwarmLocation {
+    /// Flag emoji for the country, made of the regional indicator symbols
+    /// for its code.
+    pub fn flag(&self) -> Option<String> {
+        let cc = self.cc.as_deref()?;
+        if cc.len() != 2 || !cc.chars().all(|c| c.is_ascii_alphabetic()) {
+            return None;
+        }
+        cc.to_ascii_uppercase()
+            .chars()
+            .map(|c| char::from_u32(0x1F1E6 + (c as u32 - 'A' as u32)))
+            .collect()
+    }
+
+    /// Latitude and longitude, when both are known.
+    pub fn coordinates(&self) -> Option<(f64, f64)> {
+        Some((self.lat?, self.lng?))
+    }
+
+    pub fn to_string(&self) -> Option<String> {
+        match (
+            self.city.as_ref(),
+            self.state.as_ref(),
+            self.country.as_ref(),
+        ) {
+            (Some(city), Some(state), _) => Some(format!("{}, {}", city, state)),
+            (None, Some(state), Some(country)) => Some(format!("{}, {}", state, country)),
+            (None, None, Some(country)) => Some(country.to_string()),
+            (_, _, _) => None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SwarmCategory {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub primary: bool,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct SwarmVenue {
+    pub id: String,
+    pub name: String,
+    pub location: SwarmLocation,
+    #[serde(default)]
+    pub categories: Vec<SwarmCategory>,
+    /// The user's own visits, only present in checkin details.
+    #[serde(rename = "beenHere", default)]
+    pub been_here: Option<BeenHere>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct BeenHere {
+    /// Checkins by the user at the venue, including this one
+    #[serde(default)]
+    pub count: u32,
+}
+
+impl SwarmVenue {
+    /// The venue's categories with the primary one first.
+    pub fn categories_by_priority(&self) -> impl Iterator<Item = &SwarmCategory> {
+        let primary = self.categories.iter().filter(|category| category.primary);
+        let others = self.categories.iter().filter(|category| !category.primary);
+        primary.chain(others)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct SwarmCheckin {
+    pub id: String,
+    pub r#type: String,
+    #[serde(rename = "createdAt")]
+    pub created_at: u64,
+    pub private: Option<bool>,
+    pub shout: Option<String>,
+    /// Only present in push payloads, checkins listed for the user themselves
+    /// omit it.
+    pub user: Option<SwarmUser>,
+    /// Friends tagged as being at the venue too.
+    #[serde(default)]
+    pub with: Vec<SwarmUser>,
+    /// Set when a friend checked the user in by tagging them.
+    #[serde(rename = "createdBy", default)]
+    pub created_by: Option<SwarmUser>,
+    pub venue: SwarmVenue,
+    /// Set when the checkin is for an event at the venue, e.g. a concert.
+    #[serde(default)]
+    pub event: Option<SwarmEvent>,
+    /// Photos the user attached, not always present in push payloads.
+    #[serde(default)]
+    pub photos: Option<SwarmPhotos>,
+    /// The checkin as received, for the archive. Only set through
+    /// `SwarmCheckin::from_value`.
+    #[serde(skip)]
+    pub raw: Option<Raw>,
+}
+
+impl SwarmCheckin {
+    /// Parses a checkin, keeping the JSON it came from.
+    pub fn from_value(value: serde_json::Value) -> Result<Self> {
+        let mut checkin: SwarmCheckin = serde_json::from_value(value.clone())?;
+        checkin.raw = Some(Raw(Arc::new(value)));
+        Ok(checkin)
+    }
+}
+
+/// JSON of a checkin as received. Left out of debug output, which would
+/// otherwise repeat the whole checkin.
+#[derive(Clone)]
+pub struct Raw(pub Arc<serde_json::Value>);
+
+impl std::fmt::Debug for Raw {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Raw(..)")
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct SwarmPhotos {
+    #[serde(default)]
+    pub count: u32,
+}
+
+/// A photo on Foursquare, served at `prefix` + size + `suffix`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct SwarmPhoto {
+    pub prefix: String,
+    pub suffix: String,
+    /// Who took the photo
+    #[serde(default)]
+    pub user: Option<SwarmUser>,
+}
+
+impl SwarmPhoto {
+    /// URL of the photo scaled to `width` pixels wide.
+    pub fn url(&self, width: u32) -> String {
+        format!("{}width{}{}", self.prefix, width, self.suffix)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct SwarmEvent {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SwarmCheckinDetail {
+    #[serde(flatten)]
+    pub basic: SwarmCheckin,
+
+    #[serde(rename = "checkinShortUrl")]
+    pub checkin_short_url: String,
+
+    /// Friends' checkins at the same venue around the same time.
+    #[serde(default)]
+    pub overlaps: Option<SwarmOverlaps>,
+}
+
+impl SwarmCheckinDetail {
+    /// Friends who were at the venue at the same time, without tagging or
+    /// being tagged in the checkin.
+    pub fn overlapping_users(&self) -> impl Iterator<Item = &SwarmUser> {
+        self.overlaps
+            .iter()
+            .flat_map(|overlaps| overlaps.items.iter())
+            .filter_map(|overlap| overlap.user.as_ref())
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct SwarmOverlaps {
+    #[serde(default)]
+    pub items: Vec<SwarmOverlap>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct SwarmOverlap {
+    pub user: Option<SwarmUser>,
+}
+
+/// Swarm API client authenticated as a single user.
+pub struct SwarmUserApi<'a> {
+    access_token: &'a str,
+}
+
+impl<'a> SwarmUserApi<'a> {
+    pub fn new(access_token: &'a str) -> Self {
+        Self { access_token }
+    }
+
+    async fn call(&self, method: &str, params: &[(&str, &str)]) -> Result<serde_json::Value> {
+        let mut url = Url::parse(&format!("https://api.foursquare.com/v2{}", method))?;
+        url.query_pairs_mut()
+            .append_pair("v", "20220722")
+            .append_pair("oauth_token", self.access_token)
+            .extend_pairs(params);
+
+        // The URL carries the access token, keep it out of errors.
+        let response = reqwest::get(url).await.map_err(|e| e.without_url())?;
+        let mut response = response
+            .json::<serde_json::Value>()
+            .await
+            .map_err(|e| e.without_url())?;
+        if response
+            .pointer("/meta/code")
+            .and_then(|code| code.as_u64())
+            == Some(401)
+        {
+            return Err(InvalidToken.into());
+        }
+        let Some(response) = response.get_mut("response").map(|v| v.take()) else {
+            return Err(anyhow::anyhow!("unable to retrieve response for swarm"));
+        };
+        crate::fixtures::record(method, &response);
+        Ok(response)
+    }
+
+    pub async fn get_me(&self) -> Result<SwarmUser> {
+        let mut response = self
+            .call("/users/self", &[])
+            .await
+            .with_context(|| format!("unable to retrieve information about the user"))?;
+        let response = response
+            .get_mut("user")
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("unable to retrieve user info for swarm"))?
+            .take();
+        Ok(serde_json::from_value(response)?)
+    }
+
+    pub async fn get_checkin_details(&self, checkin_id: &str) -> Result<SwarmCheckinDetail> {
+        let mut response = self.call(&format!("/checkins/{}", checkin_id), &[]).await?;
+        let response = response
+            .get_mut("checkin")
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("response from Swarm API does not contain checkin"))?
+            .take();
+
+        Ok(serde_json::from_value(response)?)
+    }
+
+    /// Returns the name of the venue containing `venue_id`, such as the
+    /// airport a gate is in.
+    pub async fn get_venue_parent(&self, venue_id: &str) -> Result<Option<String>> {
+        let mut response = self.call(&format!("/venues/{}", venue_id), &[]).await?;
+        let venue = response
+            .get_mut("venue")
+            .ok_or_else(|| anyhow::anyhow!("response from Swarm API does not contain venue"))?;
+        Ok(venue
+            .get("parent")
+            .and_then(|parent| parent.get("name"))
+            .and_then(|name| name.as_str())
+            .map(str::to_string))
+    }
+
+    /// Returns the photo Foursquare picked as the best of the venue, if it
+    /// has any.
+    pub async fn get_venue_photo(&self, venue_id: &str) -> Result<Option<SwarmPhoto>> {
+        let mut response = self.call(&format!("/venues/{}", venue_id), &[]).await?;
+        let venue = response
+            .get_mut("venue")
+            .ok_or_else(|| anyhow::anyhow!("response from Swarm API does not contain venue"))?;
+        match venue.get_mut("bestPhoto") {
+            Some(photo) => Ok(Some(serde_json::from_value(photo.take())?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns a page of the user's checkins, newest first. `offset` skips
+    /// that many of the most recent checkins.
+    pub async fn get_checkins(&self, limit: u32, offset: u32) -> Result<Vec<SwarmCheckin>> {
+        let limit = limit.to_string();
+        let offset = offset.to_string();
+        let mut response = self
+            .call(
+                "/users/self/checkins",
+                &[
+                    ("limit", &limit),
+                    ("offset", &offset),
+                    ("sort", "newestfirst"),
+                ],
+            )
+            .await?;
+        let items = response
+            .get_mut("checkins")
+            .and_then(|checkins| checkins.get_mut("items"))
+            .ok_or_else(|| anyhow::anyhow!("response from Swarm API does not contain checkins"))?
+            .take();
+
+        serde_json::from_value::<Vec<serde_json::Value>>(items)?
+            .into_iter()
+            .map(SwarmCheckin::from_value)
+            .collect()
+    }
+}
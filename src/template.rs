@@ -0,0 +1,298 @@
+//! A small template language for statuses and operator-provided pages.
+//!
+//! `{name}` is replaced with the value of the field `name`. A section
+//! `{?name}...{/name}` is only rendered when `name` has a non-empty value, so
+//! optional parts don't leave dangling words behind, e.g.
+//! `{?shout}{shout} {/shout}(@ {venue})`. Sections may be nested. Literal
+//! braces are written as `{{` and `}}`.
+
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Text(String),
+    Field(String),
+    Section(String, Vec<Node>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplateError {
+    UnclosedTag,
+    EmptyName,
+    UnclosedSection(String),
+    UnexpectedClose(String),
+    UnknownField(String),
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::UnclosedTag => write!(f, "a '{{' is never closed"),
+            TemplateError::EmptyName => write!(f, "a tag is missing its field name"),
+            TemplateError::UnclosedSection(name) => write!(f, "section '{}' is never closed", name),
+            TemplateError::UnexpectedClose(name) => {
+                write!(f, "'{{/{}}}' does not close an open section", name)
+            }
+            TemplateError::UnknownField(name) => write!(f, "unknown field '{}'", name),
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Self, TemplateError> {
+        // Open sections, each with the nodes collected so far. The bottom of
+        // the stack is the template itself.
+        let mut stack: Vec<(Option<String>, Vec<Node>)> = vec![(None, Vec::new())];
+        let mut text = String::new();
+        let mut chars = source.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut tag = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => tag.push(c),
+                            None => return Err(TemplateError::UnclosedTag),
+                        }
+                    }
+
+                    let nodes = &mut stack.last_mut().expect("template stack is never empty").1;
+                    if !text.is_empty() {
+                        nodes.push(Node::Text(std::mem::take(&mut text)));
+                    }
+
+                    if let Some(name) = tag.strip_prefix('?') {
+                        let name = name.trim();
+                        if name.is_empty() {
+                            return Err(TemplateError::EmptyName);
+                        }
+                        stack.push((Some(name.to_string()), Vec::new()));
+                    } else if let Some(name) = tag.strip_prefix('/') {
+                        let name = name.trim();
+                        match stack.last() {
+                            Some((Some(open), _)) if open == name => {
+                                let (name, children) = stack.pop().unwrap();
+                                stack
+                                    .last_mut()
+                                    .expect("template stack is never empty")
+                                    .1
+                                    .push(Node::Section(name.unwrap(), children));
+                            }
+                            _ => return Err(TemplateError::UnexpectedClose(name.to_string())),
+                        }
+                    } else {
+                        let name = tag.trim();
+                        if name.is_empty() {
+                            return Err(TemplateError::EmptyName);
+                        }
+                        nodes.push(Node::Field(name.to_string()));
+                    }
+                }
+                c => text.push(c),
+            }
+        }
+
+        if !text.is_empty() {
+            stack
+                .last_mut()
+                .expect("template stack is never empty")
+                .1
+                .push(Node::Text(text));
+        }
+        match stack.pop() {
+            Some((None, nodes)) if stack.is_empty() => Ok(Self { nodes }),
+            Some((Some(name), _)) => Err(TemplateError::UnclosedSection(name)),
+            _ => unreachable!("template stack is never empty"),
+        }
+    }
+
+    /// Parses the template and checks every field it refers to is one of
+    /// `known`.
+    pub fn parse_with_fields(source: &str, known: &[&str]) -> Result<Self, TemplateError> {
+        let template = Self::parse(source)?;
+        let mut fields = Vec::new();
+        collect_fields(&template.nodes, &mut fields);
+        match fields
+            .into_iter()
+            .find(|field| !known.contains(&field.as_str()))
+        {
+            Some(field) => Err(TemplateError::UnknownField(field)),
+            None => Ok(template),
+        }
+    }
+
+    /// Whether the template refers to the field `name`.
+    pub fn uses(&self, name: &str) -> bool {
+        let mut fields = Vec::new();
+        collect_fields(&self.nodes, &mut fields);
+        fields.iter().any(|field| field == name)
+    }
+
+    pub fn render(&self, values: &HashMap<&str, String>) -> String {
+        let mut output = String::new();
+        render_nodes(&self.nodes, values, &mut output);
+        output
+    }
+}
+
+fn collect_fields(nodes: &[Node], fields: &mut Vec<String>) {
+    for node in nodes {
+        match node {
+            Node::Text(_) => {}
+            Node::Field(name) => fields.push(name.clone()),
+            Node::Section(name, children) => {
+                fields.push(name.clone());
+                collect_fields(children, fields);
+            }
+        }
+    }
+}
+
+fn render_nodes(nodes: &[Node], values: &HashMap<&str, String>, output: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => output.push_str(text),
+            Node::Field(name) => {
+                if let Some(value) = values.get(name.as_str()) {
+                    output.push_str(value);
+                }
+            }
+            Node::Section(name, children) => {
+                if values
+                    .get(name.as_str())
+                    .map_or(false, |value| !value.is_empty())
+                {
+                    render_nodes(children, values, output);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(source: &str, values: &[(&'static str, &str)]) -> String {
+        let values = values
+            .iter()
+            .map(|(name, value)| (*name, value.to_string()))
+            .collect();
+        Template::parse(source).unwrap().render(&values)
+    }
+
+    #[test]
+    fn parse_errors() {
+        let cases = [
+            ("{venue", TemplateError::UnclosedTag),
+            ("{}", TemplateError::EmptyName),
+            ("{ }", TemplateError::EmptyName),
+            ("{?}x{/}", TemplateError::EmptyName),
+            (
+                "{?shout}{shout}",
+                TemplateError::UnclosedSection("shout".to_string()),
+            ),
+            (
+                "{?shout}{?venue}{/shout}{/venue}",
+                TemplateError::UnexpectedClose("shout".to_string()),
+            ),
+            (
+                "{/shout}",
+                TemplateError::UnexpectedClose("shout".to_string()),
+            ),
+        ];
+        for (source, error) in cases {
+            assert_eq!(Template::parse(source), Err(error), "{}", source);
+        }
+    }
+
+    #[test]
+    fn unknown_fields() {
+        let known = ["venue", "shout"];
+        assert!(Template::parse_with_fields("{?shout}{shout} {/shout}@ {venue}", &known).is_ok());
+        assert_eq!(
+            Template::parse_with_fields("@ {venu}", &known),
+            Err(TemplateError::UnknownField("venu".to_string()))
+        );
+        assert_eq!(
+            Template::parse_with_fields("{?city}in {venue}{/city}", &known),
+            Err(TemplateError::UnknownField("city".to_string()))
+        );
+    }
+
+    #[test]
+    fn fields() {
+        assert_eq!(
+            render(
+                "@ { venue } in {city}",
+                &[("venue", "Cafe"), ("city", "Tokyo")]
+            ),
+            "@ Cafe in Tokyo"
+        );
+    }
+
+    #[test]
+    fn missing_values_render_empty() {
+        assert_eq!(render("@ {venue}!", &[]), "@ !");
+        assert_eq!(
+            render("{?shout}{shout} {/shout}@ {venue}", &[("venue", "Cafe")]),
+            "@ Cafe"
+        );
+        assert_eq!(
+            render(
+                "{?shout}{shout} {/shout}@ {venue}",
+                &[("shout", ""), ("venue", "Cafe")]
+            ),
+            "@ Cafe"
+        );
+    }
+
+    #[test]
+    fn nested_sections() {
+        let source = "{?venue}@ {venue}{?city} in {city}{/city}.{/venue}";
+        assert_eq!(render(source, &[]), "");
+        assert_eq!(render(source, &[("city", "Tokyo")]), "");
+        assert_eq!(render(source, &[("venue", "Cafe")]), "@ Cafe.");
+        assert_eq!(
+            render(source, &[("venue", "Cafe"), ("city", "Tokyo")]),
+            "@ Cafe in Tokyo."
+        );
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(
+            render("{{venue}} {venue}", &[("venue", "Cafe")]),
+            "{venue} Cafe"
+        );
+        assert_eq!(render("}{{", &[]), "}{");
+        let template = Template::parse("{{venue}}").unwrap();
+        assert!(!template.uses("venue"));
+    }
+
+    #[test]
+    fn uses() {
+        let template = Template::parse("{?shout}{shout}{/shout}{?photo}!{/photo}").unwrap();
+        assert!(template.uses("shout"));
+        assert!(template.uses("photo"));
+        assert!(!template.uses("venue"));
+    }
+}
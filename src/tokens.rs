@@ -0,0 +1,295 @@
+//! Per-user tokens for the JSON API. Each token is limited to the scopes the
+//! user picked when creating it, and only a hash of it is stored.
+
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::headers::authorization::Basic;
+use axum::headers::authorization::Bearer;
+use axum::headers::Authorization;
+use axum::headers::Cookie;
+use axum::http::header::WWW_AUTHENTICATE;
+use axum::http::Request;
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::Html;
+use axum::response::IntoResponse;
+use axum::response::Redirect;
+use axum::response::Response;
+use axum::Form;
+use axum::TypedHeader;
+use serde::Deserialize;
+use sha2::Digest;
+use sha2::Sha256;
+
+use crate::error::ApiError;
+use crate::html::ago;
+use crate::html::escape;
+use crate::html::page;
+use crate::model::unix_now;
+use crate::model::ApiToken;
+use crate::model::Scope;
+use crate::AppState;
+use crate::ResultExt;
+
+/// Prefix of every token, so leaked ones are easy to recognize.
+const TOKEN_PREFIX: &str = "swarmdon_";
+
+/// Last use is only recorded this often, sparing a write per request.
+const TOUCH_INTERVAL: u64 = 60;
+
+/// The user an API request is made for, inserted as a request extension.
+#[derive(Debug, Clone)]
+pub struct ApiUser {
+    pub user_key: String,
+}
+
+fn hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+fn authenticate(state: &AppState, token: &str, scope: Scope) -> Result<ApiUser, StatusCode> {
+    let hash = hash(token);
+    let Some(token) = state
+        .db
+        .get_api_token(&hash)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    else {
+        return Err(StatusCode::UNAUTHORIZED);
+    };
+    if !token.scopes.contains(&scope) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let now = unix_now();
+    if token
+        .last_used_at
+        .map_or(true, |at| at + TOUCH_INTERVAL <= now)
+    {
+        if let Err(e) = state.db.touch_api_token(&hash, now) {
+            tracing::warn!(?e, "unable to record API token use");
+        }
+    }
+    Ok(ApiUser {
+        user_key: token.user_key,
+    })
+}
+
+/// Checks the token sent as bearer token or, for clients that only do basic
+/// auth such as OwnTracks, as password.
+async fn authorize<B>(
+    scope: Scope,
+    state: Arc<AppState>,
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+    basic: Option<TypedHeader<Authorization<Basic>>>,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let token = match (&bearer, &basic) {
+        (Some(TypedHeader(bearer)), _) => Some(bearer.token()),
+        (None, Some(TypedHeader(basic))) => Some(basic.password()),
+        (None, None) => None,
+    };
+    let Some(token) = token else {
+        return (
+            [(WWW_AUTHENTICATE, "Bearer")],
+            ApiError::unauthorized("missing API token"),
+        )
+            .into_response();
+    };
+    match authenticate(&state, token, scope) {
+        Ok(user) => {
+            request.extensions_mut().insert(user);
+            next.run(request).await
+        }
+        Err(StatusCode::FORBIDDEN) => {
+            ApiError::forbidden(format!("the API token lacks the {} scope", scope.id()))
+                .into_response()
+        }
+        Err(StatusCode::UNAUTHORIZED) => (
+            [(WWW_AUTHENTICATE, "Bearer")],
+            ApiError::unauthorized("invalid API token"),
+        )
+            .into_response(),
+        Err(status) => ApiError::new(status, "unable to check the API token").into_response(),
+    }
+}
+
+pub async fn require_read_history<B>(
+    State(state): State<Arc<AppState>>,
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+    basic: Option<TypedHeader<Authorization<Basic>>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    authorize(Scope::ReadHistory, state, bearer, basic, request, next).await
+}
+
+pub async fn require_manage_settings<B>(
+    State(state): State<Arc<AppState>>,
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+    basic: Option<TypedHeader<Authorization<Basic>>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    authorize(Scope::ManageSettings, state, bearer, basic, request, next).await
+}
+
+pub async fn require_trigger_post<B>(
+    State(state): State<Arc<AppState>>,
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+    basic: Option<TypedHeader<Authorization<Basic>>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    authorize(Scope::TriggerPost, state, bearer, basic, request, next).await
+}
+
+pub async fn get_tokens(
+    State(state): State<Arc<AppState>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+) -> Result<Html<String>, String> {
+    let user_key = crate::cookie_user_key(&state, &cookie)?;
+    let rows = state
+        .db
+        .get_api_tokens(&user_key)
+        .from_err()?
+        .iter()
+        .map(|(_, token)| token_row(token))
+        .collect::<String>();
+    let scopes = Scope::ALL
+        .iter()
+        .map(|scope| {
+            format!(
+                r#"<label><input type="checkbox" name="scope" value="{}" /> {}</label>"#,
+                scope.id(),
+                scope.description()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    Ok(page(
+        "API tokens",
+        &format!(
+            r#"<h1>API tokens</h1>
+<p>Tokens let scripts and other apps use the JSON API on your behalf, with the permissions you pick. <a href="/account">Back to your account</a></p>
+<table>
+<tr><th>Name</th><th>Permissions</th><th>Created</th><th>Last used</th><th></th></tr>
+{rows}
+</table>
+<form action="/account/tokens" method="POST">
+    <label for="name">Name</label>
+    <input type="text" id="name" name="name" required placeholder="My script" />
+    {scopes}
+    <button type="submit">Create token</button>
+</form>"#
+        ),
+    ))
+}
+
+fn token_row(token: &ApiToken) -> String {
+    format!(
+        r#"<tr>
+    <td>{name}</td>
+    <td>{scopes}</td>
+    <td>{created}</td>
+    <td>{last_used}</td>
+    <td><form action="/account/tokens/revoke" method="POST"><input type="hidden" name="id" value="{id}" /><button type="submit">Revoke</button></form></td>
+</tr>
+"#,
+        name = escape(&token.name),
+        scopes = token
+            .scopes
+            .iter()
+            .map(|scope| scope.id())
+            .collect::<Vec<_>>()
+            .join(", "),
+        created = ago(token.created_at),
+        last_used = token
+            .last_used_at
+            .map(ago)
+            .unwrap_or_else(|| "never".into()),
+        id = escape(&token.id),
+    )
+}
+
+/// Checkbox forms repeat `scope`, which `Form` can't collect into a list.
+fn parse_scopes(body: &str) -> Result<Vec<Scope>, String> {
+    let mut scopes = Vec::new();
+    for (key, value) in url::form_urlencoded::parse(body.as_bytes()) {
+        if key != "scope" {
+            continue;
+        }
+        let scope = Scope::ALL
+            .into_iter()
+            .find(|scope| scope.id() == value)
+            .ok_or_else(|| format!("unknown scope {}", value))?;
+        if !scopes.contains(&scope) {
+            scopes.push(scope);
+        }
+    }
+    Ok(scopes)
+}
+
+pub async fn post_token(
+    State(state): State<Arc<AppState>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    body: String,
+) -> Result<Html<String>, String> {
+    let user_key = crate::cookie_user_key(&state, &cookie)?;
+    let name = url::form_urlencoded::parse(body.as_bytes())
+        .find(|(key, _)| key == "name")
+        .map(|(_, value)| value.trim().to_string())
+        .unwrap_or_default();
+    if name.is_empty() {
+        return Err("the token needs a name".into());
+    }
+    let scopes = parse_scopes(&body)?;
+    if scopes.is_empty() {
+        return Err("pick at least one permission".into());
+    }
+
+    let secret = format!(
+        "{}{}",
+        TOKEN_PREFIX,
+        hex::encode(simple_cookie::generate_signing_key())
+    );
+    let token = ApiToken {
+        id: hex::encode(&simple_cookie::generate_signing_key()[..4]),
+        user_key: user_key.clone(),
+        name,
+        scopes,
+        created_at: unix_now(),
+        last_used_at: None,
+    };
+    state.db.add_api_token(&hash(&secret), &token).from_err()?;
+    tracing::info!(user=%user_key, token=%token.id, "created API token");
+
+    Ok(page(
+        "API token created",
+        &format!(
+            r#"<h1>API token created</h1>
+<p>Copy the token now, it won't be shown again:</p>
+<p><code>{}</code></p>
+<p>Send it as <code>Authorization: Bearer &lt;token&gt;</code>. <a href="/account/tokens">Back to your tokens</a></p>"#,
+            secret
+        ),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct RevokeForm {
+    id: String,
+}
+
+pub async fn post_revoke(
+    State(state): State<Arc<AppState>>,
+    TypedHeader(cookie): TypedHeader<Cookie>,
+    Form(form): Form<RevokeForm>,
+) -> Result<Redirect, String> {
+    let user_key = crate::cookie_user_key(&state, &cookie)?;
+    if !state.db.revoke_api_token(&user_key, &form.id).from_err()? {
+        return Err("no such token".into());
+    }
+    tracing::info!(user=%user_key, token=%form.id, "revoked API token");
+    Ok(Redirect::to("/account/tokens"))
+}
@@ -0,0 +1,103 @@
+//! Instance-wide counts the operator can publish at `/stats.json`. Only
+//! totals are exposed, nothing about individual users or places.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::Result;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use axum::Json;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::model::unix_now;
+use crate::AppState;
+
+/// How often the counts are recomputed.
+const INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Users who posted within this many seconds count as active.
+const ACTIVE_WINDOW: u64 = 30 * 24 * 60 * 60;
+
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct UsageStats {
+    /// Registered users
+    users: usize,
+    /// Users with a status posted in the last 30 days
+    active_users: usize,
+    /// Public checkins seen across all users
+    checkins: u64,
+    /// Distinct venues checked in at across all users
+    venues: usize,
+    /// Unix time the counts were computed at
+    generated_at: u64,
+}
+
+/// The last computed counts, served until the next aggregation.
+#[derive(Default)]
+pub struct UsageCache {
+    stats: Mutex<Option<UsageStats>>,
+}
+
+fn aggregate(state: &AppState) -> Result<UsageStats> {
+    let now = unix_now();
+    let mut stats = UsageStats {
+        users: 0,
+        active_users: 0,
+        checkins: 0,
+        venues: 0,
+        generated_at: now,
+    };
+    let mut venues = HashSet::new();
+    for (user_key, _) in state.db.get_users()? {
+        stats.users += 1;
+        let status = state.db.get_user_status(&user_key)?;
+        if status
+            .last_post_at
+            .map_or(false, |at| now.saturating_sub(at) < ACTIVE_WINDOW)
+        {
+            stats.active_users += 1;
+        }
+        let checkins = state.db.get_stats(&user_key)?;
+        stats.checkins += u64::from(checkins.total);
+        venues.extend(checkins.venues.into_keys());
+    }
+    stats.venues = venues.len();
+    Ok(stats)
+}
+
+/// Background job recomputing the counts, only run when they are published.
+pub async fn run(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(INTERVAL);
+    loop {
+        interval.tick().await;
+        match aggregate(&state) {
+            Ok(stats) => *state.usage.stats.lock().unwrap() = Some(stats),
+            Err(e) => tracing::warn!(?e, "unable to aggregate usage stats"),
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/stats.json",
+    responses(
+        (status = 200, description = "Instance-wide usage counts", body = UsageStats),
+        (status = 404, description = "The operator doesn't publish usage counts"),
+        (status = 503, description = "Counts are not computed yet"),
+    ),
+)]
+pub async fn get_stats(State(state): State<Arc<AppState>>) -> Response {
+    if !state.flags.public_stats {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    match state.usage.stats.lock().unwrap().clone() {
+        Some(stats) => Json(stats).into_response(),
+        None => StatusCode::SERVICE_UNAVAILABLE.into_response(),
+    }
+}
@@ -0,0 +1,75 @@
+use regex::Regex;
+
+use crate::model::unix_now;
+use crate::model::VenueParent;
+use crate::swarm::SwarmUserApi;
+use crate::swarm::SwarmVenue;
+use crate::AppState;
+
+/// How long a looked up parent venue is trusted. Venues rarely move.
+const PARENT_TTL: u64 = 30 * 24 * 60 * 60;
+
+/// Returns the name of the venue containing `venue_id`, looking it up on
+/// Swarm only when the cached answer is missing or stale.
+pub async fn parent_venue(
+    state: &AppState,
+    swarm: &SwarmUserApi<'_>,
+    venue_id: &str,
+) -> Option<String> {
+    match state.db.get_venue_parent(venue_id) {
+        Ok(Some(cached)) if cached.fetched_at + PARENT_TTL > unix_now() => return cached.name,
+        Ok(_) => {}
+        Err(e) => tracing::warn!(?e, "unable to read cached parent venue"),
+    }
+
+    let name = match swarm.get_venue_parent(venue_id).await {
+        Ok(name) => name,
+        Err(e) => {
+            tracing::warn!(?e, venue=%venue_id, "unable to look up parent venue");
+            return None;
+        }
+    };
+    let parent = VenueParent {
+        name: name.clone(),
+        fetched_at: unix_now(),
+    };
+    if let Err(e) = state.db.save_venue_parent(venue_id, &parent) {
+        tracing::warn!(?e, "unable to cache parent venue");
+    }
+    name
+}
+
+fn rule_regex(rule: &str) -> Option<&str> {
+    rule.strip_prefix('/')
+        .and_then(|rule| rule.strip_suffix('/'))
+}
+
+/// Whether a venue matches one of the user's venue rules. A rule is either
+/// a venue ID, `category:<name>` for every venue in that category, a name
+/// pattern wrapped in slashes like `/Starbucks/`, or a venue name matched
+/// exactly, ignoring case.
+pub fn matches_rule(rules: &[String], venue: &SwarmVenue) -> bool {
+    rules.iter().any(|rule| {
+        if let Some(category) = rule.strip_prefix("category:") {
+            venue
+                .categories
+                .iter()
+                .any(|candidate| candidate.name.eq_ignore_ascii_case(category.trim()))
+        } else if let Some(regex) = rule_regex(rule) {
+            Regex::new(regex).map_or(false, |regex| regex.is_match(&venue.name))
+        } else {
+            *rule == venue.id || rule.eq_ignore_ascii_case(&venue.name)
+        }
+    })
+}
+
+/// Checks the rules accepted by `matches_rule`, returning the first invalid
+/// name pattern's error.
+pub fn validate_rules(rules: &[String]) -> Result<(), String> {
+    for rule in rules {
+        if let Some(regex) = rule_regex(rule) {
+            Regex::new(regex).map_err(|e| format!("invalid pattern {}: {}", rule, e))?;
+        }
+    }
+    Ok(())
+}
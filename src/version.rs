@@ -0,0 +1,165 @@
+//! Build and configuration details for bug reports, logged at startup. The
+//! build details are public at `/version`, the configuration is only shown
+//! to operators at `/admin/api/config`.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::Json;
+use chrono::TimeZone;
+use chrono::Utc;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::AppState;
+
+const REDACTED: &str = "[redacted]";
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct Version {
+    version: &'static str,
+    commit: &'static str,
+    built_at: String,
+    /// Optional functionality enabled by the configuration
+    features: Vec<&'static str>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct Config {
+    /// Configuration with secrets redacted
+    #[schema(value_type = Object)]
+    config: BTreeMap<&'static str, String>,
+}
+
+fn built_at() -> String {
+    env!("SWARMDON_BUILD_TIME")
+        .parse()
+        .ok()
+        .and_then(|unix| Utc.timestamp_opt(unix, 0).single())
+        .map(|date| date.to_rfc3339())
+        .unwrap_or_default()
+}
+
+fn features(state: &AppState) -> Vec<&'static str> {
+    let flags = &state.flags;
+    [
+        ("polling", flags.poll_interval.is_some()),
+        (
+            "admin",
+            flags.admin_token.is_some() || !flags.operator.is_empty(),
+        ),
+        ("nodeinfo_usage", flags.nodeinfo_usage),
+        ("public_stats", flags.public_stats),
+        ("static_maps", flags.static_map_url.is_some()),
+        ("webhooks", flags.webhook_url.is_some()),
+        ("signed_webhooks", flags.webhook_secret.is_some()),
+        (
+            "post_hooks",
+            flags.post_hook_command.is_some() || flags.post_hook_file.is_some(),
+        ),
+        ("notify_account", flags.notify_instance_url.is_some()),
+        ("token_encryption", state.db.encrypts_tokens()),
+        ("custom_privacy_page", flags.privacy_file.is_some()),
+        ("custom_about_page", flags.about_file.is_some()),
+        ("record_fixtures", crate::fixtures::enabled()),
+    ]
+    .into_iter()
+    .filter(|(_, enabled)| *enabled)
+    .map(|(feature, _)| feature)
+    .collect()
+}
+
+fn set_or_unset<T>(value: &Option<T>) -> String {
+    match value {
+        Some(_) => REDACTED.to_string(),
+        None => "unset".to_string(),
+    }
+}
+
+fn config(state: &AppState) -> BTreeMap<&'static str, String> {
+    let flags = &state.flags;
+    let mut config = BTreeMap::new();
+    config.insert("address", flags.address.clone());
+    config.insert("base_url", flags.base_url.clone());
+    config.insert("client_name", flags.client_name.clone());
+    config.insert("swarm_client_id", flags.swarm_client_id.clone());
+    config.insert("swarm_client_secret", REDACTED.to_string());
+    config.insert("swarm_push_secret", REDACTED.to_string());
+    config.insert("admin_token", set_or_unset(&flags.admin_token));
+    config.insert("cookie_key", set_or_unset(&flags.cookie_key));
+    config.insert("operators", flags.operator.len().to_string());
+    config.insert("allowed_proxies", flags.allowed_proxy.join(" "));
+    config.insert(
+        "poll_interval",
+        flags
+            .poll_interval
+            .map_or("unset".to_string(), |interval| interval.to_string()),
+    );
+    config.insert("fast_poll_interval", flags.fast_poll_interval.to_string());
+    config.insert("fast_poll_duration", flags.fast_poll_duration.to_string());
+    config.insert("poll_pacing", flags.poll_pacing.to_string());
+    config.insert(
+        "private_rescan_window",
+        flags.private_rescan_window.to_string(),
+    );
+    config.insert("outbox_max_age", flags.outbox_max_age.to_string());
+    config.insert("push_max_in_flight", flags.push_max_in_flight.to_string());
+    config.insert("push_queue_timeout", flags.push_queue_timeout.to_string());
+    config.insert("redelivery_age", flags.redelivery_age.to_string());
+    config.insert("redelivery_burst", flags.redelivery_burst.to_string());
+    // The map URL is left out, it may well carry an API key.
+    config.insert("static_map_zoom", flags.static_map_zoom.to_string());
+    config.insert("trust_proxy", flags.trust_proxy.to_string());
+    config
+}
+
+pub fn version(state: &AppState) -> Version {
+    Version {
+        version: env!("CARGO_PKG_VERSION"),
+        commit: env!("SWARMDON_GIT_COMMIT"),
+        built_at: built_at(),
+        features: features(state),
+    }
+}
+
+/// Logs what is running and how it's configured, so it ends up in logs
+/// attached to bug reports.
+pub fn log_banner(state: &AppState) {
+    let version = version(state);
+    tracing::info!(
+        version = version.version,
+        commit = version.commit,
+        built_at = version.built_at,
+        features = ?version.features,
+        config = ?config(state),
+        "starting swarmdon"
+    );
+}
+
+#[utoipa::path(
+    get,
+    path = "/version",
+    responses(
+        (status = 200, description = "Build details", body = Version),
+    ),
+)]
+pub async fn get_version(State(state): State<Arc<AppState>>) -> Json<Version> {
+    Json(version(&state))
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/api/config",
+    responses(
+        (status = 200, description = "Configuration with secrets redacted", body = Config),
+        (status = 401, description = "Missing or invalid admin credentials", body = crate::error::ErrorResponse),
+        (status = 403, description = "The operator lacks the viewer role", body = crate::error::ErrorResponse),
+    ),
+    security(("admin" = [])),
+)]
+pub async fn get_config(State(state): State<Arc<AppState>>) -> Json<Config> {
+    Json(Config {
+        config: config(&state),
+    })
+}
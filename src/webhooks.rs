@@ -0,0 +1,74 @@
+//! Outbound webhook telling the operator's own systems about posted
+//! statuses. Deliveries are signed as described in `swarmdon::signature`
+//! when `--webhook-secret` is set.
+
+use mastodon_async::entities::status::Status;
+use serde::Serialize;
+use swarmdon::signature;
+
+use crate::clients::http_client;
+use crate::model::unix_now;
+use crate::AppState;
+
+#[derive(Serialize)]
+struct Event<'a> {
+    event: &'static str,
+    /// Opaque ID of the user, see `Database::public_id`
+    user: String,
+    checkin_id: &'a str,
+    status_id: String,
+    status_url: Option<&'a str>,
+    at: u64,
+}
+
+/// Delivers a `status.posted` event in the background. Failed deliveries are
+/// logged and not retried.
+pub fn status_posted(state: &AppState, user_key: &str, checkin_id: &str, posted: &Status) {
+    let Some(url) = state.flags.webhook_url.clone() else {
+        return;
+    };
+    let user = match state.db.public_id(user_key) {
+        Ok(user) => user,
+        Err(e) => {
+            tracing::warn!(?e, "unable to look up public user ID for webhook");
+            return;
+        }
+    };
+    let at = unix_now();
+    let body = match serde_json::to_vec(&Event {
+        event: "status.posted",
+        user,
+        checkin_id,
+        status_id: posted.id.to_string(),
+        status_url: posted.url.as_deref(),
+        at,
+    }) {
+        Ok(body) => body,
+        Err(e) => {
+            tracing::warn!(?e, "unable to encode webhook event");
+            return;
+        }
+    };
+    let signature = state
+        .flags
+        .webhook_secret
+        .as_ref()
+        .map(|secret| signature::sign(secret.as_bytes(), at, &body));
+
+    tokio::spawn(async move {
+        let result = async {
+            let mut request = http_client(None)?
+                .post(&url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json");
+            if let Some(signature) = signature {
+                request = request.header(signature::HEADER, signature);
+            }
+            request.body(body).send().await?.error_for_status()?;
+            anyhow::Ok(())
+        }
+        .await;
+        if let Err(e) = result {
+            tracing::warn!(?e, "unable to deliver webhook");
+        }
+    });
+}